@@ -13,18 +13,41 @@ use futures_util::{
     Future,
     FutureExt,
 };
+use skunk_api_protocol::{
+    flow::{
+        Flow,
+        GetFlowsRequest,
+        GetFlowsResponse,
+        Subscribe,
+    },
+    socket::{
+        SocketId,
+        SubscriptionId,
+    },
+};
 use skunk_util::trigger;
-use tokio::sync::watch;
+use tokio::sync::{
+    mpsc,
+    watch,
+};
 use tracing::Instrument;
 use url::Url;
+use uuid::Uuid;
 
 use crate::{
+    flow,
+    interrupt,
     socket::{
         Command,
         Reactor,
         ReactorHandle,
     },
-    util::platform::spawn_local,
+    util::{
+        platform::spawn_local,
+        RequestBuilderExt,
+        ResponseExt,
+    },
+    Error,
     Status,
 };
 
@@ -68,8 +91,67 @@ impl Client {
         self.reactor.status_rx.clone()
     }
 
-    pub async fn flows(&self) {
-        todo!();
+    /// Returns the `limit` most recent flows, and a receiver for events of
+    /// flows, e.g. new flows or updated annotations.
+    ///
+    /// This waits until the client is connected. The receiver ends when the
+    /// connection is lost.
+    pub async fn flows(
+        &mut self,
+        limit: usize,
+    ) -> Result<(Vec<Flow>, mpsc::Receiver<flow::Event>), Error> {
+        let socket_id = self.socket_id().await;
+        let subscription_id = SubscriptionId(Uuid::new_v4());
+
+        // subscribe before requesting the flows, so no events are missed.
+        let (event_tx, event_rx) = mpsc::channel(16);
+        self.send_command(Command::SubscribeFlowEvents {
+            subscription_id,
+            event_tx,
+        })
+        .await;
+
+        let response: GetFlowsResponse = self
+            .client
+            .post(self.base_url.clone().push("flow").finish())
+            .msgpack(&GetFlowsRequest {
+                parent: None,
+                after: None,
+                before: None,
+                limit: Some(limit),
+                subscribe: Some(Subscribe {
+                    socket_id,
+                    subscription_id,
+                    filter: None,
+                }),
+                filter: None,
+            })?
+            .send()
+            .await?
+            .msgpack()
+            .await?;
+
+        Ok((response.flows, event_rx))
+    }
+
+    async fn socket_id(&self) -> SocketId {
+        let mut socket_id_rx = self.reactor.socket_id_rx.clone();
+        let socket_id = socket_id_rx
+            .wait_for(Option::is_some)
+            .await
+            .expect("Reactor died");
+        socket_id.expect("socket ID is set")
+    }
+
+    /// Returns a receiver for interrupted messages.
+    ///
+    /// Only one receiver is active at a time. Interrupts are ignored while
+    /// there is none, so that other clients can resolve them.
+    pub async fn interrupts(&mut self) -> mpsc::Receiver<interrupt::Event> {
+        let (event_tx, event_rx) = mpsc::channel(16);
+        self.send_command(Command::SubscribeInterrupts { event_tx })
+            .await;
+        event_rx
     }
 }
 
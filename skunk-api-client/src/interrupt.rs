@@ -0,0 +1,44 @@
+use skunk_api_protocol::{
+    flow::FlowId,
+    socket::{
+        InterruptDecision,
+        InterruptId,
+        InterruptedMessage,
+    },
+};
+use tokio::sync::mpsc;
+
+use crate::socket::Command;
+
+#[derive(Debug)]
+pub enum Event {
+    /// A message was interrupted and waits for a decision.
+    Interrupted(Interrupt),
+
+    /// An interrupt was resolved, e.g. by another client or because it timed
+    /// out.
+    Resolved { interrupt_id: InterruptId },
+}
+
+/// An interrupted request or response.
+#[derive(Debug)]
+pub struct Interrupt {
+    pub interrupt_id: InterruptId,
+    pub flow_id: FlowId,
+    pub prompt: Option<String>,
+    pub message: InterruptedMessage,
+    pub(crate) command_tx: mpsc::Sender<Command>,
+}
+
+impl Interrupt {
+    /// Tells the server what to do with the interrupted message.
+    pub async fn resolve(self, decision: InterruptDecision) {
+        self.command_tx
+            .send(Command::ResolveInterrupt {
+                interrupt_id: self.interrupt_id,
+                decision,
+            })
+            .await
+            .expect("Reactor died");
+    }
+}
@@ -29,8 +29,11 @@ use skunk_api_protocol::{
     socket::{
         ClientHello,
         ClientMessage,
+        InterruptDecision,
+        InterruptId,
         ServerHello,
         ServerMessage,
+        SocketId,
         SubscriptionId,
     },
     PROTOCOL_VERSION,
@@ -44,6 +47,7 @@ use url::Url;
 
 use crate::{
     flow,
+    interrupt,
     util::platform::{
         interval,
         sleep,
@@ -75,6 +79,10 @@ pub(crate) struct ReactorHandle {
     pub command_tx: mpsc::Sender<Command>,
     pub reload_rx: trigger::Receiver,
     pub status_rx: watch::Receiver<Status>,
+
+    /// The ID the server assigned to the current connection, or `None` while
+    /// disconnected.
+    pub socket_id_rx: watch::Receiver<Option<SocketId>>,
 }
 
 /// Reactor that handles the websocket connection to the server.
@@ -86,7 +94,13 @@ pub(crate) struct Reactor {
     command_rx: mpsc::Receiver<Command>,
     reload_tx: trigger::Sender,
     status_tx: watch::Sender<Status>,
+    socket_id_tx: watch::Sender<Option<SocketId>>,
     flows_tx: HashMap<SubscriptionId, mpsc::Sender<flow::Event>>,
+    interrupts_tx: Option<mpsc::Sender<interrupt::Event>>,
+
+    /// Given to interrupts, so that they can be resolved. This is weak, since
+    /// the reactor shuts down when all clients were dropped.
+    command_tx: mpsc::WeakSender<Command>,
 }
 
 impl Reactor {
@@ -94,6 +108,7 @@ impl Reactor {
         let (command_tx, command_rx) = mpsc::channel(16);
         let (reload_tx, reload_rx) = trigger::new();
         let (status_tx, status_rx) = watch::channel(Default::default());
+        let (socket_id_tx, socket_id_rx) = watch::channel(None);
 
         let this = Self {
             client,
@@ -101,13 +116,17 @@ impl Reactor {
             command_rx,
             reload_tx,
             status_tx,
+            socket_id_tx,
             flows_tx: HashMap::new(),
+            interrupts_tx: None,
+            command_tx: command_tx.downgrade(),
         };
 
         let handle = ReactorHandle {
             command_tx,
             reload_rx,
             status_rx,
+            socket_id_rx,
         };
 
         (this, handle)
@@ -123,7 +142,13 @@ impl Reactor {
                 continue;
             };
 
-            match connection.run().await {
+            let result = connection.run().await;
+
+            // subscriptions are per connection
+            let _ = self.socket_id_tx.send(None);
+            self.flows_tx.clear();
+
+            match result {
                 Ok(()) => {
                     // ReactorConnection returns Ok(()) when the command sender has been dropped, so
                     // we should terminate
@@ -174,9 +199,10 @@ impl<'a> ReactorConnection<'a> {
             })
             .await?;
 
-        let _server_hello: ServerHello = socket.receive().await?.ok_or_else(|| Error::Handshake)?;
+        let server_hello: ServerHello = socket.receive().await?.ok_or_else(|| Error::Handshake)?;
 
         let _ = reactor.status_tx.send(Status::Connected);
+        let _ = reactor.socket_id_tx.send(Some(server_hello.socket_id));
 
         Ok(Self {
             socket,
@@ -245,12 +271,28 @@ impl<'a> ReactorConnection<'a> {
                         .await?;
                 }
             }
-            ServerMessage::Interrupt { message_id } => {
-                // todo: for now we'll just send a Continue back
-                // eventually we want to send the interrupt to the user with a oneshot channel.
-                self.socket
-                    .send(&ClientMessage::Continue { message_id })
-                    .await?;
+            ServerMessage::Interrupt {
+                interrupt_id,
+                flow_id,
+                prompt,
+                message,
+            } => {
+                let Some(command_tx) = self.reactor.command_tx.upgrade()
+                else {
+                    return Ok(());
+                };
+                self.send_interrupt_event(interrupt::Event::Interrupted(interrupt::Interrupt {
+                    interrupt_id,
+                    flow_id,
+                    prompt,
+                    message,
+                    command_tx,
+                }))
+                .await;
+            }
+            ServerMessage::InterruptResolved { interrupt_id } => {
+                self.send_interrupt_event(interrupt::Event::Resolved { interrupt_id })
+                    .await;
             }
         }
 
@@ -265,10 +307,33 @@ impl<'a> ReactorConnection<'a> {
             } => {
                 self.reactor.flows_tx.insert(subscription_id, event_tx);
             }
+            Command::SubscribeInterrupts { event_tx } => {
+                self.reactor.interrupts_tx = Some(event_tx);
+            }
+            Command::ResolveInterrupt {
+                interrupt_id,
+                decision,
+            } => {
+                self.socket
+                    .send(&ClientMessage::Continue {
+                        interrupt_id,
+                        decision,
+                    })
+                    .await?;
+            }
         }
 
         Ok(())
     }
+
+    async fn send_interrupt_event(&mut self, event: interrupt::Event) {
+        if let Some(interrupts_tx) = &self.reactor.interrupts_tx {
+            if interrupts_tx.send(event).await.is_err() {
+                // the interrupts receiver has been dropped.
+                self.reactor.interrupts_tx = None;
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -277,6 +342,13 @@ pub(crate) enum Command {
         subscription_id: SubscriptionId,
         event_tx: mpsc::Sender<flow::Event>,
     },
+    SubscribeInterrupts {
+        event_tx: mpsc::Sender<interrupt::Event>,
+    },
+    ResolveInterrupt {
+        interrupt_id: InterruptId,
+        decision: InterruptDecision,
+    },
 }
 
 /// Wrapper around [`reqwest_websocket::WebSocket`] that sends and receives
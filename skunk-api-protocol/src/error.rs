@@ -19,6 +19,7 @@ pub trait StatusCode {
 pub enum ApiError {
     Internal(#[from] InternalError),
     NoSuchSocket(#[from] NoSuchSocket),
+    InvalidFilter(#[from] InvalidFilter),
 }
 api_error!(ApiError);
 
@@ -35,6 +36,7 @@ impl StatusCode for ApiError {
         match self {
             ApiError::Internal(inner) => inner.status_code(),
             ApiError::NoSuchSocket(inner) => inner.status_code(),
+            ApiError::InvalidFilter(inner) => inner.status_code(),
         }
     }
 }
@@ -52,3 +54,10 @@ pub struct NoSuchSocket {
     pub id: SocketId,
 }
 api_error!(NoSuchSocket = BAD_REQUEST);
+
+#[derive(Debug, thiserror::Error, Serialize, Deserialize)]
+#[error("Invalid filter expression: {message}")]
+pub struct InvalidFilter {
+    pub message: String,
+}
+api_error!(InvalidFilter = BAD_REQUEST);
@@ -28,6 +28,13 @@ pub struct GetFlowsRequest {
     pub before: Option<DateTime<FixedOffset>>,
     pub limit: Option<usize>,
     pub subscribe: Option<Subscribe>,
+
+    /// mitmproxy-style filter expression, e.g. `~tag auth & !~marked`.
+    ///
+    /// Flows are only matched by their destination and annotations, so
+    /// filters on messages don't exclude any flows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
 }
 
 api_request!(GetFlowsRequest);
@@ -36,6 +43,12 @@ api_request!(GetFlowsRequest);
 pub struct Subscribe {
     pub socket_id: SocketId,
     pub subscription_id: SubscriptionId,
+
+    /// mitmproxy-style filter expression, e.g. `~d example.com & ~m POST`.
+    ///
+    /// Events of flows that are known not to match the filter are not sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -90,14 +103,74 @@ impl Metadata {
             .insert(key, serde_json::value::to_raw_value(value)?);
         Ok(())
     }
+
+    /// Returns the annotations of a flow, or empty annotations if there are
+    /// none.
+    pub fn annotations(&self) -> Result<Annotations, serde_json::Error> {
+        Ok(self.get(ANNOTATIONS)?.unwrap_or_default())
+    }
+
+    pub fn set_annotations(&mut self, annotations: &Annotations) -> Result<(), serde_json::Error> {
+        self.insert(ANNOTATIONS.to_owned(), annotations)
+    }
+}
+
+/// Metadata key for the [`Annotations`] of a flow.
+const ANNOTATIONS: &str = "annotations";
+
+/// Tags, a marker and a comment that are added to a flow, e.g. by rules.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotations {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// Usually a color, e.g. `red`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl Annotations {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.marker.is_none() && self.comment.is_none()
+    }
+
+    /// Adds the annotations from `other`. Tags are added if they're not
+    /// present yet, and the marker and comment are replaced.
+    pub fn merge(&mut self, other: Annotations) {
+        for tag in other.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+        if other.marker.is_some() {
+            self.marker = other.marker;
+        }
+        if other.comment.is_some() {
+            self.comment = other.comment;
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Event {
-    BeginFlow { flow: Flow },
-    EndFlow { flow_id: FlowId },
-    Message { message: Message },
+    BeginFlow {
+        flow: Flow,
+    },
+    EndFlow {
+        flow_id: FlowId,
+    },
+
+    /// The metadata of a flow changed, e.g. because it was annotated.
+    UpdateFlow {
+        flow: Flow,
+    },
+    Message {
+        message: Message,
+    },
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -135,6 +208,20 @@ impl MessageData {
 
 sqlx_json_type!(MessageData);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+pub struct ArtifactId(pub Uuid);
+
+/// Metadata of a binary blob associated with a message (e.g. a HTTP body).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Artifact {
+    pub artifact_id: ArtifactId,
+    pub message_id: Option<MessageId>,
+    pub mime_type: Option<String>,
+    pub file_name: Option<String>,
+    pub timestamp: DateTime<FixedOffset>,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum MessageKind {
@@ -0,0 +1,63 @@
+//! Message data for gRPC flows.
+//!
+//! A gRPC call is recorded as a child flow of the HTTP flow, with one message
+//! per gRPC message.
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::flow::ArtifactId;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Message {
+    /// The artifact containing the decompressed message.
+    pub artifact_id: Option<ArtifactId>,
+
+    /// Whether the message was compressed.
+    pub compressed: bool,
+
+    /// Size of the decompressed message. If a compressed message was
+    /// truncated, this is only the size of the decompressed part.
+    pub size: u64,
+
+    /// Whether the message was truncated because of the capture limits, or
+    /// not stored at all.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+
+    /// The message decoded with a schema from the configured descriptor sets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<serde_json::Value>,
+
+    /// The message decoded without a schema.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<Field>>,
+}
+
+/// A protobuf field decoded without a schema.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Field {
+    pub number: u32,
+    pub value: FieldValue,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldValue {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    String(String),
+    Bytes(Vec<u8>),
+    Message(Vec<Field>),
+    Group(Vec<Field>),
+}
+
+/// Status of a gRPC call.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub code: u32,
+    pub message: Option<String>,
+}
@@ -0,0 +1,96 @@
+//! Message data for HTTP flows.
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::flow::ArtifactId;
+
+/// List of header name-value pairs.
+pub type Headers = Vec<(String, String)>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub method: String,
+    pub uri: String,
+    pub version: String,
+    pub headers: Headers,
+    pub body: Option<Body>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub status: u16,
+    pub version: String,
+    pub headers: Headers,
+    pub body: Option<Body>,
+}
+
+/// A complete HTTP request, e.g. to be edited while it's interrupted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EditableRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+/// A complete HTTP response, e.g. to be edited while it's interrupted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EditableResponse {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+/// A captured HTTP body.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Body {
+    /// The artifact containing the captured bytes.
+    pub artifact_id: Option<ArtifactId>,
+
+    /// Total size of the body, including bytes that were not captured.
+    pub size: u64,
+
+    /// Whether the captured bytes were truncated.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+
+    /// Whether the body stream ended normally.
+    pub complete: bool,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailers: Headers,
+
+    /// The body decoded according to its content type, e.g. JSON or form
+    /// data.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<serde_json::Value>,
+
+    /// Parts of a multipart body.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<Part>,
+}
+
+/// A part of a multipart body.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Part {
+    /// The artifact containing the part's body.
+    pub artifact_id: ArtifactId,
+    pub name: Option<String>,
+    pub file_name: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: u64,
+}
+
+/// A single event of a `text/event-stream` response.
+///
+/// Events are recorded as messages of a child flow of the HTTP flow.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerSentEvent {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+    pub retry: Option<u64>,
+}
@@ -4,6 +4,9 @@ use semver::Version;
 mod axum;
 pub mod error;
 pub mod flow;
+pub mod grpc;
+pub mod http;
+pub mod rules;
 pub mod socket;
 #[cfg(feature = "sqlx")]
 mod sqlx;
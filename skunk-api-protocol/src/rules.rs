@@ -0,0 +1,59 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GetRulesGraphRequest {
+    #[serde(default)]
+    pub format: GraphFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GraphFormat {
+    /// A [graphviz dot file](https://graphviz.org/doc/info/lang.html).
+    #[default]
+    Dot,
+
+    /// A [`RulesGraph`] as JSON.
+    Json,
+}
+
+/// The compiled rules graph.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RulesGraph {
+    pub nodes: Vec<GraphNode>,
+
+    /// Edges from an expression to the expressions that use it as input.
+    pub edges: Vec<(usize, usize)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub id: usize,
+    pub kind: GraphNodeKind,
+
+    /// Filter descriptions for inputs, and rule paths (e.g.
+    /// `rules[1].then`) for conditions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+
+    /// Whether the expression is the condition of effects.
+    pub is_output: bool,
+
+    /// The value of the expression, if it's known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GraphNodeKind {
+    False,
+    True,
+    Variable,
+    Not,
+    And,
+    Or,
+}
@@ -7,9 +7,12 @@ use serde::{
 };
 use uuid::Uuid;
 
-use crate::flow::{
-    self,
-    MessageId,
+use crate::{
+    flow::{
+        self,
+        FlowId,
+    },
+    http,
 };
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -20,6 +23,10 @@ pub struct SocketId(pub Uuid);
 #[serde(transparent)]
 pub struct SubscriptionId(pub Uuid);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InterruptId(pub Uuid);
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ClientHello {
     pub user_agent: Cow<'static, str>,
@@ -43,9 +50,20 @@ pub enum ServerMessage {
         subscription_id: SubscriptionId,
         event: flow::Event,
     },
+    /// A request or response was interrupted by a rule, and waits for a
+    /// [`ClientMessage::Continue`].
+    ///
+    /// This is sent to all connected clients. Interrupts that are still
+    /// pending when a client connects are sent right after the hello.
     Interrupt {
-        message_id: MessageId,
-        // todo: request/response/etc.
+        interrupt_id: InterruptId,
+        flow_id: FlowId,
+        prompt: Option<String>,
+        message: InterruptedMessage,
+    },
+    /// An interrupt was resolved, either by a client or because it timed out.
+    InterruptResolved {
+        interrupt_id: InterruptId,
     },
 }
 
@@ -57,8 +75,35 @@ pub enum ClientMessage {
     },
     Start,
     Stop,
+    /// Resolves an interrupt. Only the first decision for an interrupt is
+    /// used.
     Continue {
-        message_id: MessageId,
-        // todo: modified request/response/etc.
+        interrupt_id: InterruptId,
+        decision: InterruptDecision,
     },
 }
+
+/// The complete message that was interrupted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InterruptedMessage {
+    Request(http::EditableRequest),
+    Response(http::EditableResponse),
+}
+
+/// What the proxy does with an interrupted message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InterruptDecision {
+    /// Forward the message. If `message` is set, it's forwarded instead of
+    /// the original one. It must be of the same kind as the interrupted
+    /// message.
+    Continue { message: Option<InterruptedMessage> },
+
+    /// Don't forward the message. The client gets a `502 Bad Gateway`
+    /// instead.
+    Drop,
+
+    /// Close the connection.
+    Kill,
+}
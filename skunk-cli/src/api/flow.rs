@@ -11,24 +11,51 @@ use axum::{
     routing,
     Router,
 };
+use bytes::Bytes;
 use chrono::{
     DateTime,
     FixedOffset,
 };
+use skunk::{
+    address::TcpAddress,
+    protocol::http::{
+        header::{
+            HeaderName,
+            HeaderValue,
+        },
+        HeaderMap,
+        Method,
+        StatusCode,
+        Uri,
+    },
+    rule::{
+        backend::{
+            self,
+            CompiledFilter,
+            FilterEvaluator,
+            HttpMessage,
+        },
+        file::Direction,
+    },
+};
 use skunk_api_protocol::{
     error::{
         ApiError,
-        NoSuchSocket,
+        InvalidFilter,
     },
     flow::{
+        Annotations,
+        Artifact,
         Event,
         Flow,
         FlowId,
         GetFlowsRequest,
         GetFlowsResponse,
         Message,
+        MessageKind,
         Subscribe,
     },
+    http,
     socket::{
         ServerMessage,
         SocketId,
@@ -36,6 +63,7 @@ use skunk_api_protocol::{
     },
 };
 use skunk_flow_store::FlowStore;
+use skunk_util::error::ErrorExt;
 use tokio::sync::RwLock;
 
 use super::{
@@ -45,12 +73,28 @@ use super::{
 };
 
 pub(super) fn router() -> Router<Context> {
-    Router::new().route("/", routing::get(get_flows))
+    Router::new().route("/", routing::get(get_flows).post(post_flows))
 }
 
 async fn get_flows(
     State(context): State<Context>,
     Query(request): Query<GetFlowsRequest>,
+) -> Result<GetFlowsResponse, ApiError> {
+    query_flows(context, request).await
+}
+
+/// Same as [`get_flows`], but the request is sent as body. A subscription
+/// can't be encoded in a query string.
+async fn post_flows(
+    State(context): State<Context>,
+    request: GetFlowsRequest,
+) -> Result<GetFlowsResponse, ApiError> {
+    query_flows(context, request).await
+}
+
+async fn query_flows(
+    context: Context,
+    request: GetFlowsRequest,
 ) -> Result<GetFlowsResponse, ApiError> {
     let subscribe = request
         .subscribe
@@ -58,11 +102,14 @@ async fn get_flows(
             |Subscribe {
                  socket_id,
                  subscription_id,
+                 filter,
              }| {
-                Ok::<_, NoSuchSocket>((context.socket(socket_id)?, subscription_id))
+                let filter = filter.as_deref().map(compile_filter).transpose()?;
+                Ok::<_, ApiError>((context.socket(socket_id)?, subscription_id, filter))
             },
         )
         .transpose()?;
+    let filter = request.filter.as_deref().map(compile_filter).transpose()?;
 
     let flows = context
         .flows
@@ -71,6 +118,7 @@ async fn get_flows(
             request.after,
             request.before,
             request.limit,
+            filter,
             subscribe,
         )
         .await?;
@@ -78,6 +126,17 @@ async fn get_flows(
     Ok(GetFlowsResponse { flows })
 }
 
+fn compile_filter(filter: &str) -> Result<CompiledFilter, InvalidFilter> {
+    fn invalid_filter(error: impl std::error::Error) -> InvalidFilter {
+        InvalidFilter {
+            message: error.display_chain().to_string(),
+        }
+    }
+
+    let filter = filter.parse().map_err(invalid_filter)?;
+    CompiledFilter::compile(&filter).map_err(invalid_filter)
+}
+
 #[derive(Clone, Debug)]
 pub struct Flows {
     flow_store: FlowStore,
@@ -121,6 +180,49 @@ impl Flows {
         Ok(())
     }
 
+    /// Emits a message together with artifacts belonging to it.
+    pub async fn emit_message_with_artifacts(
+        &self,
+        message: Message,
+        artifacts: &[(Artifact, Bytes)],
+    ) -> Result<(), Error> {
+        let mut transaction = self.flow_store.transaction().await?;
+        transaction.insert_message(&message).await?;
+        for (artifact, data) in artifacts {
+            transaction.insert_artifact(artifact, data).await?;
+        }
+        let mut subscriptions = self.subscriptions.write().await;
+        transaction.commit().await?;
+        subscriptions.flow_message(&message).await?;
+
+        Ok(())
+    }
+
+    /// Adds annotations to a flow, and sends the updated flow to
+    /// subscriptions. Flows that don't exist are ignored.
+    pub async fn annotate(&self, flow_id: FlowId, annotations: Annotations) -> Result<(), Error> {
+        let mut transaction = self.flow_store.transaction().await?;
+        let Some(mut flow) = transaction.get_flow(flow_id).await?
+        else {
+            return Ok(());
+        };
+
+        let mut merged = flow.metadata.annotations().unwrap_or_default();
+        merged.merge(annotations);
+        flow.metadata
+            .set_annotations(&merged)
+            .expect("failed to serialize flow metadata");
+        transaction
+            .update_flow_metadata(flow_id, &flow.metadata)
+            .await?;
+
+        let mut subscriptions = self.subscriptions.write().await;
+        transaction.commit().await?;
+        subscriptions.update_flow(&flow).await?;
+
+        Ok(())
+    }
+
     pub async fn end_flow(&self, flow_id: FlowId) -> Result<(), Error> {
         let mut subscriptions = self.subscriptions.write().await;
         subscriptions.end_flow(flow_id).await?;
@@ -133,7 +235,8 @@ impl Flows {
         after: Option<DateTime<FixedOffset>>,
         before: Option<DateTime<FixedOffset>>,
         limit: Option<usize>,
-        subscribe: Option<(socket::Sender, SubscriptionId)>,
+        filter: Option<CompiledFilter>,
+        subscribe: Option<(socket::Sender, SubscriptionId, Option<CompiledFilter>)>,
     ) -> Result<Vec<Flow>, Error> {
         let subscriptions = if subscribe.is_some() {
             Some(self.subscriptions.write().await)
@@ -146,13 +249,27 @@ impl Flows {
 
         match (subscriptions, subscribe) {
             (None, None) => {}
-            (Some(mut subscriptions), Some((sender, subscription_id))) => {
-                subscriptions.insert(sender, subscription_id);
+            (Some(mut subscriptions), Some((sender, subscription_id, filter))) => {
+                subscriptions.insert(sender, subscription_id, filter);
             }
             _ => unreachable!(),
         }
 
-        let flows = transaction.get_flows(parent, after, before, limit).await?;
+        // the filter is applied to the flows we get from the flow store, so the
+        // limit can only be applied afterwards.
+        let mut flows = transaction
+            .get_flows(parent, after, before, limit.filter(|_| filter.is_none()))
+            .await?;
+        if let Some(filter) = &filter {
+            flows.retain(|flow| {
+                let mut eval = flow_evaluator(filter, flow);
+                eval.set_annotations(&filter_annotations(
+                    &flow.metadata.annotations().unwrap_or_default(),
+                ));
+                eval.matches() != Some(false)
+            });
+            flows.truncate(limit.unwrap_or(usize::MAX));
+        }
 
         transaction.commit().await?;
 
@@ -162,29 +279,50 @@ impl Flows {
 
 #[derive(Debug, Default)]
 pub struct Subscriptions {
-    inner: HashMap<(SocketId, SubscriptionId), socket::Sender>,
+    inner: HashMap<(SocketId, SubscriptionId), Subscription>,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    sender: socket::Sender,
+    filter: Option<FlowFilter>,
 }
 
 impl Subscriptions {
-    pub fn insert(&mut self, socket: socket::Sender, subscription_id: SubscriptionId) {
-        self.inner
-            .insert((socket.socket_id(), subscription_id), socket);
+    pub fn insert(
+        &mut self,
+        socket: socket::Sender,
+        subscription_id: SubscriptionId,
+        filter: Option<CompiledFilter>,
+    ) {
+        self.inner.insert(
+            (socket.socket_id(), subscription_id),
+            Subscription {
+                sender: socket,
+                filter: filter.map(FlowFilter::new),
+            },
+        );
     }
 
     pub fn remove(&mut self, socket_id: SocketId, subscription_id: SubscriptionId) {
         self.inner.remove(&(socket_id, subscription_id));
     }
 
+    /// Sends the message returned by `f` to all subscriptions. `f` is passed
+    /// the subscription's filter, and can return `None` to not send anything.
     async fn for_each(
         &mut self,
-        mut f: impl FnMut(SubscriptionId) -> ServerMessage,
+        mut f: impl FnMut(SubscriptionId, Option<&mut FlowFilter>) -> Option<ServerMessage>,
     ) -> Result<(), Error> {
         let mut remove = vec![];
 
-        for ((socket_id, subscription_id), sender) in self.inner.iter_mut() {
-            let message = f(*subscription_id);
+        for ((socket_id, subscription_id), subscription) in self.inner.iter_mut() {
+            let Some(message) = f(*subscription_id, subscription.filter.as_mut())
+            else {
+                continue;
+            };
 
-            if let Err(socket::Closed) = sender.send_message(message).await {
+            if let Err(socket::Closed) = subscription.sender.send_message(message).await {
                 remove.push((*socket_id, *subscription_id));
             }
         }
@@ -197,34 +335,260 @@ impl Subscriptions {
     }
 
     pub async fn begin_flow(&mut self, flow: &Flow) -> Result<(), Error> {
-        self.for_each(|subscription_id| {
-            ServerMessage::FlowEvent {
-                subscription_id,
-                event: Event::BeginFlow { flow: flow.clone() },
-            }
+        self.for_each(|subscription_id, filter| {
+            filter
+                .map_or(true, |filter| filter.begin_flow(flow))
+                .then(|| {
+                    ServerMessage::FlowEvent {
+                        subscription_id,
+                        event: Event::BeginFlow { flow: flow.clone() },
+                    }
+                })
+        })
+        .await
+    }
+
+    pub async fn update_flow(&mut self, flow: &Flow) -> Result<(), Error> {
+        self.for_each(|subscription_id, filter| {
+            filter
+                .map_or(true, |filter| filter.update_flow(flow))
+                .then(|| {
+                    ServerMessage::FlowEvent {
+                        subscription_id,
+                        event: Event::UpdateFlow { flow: flow.clone() },
+                    }
+                })
         })
         .await
     }
 
     pub async fn end_flow(&mut self, flow_id: FlowId) -> Result<(), Error> {
-        self.for_each(|subscription_id| {
-            ServerMessage::FlowEvent {
-                subscription_id,
-                event: Event::EndFlow { flow_id },
-            }
+        self.for_each(|subscription_id, filter| {
+            filter
+                .map_or(true, |filter| filter.end_flow(flow_id))
+                .then(|| {
+                    ServerMessage::FlowEvent {
+                        subscription_id,
+                        event: Event::EndFlow { flow_id },
+                    }
+                })
         })
         .await
     }
 
     pub async fn flow_message(&mut self, message: &Message) -> Result<(), Error> {
-        self.for_each(|subscription_id| {
-            ServerMessage::FlowEvent {
-                subscription_id,
-                event: Event::Message {
-                    message: message.clone(),
-                },
-            }
+        self.for_each(|subscription_id, filter| {
+            filter
+                .map_or(true, |filter| filter.message(message))
+                .then(|| {
+                    ServerMessage::FlowEvent {
+                        subscription_id,
+                        event: Event::Message {
+                            message: message.clone(),
+                        },
+                    }
+                })
         })
         .await
     }
 }
+
+/// Filters the events of a subscription with a filter expression.
+///
+/// Events are only dropped if the filter is known not to match. Flows are
+/// evaluated with their destination address and annotations, and each HTTP
+/// message is evaluated on its own.
+///
+/// Since annotations can change, a flow that didn't match before can match
+/// after [`Event::UpdateFlow`]. So subscribers have to handle updates of flows
+/// they haven't seen yet.
+#[derive(Debug)]
+struct FlowFilter {
+    filter: CompiledFilter,
+
+    /// State of flows we have seen. `None` if the flow doesn't match.
+    flows: HashMap<FlowId, Option<FlowState>>,
+}
+
+#[derive(Debug)]
+struct FlowState {
+    /// Evaluator with the destination set. Annotations are set on clones of
+    /// it, since they can change.
+    eval: FilterEvaluator,
+
+    /// `None` if the flow began before the subscription, and we haven't seen
+    /// its annotations yet.
+    annotations: Option<Annotations>,
+
+    /// The last request, which is needed to evaluate its response.
+    request: Option<http::Request>,
+}
+
+impl FlowState {
+    fn new(eval: FilterEvaluator) -> Self {
+        Self {
+            eval,
+            annotations: None,
+            request: None,
+        }
+    }
+
+    /// Returns a clone of the evaluator with the annotations set.
+    fn evaluator(&self) -> FilterEvaluator {
+        let mut eval = self.eval.clone();
+        if let Some(annotations) = &self.annotations {
+            eval.set_annotations(&filter_annotations(annotations));
+        }
+        eval
+    }
+}
+
+impl FlowFilter {
+    fn new(filter: CompiledFilter) -> Self {
+        Self {
+            filter,
+            flows: HashMap::new(),
+        }
+    }
+
+    fn begin_flow(&mut self, flow: &Flow) -> bool {
+        // child flows (e.g. gRPC calls) inherit the state of their parent
+        let parent = flow
+            .parent
+            .and_then(|parent| self.flows.get(&parent))
+            .map(|parent| parent.as_ref().map(|parent| parent.eval.clone()));
+
+        let eval = match parent {
+            Some(Some(eval)) => Some(eval),
+            Some(None) => None,
+            None => Some(flow_evaluator(&self.filter, flow)),
+        };
+
+        let state = eval
+            .filter(|eval| eval.matches() != Some(false))
+            .map(|eval| {
+                FlowState {
+                    annotations: flow.metadata.annotations().ok(),
+                    ..FlowState::new(eval)
+                }
+            });
+        let matches = state
+            .as_ref()
+            .is_some_and(|state| state.evaluator().matches() != Some(false));
+        self.flows.insert(flow.flow_id, state);
+        matches
+    }
+
+    fn update_flow(&mut self, flow: &Flow) -> bool {
+        match self.flows.get_mut(&flow.flow_id) {
+            Some(Some(state)) => {
+                state.annotations = flow.metadata.annotations().ok();
+                state.evaluator().matches() != Some(false)
+            }
+            Some(None) => false,
+            // the flow began before the subscription.
+            None => self.begin_flow(flow),
+        }
+    }
+
+    fn end_flow(&mut self, flow_id: FlowId) -> bool {
+        // flows that began before the subscription are not known, but might match.
+        self.flows
+            .remove(&flow_id)
+            .map_or(true, |state| state.is_some())
+    }
+
+    fn message(&mut self, message: &Message) -> bool {
+        let filter = &self.filter;
+        let Some(state) = self
+            .flows
+            .entry(message.flow_id)
+            .or_insert_with(|| Some(FlowState::new(filter.evaluator())))
+            .as_mut()
+        else {
+            return false;
+        };
+
+        let mut eval = state.evaluator();
+        match message.kind {
+            MessageKind::Request => {
+                if let Ok(request) = message.data.to_value::<http::Request>() {
+                    eval.set_direction(Direction::Request);
+                    set_http(&mut eval, &request, None);
+                    state.request = Some(request);
+                }
+            }
+            MessageKind::Response => {
+                if let (Some(request), Ok(response)) =
+                    (&state.request, message.data.to_value::<http::Response>())
+                {
+                    eval.set_direction(Direction::Response);
+                    set_http(&mut eval, request, Some(&response));
+                }
+            }
+            MessageKind::Other => {}
+        }
+
+        eval.matches() != Some(false)
+    }
+}
+
+/// Creates an evaluator for `filter` with the destination of `flow` set.
+fn flow_evaluator(filter: &CompiledFilter, flow: &Flow) -> FilterEvaluator {
+    let mut eval = filter.evaluator();
+    if let Some(destination) = flow
+        .metadata
+        .get::<String>("destination")
+        .ok()
+        .flatten()
+        .and_then(|destination| destination.parse::<TcpAddress>().ok())
+    {
+        eval.set_tcp(&destination);
+    }
+    eval
+}
+
+fn filter_annotations(annotations: &Annotations) -> backend::Annotations<'_> {
+    backend::Annotations {
+        tags: &annotations.tags,
+        marker: annotations.marker.as_deref(),
+        comment: annotations.comment.as_deref(),
+    }
+}
+
+/// Sets the HTTP input of `eval` from a recorded request and response.
+fn set_http(
+    eval: &mut FilterEvaluator,
+    request: &http::Request,
+    response: Option<&http::Response>,
+) {
+    fn header_map(headers: &http::Headers) -> HeaderMap {
+        headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::from_bytes(name.as_bytes()).ok()?,
+                    HeaderValue::from_str(value).ok()?,
+                ))
+            })
+            .collect()
+    }
+
+    let (Ok(method), Ok(uri)) = (
+        Method::from_bytes(request.method.as_bytes()),
+        request.uri.parse::<Uri>(),
+    )
+    else {
+        return;
+    };
+    let request_headers = header_map(&request.headers);
+    let response_headers = response.map(|response| header_map(&response.headers));
+
+    eval.set_http(&HttpMessage {
+        method: &method,
+        uri: &uri,
+        request_headers: &request_headers,
+        headers: response_headers.as_ref().unwrap_or(&request_headers),
+        status: response.and_then(|response| StatusCode::from_u16(response.status).ok()),
+    });
+}
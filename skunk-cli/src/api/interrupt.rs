@@ -0,0 +1,202 @@
+//! Interrupted requests and responses, that wait for a decision from a client.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use skunk_api_protocol::{
+    flow::FlowId,
+    socket::{
+        InterruptDecision,
+        InterruptId,
+        InterruptedMessage,
+        ServerMessage,
+    },
+};
+use tokio::sync::{
+    broadcast,
+    oneshot,
+};
+use uuid::Uuid;
+
+/// Pending interrupts.
+///
+/// The proxy uses this to interrupt messages, and clients connected via
+/// websocket resolve them.
+#[derive(Clone, Debug)]
+pub struct Interrupts {
+    pending: Arc<Mutex<HashMap<InterruptId, Pending>>>,
+    events_tx: broadcast::Sender<ServerMessage>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    notification: ServerMessage,
+    decision_tx: oneshot::Sender<InterruptDecision>,
+}
+
+impl Default for Interrupts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        let (events_tx, _) = broadcast::channel(64);
+        Self {
+            pending: Default::default(),
+            events_tx,
+        }
+    }
+
+    /// Notifies all clients about the interrupted `message`, and waits for a
+    /// decision.
+    ///
+    /// Returns `None` if no decision was made within `timeout`.
+    pub async fn interrupt(
+        &self,
+        flow_id: FlowId,
+        prompt: Option<String>,
+        message: InterruptedMessage,
+        timeout: Duration,
+    ) -> Option<InterruptDecision> {
+        let interrupt_id = InterruptId(Uuid::new_v4());
+        let (decision_tx, decision_rx) = oneshot::channel();
+        let notification = ServerMessage::Interrupt {
+            interrupt_id,
+            flow_id,
+            prompt,
+            message,
+        };
+
+        {
+            // we hold the lock while sending, so that clients that subscribe
+            // concurrently see the interrupt exactly once.
+            let mut pending = self.pending.lock();
+            // if no client is connected, this fails. that's fine, since clients
+            // get the pending interrupts when they connect.
+            let _ = self.events_tx.send(notification.clone());
+            pending.insert(
+                interrupt_id,
+                Pending {
+                    notification,
+                    decision_tx,
+                },
+            );
+        }
+
+        // removes the interrupt, even if the connection is dropped while we're
+        // waiting.
+        let _guard = ResolveOnDrop {
+            interrupts: self,
+            interrupt_id,
+        };
+
+        tokio::time::timeout(timeout, decision_rx)
+            .await
+            .ok()
+            .and_then(Result::ok)
+    }
+
+    /// Resolves a pending interrupt.
+    ///
+    /// Returns `false` if there is no such interrupt, e.g. because it was
+    /// already resolved.
+    pub fn resolve(&self, interrupt_id: InterruptId, decision: InterruptDecision) -> bool {
+        let Some(pending) = self.pending.lock().remove(&interrupt_id)
+        else {
+            return false;
+        };
+        pending.decision_tx.send(decision).is_ok()
+    }
+
+    /// Returns the currently pending interrupts, and a receiver for
+    /// notifications about new and resolved interrupts.
+    pub fn subscribe(&self) -> (Vec<ServerMessage>, broadcast::Receiver<ServerMessage>) {
+        let pending = self.pending.lock();
+        let notifications = pending
+            .values()
+            .map(|pending| pending.notification.clone())
+            .collect();
+        (notifications, self.events_tx.subscribe())
+    }
+}
+
+struct ResolveOnDrop<'a> {
+    interrupts: &'a Interrupts,
+    interrupt_id: InterruptId,
+}
+
+impl Drop for ResolveOnDrop<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.interrupts.pending.lock();
+        pending.remove(&self.interrupt_id);
+        let _ = self
+            .interrupts
+            .events_tx
+            .send(ServerMessage::InterruptResolved {
+                interrupt_id: self.interrupt_id,
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use skunk_api_protocol::{
+        flow::FlowId,
+        http::EditableRequest,
+        socket::{
+            InterruptDecision,
+            InterruptedMessage,
+            ServerMessage,
+        },
+    };
+    use uuid::Uuid;
+
+    use super::Interrupts;
+
+    #[tokio::test(start_paused = true)]
+    async fn it_times_out_when_the_client_disconnects() {
+        let interrupts = Interrupts::new();
+        let (_, mut notifications) = interrupts.subscribe();
+
+        let interrupt = interrupts.interrupt(
+            FlowId(Uuid::new_v4()),
+            None,
+            InterruptedMessage::Request(EditableRequest {
+                method: "GET".to_owned(),
+                uri: "http://example.com/".to_owned(),
+                headers: vec![],
+                body: vec![],
+            }),
+            Duration::from_secs(10),
+        );
+        let client = async {
+            let ServerMessage::Interrupt { interrupt_id, .. } = notifications.recv().await.unwrap()
+            else {
+                panic!("expected an interrupt");
+            };
+            // the client disconnects without deciding.
+            drop(notifications);
+
+            // a client that connects later still gets the interrupt.
+            let (pending, _) = interrupts.subscribe();
+            assert!(matches!(
+                pending.as_slice(),
+                [ServerMessage::Interrupt { interrupt_id: id, .. }] if *id == interrupt_id
+            ));
+            interrupt_id
+        };
+        let (decision, interrupt_id) = tokio::join!(interrupt, client);
+
+        assert!(decision.is_none());
+        assert!(interrupts.subscribe().0.is_empty());
+        assert!(!interrupts.resolve(interrupt_id, InterruptDecision::Drop));
+    }
+}
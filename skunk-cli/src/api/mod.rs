@@ -1,5 +1,7 @@
 mod capture;
 mod flow;
+mod interrupt;
+mod rules;
 mod socket;
 
 use std::{
@@ -21,8 +23,11 @@ use axum::{
     routing,
     Router,
 };
-use flow::Flows;
 use parking_lot::RwLock;
+use skunk::rule::backend::{
+    DefaultBackend,
+    Rules,
+};
 use skunk_api_protocol::{
     error::{
         ApiError,
@@ -30,9 +35,12 @@ use skunk_api_protocol::{
     },
     socket::SocketId,
 };
-use skunk_flow_store::FlowStore;
 use skunk_util::trigger;
 
+pub use self::{
+    flow::Flows,
+    interrupt::Interrupts,
+};
 use crate::env::{
     config::TlsConfig,
     Environment,
@@ -60,7 +68,9 @@ pub fn builder(env: Environment) -> Builder {
     Builder {
         env,
         reload_ui: Default::default(),
-        flow_store: None,
+        flows: None,
+        interrupts: None,
+        rules: None,
     }
 }
 
@@ -68,7 +78,9 @@ pub fn builder(env: Environment) -> Builder {
 pub struct Builder {
     env: Environment,
     reload_ui: trigger::Receiver,
-    flow_store: Option<FlowStore>,
+    flows: Option<Flows>,
+    interrupts: Option<Interrupts>,
+    rules: Option<Rules>,
 }
 
 impl Builder {
@@ -78,8 +90,18 @@ impl Builder {
         reload_tx
     }
 
-    pub fn with_flow_store(mut self, flow_store: FlowStore) -> Self {
-        self.flow_store = Some(flow_store);
+    pub fn with_flows(mut self, flows: Flows) -> Self {
+        self.flows = Some(flows);
+        self
+    }
+
+    pub fn with_interrupts(mut self, interrupts: Interrupts) -> Self {
+        self.interrupts = Some(interrupts);
+        self
+    }
+
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        self.rules = Some(rules);
         self
     }
 }
@@ -90,13 +112,16 @@ impl Builder {
             env: self.env,
             sockets: Arc::new(RwLock::new(HashMap::new())),
             reload_ui: Arc::new(self.reload_ui),
-            flows: Flows::new(self.flow_store),
+            flows: self.flows.unwrap_or_else(|| Flows::new(None)),
+            interrupts: self.interrupts.unwrap_or_default(),
+            rules: self.rules.unwrap_or_else(|| DefaultBackend::new().build()),
         };
 
         Router::default()
             .route("/ws", routing::get(socket::handle))
             .nest("/flow", flow::router())
             .nest("/capture", capture::router())
+            .nest("/rules", rules::router())
             .route("/feralsec-root-cert.pem", routing::get(get_tls_root_cert))
             .fallback(|| async { "404 - Not found" })
             .with_state(context)
@@ -109,6 +134,8 @@ pub struct Context {
     sockets: Arc<RwLock<HashMap<SocketId, socket::Sender>>>,
     reload_ui: Arc<trigger::Receiver>,
     flows: Flows,
+    interrupts: Interrupts,
+    rules: Rules,
 }
 
 impl Context {
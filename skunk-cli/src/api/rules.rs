@@ -0,0 +1,84 @@
+//! Inspecting the compiled rules.
+
+use axum::{
+    extract::{
+        Query,
+        State,
+    },
+    http::header,
+    response::{
+        IntoResponse,
+        Response,
+    },
+    routing,
+    Json,
+    Router,
+};
+use skunk::rule::eval::{
+    Snapshot,
+    SnapshotNodeKind,
+};
+use skunk_api_protocol::rules::{
+    GetRulesGraphRequest,
+    GraphFormat,
+    GraphNode,
+    GraphNodeKind,
+    RulesGraph,
+};
+
+use super::Context;
+
+pub(super) fn router() -> Router<Context> {
+    Router::new().route("/graph", routing::get(get_graph))
+}
+
+async fn get_graph(
+    State(context): State<Context>,
+    Query(request): Query<GetRulesGraphRequest>,
+) -> Response {
+    let snapshot = context.rules.graph().snapshot();
+
+    match request.format {
+        GraphFormat::Dot => {
+            let mut dot = vec![];
+            snapshot
+                .write_dot(&mut dot)
+                .expect("writing to a Vec can't fail");
+            ([(header::CONTENT_TYPE, "text/vnd.graphviz")], dot).into_response()
+        }
+        GraphFormat::Json => Json(rules_graph(snapshot)).into_response(),
+    }
+}
+
+fn rules_graph(snapshot: Snapshot) -> RulesGraph {
+    let nodes = snapshot
+        .nodes
+        .into_iter()
+        .map(|node| {
+            GraphNode {
+                id: node.id,
+                kind: match node.kind {
+                    SnapshotNodeKind::Literal(false) => GraphNodeKind::False,
+                    SnapshotNodeKind::Literal(true) => GraphNodeKind::True,
+                    SnapshotNodeKind::Variable => GraphNodeKind::Variable,
+                    SnapshotNodeKind::Not => GraphNodeKind::Not,
+                    SnapshotNodeKind::And => GraphNodeKind::And,
+                    SnapshotNodeKind::Or => GraphNodeKind::Or,
+                },
+                labels: node
+                    .label
+                    .iter()
+                    .flat_map(|label| label.lines())
+                    .map(ToOwned::to_owned)
+                    .collect(),
+                is_output: node.is_output,
+                value: node.value,
+            }
+        })
+        .collect();
+
+    RulesGraph {
+        nodes,
+        edges: snapshot.edges,
+    }
+}
@@ -20,7 +20,10 @@ use skunk_api_protocol::{
     },
     PROTOCOL_VERSION,
 };
-use tokio::sync::mpsc;
+use tokio::sync::{
+    broadcast::error::RecvError,
+    mpsc,
+};
 use tracing::Instrument;
 use uuid::Uuid;
 
@@ -88,6 +91,12 @@ impl Reactor {
 
         let mut reload_ui = self.context.reload_ui();
 
+        // send interrupts that are already pending
+        let (pending_interrupts, mut interrupts) = self.context.interrupts.subscribe();
+        for message in &pending_interrupts {
+            self.socket.send(message).await?;
+        }
+
         tracing::debug!(user_agent = %client_hello.user_agent, "client connected");
 
         loop {
@@ -118,6 +127,18 @@ impl Reactor {
                 _ = reload_ui.triggered() => {
                     self.socket.send(&ServerMessage::ReloadUi).await?;
                 }
+
+                // new or resolved interrupt
+                message_res = interrupts.recv() => {
+                    match message_res {
+                        Ok(message) => self.socket.send(&message).await?,
+                        Err(RecvError::Lagged(num_skipped)) => {
+                            tracing::warn!(num_skipped, "websocket missed interrupt notifications");
+                        }
+                        // the sender lives in the context, which we hold.
+                        Err(RecvError::Closed) => unreachable!(),
+                    }
+                }
             }
         }
 
@@ -147,7 +168,14 @@ impl Reactor {
             }
             ClientMessage::Start => todo!(),
             ClientMessage::Stop => todo!(),
-            ClientMessage::Continue { .. } => todo!(),
+            ClientMessage::Continue {
+                interrupt_id,
+                decision,
+            } => {
+                if !self.context.interrupts.resolve(interrupt_id, decision) {
+                    tracing::debug!(?interrupt_id, "interrupt already resolved");
+                }
+            }
         }
 
         Ok(())
@@ -1,4 +1,9 @@
-use color_eyre::eyre::Error;
+use std::io::Write;
+
+use color_eyre::eyre::{
+    bail,
+    Error,
+};
 use semver::Version;
 use semver_macro::env_version;
 use skunk::protocol::tls;
@@ -8,6 +13,7 @@ use crate::env::{
         Command,
         Options,
         ProxyArgs,
+        RulesCommand,
     },
     config::TlsConfig,
     Environment,
@@ -35,6 +41,9 @@ impl App {
             Command::Proxy(args) => {
                 self.proxy(args).await?;
             }
+            Command::Rules { command } => {
+                self.rules(command).await?;
+            }
         }
 
         Ok(())
@@ -73,4 +82,44 @@ impl App {
     async fn proxy(&self, args: ProxyArgs) -> Result<(), Error> {
         crate::proxy::run(self.environment.clone(), args).await
     }
+
+    async fn rules(&self, command: RulesCommand) -> Result<(), Error> {
+        match command {
+            RulesCommand::Graph { files } => {
+                let rules = crate::proxy::rules::load_only(&self.environment, &files)?;
+                let mut stdout = std::io::stdout().lock();
+                rules.graph().write_dot(&mut stdout)?;
+                writeln!(stdout)?;
+            }
+            RulesCommand::Check {
+                no_user_interaction,
+                files,
+            } => {
+                let errors =
+                    crate::rules::check::run(&self.environment, &files, !no_user_interaction)?;
+                if errors > 0 {
+                    bail!("rules files have {errors} errors");
+                }
+            }
+            RulesCommand::Test {
+                rules,
+                flows,
+                files,
+            } => {
+                let failed =
+                    crate::rules::harness::run(&self.environment, &files, &rules, flows.as_deref())
+                        .await?;
+                if failed > 0 {
+                    bail!("{failed} rules tests failed");
+                }
+            }
+            RulesCommand::Schema => {
+                let mut stdout = std::io::stdout().lock();
+                serde_json::to_writer_pretty(&mut stdout, &skunk::rule::file::json_schema())?;
+                writeln!(stdout)?;
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -1,6 +1,7 @@
 use std::{
     net::SocketAddr,
     path::PathBuf,
+    str::FromStr,
 };
 
 use clap::{
@@ -22,6 +23,10 @@ use skunk::{
     self,
     address::TcpAddress,
     proxy::socks::server as socks,
+    rule::filter::mitmproxy::{
+        FilterExpression,
+        ParseError,
+    },
 };
 
 /// skunk - 🦨 A person-in-the-middle proxy
@@ -66,6 +71,73 @@ pub enum Command {
     },
     /// Example command to log (possibly decrypted) HTTP traffic to console.
     Proxy(ProxyArgs),
+    /// Commands for working with rules files.
+    Rules {
+        #[clap(subcommand)]
+        command: RulesCommand,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub enum RulesCommand {
+    /// Writes the compiled rules as graphviz dot file to stdout.
+    ///
+    /// Inputs are labelled with their filters, and conditions with the path
+    /// of their rule. Conditions of effects have a double border.
+    Graph {
+        /// Rules files to compile. By default the rules files in the `rules`
+        /// directory in the configuration directory are compiled.
+        #[clap(value_name("FILE"))]
+        files: Vec<PathBuf>,
+    },
+
+    /// Checks rules files for errors and likely mistakes, e.g. conditions
+    /// that are always false, or effects that conflict.
+    ///
+    /// Exits with an error if any errors are found.
+    Check {
+        /// Report effects that require user interaction (e.g. `interrupt`) as
+        /// errors, e.g. for rules that are used without the UI.
+        #[clap(long)]
+        no_user_interaction: bool,
+
+        /// Rules files to check. By default the rules files in the `rules`
+        /// directory in the configuration directory are checked.
+        #[clap(value_name("FILE"))]
+        files: Vec<PathBuf>,
+    },
+
+    /// Runs rules tests against request and response fixtures, or recorded
+    /// flows.
+    ///
+    /// Nothing is sent over the network. Exits with an error if any test
+    /// fails.
+    Test {
+        /// Rules files to test, if a test file doesn't specify any. By default
+        /// the rules files in the `rules` directory in the configuration
+        /// directory are tested.
+        #[clap(long, value_name("FILE"))]
+        rules: Vec<PathBuf>,
+
+        /// Flow store to load recorded flows from, if a test file doesn't
+        /// specify one. By default the flow store in the data directory is
+        /// used.
+        #[clap(long, value_name("FILE"))]
+        flows: Option<PathBuf>,
+
+        /// Test files to run.
+        #[clap(value_name("FILE"), required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// Writes the JSON Schema for rules files to stdout.
+    ///
+    /// Editors with a YAML language server can use it to complete and
+    /// validate rules files. The published schema can be referenced with a
+    /// modeline at the top of a rules file:
+    ///
+    /// # yaml-language-server: $schema=https://raw.githubusercontent.com/jgraef/skunk/main/skunk/rules.schema.json
+    Schema,
 }
 
 #[derive(Debug, Parser)]
@@ -82,12 +154,48 @@ pub struct ProxyArgs {
     #[clap(long)]
     pub no_graceful_shutdown: bool,
 
+    /// File to record flows to. Defaults to `skunk.flows` in the data
+    /// directory.
+    #[clap(long, value_name("FILE"))]
+    pub flows: Option<PathBuf>,
+
+    /// Rules file to apply to intercepted traffic. Multiple can be specified.
+    /// Rules files in the `rules` directory in the configuration directory
+    /// are always applied.
+    #[clap(long, value_name("FILE"))]
+    pub rules: Vec<PathBuf>,
+
     /// Only intercept specified addresses.
     ///
-    /// `host:port` pairs. Multiple can be specified. This can be used to only
+    /// `host:port` pairs or mitmproxy-style filter expressions (e.g. `~d
+    /// example.com`). Multiple can be specified. This can be used to only
     /// selectively inspect traffic. By default all traffic is inspected.
     /// Currently only ports 80 and 443 are supported.
-    pub filter: Vec<TcpAddress>,
+    ///
+    /// Filter expressions are evaluated when a connection is opened. A
+    /// connection is intercepted unless the expression is known not to match
+    /// with the destination address alone.
+    pub filter: Vec<TargetFilter>,
+}
+
+/// A filter for which connections to intercept.
+#[derive(Clone, Debug)]
+pub enum TargetFilter {
+    Address(TcpAddress),
+    Expression(FilterExpression),
+}
+
+impl FromStr for TargetFilter {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = s.parse() {
+            Ok(Self::Address(address))
+        }
+        else {
+            Ok(Self::Expression(s.parse()?))
+        }
+    }
 }
 
 #[derive(Debug, Parser)]
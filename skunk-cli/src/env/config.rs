@@ -16,6 +16,10 @@ use serde::{
     de::IntoDeserializer,
     Deserialize,
 };
+use skunk::protocol::http::body::{
+    CaptureLimits,
+    Overflow,
+};
 use tokio::sync::RwLock;
 use toml_edit::DocumentMut;
 use tracing::Instrument;
@@ -271,3 +275,95 @@ impl Default for UiConfig {
         }
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct CaptureConfig {
+    /// Maximum size of a body that is kept in memory. Captured bodies are
+    /// stored with their flow up to this size.
+    #[serde(default = "default_capture_config_memory_limit")]
+    pub memory_limit: usize,
+
+    /// Whether bodies larger than `memory_limit` are spilled to a temporary
+    /// file. Otherwise they're truncated.
+    #[serde(default = "default_capture_config_spill")]
+    pub spill: bool,
+
+    /// Maximum size of a body that is spilled to a file.
+    #[serde(default = "default_capture_config_file_limit")]
+    pub file_limit: Option<u64>,
+}
+
+fn default_capture_config_memory_limit() -> usize {
+    CaptureLimits::default().memory_limit
+}
+
+fn default_capture_config_spill() -> bool {
+    true
+}
+
+fn default_capture_config_file_limit() -> Option<u64> {
+    Some(256 * 1024 * 1024)
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            memory_limit: default_capture_config_memory_limit(),
+            spill: default_capture_config_spill(),
+            file_limit: default_capture_config_file_limit(),
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn limits(&self) -> CaptureLimits {
+        CaptureLimits {
+            memory_limit: self.memory_limit,
+            overflow: if self.spill {
+                Overflow::Spill {
+                    file_limit: self.file_limit,
+                }
+            }
+            else {
+                Overflow::Truncate
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GrpcConfig {
+    /// Protobuf descriptor sets (`protoc --descriptor_set_out`) used to decode
+    /// gRPC messages with named fields. Relative paths are relative to the
+    /// config directory.
+    #[serde(default)]
+    pub descriptor_sets: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleLogConfig {
+    /// Size in bytes after which a log file is rotated.
+    #[serde(default = "default_rule_log_config_max_size")]
+    pub max_size: u64,
+
+    /// Number of rotated log files that are kept.
+    #[serde(default = "default_rule_log_config_max_files")]
+    pub max_files: usize,
+}
+
+fn default_rule_log_config_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_rule_log_config_max_files() -> usize {
+    5
+}
+
+impl Default for RuleLogConfig {
+    fn default() -> Self {
+        Self {
+            max_size: default_rule_log_config_max_size(),
+            max_files: default_rule_log_config_max_files(),
+        }
+    }
+}
@@ -35,6 +35,16 @@ pub const DATA_DIR_NAME: &str = "feralsec/skunk";
 /// Main configuration file name.
 pub const CONFIG_FILE: &str = "skunk.toml";
 
+/// Default file to record flows to, relative to the data directory.
+pub const FLOWS_FILE: &str = "skunk.flows";
+
+/// Directory with rules files that are always loaded, relative to the
+/// configuration directory.
+pub const RULES_DIR: &str = "rules";
+
+/// Directory for log files written by rules, relative to the data directory.
+pub const LOGS_DIR: &str = "logs";
+
 pub const DEFAULT_CONFIG: &str = include_str!("skunk.default.toml");
 
 #[derive(Clone, Debug)]
@@ -4,6 +4,7 @@ mod api;
 mod app;
 mod env;
 mod proxy;
+mod rules;
 mod util;
 
 use clap::Parser;
@@ -0,0 +1,380 @@
+//! Interrupting HTTP requests and responses, so that a user can inspect and
+//! edit them before they're forwarded.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use skunk::{
+    protocol::http::{
+        self,
+        body::{
+            Body,
+            Replace,
+        },
+        header::{
+            self,
+            HeaderName,
+            HeaderValue,
+        },
+        HeaderMap,
+        Method,
+        Request,
+        Response,
+        StatusCode,
+        Uri,
+    },
+    rule::file::{
+        InterruptEffect,
+        InterruptTimeout,
+    },
+};
+use skunk_api_protocol::{
+    flow::FlowId,
+    http::{
+        EditableRequest,
+        EditableResponse,
+        Headers,
+    },
+    socket::{
+        InterruptDecision,
+        InterruptedMessage,
+    },
+};
+
+use crate::api::Interrupts;
+
+/// How long to wait for a decision, if the effect doesn't say.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What to do with an interrupted message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Forward,
+
+    /// Don't forward the message, and answer with [`bad_gateway`] instead.
+    Drop,
+
+    /// Close the connection.
+    Kill,
+}
+
+/// Interrupts `request` and waits for a decision.
+///
+/// The request body is read, so that it can be sent to clients. The request
+/// is returned with any edits the user made.
+pub async fn interrupt_request<B>(
+    interrupts: &Interrupts,
+    flow_id: FlowId,
+    effect: &InterruptEffect,
+    request: Request<Replace<B>>,
+) -> Result<(Request<Replace<B>>, Decision), skunk::Error>
+where
+    B: Body<Data = Bytes>,
+    http::Error: From<B::Error>,
+{
+    let (mut parts, body) = request.into_parts();
+    let mut body = body.collect().await.map_err(http::Error::from)?.to_bytes();
+
+    let message = InterruptedMessage::Request(EditableRequest {
+        method: parts.method.to_string(),
+        uri: parts.uri.to_string(),
+        headers: to_headers(&parts.headers),
+        body: body.to_vec(),
+    });
+
+    let (edited, decision) = decide(interrupts, flow_id, effect, message).await;
+    let mut is_edited = false;
+    match edited {
+        None => {}
+        Some(InterruptedMessage::Request(edited)) => {
+            match (
+                Method::from_bytes(edited.method.as_bytes()),
+                edited.uri.parse::<Uri>(),
+                from_headers(&edited.headers),
+            ) {
+                (Ok(method), Ok(uri), Some(headers)) => {
+                    parts.method = method;
+                    parts.uri = uri;
+                    parts.headers = headers;
+                    body = edited.body.into();
+                    is_edited = true;
+                }
+                _ => tracing::warn!("Ignoring invalid edit of interrupted request"),
+            }
+        }
+        Some(InterruptedMessage::Response(_)) => {
+            tracing::warn!("Ignoring response sent for interrupted request");
+        }
+    }
+
+    set_content_length(&mut parts.headers, &body, is_edited);
+    Ok((
+        Request::from_parts(parts, Replace::replaced(body)),
+        decision,
+    ))
+}
+
+/// Interrupts `response` and waits for a decision.
+///
+/// The response body is read, so that it can be sent to clients. The
+/// response is returned with any edits the user made.
+pub async fn interrupt_response<B>(
+    interrupts: &Interrupts,
+    flow_id: FlowId,
+    effect: &InterruptEffect,
+    response: Response<Replace<B>>,
+) -> Result<(Response<Replace<B>>, Decision), skunk::Error>
+where
+    B: Body<Data = Bytes>,
+    http::Error: From<B::Error>,
+{
+    let (mut parts, body) = response.into_parts();
+    let mut body = body.collect().await.map_err(http::Error::from)?.to_bytes();
+
+    let message = InterruptedMessage::Response(EditableResponse {
+        status: parts.status.as_u16(),
+        headers: to_headers(&parts.headers),
+        body: body.to_vec(),
+    });
+
+    let (edited, decision) = decide(interrupts, flow_id, effect, message).await;
+    let mut is_edited = false;
+    match edited {
+        None => {}
+        Some(InterruptedMessage::Response(edited)) => {
+            match (
+                StatusCode::from_u16(edited.status),
+                from_headers(&edited.headers),
+            ) {
+                (Ok(status), Some(headers)) => {
+                    parts.status = status;
+                    parts.headers = headers;
+                    body = edited.body.into();
+                    is_edited = true;
+                }
+                _ => tracing::warn!("Ignoring invalid edit of interrupted response"),
+            }
+        }
+        Some(InterruptedMessage::Request(_)) => {
+            tracing::warn!("Ignoring request sent for interrupted response");
+        }
+    }
+
+    set_content_length(&mut parts.headers, &body, is_edited);
+    Ok((
+        Response::from_parts(parts, Replace::replaced(body)),
+        decision,
+    ))
+}
+
+/// The response sent to the client if a message was dropped, or the request
+/// could not be mapped.
+pub fn bad_gateway<B>() -> Response<Replace<B>> {
+    empty_response(StatusCode::BAD_GATEWAY)
+}
+
+/// The response sent to the client if applying an effect to a message failed.
+pub fn internal_server_error<B>() -> Response<Replace<B>> {
+    empty_response(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn empty_response<B>(status: StatusCode) -> Response<Replace<B>> {
+    let mut response = Response::new(Replace::replaced(Bytes::new()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_LENGTH, 0.into());
+    response
+}
+
+async fn decide(
+    interrupts: &Interrupts,
+    flow_id: FlowId,
+    effect: &InterruptEffect,
+    message: InterruptedMessage,
+) -> (Option<InterruptedMessage>, Decision) {
+    tracing::info!(prompt = effect.prompt.as_deref(), "Interrupted");
+
+    let timeout = effect
+        .timeout_secs
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+    match interrupts
+        .interrupt(flow_id, effect.prompt.clone(), message, timeout)
+        .await
+    {
+        Some(InterruptDecision::Continue { message }) => (message, Decision::Forward),
+        Some(InterruptDecision::Drop) => (None, Decision::Drop),
+        Some(InterruptDecision::Kill) => (None, Decision::Kill),
+        None => {
+            tracing::info!(on_timeout = ?effect.on_timeout, "Interrupt timed out");
+            let decision = match effect.on_timeout {
+                InterruptTimeout::Continue => Decision::Forward,
+                InterruptTimeout::Drop => Decision::Drop,
+                InterruptTimeout::Kill => Decision::Kill,
+            };
+            (None, decision)
+        }
+    }
+}
+
+/// Updates the headers for a body that was read completely, and might have
+/// been edited.
+fn set_content_length(headers: &mut HeaderMap, body: &Bytes, is_edited: bool) {
+    let was_chunked = headers.remove(header::TRANSFER_ENCODING).is_some();
+    if was_chunked || is_edited {
+        headers.insert(header::CONTENT_LENGTH, body.len().into());
+    }
+}
+
+fn to_headers(headers: &HeaderMap) -> Headers {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().to_owned(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+fn from_headers(headers: &Headers) -> Option<HeaderMap> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            Some((
+                HeaderName::from_bytes(name.as_bytes()).ok()?,
+                HeaderValue::from_str(value).ok()?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::{
+        combinators::BoxBody,
+        BodyExt,
+    };
+    use skunk::{
+        protocol::http::{
+            body::Replace,
+            header,
+            Method,
+            Request,
+        },
+        rule::file::InterruptEffect,
+    };
+    use skunk_api_protocol::{
+        flow::FlowId,
+        http::EditableRequest,
+        socket::{
+            InterruptDecision,
+            InterruptedMessage,
+            ServerMessage,
+        },
+    };
+    use uuid::Uuid;
+
+    use super::{
+        interrupt_request,
+        Decision,
+    };
+    use crate::api::Interrupts;
+
+    type TestBody = Replace<BoxBody<Bytes, std::io::Error>>;
+
+    /// Interrupts a POST request, and lets `decide` make the decision once the
+    /// interrupt was sent to a client.
+    async fn interrupt(
+        decide: impl FnOnce(InterruptedMessage) -> InterruptDecision,
+    ) -> (Request<TestBody>, Decision) {
+        let interrupts = Interrupts::new();
+        let (_, mut notifications) = interrupts.subscribe();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("http://example.com/")
+            .header(header::CONTENT_LENGTH, 5)
+            .body(TestBody::replaced("hello"))
+            .unwrap();
+
+        let client = async {
+            let ServerMessage::Interrupt {
+                interrupt_id,
+                message,
+                ..
+            } = notifications.recv().await.unwrap()
+            else {
+                panic!("expected an interrupt");
+            };
+            assert!(interrupts.resolve(interrupt_id, decide(message)));
+        };
+        let ((request, decision), ()) = tokio::join!(
+            async {
+                interrupt_request(
+                    &interrupts,
+                    FlowId(Uuid::new_v4()),
+                    &InterruptEffect::default(),
+                    request,
+                )
+                .await
+                .unwrap()
+            },
+            client,
+        );
+        (request, decision)
+    }
+
+    async fn body(request: Request<TestBody>) -> Bytes {
+        request.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn it_resumes_without_edits() {
+        let (request, decision) = interrupt(|message| {
+            let InterruptedMessage::Request(request) = message
+            else {
+                panic!("expected a request");
+            };
+            assert_eq!(request.method, "POST");
+            assert_eq!(request.body, b"hello");
+            InterruptDecision::Continue { message: None }
+        })
+        .await;
+
+        assert_eq!(decision, Decision::Forward);
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.headers()[header::CONTENT_LENGTH], "5");
+        assert_eq!(body(request).await, "hello");
+    }
+
+    #[tokio::test]
+    async fn it_resumes_with_edits() {
+        let (request, decision) = interrupt(|_| {
+            InterruptDecision::Continue {
+                message: Some(InterruptedMessage::Request(EditableRequest {
+                    method: "PUT".to_owned(),
+                    uri: "http://example.org/edited".to_owned(),
+                    headers: vec![("x-edited".to_owned(), "yes".to_owned())],
+                    body: b"edited body".to_vec(),
+                })),
+            }
+        })
+        .await;
+
+        assert_eq!(decision, Decision::Forward);
+        assert_eq!(request.method(), Method::PUT);
+        assert_eq!(request.uri(), "http://example.org/edited");
+        assert_eq!(request.headers()["x-edited"], "yes");
+        assert_eq!(request.headers()[header::CONTENT_LENGTH], "11");
+        assert_eq!(body(request).await, "edited body");
+    }
+
+    #[tokio::test]
+    async fn it_drops_requests() {
+        let (_, decision) = interrupt(|_| InterruptDecision::Drop).await;
+        assert_eq!(decision, Decision::Drop);
+    }
+}
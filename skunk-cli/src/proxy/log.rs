@@ -0,0 +1,311 @@
+//! JSON Lines log files written by log effects.
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::PathBuf,
+};
+
+use chrono::{
+    DateTime,
+    FixedOffset,
+};
+use serde::Serialize;
+use skunk_api_protocol::flow::FlowId;
+use tokio::{
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::AsyncWriteExt,
+    sync::mpsc::{
+        self,
+        error::TrySendError,
+    },
+};
+
+/// Name of the log file, if the effect doesn't have a name.
+pub const DEFAULT_FILE: &str = "rules";
+
+/// How many records can wait to be written. If the writer can't keep up,
+/// further records are dropped.
+const RECORD_BUFFER: usize = 1024;
+
+/// How many log files are kept open. If more files are written to, the least
+/// recently used one is closed.
+const MAX_OPEN_FILES: usize = 16;
+
+/// A record in a log file.
+#[derive(Clone, Debug, Serialize)]
+pub struct Record {
+    pub timestamp: DateTime<FixedOffset>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_id: Option<FlowId>,
+
+    pub destination: String,
+
+    /// Name of the rule that fired.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+}
+
+/// Writes log records to files in a directory.
+///
+/// The files are rotated once they would grow larger than `max_size`: The
+/// current file `NAME.jsonl` is renamed to `NAME.1.jsonl`, `NAME.1.jsonl` to
+/// `NAME.2.jsonl`, and so on. Up to `max_files` rotated files are kept.
+///
+/// Records are written by a background task, so that writing them doesn't
+/// block the proxy. If it can't keep up, records are dropped.
+#[derive(Clone, Debug)]
+pub struct FileLog {
+    tx: mpsc::Sender<(String, Record)>,
+}
+
+impl FileLog {
+    pub fn new(dir: PathBuf, max_size: u64, max_files: usize) -> Self {
+        let (tx, rx) = mpsc::channel(RECORD_BUFFER);
+        tokio::spawn(Writer::new(dir, max_size, max_files).run(rx));
+
+        Self { tx }
+    }
+
+    /// Appends `record` to the log file `name`.
+    ///
+    /// Characters in `name` that are not allowed in file names are replaced.
+    pub fn write(&self, name: &str, record: Record) {
+        match self.tx.try_send((file_name(name), record)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                tracing::warn!("Log writer can't keep up, dropping record");
+            }
+            // the writer only stops if we're dropped.
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+struct Writer {
+    dir: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    files: HashMap<String, LogFile>,
+
+    /// Incremented for every write, to find the least recently used file.
+    num_writes: u64,
+}
+
+struct LogFile {
+    file: File,
+    size: u64,
+    last_write: u64,
+}
+
+impl Writer {
+    fn new(dir: PathBuf, max_size: u64, max_files: usize) -> Self {
+        Self {
+            dir,
+            max_size,
+            max_files,
+            files: HashMap::new(),
+            num_writes: 0,
+        }
+    }
+
+    async fn run(mut self, mut rx: mpsc::Receiver<(String, Record)>) {
+        while let Some((name, record)) = rx.recv().await {
+            if let Err(error) = self.write(&name, &record).await {
+                tracing::error!(?error, name, "Could not write log record");
+            }
+        }
+    }
+
+    async fn write(&mut self, name: &str, record: &Record) -> Result<(), std::io::Error> {
+        let mut line = serde_json::to_vec(record).expect("failed to serialize log record");
+        line.push(b'\n');
+        let length = line.len() as u64;
+
+        if !self.files.contains_key(name) {
+            self.close_least_recently_used();
+            let file = self.open(name).await?;
+            self.files.insert(name.to_owned(), file);
+        }
+
+        let size = self.files[name].size;
+        if size > 0 && size + length > self.max_size {
+            self.files.remove(name);
+            self.rotate(name).await?;
+            let file = self.open(name).await?;
+            self.files.insert(name.to_owned(), file);
+        }
+
+        self.num_writes += 1;
+        let file = self.files.get_mut(name).expect("log file is open");
+        file.last_write = self.num_writes;
+        file.file.write_all(&line).await?;
+        file.file.flush().await?;
+        file.size += length;
+
+        Ok(())
+    }
+
+    async fn open(&self, name: &str) -> Result<LogFile, std::io::Error> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(name, 0))
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(LogFile {
+            file,
+            size,
+            last_write: self.num_writes,
+        })
+    }
+
+    /// Closes the least recently used file, if [`MAX_OPEN_FILES`] are open.
+    fn close_least_recently_used(&mut self) {
+        if self.files.len() < MAX_OPEN_FILES {
+            return;
+        }
+        if let Some(name) = self
+            .files
+            .iter()
+            .min_by_key(|(_, file)| file.last_write)
+            .map(|(name, _)| name.clone())
+        {
+            self.files.remove(&name);
+        }
+    }
+
+    async fn rotate(&self, name: &str) -> Result<(), std::io::Error> {
+        if self.max_files == 0 {
+            return ignore_not_found(tokio::fs::remove_file(self.path(name, 0)).await);
+        }
+
+        // renaming overwrites the oldest file.
+        for index in (0..self.max_files).rev() {
+            ignore_not_found(
+                tokio::fs::rename(self.path(name, index), self.path(name, index + 1)).await,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the path of the log file `name`, or of a rotated file if
+    /// `index` is not 0.
+    fn path(&self, name: &str, index: usize) -> PathBuf {
+        if index == 0 {
+            self.dir.join(format!("{name}.jsonl"))
+        }
+        else {
+            self.dir.join(format!("{name}.{index}.jsonl"))
+        }
+    }
+}
+
+fn ignore_not_found(result: Result<(), std::io::Error>) -> Result<(), std::io::Error> {
+    match result {
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Makes `name` safe to use as file name in the log directory.
+fn file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            }
+            else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let name = name.trim_start_matches('.');
+
+    if name.is_empty() {
+        DEFAULT_FILE.to_owned()
+    }
+    else {
+        name.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+
+    use super::{
+        Record,
+        Writer,
+        MAX_OPEN_FILES,
+    };
+
+    fn record(message: &str) -> Record {
+        Record {
+            timestamp: Local::now().fixed_offset(),
+            flow_id: None,
+            destination: "example.com:80".to_owned(),
+            name: None,
+            message: Some(message.to_owned()),
+            method: None,
+            url: None,
+            status: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_rotates_files() {
+        let dir = tempfile::tempdir().unwrap();
+        // every record is larger than this, so every write rotates.
+        let mut writer = Writer::new(dir.path().to_owned(), 1, 2);
+
+        for message in ["1", "2", "3", "4"] {
+            writer.write("test", &record(message)).await.unwrap();
+        }
+
+        let message = |file: &str| {
+            let line = std::fs::read_to_string(dir.path().join(file)).unwrap();
+            let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+            record["message"].as_str().unwrap().to_owned()
+        };
+        assert_eq!(message("test.jsonl"), "4");
+        assert_eq!(message("test.1.jsonl"), "3");
+        assert_eq!(message("test.2.jsonl"), "2");
+        assert!(!dir.path().join("test.3.jsonl").exists());
+    }
+
+    #[tokio::test]
+    async fn it_limits_open_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = Writer::new(dir.path().to_owned(), 1024, 1);
+
+        for i in 0..=MAX_OPEN_FILES {
+            writer
+                .write(&format!("test-{i}"), &record("hello"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(writer.files.len(), MAX_OPEN_FILES);
+        assert!(!writer.files.contains_key("test-0"));
+    }
+}
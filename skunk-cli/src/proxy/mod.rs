@@ -0,0 +1,993 @@
+mod interrupt;
+mod log;
+mod record;
+pub mod rules;
+
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::Arc,
+};
+
+use axum::Router;
+use bytes::Bytes;
+use color_eyre::eyre::Error;
+use http_body_util::BodyExt;
+use skunk::{
+    address::TcpAddress,
+    connect::{
+        Connect,
+        ConnectTcp,
+    },
+    protocol::{
+        http::{
+            self,
+            body::{
+                collect_limited,
+                CaptureLimits,
+                Collected,
+                Incoming,
+                Replace,
+            },
+            HeaderMap,
+            Method,
+            Request,
+            Response,
+            SendRequest,
+            StatusCode,
+            Uri,
+        },
+        protobuf::Descriptors,
+        tls,
+    },
+    proxy::{
+        condition::{
+            Conditioned,
+            Conditions,
+        },
+        pcap::{
+            self,
+            interface::Interface,
+            VirtualNetwork,
+        },
+        socks::server as socks,
+        DestinationAddress,
+        Passthrough,
+        Proxy,
+    },
+    rule::{
+        backend::{
+            CompiledFilter,
+            Evaluator,
+            HttpBody,
+            HttpMessage,
+            Rules,
+            Tls,
+        },
+        file::Direction,
+        map::{
+            map_local,
+            map_remote,
+        },
+        mock::mock,
+        modify::{
+            modify_request,
+            modify_response,
+        },
+        script::{
+            script_request,
+            script_response,
+        },
+    },
+};
+use skunk_flow_store::FlowStore;
+use skunk_util::error::ResultExt;
+use tokio::{
+    net::TcpStream,
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use self::{
+    interrupt::{
+        bad_gateway,
+        internal_server_error,
+        interrupt_request,
+        interrupt_response,
+        Decision,
+    },
+    log::FileLog,
+    record::{
+        self,
+        Exchange,
+        HttpFlow,
+        RecordedRequestBody,
+        RecordedResponseBody,
+        Recorder,
+    },
+    rules::{
+        Action,
+        MapEffect,
+        MessageEffects,
+        Scope,
+    },
+};
+use crate::{
+    api::{
+        Flows,
+        Interrupts,
+    },
+    env::{
+        args::{
+            ProxyArgs,
+            TargetFilter,
+        },
+        config::{
+            CaptureConfig,
+            GrpcConfig,
+            RuleLogConfig,
+        },
+        Environment,
+        FLOWS_FILE,
+        LOGS_DIR,
+    },
+    util::{
+        serve_ui::ServeUi,
+        shutdown::cancel_on_ctrlc_or_sigterm,
+    },
+};
+
+pub async fn run(environment: Environment, args: ProxyArgs) -> Result<(), Error> {
+    let pcap_interface = if args.pcap.enabled {
+        fn print_interfaces() -> Result<(), Error> {
+            println!("Available interfaces:");
+            for interface in Interface::list()? {
+                println!("{}\n", interface.name());
+            }
+            Ok(())
+        }
+
+        if let Some(interface) = args.pcap.interface {
+            let interface_opt = Interface::from_name(&interface)?;
+            if interface_opt.is_none() {
+                eprintln!("Interface '{interface}' not found");
+                print_interfaces()?;
+                return Ok(());
+            }
+            interface_opt
+        }
+        else {
+            print_interfaces()?;
+            return Ok(());
+        }
+    }
+    else {
+        None
+    };
+
+    // create TLS context
+    let tls = environment.tls_context().await?;
+
+    // target filters
+    let filter = Arc::new(if args.filter.is_empty() {
+        tracing::info!("Matching all flows");
+        Filter::default()
+    }
+    else {
+        tracing::info!("Matching: {:?}", args.filter);
+        Filter::new(args.filter)?
+    });
+
+    // rules
+    let (rules, dependencies) = rules::load(&environment, &args.rules)?;
+    if rules.is_empty() {
+        tracing::info!("No rules loaded");
+    }
+    rules::watch(&environment, &args.rules, dependencies, rules.clone())?;
+
+    // flow recording
+    let flows_path = args
+        .flows
+        .clone()
+        .unwrap_or_else(|| environment.data_relative_path(FLOWS_FILE));
+    tracing::info!(path = %flows_path.display(), "Recording flows");
+    let flows = Flows::new(Some(FlowStore::create(&flows_path).await?));
+    let capture_limits = environment
+        .get_untracked::<CaptureConfig>("capture")
+        .await?
+        .unwrap_or_default()
+        .limits();
+
+    // protobuf descriptors for decoding gRPC messages
+    let mut descriptors = Descriptors::new();
+    for path in environment
+        .get_untracked::<GrpcConfig>("grpc")
+        .await?
+        .unwrap_or_default()
+        .descriptor_sets
+    {
+        let path = environment.config_relative_path(path);
+        tracing::info!(path = %path.display(), "Loading protobuf descriptor set");
+        descriptors.add_descriptor_set_file(&path)?;
+    }
+
+    // interrupted messages are resolved by API clients
+    let interrupts = Interrupts::new();
+
+    // log files written by rules
+    let rule_log_config = environment
+        .get_untracked::<RuleLogConfig>("rule_log")
+        .await?
+        .unwrap_or_default();
+    let file_log = FileLog::new(
+        environment.data_relative_path(LOGS_DIR),
+        rule_log_config.max_size,
+        rule_log_config.max_files,
+    );
+
+    let recorder = Recorder::new(flows.clone())
+        .with_capture_limits(capture_limits)
+        .with_descriptors(descriptors);
+
+    let context = Context {
+        tls,
+        filter,
+        rules: rules.clone(),
+        recorder,
+        capture_limits,
+        interrupts: interrupts.clone(),
+        file_log,
+    };
+
+    // shutdown token
+    let shutdown = if args.no_graceful_shutdown {
+        CancellationToken::default()
+    }
+    else {
+        cancel_on_ctrlc_or_sigterm()
+    };
+
+    let mut join_set = JoinSet::new();
+
+    if args.socks.enabled {
+        let shutdown = shutdown.clone();
+        let context = context.clone();
+
+        join_set.spawn(async move {
+            // run the SOCKS server. `proxy` will handle connections. The default
+            // [`Connect`][skunk::connect::Connect] (i.e.
+            // [`ConnectTcp`][skunk::connect::ConnectTcp]) is used.
+            let mut listener = args.socks.builder()?.listen().await?;
+            tracing::info!("SOCKS server listening on: {}", args.socks.bind_address);
+
+            let mut join_set = JoinSet::default();
+
+            loop {
+                let request = tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    request_res = listener.next() => request_res?,
+                };
+
+                match ConnectTcp.connect(request.destination_address()).await {
+                    Ok(outgoing) => {
+                        let bind_address = outgoing.local_addr().unwrap().into();
+                        let incoming = request.accept(bind_address).await?;
+                        let context = context.clone();
+                        let shutdown = shutdown.clone();
+
+                        join_set.spawn(async move {
+                            tokio::select! {
+                                _ = shutdown.cancelled() => {},
+                                result = proxy(context, incoming, outgoing) => {
+                                    let _ = result.log_error();
+                                }
+                            }
+                        });
+                    }
+                    Err(_) => {
+                        request.reject(None);
+                    }
+                }
+            }
+
+            while join_set.join_next().await.is_some() {}
+
+            Ok::<(), Error>(())
+        });
+    }
+
+    if let Some(interface) = pcap_interface {
+        join_set.spawn({
+            let shutdown = shutdown.clone();
+            let interface = interface.clone();
+            async move {
+                let _hostapd = if args.pcap.ap {
+                    let country_code = std::env::var("HOSTAPD_CC")
+                    .expect("Environment variable `HOSTAPD_CC` not set. You need to set this variable to your country code.");
+
+                    tracing::info!("Starting hostapd");
+                    let mut hostapd = pcap::ap::Builder::new(&interface, &country_code)
+                            .with_channel(11)
+                            .start()?;
+
+                    tracing::info!("Waiting for hostapd to configure the interface...");
+                    hostapd.ready().await?;
+                    tracing::info!("hostapd ready");
+
+                    Some(hostapd)
+                }
+                else {
+                    None
+                };
+
+                let _network = VirtualNetwork::new(&interface)?;
+                shutdown.cancelled().await;
+                Ok::<(), Error>(())
+            }
+        });
+    }
+
+    if args.api.enabled {
+        let shutdown = shutdown.clone();
+        let mut api_builder = super::api::builder(environment.clone())
+            .with_flows(flows.clone())
+            .with_interrupts(interrupts.clone())
+            .with_rules(rules);
+        let serve_ui = ServeUi::from_environment(&environment, &mut api_builder).await?;
+
+        join_set.spawn(async move {
+            tracing::info!(bind_address = ?args.api.bind_address, "Starting API");
+
+            let router = Router::new()
+                .nest("/api", api_builder.finish())
+                .fallback_service(serve_ui);
+
+            let listener = tokio::net::TcpListener::bind(args.api.bind_address).await?;
+            tracing::info!(bind_address = ?args.api.bind_address, "UI and API being served at: http://{}", args.api.bind_address);
+
+            axum::serve(listener, router)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await?;
+
+            Ok::<(), Error>(())
+        });
+    }
+
+    // join all tasks
+    while let Some(result) = join_set.join_next().await {
+        let _ = result.log_error();
+    }
+
+    Ok(())
+}
+
+/// State shared by all proxied connections.
+#[derive(Clone)]
+struct Context {
+    tls: tls::Context,
+    filter: Arc<Filter>,
+    rules: Rules,
+    recorder: Recorder,
+    capture_limits: CaptureLimits,
+    interrupts: Interrupts,
+    file_log: FileLog,
+}
+
+/// Proxy connections.
+///
+/// This will first check if the connection matches any filters. Then it will
+/// decide using the port whether to decrypt TLS for that connection. Finally it
+/// will run a HTTP server and client to proxy HTTP requests. Requests and
+/// responses are recorded with the `recorder`. Interrupted requests and
+/// responses wait for a decision from an API client via `interrupts`.
+///
+/// The `rules` are evaluated as we learn more about the connection: First the
+/// destination address, then the TLS server name and certificate, and finally
+/// each request and response. Effects are applied as soon as they're known to
+/// fire. Stateful filters (e.g. `nth`) are evaluated for each request and
+/// response, or for the connection if it's not intercepted.
+async fn proxy(
+    context: Context,
+    incoming: socks::Incoming,
+    outgoing: TcpStream,
+) -> Result<(), skunk::Error> {
+    let Context {
+        tls,
+        filter,
+        rules,
+        recorder,
+        capture_limits,
+        interrupts,
+        file_log,
+    } = context;
+    let destination_address = incoming.destination_address().clone();
+    let client = incoming.client_address().ip();
+
+    // effects on messages that fired for the connection apply to all its
+    // messages.
+    let mut message_effects = MessageEffects::default();
+
+    let mut scope = Scope {
+        file_log: &file_log,
+        destination: &destination_address,
+        client: Some(client),
+        flow_id: None,
+        message: None,
+    };
+
+    let intercept = filter.matches(&destination_address);
+
+    let mut eval = rules.evaluator();
+    eval.set_tcp(&destination_address);
+    if !intercept {
+        // we won't see any messages of this connection, so stateful filters
+        // are evaluated for the connection.
+        eval.set_state(&scope.state());
+    }
+    if rules::apply(eval.take_effects(), &scope, &mut message_effects) == Action::Drop {
+        tracing::info!(destination = %destination_address, "Dropping connection");
+        return Ok(());
+    }
+
+    // network conditions can only be applied to the whole connection, so only
+    // effects that fired for the destination address are used.
+    let network = message_effects.network.take().unwrap_or_default();
+    let is_tls = destination_address.port == 443;
+    if network.fail_tls && is_tls {
+        tracing::info!(destination = %destination_address, "Failing TLS handshake");
+        let _ = tls::fail_handshake(incoming)
+            .await
+            .log_error_with_message("Could not fail TLS handshake");
+        return Ok(());
+    }
+    let conditions = Conditions::from(&network);
+    let incoming = Conditioned::new(
+        incoming,
+        Conditions {
+            // truncating only applies to responses.
+            truncate: None,
+            ..conditions.clone()
+        },
+    );
+    let outgoing = Conditioned::new(outgoing, conditions);
+
+    if intercept {
+        let span = tracing::info_span!("connection", destination = %destination_address);
+
+        let flow = recorder
+            .begin_http(&destination_address, if is_tls { "https" } else { "http" })
+            .await;
+        scope.flow_id = Some(flow.flow_id());
+
+        let (incoming, outgoing) = tls.maybe_decrypt(incoming, outgoing, is_tls).await?;
+
+        let action = if is_tls {
+            let subject = outgoing.server_certificate_subject();
+            eval.set_tls(&Tls {
+                server_name: incoming
+                    .get_tls_connection()
+                    .and_then(|connection| connection.server_name())
+                    .map(ToOwned::to_owned),
+                common_name: subject
+                    .as_ref()
+                    .and_then(|subject| subject.common_name.clone()),
+                distinguished_name: subject.map(|subject| subject.distinguished_name),
+            });
+            rules::apply(eval.take_effects(), &scope, &mut message_effects)
+        }
+        else {
+            Action::Continue
+        };
+
+        // annotations of the connection are only added once, and not for every message.
+        flow.annotate(std::mem::take(&mut message_effects.annotations))
+            .await;
+        if action == Action::Drop {
+            tracing::info!(parent: &span, "Dropping connection");
+            flow.end().await;
+            return Ok(());
+        }
+
+        // if nothing is pending anymore, we don't need to evaluate requests and
+        // responses.
+        let connection = Intercepted {
+            flow: flow.clone(),
+            filter,
+            eval: eval.is_pending().then_some(eval),
+            message_effects: Arc::new(message_effects),
+            tls,
+            interrupts,
+            file_log: file_log.clone(),
+            destination: destination_address.clone(),
+            client,
+            capture_limits,
+        };
+        let drop_connection = CancellationToken::new();
+
+        let result = tokio::select! {
+            _ = drop_connection.cancelled() => {
+                tracing::info!(parent: &span, "Dropping connection");
+                Ok(())
+            }
+            result = http::proxy(incoming, outgoing, |request, send_request| {
+                let span = tracing::info_span!(
+                    parent: &span,
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri()
+                );
+                let connection = connection.clone();
+                let drop_connection = drop_connection.clone();
+
+                async move {
+                    match connection.handle_request(request, send_request).await {
+                        Ok(response) => Ok(response),
+                        Err(Abort::DropConnection) => {
+                            // the connection is dropped by the select, so this
+                            // request never gets a response.
+                            drop_connection.cancel();
+                            std::future::pending().await
+                        }
+                        Err(Abort::Error(error)) => Err(error),
+                    }
+                }
+                .instrument(span)
+            }) => result,
+        };
+
+        flow.end().await;
+        result?;
+    }
+    else {
+        Passthrough.proxy(incoming, outgoing).await?;
+    };
+
+    Ok::<_, skunk::Error>(())
+}
+
+/// The response type of intercepted connections.
+type ProxiedResponse = Response<RecordedResponseBody<Replace<Incoming>>>;
+
+/// Why handling a request stopped without a response.
+enum Abort {
+    /// An effect dropped the connection.
+    DropConnection,
+    Error(skunk::Error),
+}
+
+impl From<skunk::Error> for Abort {
+    fn from(error: skunk::Error) -> Self {
+        Self::Error(error)
+    }
+}
+
+impl From<http::Error> for Abort {
+    fn from(error: http::Error) -> Self {
+        Self::Error(error.into())
+    }
+}
+
+/// An intercepted connection, whose requests are recorded and have rules
+/// applied to them.
+#[derive(Clone)]
+struct Intercepted {
+    flow: HttpFlow,
+    filter: Arc<Filter>,
+
+    /// The evaluator of the connection, or `None` if no rules depend on the
+    /// messages.
+    eval: Option<Evaluator>,
+
+    /// Effects that fired for the connection, and apply to all its messages.
+    message_effects: Arc<MessageEffects>,
+
+    tls: tls::Context,
+    interrupts: Interrupts,
+    file_log: FileLog,
+    destination: TcpAddress,
+    client: IpAddr,
+    capture_limits: CaptureLimits,
+}
+
+impl Intercepted {
+    fn scope(&self) -> Scope<'_> {
+        Scope {
+            file_log: &self.file_log,
+            destination: &self.destination,
+            client: Some(self.client),
+            flow_id: Some(self.flow.flow_id()),
+            message: None,
+        }
+    }
+
+    /// Applies the rules to a request, and forwards it or answers it.
+    async fn handle_request(
+        &self,
+        request: Request<Incoming>,
+        send_request: SendRequest<RecordedRequestBody<Replace<Incoming>>>,
+    ) -> Result<ProxiedResponse, Abort> {
+        // log request
+        tracing::info!("Request");
+
+        let flow = &self.flow;
+        let scope = self.scope();
+        let mut request_effects = MessageEffects::clone(&self.message_effects);
+
+        let request = request.map(Replace::original);
+
+        if !self.filter.matches_request(&self.destination, &request) {
+            tracing::debug!("Request doesn't match the filter");
+            let response = send_request
+                .send(record::unrecorded_request(request))
+                .await?;
+            return Ok(record::unrecorded_response(response.map(Replace::original)));
+        }
+
+        // the request head is needed again to evaluate the response
+        let (request, request_head) = if let Some(eval) = &self.eval {
+            let head = RequestHead {
+                method: request.method().clone(),
+                uri: request.uri().clone(),
+                headers: request.headers().clone(),
+            };
+            let message = head.message(&head.headers, None);
+            let message_eval = rules::evaluate_http(eval, Direction::Request, &message);
+            let (request, body) = if message_eval.needs_body() {
+                let (parts, body) = request.into_parts();
+                let (body, data) = collect_body(body, self.capture_limits).await?;
+                (Request::from_parts(parts, body), data)
+            }
+            else {
+                (request, None)
+            };
+            let body = body.as_deref().map(HttpBody::new);
+            let action = rules::apply_http(
+                message_eval,
+                &message,
+                body.as_ref(),
+                &scope,
+                &mut request_effects,
+            );
+            flow.annotate(std::mem::take(&mut request_effects.annotations))
+                .await;
+            if action == Action::Drop {
+                return Err(Abort::DropConnection);
+            }
+            (request, Some(head))
+        }
+        else {
+            (request, None)
+        };
+
+        // if an effect fails, only this request is answered with an error.
+        let Ok(request) = modify_request(request, &request_effects.modify)
+            .await
+            .log_error_with_message("Could not modify request")
+        else {
+            return Ok(flow.response_without_request(internal_server_error()));
+        };
+
+        let script_flow = rules::script_flow(&scope);
+        let Ok((request, outcome)) =
+            script_request(request, &request_effects.scripts, &script_flow)
+                .await
+                .log_error_with_message("Could not run scripts for request")
+        else {
+            return Ok(flow.response_without_request(internal_server_error()));
+        };
+        let (annotations, action) = rules::script_outcome(outcome);
+        flow.annotate(annotations).await;
+        if action == Action::Drop {
+            return Err(Abort::DropConnection);
+        }
+
+        // the request line is needed again to run scripts for the response.
+        let request_line = (request.method().clone(), request.uri().clone());
+
+        let (request, decision) = match &request_effects.interrupt {
+            Some(effect) if !matches!(effect.direction, Direction::Response) => {
+                interrupt_request(&self.interrupts, flow.flow_id(), effect, request).await?
+            }
+            _ => (request, Decision::Forward),
+        };
+        if decision == Decision::Kill {
+            return Err(Abort::DropConnection);
+        }
+
+        // the mock response is rendered before the request is recorded, since
+        // rendering reads the request body.
+        let (request, mocked) = match &request_effects.map {
+            _ if decision == Decision::Drop => (request, Some((bad_gateway(), false))),
+            Some(MapEffect::Mock(effect)) => {
+                let Ok((request, response)) = mock(request, effect)
+                    .await
+                    .log_error_with_message("Could not mock response")
+                else {
+                    return Ok(flow.response_without_request(internal_server_error()));
+                };
+                (request, Some((response, effect.forward)))
+            }
+            _ => (request, None),
+        };
+
+        let (request, exchange) = flow.request(request);
+        let response = if let Some((response, forward)) = mocked {
+            if forward {
+                // the server still gets the request, but its response is discarded.
+                let response = send_request.send(request).await?;
+                let _ = response.into_body().collect().await;
+            }
+            else {
+                // read the request body, so that it's recorded.
+                let _ = request.into_body().collect().await;
+            }
+            response
+        }
+        else {
+            match &request_effects.map {
+                Some(MapEffect::Local(effect)) => {
+                    let Ok(response) = map_local(request, effect)
+                        .await
+                        .log_error_with_message("Could not map request to local file")
+                    else {
+                        return Ok(exchange.response(bad_gateway()));
+                    };
+                    response
+                }
+                Some(MapEffect::Remote(effect)) => {
+                    let Ok(response) = map_remote(&ConnectTcp, &self.tls, effect, request)
+                        .await
+                        .log_error_with_message("Could not map request to remote")
+                    else {
+                        return Ok(exchange.response(bad_gateway()));
+                    };
+                    response.map(Replace::original)
+                }
+                _ => send_request.send(request).await?.map(Replace::original),
+            }
+        };
+
+        self.handle_response(response, exchange, request_head, &request_line)
+            .await
+    }
+
+    /// Applies the rules to the response to a request.
+    ///
+    /// `request_head` is the head of the request as it was received, and is
+    /// only set if rules are evaluated. `request_line` is the method and URL
+    /// of the request as it was sent.
+    async fn handle_response(
+        &self,
+        response: Response<Replace<Incoming>>,
+        exchange: Exchange,
+        request_head: Option<RequestHead>,
+        request_line: &(Method, Uri),
+    ) -> Result<ProxiedResponse, Abort> {
+        // log response
+        tracing::info!(
+            status = %response.status(),
+            "Response"
+        );
+
+        let flow = &self.flow;
+        let scope = self.scope();
+        let mut response_effects = MessageEffects::clone(&self.message_effects);
+
+        let response = match (&self.eval, request_head) {
+            (Some(eval), Some(head)) => {
+                let (parts, body) = response.into_parts();
+                let message = head.message(&parts.headers, Some(parts.status));
+                let message_eval = rules::evaluate_http(eval, Direction::Response, &message);
+                let (body, data) = if message_eval.needs_body() {
+                    collect_body(body, self.capture_limits).await?
+                }
+                else {
+                    (body, None)
+                };
+                let data = data.as_deref().map(HttpBody::new);
+                let action = rules::apply_http(
+                    message_eval,
+                    &message,
+                    data.as_ref(),
+                    &scope,
+                    &mut response_effects,
+                );
+                flow.annotate(std::mem::take(&mut response_effects.annotations))
+                    .await;
+                if action == Action::Drop {
+                    return Err(Abort::DropConnection);
+                }
+                Response::from_parts(parts, body)
+            }
+            _ => response,
+        };
+
+        let Ok(response) = modify_response(response, &response_effects.modify)
+            .await
+            .log_error_with_message("Could not modify response")
+        else {
+            return Ok(exchange.response(internal_server_error()));
+        };
+
+        let (method, uri) = request_line;
+        let script_flow = rules::script_flow(&scope);
+        let Ok((response, outcome)) = script_response(
+            response,
+            method,
+            uri,
+            &response_effects.scripts,
+            &script_flow,
+        )
+        .await
+        .log_error_with_message("Could not run scripts for response")
+        else {
+            return Ok(exchange.response(internal_server_error()));
+        };
+        let (annotations, action) = rules::script_outcome(outcome);
+        flow.annotate(annotations).await;
+        if action == Action::Drop {
+            return Err(Abort::DropConnection);
+        }
+
+        let response = match &response_effects.interrupt {
+            Some(effect) if !matches!(effect.direction, Direction::Request) => {
+                let (response, decision) =
+                    interrupt_response(&self.interrupts, flow.flow_id(), effect, response).await?;
+                match decision {
+                    Decision::Forward => response,
+                    Decision::Drop => bad_gateway(),
+                    Decision::Kill => return Err(Abort::DropConnection),
+                }
+            }
+            _ => response,
+        };
+        Ok(exchange.response(response))
+    }
+}
+
+/// The head of a request as it was received, which is needed again to evaluate
+/// the rules for its response.
+struct RequestHead {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+}
+
+impl RequestHead {
+    /// Returns the message for evaluating rules, with the `headers` and
+    /// `status` of the request or response.
+    fn message<'a>(
+        &'a self,
+        headers: &'a HeaderMap,
+        status: Option<StatusCode>,
+    ) -> HttpMessage<'a> {
+        HttpMessage {
+            method: &self.method,
+            uri: &self.uri,
+            request_headers: &self.headers,
+            headers,
+            status,
+        }
+    }
+}
+
+/// Reads a body to evaluate body filters.
+///
+/// Bodies are only read up to the memory limit for captures. If a body is
+/// larger, no data is returned, so that body filters stay undecided and effects
+/// depending on them don't fire. The returned body then forwards what was read,
+/// followed by the rest of the body.
+async fn collect_body(
+    body: Replace<Incoming>,
+    limits: CaptureLimits,
+) -> Result<(Replace<Incoming>, Option<Bytes>), http::Error> {
+    match collect_limited(body, limits.memory_limit).await? {
+        Collected::Complete(data) => Ok((Replace::replaced(data.clone()), Some(data))),
+        Collected::Exceeded(body) => {
+            tracing::warn!(
+                limit = limits.memory_limit,
+                "Not evaluating body filters, because the body is too large"
+            );
+            Ok((body, None))
+        }
+    }
+}
+
+/// A simple filter to decide which target addresses should be intercepted.
+///
+/// If no addresses or expressions are given, all addresses match.
+#[derive(Clone, Debug, Default)]
+struct Filter {
+    addresses: HashSet<TcpAddress>,
+    expressions: Vec<CompiledFilter>,
+}
+
+impl Filter {
+    pub fn new(targets: Vec<TargetFilter>) -> Result<Self, Error> {
+        let mut filter = Self::default();
+        for target in targets {
+            match target {
+                TargetFilter::Address(address) => {
+                    filter.addresses.insert(address);
+                }
+                TargetFilter::Expression(expression) => {
+                    filter
+                        .expressions
+                        .push(CompiledFilter::compile(&expression)?);
+                }
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, address: &TcpAddress) -> bool {
+        if address.port != 80 && address.port != 443 {
+            return false;
+        }
+
+        if self.addresses.is_empty() && self.expressions.is_empty() {
+            return true;
+        }
+
+        // an expression matches if it can't be decided with the address alone.
+        // requests are checked again with `matches_request`.
+        self.addresses.contains(address)
+            || self.expressions.iter().any(|expression| {
+                let mut eval = expression.evaluator();
+                eval.set_tcp(address);
+                eval.matches() != Some(false)
+            })
+    }
+
+    /// Checks a request of a connection that [matched](Self::matches).
+    ///
+    /// An expression matches if it can't be decided without the body or the
+    /// response, e.g. `~c 404`.
+    pub fn matches_request<B>(&self, address: &TcpAddress, request: &Request<B>) -> bool {
+        if self.expressions.is_empty() || self.addresses.contains(address) {
+            return true;
+        }
+
+        let message = HttpMessage {
+            method: request.method(),
+            uri: request.uri(),
+            request_headers: request.headers(),
+            headers: request.headers(),
+            status: None,
+        };
+        self.expressions.iter().any(|expression| {
+            let mut eval = expression.evaluator();
+            eval.set_tcp(address);
+            eval.set_direction(Direction::Request);
+            eval.set_http(&message);
+            eval.matches() != Some(false)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use skunk::{
+        address::TcpAddress,
+        protocol::http::{
+            Method,
+            Request,
+        },
+    };
+
+    use super::Filter;
+
+    #[test]
+    fn it_only_intercepts_requests_that_match() {
+        let filter = Filter::new(vec!["~m POST".parse().unwrap()]).unwrap();
+        let address = "example.com:443".parse::<TcpAddress>().unwrap();
+
+        // the method is only known once a request is sent.
+        assert!(filter.matches(&address));
+
+        let request = |method| Request::builder().method(method).uri("/").body(()).unwrap();
+        assert!(filter.matches_request(&address, &request(Method::POST)));
+        assert!(!filter.matches_request(&address, &request(Method::GET)));
+    }
+}
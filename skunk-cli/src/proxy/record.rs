@@ -0,0 +1,770 @@
+//! Recording of proxied traffic as flows.
+
+use std::{
+    sync::Arc,
+    time::SystemTime,
+};
+
+use chrono::{
+    DateTime,
+    FixedOffset,
+    Utc,
+};
+use skunk::{
+    address::TcpAddress,
+    protocol::{
+        http::{
+            body::{
+                CaptureLimits,
+                CaptureReceiver,
+                Captured,
+                Tee,
+            },
+            decode::Registry,
+            grpc::{
+                self as grpc_framing,
+                Encoding,
+                Messages,
+            },
+            header,
+            raw::RawHeaders,
+            sse::{
+                self,
+                EventReceiver,
+                Events,
+            },
+            Extensions,
+            HeaderMap,
+            Request,
+            Response,
+        },
+        protobuf::{
+            self,
+            Descriptors,
+        },
+    },
+};
+use skunk_api_protocol::{
+    flow::{
+        Annotations,
+        Artifact,
+        ArtifactId,
+        Flow,
+        FlowId,
+        Message,
+        MessageData,
+        MessageId,
+        MessageKind,
+        Metadata,
+    },
+    grpc,
+    http,
+};
+use skunk_util::{
+    error::ResultExt,
+    ordered_multimap::OrderedMultiMap,
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::api::Flows;
+
+/// Records proxied connections into [`Flows`].
+///
+/// This holds the settings for recording that are shared by all connections.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    flows: Flows,
+    limits: CaptureLimits,
+    descriptors: Descriptors,
+    decoders: Arc<Registry>,
+}
+
+impl Recorder {
+    pub fn new(flows: Flows) -> Self {
+        Self {
+            flows,
+            limits: CaptureLimits::default(),
+            descriptors: Descriptors::default(),
+            decoders: Arc::new(Registry::default()),
+        }
+    }
+
+    /// Sets the limits for capturing bodies.
+    pub fn with_capture_limits(mut self, limits: CaptureLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the protobuf descriptors used to decode gRPC messages.
+    pub fn with_descriptors(mut self, descriptors: Descriptors) -> Self {
+        self.descriptors = descriptors;
+        self
+    }
+
+    /// Sets the decoders used to decode bodies into structured values.
+    pub fn with_decoders(mut self, decoders: Registry) -> Self {
+        self.decoders = Arc::new(decoders);
+        self
+    }
+
+    /// Begins recording a HTTP connection to `destination`.
+    pub async fn begin_http(&self, destination: &TcpAddress, protocol: &str) -> HttpFlow {
+        HttpFlow::begin(self.clone(), destination, protocol).await
+    }
+}
+
+/// Records the HTTP requests and responses of a connection as messages of a
+/// flow.
+///
+/// Bodies are captured while they're streamed, and the message is emitted once
+/// its body ended.
+#[derive(Clone, Debug)]
+pub struct HttpFlow {
+    recorder: Recorder,
+    flow_id: FlowId,
+}
+
+impl HttpFlow {
+    async fn begin(recorder: Recorder, destination: &TcpAddress, protocol: &str) -> Self {
+        let flow_id = FlowId(Uuid::new_v4());
+
+        let mut metadata = Metadata::default();
+        metadata
+            .insert("destination".to_owned(), &destination.to_string())
+            .expect("failed to serialize flow metadata");
+
+        let _ = recorder
+            .flows
+            .begin_flow(&Flow {
+                flow_id,
+                parent: None,
+                protocol: Some(protocol.to_owned()),
+                timestamp: now(),
+                metadata,
+            })
+            .await
+            .log_error_with_message("Could not record flow");
+
+        Self { recorder, flow_id }
+    }
+
+    pub async fn end(self) {
+        let _ = self
+            .recorder
+            .flows
+            .end_flow(self.flow_id)
+            .await
+            .log_error_with_message("Could not record end of flow");
+    }
+
+    pub fn flow_id(&self) -> FlowId {
+        self.flow_id
+    }
+
+    /// Adds annotations to the flow. Nothing is recorded if `annotations` is
+    /// empty.
+    pub async fn annotate(&self, annotations: Annotations) {
+        if annotations.is_empty() {
+            return;
+        }
+        let _ = self
+            .recorder
+            .flows
+            .annotate(self.flow_id, annotations)
+            .await
+            .log_error_with_message("Could not annotate flow");
+    }
+
+    /// Records a response for which no request was recorded, e.g. because the
+    /// request could not be processed.
+    pub fn response_without_request<B>(
+        &self,
+        response: Response<B>,
+    ) -> Response<Tee<Events<Messages<B>>>> {
+        self.response(response, None)
+    }
+
+    /// Starts capturing the request body. The request message is emitted when
+    /// the body ended.
+    ///
+    /// The returned [`Exchange`] is used to record the response to this
+    /// request. If the request is a gRPC call, its messages are recorded as
+    /// messages of a child flow as they arrive.
+    pub fn request<B>(&self, request: Request<B>) -> (Request<Tee<Messages<B>>>, Exchange) {
+        let timestamp = now();
+        let (parts, body) = request.into_parts();
+        let (body, grpc) = if grpc_framing::is_grpc(&parts.headers) {
+            let (body, events) = Messages::new(body, self.recorder.limits.memory_limit);
+            let grpc = self.record_grpc_call(parts.uri.path());
+            grpc.forward(MessageKind::Request, &parts.headers, events);
+            (body, Some(grpc))
+        }
+        else {
+            (Messages::passthrough(body), None)
+        };
+        let (body, captured) = Tee::new(body, self.recorder.limits);
+
+        let mut data = http::Request {
+            method: parts.method.to_string(),
+            uri: parts.uri.to_string(),
+            version: format!("{:?}", parts.version),
+            headers: headers(&parts.headers),
+            body: None,
+        };
+
+        self.emit(
+            MessageKind::Request,
+            timestamp,
+            metadata(&parts.extensions),
+            content_type(&parts.headers),
+            captured,
+            move |body| {
+                data.body = body;
+                MessageData::from_value(&data)
+            },
+        );
+
+        let exchange = Exchange {
+            flow: self.clone(),
+            grpc,
+        };
+
+        (Request::from_parts(parts, body), exchange)
+    }
+
+    fn response<B>(
+        &self,
+        response: Response<B>,
+        grpc: Option<GrpcCall>,
+    ) -> Response<Tee<Events<Messages<B>>>> {
+        let timestamp = now();
+        let (parts, body) = response.into_parts();
+        let body = match grpc {
+            Some(grpc) if grpc_framing::is_grpc(&parts.headers) => {
+                let (body, events) = Messages::new(body, self.recorder.limits.memory_limit);
+                grpc.forward(MessageKind::Response, &parts.headers, events);
+                body
+            }
+            _ => Messages::passthrough(body),
+        };
+        let body = if sse::is_event_stream(&parts.headers) {
+            let (body, events) = Events::new(body, self.recorder.limits.memory_limit);
+            self.record_events(events);
+            body
+        }
+        else {
+            Events::passthrough(body)
+        };
+        let (body, captured) = Tee::new(body, self.recorder.limits);
+
+        let mut data = http::Response {
+            status: parts.status.as_u16(),
+            version: format!("{:?}", parts.version),
+            headers: headers(&parts.headers),
+            body: None,
+        };
+
+        self.emit(
+            MessageKind::Response,
+            timestamp,
+            metadata(&parts.extensions),
+            content_type(&parts.headers),
+            captured,
+            move |body| {
+                data.body = body;
+                MessageData::from_value(&data)
+            },
+        );
+
+        Response::from_parts(parts, body)
+    }
+
+    fn record_events(&self, mut events: EventReceiver) {
+        let flows = self.recorder.flows.clone();
+        let parent = self.flow_id;
+
+        tokio::spawn(async move {
+            let flow_id = FlowId(Uuid::new_v4());
+
+            let _ = flows
+                .begin_flow(&Flow {
+                    flow_id,
+                    parent: Some(parent),
+                    protocol: Some("sse".to_owned()),
+                    timestamp: now(),
+                    metadata: Default::default(),
+                })
+                .await
+                .log_error_with_message("Could not record flow");
+
+            while let Some((received_at, event)) = events.next().await {
+                let data = http::ServerSentEvent {
+                    event: event.event,
+                    id: event.id,
+                    data: event.data,
+                    retry: event.retry,
+                };
+
+                let _ = flows
+                    .emit_message(Message {
+                        message_id: MessageId(Uuid::new_v4()),
+                        flow_id,
+                        kind: MessageKind::Other,
+                        timestamp: DateTime::<Utc>::from(received_at).fixed_offset(),
+                        data: MessageData::from_value(&data)
+                            .expect("failed to serialize message data"),
+                        metadata: Default::default(),
+                    })
+                    .await
+                    .log_error_with_message("Could not record message");
+            }
+
+            let _ = flows
+                .end_flow(flow_id)
+                .await
+                .log_error_with_message("Could not record end of flow");
+        });
+    }
+
+    /// Begins a child flow for a gRPC call to `path`.
+    ///
+    /// The messages of both directions are forwarded to a single task, which
+    /// ends the flow once both bodies ended.
+    ///
+    /// Messages are captured with the same [`CaptureLimits`] as bodies: Each
+    /// message is truncated to the memory limit, and once the messages of a
+    /// direction exceed the total limit, they're recorded without their data.
+    fn record_grpc_call(&self, path: &str) -> GrpcCall {
+        let flows = self.recorder.flows.clone();
+        let limits = self.recorder.limits;
+        let parent = self.flow_id;
+        let descriptors = self.recorder.descriptors.clone();
+        let method = grpc_framing::method(&descriptors, path);
+        let path = path.to_owned();
+        let (tx, mut rx) = mpsc::unbounded_channel::<GrpcEvent>();
+
+        tokio::spawn(async move {
+            let flow_id = FlowId(Uuid::new_v4());
+
+            let mut metadata = Metadata::default();
+            metadata
+                .insert("path".to_owned(), &path)
+                .expect("failed to serialize flow metadata");
+
+            let _ = flows
+                .begin_flow(&Flow {
+                    flow_id,
+                    parent: Some(parent),
+                    protocol: Some("grpc".to_owned()),
+                    timestamp: now(),
+                    metadata,
+                })
+                .await
+                .log_error_with_message("Could not record flow");
+
+            let mut request_captured = 0;
+            let mut response_captured = 0;
+
+            while let Some(event) = rx.recv().await {
+                let message_id = MessageId(Uuid::new_v4());
+                let timestamp = DateTime::<Utc>::from(event.received_at).fixed_offset();
+                let mut artifacts = vec![];
+
+                let data = match event.event {
+                    grpc_framing::Event::Message(message) => {
+                        let (data, truncated) =
+                            match message.decompress(&event.encoding, limits.memory_limit) {
+                                Ok(decompressed) => decompressed,
+                                Err(error) => {
+                                    tracing::warn!(?error, "Could not decompress gRPC message");
+                                    (message.data.clone(), message.truncated)
+                                }
+                            };
+                        let size = if message.compressed {
+                            data.len()
+                        }
+                        else {
+                            message.size
+                        };
+
+                        // truncated messages can't be decoded.
+                        let schema = method.as_ref().filter(|_| !truncated).map(|method| {
+                            match event.kind {
+                                MessageKind::Request => method.input(),
+                                _ => method.output(),
+                            }
+                        });
+                        let decoded = schema.and_then(|schema| {
+                            descriptors
+                                .decode(schema, &data)
+                                .log_error_with_message("Could not decode gRPC message")
+                                .ok()
+                                .and_then(|message| serde_json::to_value(&message).ok())
+                        });
+                        let fields = if decoded.is_none() && !truncated {
+                            protobuf::decode_schemaless(&data).ok().map(convert_fields)
+                        }
+                        else {
+                            None
+                        };
+
+                        let captured = match event.kind {
+                            MessageKind::Request => &mut request_captured,
+                            _ => &mut response_captured,
+                        };
+                        let within_limits = limits
+                            .max_size()
+                            .map_or(true, |max_size| *captured + data.len() as u64 <= max_size);
+                        let artifact_id = within_limits.then(|| {
+                            *captured += data.len() as u64;
+                            let artifact_id = ArtifactId(Uuid::new_v4());
+                            artifacts.push((
+                                Artifact {
+                                    artifact_id,
+                                    message_id: Some(message_id),
+                                    mime_type: Some("application/protobuf".to_owned()),
+                                    file_name: None,
+                                    timestamp,
+                                },
+                                data.clone(),
+                            ));
+                            artifact_id
+                        });
+
+                        MessageData::from_value(&grpc::Message {
+                            artifact_id,
+                            compressed: message.compressed,
+                            size: size as u64,
+                            truncated: truncated || !within_limits,
+                            message: decoded,
+                            fields,
+                        })
+                    }
+                    grpc_framing::Event::Status(status) => {
+                        MessageData::from_value(&grpc::Status {
+                            code: status.code,
+                            message: status.message,
+                        })
+                    }
+                };
+
+                let message = Message {
+                    message_id,
+                    flow_id,
+                    kind: event.kind,
+                    timestamp,
+                    data: data.expect("failed to serialize message data"),
+                    metadata: Default::default(),
+                };
+
+                let _ = flows
+                    .emit_message_with_artifacts(message, &artifacts)
+                    .await
+                    .log_error_with_message("Could not record message");
+            }
+
+            let _ = flows
+                .end_flow(flow_id)
+                .await
+                .log_error_with_message("Could not record end of flow");
+        });
+
+        GrpcCall { tx }
+    }
+
+    fn emit<F>(
+        &self,
+        kind: MessageKind,
+        timestamp: DateTime<FixedOffset>,
+        metadata: Metadata,
+        mime_type: Option<String>,
+        captured: CaptureReceiver,
+        data: F,
+    ) where
+        F: FnOnce(Option<http::Body>) -> Result<MessageData, serde_json::Error> + Send + 'static,
+    {
+        let flows = self.recorder.flows.clone();
+        let decoders = self.recorder.decoders.clone();
+        let memory_limit = self.recorder.limits.memory_limit;
+        let flow_id = self.flow_id;
+
+        tokio::spawn(async move {
+            let message_id = MessageId(Uuid::new_v4());
+            let mut artifacts = vec![];
+            let mut decoded = None;
+            let mut parts = vec![];
+
+            let body = if let Some(captured) = captured.await {
+                let Captured {
+                    data: captured_data,
+                    size,
+                    mut truncated,
+                    complete,
+                    trailers,
+                } = captured;
+
+                let artifact_id = if captured_data.is_empty() {
+                    None
+                }
+                else {
+                    // the store needs the whole artifact in memory, so a spilled body is only
+                    // stored up to the memory limit.
+                    let captured_truncated = truncated;
+                    let content_type = mime_type.clone();
+                    // reading a spilled body uses blocking IO, and decoding might take a while
+                    let result = tokio::task::spawn_blocking(move || {
+                        let (bytes, read_truncated) = captured_data.read(memory_limit)?;
+                        let decoded = content_type
+                            .filter(|_| complete && !captured_truncated && !read_truncated)
+                            .and_then(|content_type| decoders.decode(&content_type, &bytes))
+                            .and_then(|result| {
+                                result
+                                    .map_err(|error| {
+                                        tracing::debug!(?error, "Could not decode body");
+                                    })
+                                    .ok()
+                            });
+                        Ok::<_, std::io::Error>((bytes, read_truncated, decoded))
+                    })
+                    .await;
+                    match result {
+                        Ok(Ok((bytes, read_truncated, decoded_body))) => {
+                            truncated |= read_truncated;
+                            let artifact_id = ArtifactId(Uuid::new_v4());
+                            artifacts.push((
+                                Artifact {
+                                    artifact_id,
+                                    message_id: Some(message_id),
+                                    mime_type,
+                                    file_name: None,
+                                    timestamp,
+                                },
+                                bytes,
+                            ));
+
+                            if let Some(decoded_body) = decoded_body {
+                                for part in decoded_body.parts {
+                                    let artifact_id = ArtifactId(Uuid::new_v4());
+                                    parts.push(http::Part {
+                                        artifact_id,
+                                        name: part.name,
+                                        file_name: part.file_name.clone(),
+                                        mime_type: part.content_type.clone(),
+                                        size: part.data.len() as u64,
+                                    });
+                                    artifacts.push((
+                                        Artifact {
+                                            artifact_id,
+                                            message_id: Some(message_id),
+                                            mime_type: part.content_type,
+                                            file_name: part.file_name,
+                                            timestamp,
+                                        },
+                                        part.data,
+                                    ));
+                                }
+                                decoded = Some(decoded_body.value);
+                            }
+
+                            Some(artifact_id)
+                        }
+                        Ok(Err(error)) => {
+                            tracing::warn!(?error, "Could not read captured body");
+                            None
+                        }
+                        Err(error) => {
+                            tracing::error!(?error, "Reading captured body failed");
+                            None
+                        }
+                    }
+                };
+
+                Some(http::Body {
+                    artifact_id,
+                    size,
+                    truncated,
+                    complete,
+                    trailers: trailers.as_ref().map(headers).unwrap_or_default(),
+                    decoded,
+                    parts,
+                })
+            }
+            else {
+                None
+            };
+
+            let message = Message {
+                message_id,
+                flow_id,
+                kind,
+                timestamp,
+                data: data(body).expect("failed to serialize message data"),
+                metadata,
+            };
+
+            let _ = flows
+                .emit_message_with_artifacts(message, &artifacts)
+                .await
+                .log_error_with_message("Could not record message");
+        });
+    }
+}
+
+/// Body of a request that is recorded with [`HttpFlow::request`].
+pub type RecordedRequestBody<B> = Tee<Messages<B>>;
+
+/// Body of a response that is recorded with [`Exchange::response`].
+pub type RecordedResponseBody<B> = Tee<Events<Messages<B>>>;
+
+/// Wraps a request that isn't recorded, so that it can be sent like recorded
+/// requests.
+pub fn unrecorded_request<B>(request: Request<B>) -> Request<RecordedRequestBody<B>> {
+    request.map(|body| Tee::passthrough(Messages::passthrough(body)))
+}
+
+/// Wraps a response that isn't recorded, so that it can be returned like
+/// recorded responses.
+pub fn unrecorded_response<B>(response: Response<B>) -> Response<RecordedResponseBody<B>> {
+    response.map(|body| Tee::passthrough(Events::passthrough(Messages::passthrough(body))))
+}
+
+/// Records the response to a request. See [`HttpFlow::request`].
+#[derive(Debug)]
+pub struct Exchange {
+    flow: HttpFlow,
+    grpc: Option<GrpcCall>,
+}
+
+impl Exchange {
+    /// Starts capturing the response body. The response message is emitted
+    /// when the body ended.
+    ///
+    /// If the response is an event stream, its events are recorded as
+    /// messages of a child flow as they arrive.
+    pub fn response<B>(self, response: Response<B>) -> Response<Tee<Events<Messages<B>>>> {
+        self.flow.response(response, self.grpc)
+    }
+}
+
+/// Sender for the messages of a gRPC call. The child flow of the call ends
+/// once all senders are dropped.
+#[derive(Debug)]
+struct GrpcCall {
+    tx: mpsc::UnboundedSender<GrpcEvent>,
+}
+
+impl GrpcCall {
+    fn forward(
+        &self,
+        kind: MessageKind,
+        headers: &HeaderMap,
+        mut events: grpc_framing::EventReceiver,
+    ) {
+        let tx = self.tx.clone();
+        let encoding = Encoding::from_headers(headers);
+
+        // a "Trailers-Only" response sends the status in its headers.
+        let mut header_status = grpc_framing::Status::from_headers(headers);
+
+        tokio::spawn(async move {
+            while let Some((received_at, event)) = events.next().await {
+                if let grpc_framing::Event::Status(_) = &event {
+                    header_status = None;
+                }
+                let event = GrpcEvent {
+                    kind,
+                    encoding: encoding.clone(),
+                    received_at,
+                    event,
+                };
+                if tx.send(event).is_err() {
+                    return;
+                }
+            }
+
+            if let Some(status) = header_status {
+                let _ = tx.send(GrpcEvent {
+                    kind,
+                    encoding,
+                    received_at: SystemTime::now(),
+                    event: grpc_framing::Event::Status(status),
+                });
+            }
+        });
+    }
+}
+
+#[derive(Debug)]
+struct GrpcEvent {
+    kind: MessageKind,
+    encoding: Encoding,
+    received_at: SystemTime,
+    event: grpc_framing::Event,
+}
+
+fn convert_fields(fields: Vec<protobuf::Field>) -> Vec<grpc::Field> {
+    fields
+        .into_iter()
+        .map(|field| {
+            let value = match field.value {
+                protobuf::Value::Varint(value) => grpc::FieldValue::Varint(value),
+                protobuf::Value::Fixed64(value) => grpc::FieldValue::Fixed64(value),
+                protobuf::Value::Fixed32(value) => grpc::FieldValue::Fixed32(value),
+                protobuf::Value::String(value) => grpc::FieldValue::String(value),
+                protobuf::Value::Bytes(value) => grpc::FieldValue::Bytes(value.to_vec()),
+                protobuf::Value::Message(fields) => {
+                    grpc::FieldValue::Message(convert_fields(fields))
+                }
+                protobuf::Value::Group(fields) => grpc::FieldValue::Group(convert_fields(fields)),
+            };
+            grpc::Field {
+                number: field.number,
+                value,
+            }
+        })
+        .collect()
+}
+
+fn now() -> DateTime<FixedOffset> {
+    Utc::now().fixed_offset()
+}
+
+fn headers(headers: &HeaderMap) -> http::Headers {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().to_owned(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Metadata for a request or response message.
+///
+/// [`http::Headers`] has the headers as hyper normalized them, so if we know
+/// them, we also record the headers in their original case and order as
+/// `raw-headers`.
+fn metadata(extensions: &Extensions) -> Metadata {
+    let mut metadata = Metadata::default();
+    if let Some(RawHeaders(raw_headers)) = extensions.get::<RawHeaders>() {
+        let raw_headers = raw_headers
+            .iter()
+            .map(|(name, value)| (name.clone(), String::from_utf8_lossy(value).into_owned()))
+            .collect::<OrderedMultiMap<_, _>>();
+        metadata
+            .insert("raw-headers".to_owned(), &raw_headers)
+            .expect("failed to serialize message metadata");
+    }
+    metadata
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::CONTENT_TYPE)?
+        .to_str()
+        .ok()
+        .map(ToOwned::to_owned)
+}
@@ -0,0 +1,657 @@
+//! Loading rules files and applying their effects.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    net::IpAddr,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
+};
+
+use chrono::{
+    Local,
+    Utc,
+};
+use color_eyre::eyre::Error;
+use notify_async::watch_modified;
+use skunk::{
+    address::TcpAddress,
+    rule::{
+        backend::{
+            DefaultBackend,
+            Evaluator,
+            HttpBody,
+            HttpMessage,
+            Rules,
+            State,
+        },
+        compiler::Config,
+        fields::RequestFields,
+        file::{
+            self,
+            DefaultEffects,
+            Direction,
+            InterruptEffect,
+            LogEffect,
+            LogTarget,
+            MapLocalEffect,
+            MapRemoteEffect,
+            MockEffect,
+            ModifyEffect,
+            NetworkEffect,
+            ScriptEffect,
+        },
+        script::{
+            self,
+            Outcome,
+            ScriptFlow,
+        },
+        template::Template,
+    },
+};
+use skunk_api_protocol::flow::{
+    Annotations,
+    FlowId,
+};
+use skunk_util::trigger;
+use tokio::task::AbortHandle;
+use tracing::Instrument;
+
+use super::log::{
+    FileLog,
+    Record,
+    DEFAULT_FILE,
+};
+use crate::env::{
+    Environment,
+    RULES_DIR,
+};
+
+/// How long to wait for more changes to rules files before reloading them.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Loads and compiles the rules files in the `rules` directory of the
+/// configuration directory and the rules files in `paths`.
+///
+/// This also returns the files that the rules depend on, i.e. included rules
+/// files and scripts. These should be passed to [`watch`].
+pub fn load(environment: &Environment, paths: &[PathBuf]) -> Result<(Rules, Vec<PathBuf>), Error> {
+    let (backend, dependencies) = compile(&environment.config_relative_path(RULES_DIR), paths)?;
+    Ok((backend.build(), dependencies))
+}
+
+/// Loads and compiles only the rules files in `paths`, or the rules files in
+/// the `rules` directory if `paths` is empty.
+pub fn load_only(environment: &Environment, paths: &[PathBuf]) -> Result<Rules, Error> {
+    let (backend, _) = compile_files(
+        &environment.config_relative_path(RULES_DIR),
+        &files_or_rules_dir(environment, paths)?,
+    )?;
+    Ok(backend.build())
+}
+
+/// Returns `paths`, or the rules files in the `rules` directory if `paths` is
+/// empty.
+pub fn files_or_rules_dir(
+    environment: &Environment,
+    paths: &[PathBuf],
+) -> Result<Vec<PathBuf>, Error> {
+    if paths.is_empty() {
+        rules_dir_files(&environment.config_relative_path(RULES_DIR))
+    }
+    else {
+        Ok(paths.to_owned())
+    }
+}
+
+fn compile(rules_dir: &Path, paths: &[PathBuf]) -> Result<(DefaultBackend, Vec<PathBuf>), Error> {
+    let mut files = rules_dir_files(rules_dir)?;
+    files.extend(paths.iter().cloned());
+    compile_files(rules_dir, &files)
+}
+
+/// Compiles the rules files in `files`. Scripts are loaded from `rules_dir`.
+///
+/// Returns the backend, and the included files and scripts that the rules
+/// depend on.
+fn compile_files(
+    rules_dir: &Path,
+    files: &[PathBuf],
+) -> Result<(DefaultBackend, Vec<PathBuf>), Error> {
+    let config = Config::default();
+    let mut backend = DefaultBackend::new();
+    let mut dependencies = vec![];
+
+    for path in files {
+        tracing::info!(path = %path.display(), "Loading rules");
+        let mut rules_file = file::load(path)?;
+        dependencies.extend(script::load(&mut rules_file, rules_dir)?);
+        for included in &rules_file.included {
+            dependencies.push(included.path.clone());
+            tracing::info!(
+                path = %included.path.display(),
+                name = ?included.meta.name,
+                version = ?included.meta.version,
+                "Included rules"
+            );
+        }
+        backend.add(&rules_file, &config)?;
+    }
+
+    Ok((backend, dependencies))
+}
+
+/// Watches the rules files for changes, and replaces `rules` when they were
+/// modified.
+///
+/// Connections that are already being proxied keep using the rules they
+/// started with. If the modified rules files can't be compiled, the error is
+/// logged and the previous rules stay in place.
+///
+/// The `rules` directory is only watched if it exists when this is called.
+/// The `dependencies` returned by [`load`] are watched too, and are updated
+/// whenever the rules are reloaded.
+pub fn watch(
+    environment: &Environment,
+    paths: &[PathBuf],
+    dependencies: Vec<PathBuf>,
+    rules: Rules,
+) -> Result<(), Error> {
+    let rules_dir = environment.config_relative_path(RULES_DIR);
+    let (reload_tx, mut reload_rx) = trigger::new();
+
+    let watch_paths = rules_dir
+        .exists()
+        .then(|| rules_dir.clone())
+        .into_iter()
+        .chain(paths.iter().cloned());
+    for path in watch_paths {
+        watch_file(path, reload_tx.clone())?;
+    }
+
+    let mut dependency_watches = HashMap::new();
+    watch_dependencies(&mut dependency_watches, dependencies, &reload_tx);
+
+    let paths = paths.to_owned();
+    let span = tracing::info_span!("watch-rules");
+    tokio::spawn(
+        async move {
+            loop {
+                reload_rx.triggered().await;
+                match compile(&rules_dir, &paths) {
+                    Ok((backend, dependencies)) => {
+                        rules.replace(backend);
+                        watch_dependencies(&mut dependency_watches, dependencies, &reload_tx);
+                        tracing::info!("Rules reloaded");
+                    }
+                    Err(error) => {
+                        tracing::error!(
+                            "Could not reload rules. Keeping previous rules: {error:#}"
+                        );
+                    }
+                }
+            }
+        }
+        .instrument(span),
+    );
+
+    Ok(())
+}
+
+/// Triggers `reload_tx` when the file or directory at `path` is modified,
+/// until the returned task is aborted.
+fn watch_file(path: PathBuf, reload_tx: trigger::Sender) -> Result<AbortHandle, Error> {
+    let mut watch = watch_modified(&path, DEBOUNCE)?;
+    let task = tokio::spawn(async move {
+        while let Ok(()) = watch.modified().await {
+            tracing::debug!(path = %path.display(), "Rules modified");
+            reload_tx.trigger();
+        }
+    });
+    Ok(task.abort_handle())
+}
+
+/// Watches the files in `dependencies` that aren't watched yet, and stops
+/// watching files that the rules don't depend on anymore.
+fn watch_dependencies(
+    watches: &mut HashMap<PathBuf, AbortHandle>,
+    dependencies: Vec<PathBuf>,
+    reload_tx: &trigger::Sender,
+) {
+    let dependencies = dependencies.into_iter().collect::<HashSet<_>>();
+
+    watches.retain(|path, task| {
+        let keep = dependencies.contains(path);
+        if !keep {
+            task.abort();
+        }
+        keep
+    });
+
+    for path in dependencies {
+        if watches.contains_key(&path) {
+            continue;
+        }
+        match watch_file(path.clone(), reload_tx.clone()) {
+            Ok(task) => {
+                watches.insert(path, task);
+            }
+            Err(error) => {
+                tracing::warn!(path = %path.display(), "Could not watch file: {error:#}");
+            }
+        }
+    }
+}
+
+/// Returns the rules files in `path` sorted by name, or nothing if the
+/// directory doesn't exist.
+fn rules_dir_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut files = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_file()
+            && path
+                .extension()
+                .is_some_and(|extension| extension == "yaml" || extension == "yml")
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// What to do with a connection or message after applying effects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Action {
+    #[default]
+    Continue,
+    Drop,
+}
+
+/// Effects that change HTTP messages, or where requests are sent.
+#[derive(Clone, Debug, Default)]
+pub struct MessageEffects {
+    pub modify: Vec<ModifyEffect>,
+
+    /// If multiple map or mock effects fire, the last one is used.
+    pub map: Option<MapEffect>,
+
+    /// Network conditions for the connection. These are only used if they
+    /// fire for the destination address, since the connection is already
+    /// established afterwards. If multiple fire, the last one is used.
+    pub network: Option<NetworkEffect>,
+
+    /// If multiple interrupt effects fire, the last one is used.
+    pub interrupt: Option<InterruptEffect>,
+
+    /// Scripts are run in the order their effects fired.
+    pub scripts: Vec<ScriptEffect>,
+
+    /// Annotations for the flow. These should be taken once they were added
+    /// to the flow, so that they're not added again for every message.
+    pub annotations: Annotations,
+}
+
+/// How a request is answered, if not by the server it was sent to.
+#[derive(Clone, Debug)]
+pub enum MapEffect {
+    Local(MapLocalEffect),
+    Remote(MapRemoteEffect),
+    Mock(Box<MockEffect>),
+}
+
+/// Where rules fired, which is recorded by log effects.
+#[derive(Clone, Copy, Debug)]
+pub struct Scope<'a> {
+    pub file_log: &'a FileLog,
+    pub destination: &'a TcpAddress,
+
+    /// IP address of the client, if it's known.
+    pub client: Option<IpAddr>,
+
+    /// The flow of the connection, if it's recorded.
+    pub flow_id: Option<FlowId>,
+
+    /// The message the rules were evaluated for.
+    pub message: Option<&'a HttpMessage<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Returns the [`State`] for evaluating stateful filters now.
+    pub fn state(&self) -> State<'a> {
+        State {
+            destination: self.destination,
+            client: self.client,
+            time: Local::now(),
+        }
+    }
+}
+
+/// Applies effects that fired.
+///
+/// Effects that only have side effects (e.g. logging) are performed right
+/// away. Annotations are rendered and added to `message_effects`. The other
+/// effects are [collected](collect).
+pub fn apply(
+    effects: Vec<DefaultEffects>,
+    scope: &Scope,
+    message_effects: &mut MessageEffects,
+) -> Action {
+    for effect in &effects {
+        match effect {
+            DefaultEffects::Log(effect) => log(effect, scope),
+            DefaultEffects::Tag(tags) => {
+                message_effects.annotations.merge(Annotations {
+                    tags: tags.clone(),
+                    ..Default::default()
+                });
+            }
+            DefaultEffects::Mark(marker) => {
+                message_effects.annotations.marker = Some(marker.clone());
+            }
+            DefaultEffects::Comment(template) => {
+                message_effects.annotations.comment = Some(render(template, scope));
+            }
+            _ => {}
+        }
+    }
+    collect(effects, message_effects)
+}
+
+/// Collects effects that fired, without performing any side effects.
+///
+/// Effects that change what happens with the connection are returned as
+/// [`Action`]. Effects that change messages are added to `message_effects`,
+/// since they can only be applied once we have the message.
+pub fn collect(effects: Vec<DefaultEffects>, message_effects: &mut MessageEffects) -> Action {
+    let mut action = Action::Continue;
+
+    for effect in effects {
+        match effect {
+            DefaultEffects::Log(_)
+            | DefaultEffects::Tag(_)
+            | DefaultEffects::Mark(_)
+            | DefaultEffects::Comment(_) => {}
+            DefaultEffects::Interrupt(effect) => message_effects.interrupt = Some(effect),
+            DefaultEffects::Drop => action = Action::Drop,
+            DefaultEffects::Modify(effect) => message_effects.modify.push(*effect),
+            DefaultEffects::MapLocal(effect) => {
+                message_effects.map = Some(MapEffect::Local(effect))
+            }
+            DefaultEffects::MapRemote(effect) => {
+                message_effects.map = Some(MapEffect::Remote(effect))
+            }
+            DefaultEffects::Mock(effect) => message_effects.map = Some(MapEffect::Mock(effect)),
+            DefaultEffects::Network(effect) => message_effects.network = Some(effect),
+            DefaultEffects::Script(effect) => message_effects.scripts.push(effect),
+        }
+    }
+
+    action
+}
+
+/// Evaluates the rules for the head of a HTTP message.
+///
+/// `connection` is the evaluator of the connection the message was sent on.
+/// If [`Evaluator::needs_body`] returns `true` for the returned evaluator, the
+/// body should be read and passed to [`apply_http`].
+pub fn evaluate_http(
+    connection: &Evaluator,
+    direction: Direction,
+    message: &HttpMessage,
+) -> Evaluator {
+    let mut eval = connection.clone();
+    eval.set_direction(direction);
+    eval.set_http(message);
+    eval
+}
+
+/// Applies the effects for a HTTP message evaluated with [`evaluate_http`].
+///
+/// Stateful filters are evaluated after the body, so that they only count
+/// messages that match otherwise.
+pub fn apply_http(
+    mut eval: Evaluator,
+    message: &HttpMessage,
+    body: Option<&HttpBody>,
+    scope: &Scope,
+    message_effects: &mut MessageEffects,
+) -> Action {
+    if let Some(body) = body {
+        eval.set_body(body);
+    }
+    eval.set_state(&scope.state());
+    let scope = Scope {
+        message: Some(message),
+        ..*scope
+    };
+    apply(eval.take_effects(), &scope, message_effects)
+}
+
+/// Returns the flow that scripts are run for.
+pub fn script_flow(scope: &Scope) -> ScriptFlow {
+    ScriptFlow {
+        id: scope.flow_id.map(|flow_id| flow_id.0.to_string()),
+        destination: scope.destination.to_string(),
+    }
+}
+
+/// Returns the annotations that scripts set, and whether one of them dropped
+/// the message.
+pub fn script_outcome(outcome: Outcome) -> (Annotations, Action) {
+    let action = if outcome.drop {
+        Action::Drop
+    }
+    else {
+        Action::Continue
+    };
+    let annotations = Annotations {
+        tags: outcome.tags,
+        marker: outcome.marker,
+        comment: outcome.comment,
+    };
+    (annotations, action)
+}
+
+/// Renders a template with the fields of the request, if the effect fired
+/// for a message.
+fn render(template: &Template, scope: &Scope) -> String {
+    if let Some(message) = scope.message {
+        RequestFields::new(message.method, message.uri, message.request_headers).render(template)
+    }
+    else {
+        template.render(|_| None::<&str>)
+    }
+}
+
+/// Logs that a rule matched, either to the terminal or to a file.
+fn log(effect: &LogEffect, scope: &Scope) {
+    let message = effect
+        .message
+        .as_ref()
+        .map(|template| render(template, scope));
+
+    match effect.target {
+        LogTarget::User => {
+            tracing::info!(
+                name = effect.name.as_deref(),
+                message = message.as_deref(),
+                "Rule matched"
+            );
+        }
+        LogTarget::File => {
+            let file = effect
+                .file
+                .as_deref()
+                .or(effect.name.as_deref())
+                .unwrap_or(DEFAULT_FILE);
+            scope.file_log.write(
+                file,
+                Record {
+                    timestamp: Utc::now().fixed_offset(),
+                    flow_id: scope.flow_id,
+                    destination: scope.destination.to_string(),
+                    name: effect.name.clone(),
+                    message,
+                    method: scope.message.map(|message| message.method.to_string()),
+                    url: scope.message.map(|message| message.uri.to_string()),
+                    status: scope
+                        .message
+                        .and_then(|message| message.status)
+                        .map(|status| status.as_u16()),
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::{
+        BodyExt,
+        Full,
+    };
+    use skunk::{
+        address::TcpAddress,
+        protocol::http::{
+            body::Replace,
+            Method,
+            Request,
+            Response,
+            StatusCode,
+        },
+        rule::{
+            backend::{
+                DefaultBackend,
+                HttpMessage,
+                Rules,
+            },
+            compiler::Config,
+            file::{
+                self,
+                Direction,
+            },
+            modify::{
+                modify_request,
+                modify_response,
+            },
+        },
+    };
+
+    use super::{
+        apply_http,
+        evaluate_http,
+        Action,
+        MessageEffects,
+        Scope,
+    };
+    use crate::proxy::log::FileLog;
+
+    fn compile(yaml: &str) -> Rules {
+        let mut backend = DefaultBackend::new();
+        backend
+            .add(
+                &file::from_reader(yaml.as_bytes()).unwrap(),
+                &Config::default(),
+            )
+            .unwrap();
+        backend.build()
+    }
+
+    #[tokio::test]
+    async fn it_modifies_requests_and_responses() {
+        let rules = compile(
+            r#"
+rules:
+  - if:
+      - http:
+          - method: [POST]
+    then:
+      effects:
+        - modify:
+            set-headers: {x-set: set}
+            remove-headers: [x-remove]
+            body:
+              text: replaced
+            status: 418
+"#,
+        );
+        let file_log = FileLog::new(std::env::temp_dir(), 1024, 1);
+        let destination = "example.com:443".parse::<TcpAddress>().unwrap();
+        let scope = Scope {
+            file_log: &file_log,
+            destination: &destination,
+            client: None,
+            flow_id: None,
+            message: None,
+        };
+        let mut eval = rules.evaluator();
+        eval.set_tcp(&destination);
+
+        let effects = |direction, request: &Request<_>, status| {
+            let message = HttpMessage {
+                method: request.method(),
+                uri: request.uri(),
+                request_headers: request.headers(),
+                headers: request.headers(),
+                status,
+            };
+            let message_eval = evaluate_http(&eval, direction, &message);
+            let mut message_effects = MessageEffects::default();
+            let action = apply_http(message_eval, &message, None, &scope, &mut message_effects);
+            assert_eq!(action, Action::Continue);
+            message_effects
+        };
+        let request = |method| {
+            Request::builder()
+                .method(method)
+                .uri("/")
+                .header("x-remove", "original")
+                .body(Replace::original(Full::new(Bytes::from("original"))))
+                .unwrap()
+        };
+
+        // a GET request isn't modified.
+        let get = request(Method::GET);
+        assert!(effects(Direction::Request, &get, None).modify.is_empty());
+
+        let post = request(Method::POST);
+        let request_effects = effects(Direction::Request, &post, None);
+        let response_effects = effects(Direction::Response, &post, Some(StatusCode::OK));
+
+        let post = modify_request(post, &request_effects.modify).await.unwrap();
+        assert_eq!(post.headers()["x-set"], "set");
+        assert!(!post.headers().contains_key("x-remove"));
+        assert_eq!(
+            post.into_body().collect().await.unwrap().to_bytes(),
+            "replaced"
+        );
+
+        let response = Response::builder()
+            .header("x-remove", "original")
+            .body(Replace::original(Full::new(Bytes::from("original"))))
+            .unwrap();
+        let response = modify_response(response, &response_effects.modify)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+        assert_eq!(response.headers()["x-set"], "set");
+        assert!(!response.headers().contains_key("x-remove"));
+        assert_eq!(
+            response.into_body().collect().await.unwrap().to_bytes(),
+            "replaced"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Checking rules files for likely mistakes.
+
+use std::{
+    fmt::Write,
+    path::PathBuf,
+};
+
+use color_eyre::eyre::Error;
+use skunk::rule::{
+    compiler::Config,
+    lint::{
+        self,
+        Severity,
+    },
+};
+
+use crate::{
+    env::Environment,
+    proxy::rules::files_or_rules_dir,
+};
+
+/// Checks the rules files in `paths` (or the `rules` directory) and prints
+/// the diagnostics. Returns the number of errors.
+pub fn run(
+    environment: &Environment,
+    paths: &[PathBuf],
+    with_user_interaction: bool,
+) -> Result<usize, Error> {
+    let config = Config {
+        with_user_interaction,
+    };
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for path in files_or_rules_dir(environment, paths)? {
+        let diagnostics = lint::check_file(&path, &config)?;
+
+        for diagnostic in diagnostics {
+            match diagnostic.severity() {
+                Severity::Error => errors += 1,
+                Severity::Warning => warnings += 1,
+            }
+
+            let file = diagnostic.file.as_ref().unwrap_or(&path);
+            let mut location = file.display().to_string();
+            if let Some(position) = &diagnostic.location {
+                write!(location, ":{}:{}", position.line, position.column)?;
+            }
+            if !diagnostic.path.is_empty() {
+                write!(location, ": {}", diagnostic.path)?;
+            }
+            println!("{location}: {}: {}", diagnostic.severity(), diagnostic.lint);
+        }
+    }
+
+    println!("{errors} errors, {warnings} warnings");
+
+    Ok(errors)
+}
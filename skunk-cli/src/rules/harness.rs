@@ -0,0 +1,889 @@
+//! Testing rules files against fixtures, without any network.
+//!
+//! A test file lists fixtures, and what the rules are expected to do with
+//! them:
+//!
+//! ```yaml
+//! # rules files to test, relative to the test file.
+//! rules: [rules.yaml]
+//!
+//! tests:
+//!   - name: blocks trackers
+//!     request:
+//!       url: https://tracker.example.com/pixel.gif
+//!     expect:
+//!       dropped: true
+//!
+//!   - name: adds debug header
+//!     # the first request and response of a recorded flow.
+//!     flow: 0190a4b2-6c1e-7d3a-9f0e-2b7c5d8e1a4f
+//!     expect:
+//!       effects: [modify]
+//!       request:
+//!         headers:
+//!           x-debug: "1"
+//!           cookie: null
+//! ```
+//!
+//! The fixtures go through the same steps as in the proxy: The rules are
+//! evaluated for the destination address, the TLS server name (for port 443),
+//! the request and the response. Modify, mock and map-local effects are
+//! applied. Map-remote and interrupt effects are not, and log effects don't
+//! log anything.
+//!
+//! Stateful filters (e.g. `nth`) are evaluated for the request and the
+//! response, and keep counting across the tests of a test file. Recorded flows
+//! are evaluated at the time their request was recorded.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    fs::File,
+    io::{
+        BufReader,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use bytes::Bytes;
+use chrono::{
+    DateTime,
+    Local,
+};
+use color_eyre::eyre::{
+    bail,
+    eyre,
+    Error,
+};
+use http_body_util::{
+    BodyExt,
+    Full,
+};
+use serde::Deserialize;
+use skunk::{
+    address::TcpAddress,
+    protocol::http::{
+        body::Replace,
+        header::{
+            HeaderName,
+            HeaderValue,
+        },
+        HeaderMap,
+        Method,
+        Request,
+        Response,
+        StatusCode,
+        Uri,
+    },
+    rule::{
+        backend::{
+            Evaluator,
+            HttpBody,
+            HttpMessage,
+            Rules,
+            State,
+            Tls,
+        },
+        file::{
+            DefaultEffects,
+            Direction,
+        },
+        map::map_local,
+        mock::mock,
+        modify::{
+            modify_request,
+            modify_response,
+        },
+        script::{
+            script_request,
+            script_response,
+            ScriptFlow,
+        },
+    },
+};
+use skunk_api_protocol::{
+    flow::{
+        FlowId,
+        MessageKind,
+    },
+    http,
+};
+use skunk_flow_store::FlowStore;
+
+use crate::{
+    env::{
+        Environment,
+        FLOWS_FILE,
+    },
+    proxy::rules::{
+        self,
+        Action,
+        MapEffect,
+        MessageEffects,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TestFile {
+    /// Rules files to test, relative to the test file. If there are none,
+    /// the rules files given on the command-line are tested.
+    #[serde(default)]
+    pub rules: Vec<PathBuf>,
+
+    /// Flow store that flows are loaded from, relative to the test file.
+    pub flows: Option<PathBuf>,
+
+    pub tests: Vec<Test>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Test {
+    pub name: String,
+
+    /// Destination address of the connection. Defaults to the host and port
+    /// of the request URL, or the destination of the recorded flow.
+    pub destination: Option<TcpAddress>,
+
+    /// A recorded flow to use instead of `request` and `response`. Its first
+    /// request and response are used.
+    pub flow: Option<FlowId>,
+
+    pub request: Option<RequestFixture>,
+
+    /// The response sent by the server. If there is none, only the request
+    /// is tested.
+    pub response: Option<ResponseFixture>,
+
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RequestFixture {
+    #[serde(default = "default_method")]
+    pub method: String,
+
+    pub url: String,
+
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub body: String,
+}
+
+fn default_method() -> String {
+    "GET".to_owned()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ResponseFixture {
+    #[serde(default = "default_status")]
+    pub status: u16,
+
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub body: String,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// Expected outcome of a test. Only what is given is checked.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Expect {
+    /// Whether any effect fires.
+    pub matches: Option<bool>,
+
+    /// Names of the effects that fire, in order, e.g. `[modify, log]`.
+    pub effects: Option<Vec<String>>,
+
+    /// Whether the connection or message is dropped.
+    pub dropped: Option<bool>,
+
+    /// The request after effects were applied.
+    pub request: Option<ExpectRequest>,
+
+    /// The response after effects were applied.
+    pub response: Option<ExpectResponse>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExpectRequest {
+    pub method: Option<String>,
+    pub url: Option<String>,
+
+    /// Expected header values. `null` means the header must not be present.
+    #[serde(default)]
+    pub headers: BTreeMap<String, Option<String>>,
+
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExpectResponse {
+    pub status: Option<u16>,
+
+    /// Expected header values. `null` means the header must not be present.
+    #[serde(default)]
+    pub headers: BTreeMap<String, Option<String>>,
+
+    pub body: Option<String>,
+}
+
+/// Runs the tests in `files` and prints a report.
+///
+/// `default_rules` and `default_flows` are used for test files that don't
+/// specify them. Returns the number of failed tests.
+pub async fn run(
+    environment: &Environment,
+    files: &[PathBuf],
+    default_rules: &[PathBuf],
+    default_flows: Option<&Path>,
+) -> Result<usize, Error> {
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for path in files {
+        let test_file: TestFile = serde_yml::from_reader(BufReader::new(File::open(path)?))
+            .map_err(|error| eyre!("Invalid test file {}: {error}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        let rules = if test_file.rules.is_empty() {
+            rules::load_only(environment, default_rules)?
+        }
+        else {
+            let paths = test_file
+                .rules
+                .iter()
+                .map(|rules| base.join(rules))
+                .collect::<Vec<_>>();
+            rules::load_only(environment, &paths)?
+        };
+
+        let flows_path = test_file
+            .flows
+            .as_ref()
+            .map(|flows| base.join(flows))
+            .or_else(|| default_flows.map(ToOwned::to_owned))
+            .unwrap_or_else(|| environment.data_relative_path(FLOWS_FILE));
+
+        println!("{}", path.display());
+
+        let (file_passed, file_failed) =
+            run_tests(&mut std::io::stdout(), &test_file, &rules, &flows_path).await?;
+        passed += file_passed;
+        failed += file_failed;
+    }
+
+    println!();
+    println!("{passed} passed, {failed} failed");
+
+    Ok(failed)
+}
+
+/// Runs the tests of `test_file` against `rules`, and writes a report to
+/// `out`.
+///
+/// Returns the number of passed and failed tests.
+async fn run_tests(
+    out: &mut impl Write,
+    test_file: &TestFile,
+    rules: &Rules,
+    flows_path: &Path,
+) -> Result<(usize, usize), Error> {
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut flow_store = None;
+
+    for test in &test_file.tests {
+        if test.flow.is_some() && flow_store.is_none() {
+            flow_store = Some(FlowStore::open(flows_path).await?);
+        }
+
+        let result = async {
+            let exchange = match test.flow {
+                Some(flow_id) => {
+                    let flow_store = flow_store.as_ref().expect("flow store is open");
+                    Exchange::from_flow(flow_store, flow_id, test.destination.as_ref()).await?
+                }
+                None => Exchange::from_fixture(test)?,
+            };
+            let outcome = exchange.run(rules).await?;
+            Ok::<_, Error>(outcome.check(&test.expect))
+        }
+        .await;
+
+        match result {
+            Ok(diffs) if diffs.is_empty() => {
+                passed += 1;
+                writeln!(out, "  ok      {}", test.name)?;
+            }
+            Ok(diffs) => {
+                failed += 1;
+                writeln!(out, "  FAILED  {}", test.name)?;
+                for diff in diffs {
+                    writeln!(out, "          {diff}")?;
+                }
+            }
+            Err(error) => {
+                failed += 1;
+                writeln!(out, "  ERROR   {}: {error:#}", test.name)?;
+            }
+        }
+    }
+
+    Ok((passed, failed))
+}
+
+/// A request and response to run the rules against.
+struct Exchange {
+    destination: TcpAddress,
+    request: Request<Bytes>,
+    response: Option<Response<Bytes>>,
+
+    /// When the request was sent, for stateful filters.
+    time: DateTime<Local>,
+}
+
+impl Exchange {
+    fn from_fixture(test: &Test) -> Result<Self, Error> {
+        let Some(fixture) = &test.request
+        else {
+            bail!("Test has neither a `request` nor a `flow`");
+        };
+
+        let uri = fixture.url.parse::<Uri>()?;
+        let destination = match &test.destination {
+            Some(destination) => destination.clone(),
+            None => {
+                let Some(host) = uri.host()
+                else {
+                    bail!("Test needs a `destination`, since the request URL has no host");
+                };
+                let port = uri.port_u16().unwrap_or_else(|| {
+                    if uri.scheme_str() == Some("http") {
+                        80
+                    }
+                    else {
+                        443
+                    }
+                });
+                format!("{host}:{port}").parse()?
+            }
+        };
+
+        let mut request = Request::new(Bytes::from(fixture.body.clone()));
+        *request.method_mut() = Method::from_bytes(fixture.method.as_bytes())?;
+        *request.uri_mut() = uri;
+        *request.headers_mut() = to_header_map(&fixture.headers)?;
+
+        let response = test
+            .response
+            .as_ref()
+            .map(|fixture| {
+                let mut response = Response::new(Bytes::from(fixture.body.clone()));
+                *response.status_mut() = StatusCode::from_u16(fixture.status)?;
+                *response.headers_mut() = to_header_map(&fixture.headers)?;
+                Ok::<_, Error>(response)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            destination,
+            request,
+            response,
+            time: Local::now(),
+        })
+    }
+
+    async fn from_flow(
+        flow_store: &FlowStore,
+        flow_id: FlowId,
+        destination: Option<&TcpAddress>,
+    ) -> Result<Self, Error> {
+        let mut transaction = flow_store.transaction().await?;
+
+        let Some(flow) = transaction.get_flow(flow_id).await?
+        else {
+            bail!("No such flow: {}", flow_id.0);
+        };
+        let destination = match destination {
+            Some(destination) => destination.clone(),
+            None => {
+                flow.metadata
+                    .get::<String>("destination")?
+                    .ok_or_else(|| eyre!("Flow has no destination"))?
+                    .parse()?
+            }
+        };
+
+        let messages = transaction
+            .get_messages(Some(flow_id), None, None, None)
+            .await?;
+        let mut messages = messages.iter();
+        let Some(request) = messages.find(|message| matches!(message.kind, MessageKind::Request))
+        else {
+            bail!("Flow has no request");
+        };
+        let time = request.timestamp.with_timezone(&Local);
+        let response = messages.find(|message| matches!(message.kind, MessageKind::Response));
+
+        let data = request.data.to_value::<http::Request>()?;
+        let body = load_body(&mut transaction, data.body.as_ref()).await?;
+        let mut request = Request::new(body);
+        *request.method_mut() = Method::from_bytes(data.method.as_bytes())?;
+        *request.uri_mut() = data.uri.parse()?;
+        *request.headers_mut() = to_header_map(data.headers.iter().map(|(n, v)| (n, v)))?;
+
+        let response = match response {
+            Some(response) => {
+                let data = response.data.to_value::<http::Response>()?;
+                let body = load_body(&mut transaction, data.body.as_ref()).await?;
+                let mut response = Response::new(body);
+                *response.status_mut() = StatusCode::from_u16(data.status)?;
+                *response.headers_mut() = to_header_map(data.headers.iter().map(|(n, v)| (n, v)))?;
+                Some(response)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            destination,
+            request,
+            response,
+            time,
+        })
+    }
+
+    /// Runs the rules against this exchange, like the proxy would.
+    async fn run(self, rules: &Rules) -> Result<Outcome, Error> {
+        let Self {
+            destination,
+            request,
+            response,
+            time,
+        } = self;
+        let state = State {
+            destination: &destination,
+            client: None,
+            time,
+        };
+        let script_flow = ScriptFlow {
+            id: None,
+            destination: destination.to_string(),
+        };
+        let mut outcome = Outcome::default();
+        let mut connection_effects = MessageEffects::default();
+
+        let mut eval = rules.evaluator();
+        eval.set_tcp(&destination);
+        if outcome.collect(eval.take_effects(), &mut connection_effects) == Action::Drop {
+            return Ok(outcome);
+        }
+
+        if destination.port == 443 {
+            // there is no server, so the certificate is unknown.
+            eval.set_tls(&Tls {
+                server_name: Some(destination.host.to_string()),
+                ..Default::default()
+            });
+            if outcome.collect(eval.take_effects(), &mut connection_effects) == Action::Drop {
+                return Ok(outcome);
+            }
+        }
+
+        // the request head is needed again to evaluate the response
+        let method = request.method().clone();
+        let uri = request.uri().clone();
+        let request_headers = request.headers().clone();
+
+        let mut request_effects = connection_effects.clone();
+        let message = HttpMessage {
+            method: &method,
+            uri: &uri,
+            request_headers: &request_headers,
+            headers: &request_headers,
+            status: None,
+        };
+        let body = HttpBody::new(request.body());
+        let effects = evaluate(&eval, Direction::Request, &message, &body, &state);
+        if outcome.collect(effects, &mut request_effects) == Action::Drop {
+            return Ok(outcome);
+        }
+
+        let request = modify_request(
+            request.map(Replace::<Full<Bytes>>::replaced),
+            &request_effects.modify,
+        )
+        .await?;
+        let (request, script_outcome) =
+            script_request(request, &request_effects.scripts, &script_flow).await?;
+        if script_outcome.drop {
+            outcome.dropped = true;
+            return Ok(outcome);
+        }
+        let (parts, body) = request.into_parts();
+        let body = body.collect().await?.to_bytes();
+        let request = Request::from_parts(parts, Replace::<Full<Bytes>>::replaced(body.clone()));
+        outcome.request = Some(RequestOutcome {
+            method: request.method().clone(),
+            uri: request.uri().clone(),
+            headers: request.headers().clone(),
+            body,
+        });
+
+        let response: Option<Response<Replace<Full<Bytes>>>> = match &request_effects.map {
+            Some(MapEffect::Mock(effect)) => Some(mock(request, effect).await?.1),
+            Some(MapEffect::Local(effect)) => Some(map_local(request, effect).await?),
+            Some(MapEffect::Remote(_)) | None => {
+                response.map(|response| response.map(Replace::replaced))
+            }
+        };
+
+        if let Some(response) = response {
+            let mut response_effects = connection_effects;
+            let (parts, body) = response.into_parts();
+            let body = body.collect().await?.to_bytes();
+            let message = HttpMessage {
+                method: &method,
+                uri: &uri,
+                request_headers: &request_headers,
+                headers: &parts.headers,
+                status: Some(parts.status),
+            };
+            let effects = evaluate(
+                &eval,
+                Direction::Response,
+                &message,
+                &HttpBody::new(&body),
+                &state,
+            );
+            if outcome.collect(effects, &mut response_effects) == Action::Drop {
+                return Ok(outcome);
+            }
+
+            let response = Response::from_parts(parts, Replace::replaced(body));
+            let response = modify_response(response, &response_effects.modify).await?;
+            let sent = outcome.request.as_ref().expect("request was recorded");
+            let (response, script_outcome) = script_response(
+                response,
+                &sent.method,
+                &sent.uri,
+                &response_effects.scripts,
+                &script_flow,
+            )
+            .await?;
+            if script_outcome.drop {
+                outcome.dropped = true;
+                return Ok(outcome);
+            }
+            let (parts, body) = response.into_parts();
+            outcome.response = Some(ResponseOutcome {
+                status: parts.status,
+                headers: parts.headers,
+                body: body.collect().await?.to_bytes(),
+            });
+        }
+
+        Ok(outcome)
+    }
+}
+
+/// Evaluates the rules for a HTTP message.
+fn evaluate(
+    connection: &Evaluator,
+    direction: Direction,
+    message: &HttpMessage,
+    body: &HttpBody,
+    state: &State,
+) -> Vec<DefaultEffects> {
+    let mut eval = connection.clone();
+    eval.set_direction(direction);
+    eval.set_http(message);
+    eval.set_body(body);
+    eval.set_state(state);
+    eval.take_effects()
+}
+
+/// What the rules did with an [`Exchange`].
+#[derive(Debug, Default)]
+struct Outcome {
+    effects: Vec<&'static str>,
+    dropped: bool,
+    request: Option<RequestOutcome>,
+    response: Option<ResponseOutcome>,
+}
+
+#[derive(Debug)]
+struct RequestOutcome {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+#[derive(Debug)]
+struct ResponseOutcome {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Outcome {
+    fn collect(
+        &mut self,
+        effects: Vec<DefaultEffects>,
+        message_effects: &mut MessageEffects,
+    ) -> Action {
+        self.effects
+            .extend(effects.iter().map(DefaultEffects::name));
+        let action = rules::collect(effects, message_effects);
+        if action == Action::Drop {
+            self.dropped = true;
+        }
+        action
+    }
+
+    /// Compares the outcome with what was expected, and returns the
+    /// differences.
+    fn check(&self, expect: &Expect) -> Vec<String> {
+        let mut diffs = vec![];
+
+        compare(
+            &mut diffs,
+            "matches",
+            expect.matches.as_ref(),
+            &!self.effects.is_empty(),
+        );
+        compare(
+            &mut diffs,
+            "effects",
+            expect.effects.as_ref(),
+            &self
+                .effects
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+        );
+        compare(
+            &mut diffs,
+            "dropped",
+            expect.dropped.as_ref(),
+            &self.dropped,
+        );
+
+        match (&expect.request, &self.request) {
+            (None, _) => {}
+            (Some(_), None) => {
+                diffs.push("request: expected a request, but there is none".to_owned())
+            }
+            (Some(expect), Some(request)) => {
+                compare(
+                    &mut diffs,
+                    "request.method",
+                    expect.method.as_ref(),
+                    &request.method.to_string(),
+                );
+                compare(
+                    &mut diffs,
+                    "request.url",
+                    expect.url.as_ref(),
+                    &request.uri.to_string(),
+                );
+                compare_headers(&mut diffs, "request", &expect.headers, &request.headers);
+                compare(
+                    &mut diffs,
+                    "request.body",
+                    expect.body.as_ref(),
+                    &String::from_utf8_lossy(&request.body).into_owned(),
+                );
+            }
+        }
+
+        match (&expect.response, &self.response) {
+            (None, _) => {}
+            (Some(_), None) => {
+                diffs.push("response: expected a response, but there is none".to_owned())
+            }
+            (Some(expect), Some(response)) => {
+                compare(
+                    &mut diffs,
+                    "response.status",
+                    expect.status.as_ref(),
+                    &response.status.as_u16(),
+                );
+                compare_headers(&mut diffs, "response", &expect.headers, &response.headers);
+                compare(
+                    &mut diffs,
+                    "response.body",
+                    expect.body.as_ref(),
+                    &String::from_utf8_lossy(&response.body).into_owned(),
+                );
+            }
+        }
+
+        diffs
+    }
+}
+
+fn compare<T: PartialEq + Debug>(
+    diffs: &mut Vec<String>,
+    what: &str,
+    expected: Option<&T>,
+    actual: &T,
+) {
+    if let Some(expected) = expected {
+        if expected != actual {
+            diffs.push(format!("{what}: expected {expected:?}, got {actual:?}"));
+        }
+    }
+}
+
+fn compare_headers(
+    diffs: &mut Vec<String>,
+    what: &str,
+    expected: &BTreeMap<String, Option<String>>,
+    headers: &HeaderMap,
+) {
+    for (name, expected) in expected {
+        let actual = headers
+            .get(name.as_str())
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+        compare(
+            diffs,
+            &format!("{what}.headers.{name}"),
+            Some(expected),
+            &actual,
+        );
+    }
+}
+
+async fn load_body(
+    transaction: &mut skunk_flow_store::Transaction<'_>,
+    body: Option<&http::Body>,
+) -> Result<Bytes, Error> {
+    let Some(artifact_id) = body.and_then(|body| body.artifact_id)
+    else {
+        return Ok(Bytes::new());
+    };
+    let data = transaction
+        .get_artifact_data(artifact_id)
+        .await?
+        .ok_or_else(|| eyre!("Body of recorded message is missing"))?;
+    Ok(data.into())
+}
+
+fn to_header_map<'a>(
+    headers: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> Result<HeaderMap, Error> {
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            Ok::<_, Error>((
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use skunk::rule::{
+        backend::Rules,
+        compiler::Config,
+        file,
+    };
+
+    use super::{
+        run_tests,
+        TestFile,
+    };
+
+    #[tokio::test]
+    async fn it_reports_passed_and_failed_tests() {
+        let rules = file::from_reader(
+            r#"
+rules:
+  - if:
+      - http:
+          - method: [POST]
+    then:
+      effects:
+        - modify:
+            set-headers:
+              x-debug: "1"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let rules = Rules::compile(&rules, &Config::default()).unwrap();
+
+        let test_file: TestFile = serde_yml::from_str(
+            r#"
+tests:
+  - name: adds debug header
+    request:
+      method: POST
+      url: http://example.com/
+    expect:
+      effects: [modify]
+      request:
+        headers:
+          x-debug: "1"
+
+  - name: expects the wrong header
+    request:
+      method: POST
+      url: http://example.com/
+    expect:
+      request:
+        headers:
+          x-debug: "2"
+
+  - name: ignores other methods
+    request:
+      url: http://example.com/
+    expect:
+      matches: false
+      request:
+        headers:
+          x-debug: null
+"#,
+        )
+        .unwrap();
+
+        let mut out = vec![];
+        let (passed, failed) = run_tests(&mut out, &test_file, &rules, Path::new("flows.db"))
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!((passed, failed), (2, 1), "{out}");
+        assert_eq!(
+            out,
+            r#"  ok      adds debug header
+  FAILED  expects the wrong header
+          request.headers.x-debug: expected Some("2"), got Some("1")
+  ok      ignores other methods
+"#
+        );
+    }
+}
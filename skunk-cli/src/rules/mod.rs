@@ -0,0 +1,4 @@
+//! Commands for working with rules files.
+
+pub mod check;
+pub mod harness;
@@ -0,0 +1,22 @@
+-- artifacts referred to `flow(message_id)`, which doesn't exist. sqlite can't
+-- change foreign keys, so the table is recreated.
+
+CREATE TABLE artifact_new (
+    artifact_id UUID NOT NULL PRIMARY KEY,
+    message_id UUID,
+    mime_type TEXT,
+    file_name TEXT,
+    timestamp DATETIME NOT NULL,
+    hash BLOB NOT NULL,
+
+    FOREIGN KEY(message_id) REFERENCES message(message_id),
+    FOREIGN KEY(hash) REFERENCES artifact_blob(hash)
+);
+
+INSERT INTO artifact_new SELECT * FROM artifact;
+DROP TABLE artifact;
+ALTER TABLE artifact_new RENAME TO artifact;
+
+CREATE INDEX index_artifact_mime_type ON artifact(mime_type);
+CREATE INDEX index_artifact_file_name ON artifact(file_name);
+CREATE INDEX index_artifact_timestamp ON artifact(timestamp);
@@ -10,7 +10,13 @@ use serde::{
     Deserialize,
     Serialize,
 };
+use sha2::{
+    Digest,
+    Sha256,
+};
 use skunk_api_protocol::flow::{
+    Artifact,
+    ArtifactId,
     Flow,
     FlowId,
     Message,
@@ -174,6 +180,26 @@ impl<'a> Transaction<'a> {
         Ok(())
     }
 
+    /// Replaces the metadata of a flow, e.g. when it was annotated.
+    pub async fn update_flow_metadata(
+        &mut self,
+        flow_id: FlowId,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            UPDATE flow
+            SET metadata = ?
+            WHERE flow_id = ?
+            "#,
+            metadata,
+            flow_id,
+        )
+        .execute(self.transaction.as_mut())
+        .await?;
+        Ok(())
+    }
+
     pub async fn insert_message(&mut self, message: &Message) -> Result<(), Error> {
         sqlx::query!(
             r#"
@@ -192,6 +218,88 @@ impl<'a> Transaction<'a> {
         Ok(())
     }
 
+    /// Inserts an artifact with its data. The data is stored by its hash, so
+    /// identical blobs are only stored once.
+    pub async fn insert_artifact(&mut self, artifact: &Artifact, data: &[u8]) -> Result<(), Error> {
+        let hash = Sha256::digest(data).to_vec();
+        let size = i64::try_from(data.len()).expect("artifact too large");
+
+        sqlx::query!(
+            r#"
+            INSERT INTO artifact_blob (hash, size, data)
+            VALUES (?, ?, ?)
+            ON CONFLICT(hash) DO NOTHING
+            "#,
+            hash,
+            size,
+            data,
+        )
+        .execute(self.transaction.as_mut())
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO artifact (artifact_id, message_id, mime_type, file_name, timestamp, hash)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+            artifact.artifact_id,
+            artifact.message_id,
+            artifact.mime_type,
+            artifact.file_name,
+            artifact.timestamp,
+            hash,
+        )
+        .execute(self.transaction.as_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_artifact_data(
+        &mut self,
+        artifact_id: ArtifactId,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT artifact_blob.data AS "data: Vec<u8>"
+            FROM artifact
+            JOIN artifact_blob ON artifact.hash = artifact_blob.hash
+            WHERE artifact.artifact_id = ?
+            "#,
+            artifact_id,
+        )
+        .fetch_optional(self.transaction.as_mut())
+        .await?
+        .map(|row| row.data))
+    }
+
+    pub async fn get_flow(&mut self, flow_id: FlowId) -> Result<Option<Flow>, Error> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                flow_id AS "flow_id: FlowId",
+                parent_id AS "parent_id: FlowId",
+                protocol AS "protocol: String",
+                timestamp AS "timestamp: DateTime<FixedOffset>",
+                metadata AS "metadata!: Metadata"
+            FROM flow
+            WHERE flow_id = ?
+            "#,
+            flow_id,
+        )
+        .fetch_optional(self.transaction.as_mut())
+        .await?
+        .map(|row| {
+            Flow {
+                flow_id: row.flow_id,
+                parent: row.parent_id,
+                protocol: row.protocol,
+                timestamp: row.timestamp,
+                metadata: row.metadata,
+            }
+        }))
+    }
+
     pub async fn get_flows(
         &mut self,
         parent_id: Option<FlowId>,
@@ -4,14 +4,90 @@ use leptos::{
     view,
     For,
     IntoView,
+    RwSignal,
+    SignalGet,
+    SignalSet,
+    SignalUpdate,
+    SignalWith,
+    SignalWithUntracked,
+};
+use skunk_api_protocol::flow::{
+    Event,
+    Flow,
+    FlowId,
 };
 
-use crate::components::expand_button::ExpandButton;
+use crate::{
+    app::Context,
+    components::{
+        annotations::FlowAnnotations,
+        expand_button::ExpandButton,
+    },
+};
 
 stylance::import_crate_style!(style, "src/app/flows.module.scss");
 
+/// How many of the most recent flows are loaded.
+const FLOWS_LIMIT: usize = 100;
+
+/// A row of the flows table. The flow is a signal, so that the row is updated
+/// when the flow changes, e.g. when it's annotated.
+#[derive(Clone)]
+struct FlowRow {
+    flow_id: FlowId,
+    flow: RwSignal<Flow>,
+}
+
+impl FlowRow {
+    fn new(flow: Flow) -> Self {
+        Self {
+            flow_id: flow.flow_id,
+            flow: create_rw_signal(flow),
+        }
+    }
+}
+
 #[component]
 pub fn Flows() -> impl IntoView {
+    let Context { mut client, .. } = Context::get();
+    let flows = create_rw_signal(Vec::<FlowRow>::new());
+
+    leptos::spawn_local(async move {
+        let (initial, mut events) = match client.flows(FLOWS_LIMIT).await {
+            Ok(flows) => flows,
+            Err(error) => {
+                tracing::error!(?error, "Could not load flows");
+                return;
+            }
+        };
+
+        // only top-level flows are shown, e.g. not the event streams of HTTP
+        // responses.
+        flows.set(
+            initial
+                .into_iter()
+                .filter(|flow| flow.parent.is_none())
+                .map(FlowRow::new)
+                .collect(),
+        );
+
+        while let Some(event) = events.recv().await {
+            match event {
+                Event::BeginFlow { flow } if flow.parent.is_none() => {
+                    flows.update(|flows| flows.push(FlowRow::new(flow)));
+                }
+                Event::UpdateFlow { flow } => {
+                    flows.with_untracked(|flows| {
+                        if let Some(row) = flows.iter().find(|row| row.flow_id == flow.flow_id) {
+                            row.flow.set(flow);
+                        }
+                    });
+                }
+                _ => {}
+            }
+        }
+    });
+
     view! {
         <div class=style::flows>
             <table>
@@ -20,33 +96,39 @@ pub fn Flows() -> impl IntoView {
                         <th scope="col"></th>
                         <th scope="col">"Timestamp"</th>
                         <th scope="col">"Protocol"</th>
-                        <th scope="col">"Source"</th>
                         <th scope="col">"Destination"</th>
-                        <th scope="col">"Info"</th>
+                        <th scope="col">"Annotations"</th>
                     </tr>
                 </thead>
                 <tbody>
                     <For
-                        each=move || (0..10)
-                        key=|x| *x
-                        children=move |i| {
+                        each=move || flows.get()
+                        key=|row| row.flow_id
+                        children=move |row| {
                             let expanded = create_rw_signal(false);
+                            let flow = row.flow;
+                            let destination = move || {
+                                flow.with(|flow| flow.metadata.get::<String>("destination").ok().flatten())
+                            };
+                            let annotations = move || {
+                                let annotations = flow.with(|flow| flow.metadata.annotations().unwrap_or_default());
+                                view! { <FlowAnnotations annotations /> }
+                            };
                             view! {
                                 <tr class=style::entry>
                                     <td><ExpandButton expanded /></td>
-                                    <td>"Fri Jul  5 06:47:49 AM CEST 2024"</td>
-                                    <td>"https"</td>
-                                    <td>"localhost:12345"</td>
-                                    <td>"maia.crimew.gay:443"</td>
-                                    <td>"GET https://maia.crimew.gay/posts/" {i}</td>
+                                    <td>{move || flow.with(|flow| flow.timestamp.to_rfc2822())}</td>
+                                    <td>{move || flow.with(|flow| flow.protocol.clone())}</td>
+                                    <td>{destination}</td>
+                                    <td>{annotations}</td>
                                 </tr>
                                 <tr
                                     class=style::info
                                 >
-                                    <td colspan="6">
+                                    <td colspan="5">
                                         <div class=style::expander data-expanded=expanded>
                                             <div class=style::expander_content>
-                                                "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nostrud exercitation ullamco laboris nisi ut aliquip ex ea commodo consequat. Duis aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugiat nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culpa qui officia deserunt mollit anim id est laborum."
+                                                {row.flow_id.0.to_string()}
                                             </div>
                                         </div>
                                     </td>
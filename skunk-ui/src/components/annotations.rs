@@ -0,0 +1,40 @@
+use leptos::{
+    component,
+    view,
+    CollectView,
+    IntoView,
+};
+use skunk_api_protocol::flow::Annotations;
+
+stylance::import_crate_style!(style, "src/components/annotations.module.scss");
+
+/// Shows the marker, tags and comment of a flow.
+///
+/// The marker is shown as a dot, with the marker as its color.
+#[component]
+pub fn FlowAnnotations(annotations: Annotations) -> impl IntoView {
+    let Annotations {
+        tags,
+        marker,
+        comment,
+    } = annotations;
+
+    view! {
+        <span class=style::annotations>
+            {marker.map(|marker| {
+                view! {
+                    <span class=style::marker style:background-color=marker.clone() title=marker></span>
+                }
+            })}
+            {tags
+                .into_iter()
+                .map(|tag| view! { <span class=style::tag>{tag}</span> })
+                .collect_view()}
+            {comment.map(|comment| {
+                view! {
+                    <span class=style::comment title=comment.clone()>{comment}</span>
+                }
+            })}
+        </span>
+    }
+}
@@ -1,3 +1,4 @@
+pub mod annotations;
 pub mod command_menu;
 pub mod dock;
 pub mod expand_button;
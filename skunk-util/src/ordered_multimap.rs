@@ -305,6 +305,28 @@ impl<K: PartialEq, V: PartialEq, H> PartialEq for OrderedMultiMap<K, V, H> {
     }
 }
 
+/// Serializes the map as a sequence of key-value pairs, preserving their
+/// order.
+#[cfg(feature = "serde")]
+impl<K: serde::Serialize, V: serde::Serialize, H> serde::Serialize for OrderedMultiMap<K, V, H> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V> serde::Deserialize<'de> for OrderedMultiMap<K, V, AHasher>
+where
+    K: serde::Deserialize<'de> + Hash + Eq,
+    V: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
 pub enum Entry<'a, K, V, H> {
     Occupied(OccupiedEntry<'a, K, V, H>),
     Vacant(VacantEntry<'a, K, V, H>),
@@ -5,6 +5,10 @@ use std::{
     cmp::Ordering,
     convert::Infallible,
     fmt::Display,
+    hash::{
+        Hash,
+        Hasher,
+    },
     net::{
         IpAddr,
         Ipv4Addr,
@@ -207,7 +211,7 @@ impl<'de> Deserialize<'de> for UdpAddress {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Ports {
     Single(Port),
     /// A range of ports. Represented in string form by `min .. max`. We use
@@ -225,6 +229,10 @@ impl Ports {
             Self::Range { min, max } => min.number..=max.number,
         }
     }
+
+    pub fn contains(&self, port: u16) -> bool {
+        self.range().contains(&port)
+    }
 }
 
 impl From<u16> for Ports {
@@ -307,6 +315,28 @@ impl Serialize for Ports {
     }
 }
 
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Ports {
+    fn schema_name() -> String {
+        "Ports".to_owned()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        // a port number, a service name, or a `..` separated range of these.
+        schemars::schema::SchemaObject {
+            subschemas: Some(Box::new(schemars::schema::SubschemaValidation {
+                any_of: Some(vec![
+                    gen.subschema_for::<u16>(),
+                    gen.subschema_for::<String>(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Port {
     /// Service name as defined by [1].
@@ -334,6 +364,12 @@ impl PartialEq for Port {
 
 impl Eq for Port {}
 
+impl Hash for Port {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.number.hash(state);
+    }
+}
+
 impl PartialOrd for Port {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
@@ -35,4 +35,20 @@ pub enum Error {
     #[cfg(feature = "http")]
     #[error("http error")]
     Http(#[from] self::protocol::http::Error),
+
+    #[cfg(feature = "http")]
+    #[error("could not modify message")]
+    Modify(#[from] self::rule::modify::Error),
+
+    #[cfg(feature = "http")]
+    #[error("could not map request")]
+    Map(#[from] self::rule::map::Error),
+
+    #[cfg(feature = "http")]
+    #[error("could not mock response")]
+    Mock(#[from] self::rule::mock::Error),
+
+    #[cfg(feature = "script")]
+    #[error("could not run script")]
+    Script(#[from] self::rule::script::Error),
 }
@@ -1,5 +1,13 @@
 use std::{
     convert::Infallible,
+    fs::File,
+    future::Future,
+    io::{
+        Read as _,
+        Seek,
+        SeekFrom,
+        Write,
+    },
     pin::Pin,
     task::{
         Context,
@@ -11,15 +19,25 @@ use bytes::{
     Bytes,
     BytesMut,
 };
-use hyper::body::Frame;
+use http_body_util::BodyExt;
 pub use hyper::body::{
     Body,
     Incoming,
 };
+use hyper::{
+    body::{
+        Frame,
+        SizeHint,
+    },
+    HeaderMap,
+};
 use pin_project_lite::pin_project;
-use tokio::io::{
-    AsyncRead,
-    ReadBuf,
+use tokio::{
+    io::{
+        AsyncRead,
+        ReadBuf,
+    },
+    sync::oneshot,
 };
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -88,3 +106,590 @@ impl<R: AsyncRead> Body for Read<R> {
         })
     }
 }
+
+pin_project! {
+    /// Body that either forwards the original body, or replaces it with other
+    /// data.
+    ///
+    /// If the start of the original body was already read, e.g. by
+    /// [`collect_limited`], it's sent before the rest of the original body.
+    #[derive(Debug)]
+    #[project = ReplaceProj]
+    pub enum Replace<B> {
+        Original {
+            #[pin]
+            inner: B,
+        },
+        Replaced {
+            data: Option<Bytes>,
+        },
+        Prefixed {
+            prefix: Option<Bytes>,
+            #[pin]
+            inner: B,
+        },
+    }
+}
+
+impl<B> Replace<B> {
+    pub fn original(inner: B) -> Self {
+        Self::Original { inner }
+    }
+
+    pub fn replaced(data: impl Into<Bytes>) -> Self {
+        Self::Replaced {
+            data: Some(data.into()),
+        }
+    }
+
+    pub fn prefixed(prefix: impl Into<Bytes>, inner: B) -> Self {
+        let prefix = prefix.into();
+        Self::Prefixed {
+            prefix: (!prefix.is_empty()).then_some(prefix),
+            inner,
+        }
+    }
+}
+
+impl<B> Body for Replace<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project() {
+            ReplaceProj::Original { inner } => inner.poll_frame(cx),
+            ReplaceProj::Replaced { data } => {
+                Poll::Ready(data.take().map(|data| Ok(Frame::data(data))))
+            }
+            ReplaceProj::Prefixed { prefix, inner } => {
+                if let Some(prefix) = prefix.take() {
+                    Poll::Ready(Some(Ok(Frame::data(prefix))))
+                }
+                else {
+                    inner.poll_frame(cx)
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            Self::Original { inner } => inner.is_end_stream(),
+            Self::Replaced { data } => data.is_none(),
+            Self::Prefixed { prefix, inner } => prefix.is_none() && inner.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            Self::Original { inner } => inner.size_hint(),
+            Self::Replaced { data } => {
+                SizeHint::with_exact(data.as_ref().map_or(0, |data| data.len() as u64))
+            }
+            Self::Prefixed { prefix, inner } => {
+                let prefix = prefix.as_ref().map_or(0, |prefix| prefix.len() as u64);
+                let inner = inner.size_hint();
+                let mut hint = SizeHint::new();
+                hint.set_lower(inner.lower().saturating_add(prefix));
+                if let Some(upper) = inner.upper() {
+                    hint.set_upper(upper.saturating_add(prefix));
+                }
+                hint
+            }
+        }
+    }
+}
+
+/// A body read by [`collect_limited`].
+#[derive(Debug)]
+pub enum Collected<B> {
+    /// The whole body.
+    Complete(Bytes),
+
+    /// The body was larger than the limit. This forwards the bytes that were
+    /// already read, followed by the rest of the body.
+    Exceeded(Replace<B>),
+}
+
+/// Reads a body into memory, unless it's larger than `limit`.
+///
+/// Trailers are discarded, like with [`BodyExt::collect`].
+pub async fn collect_limited<B>(body: Replace<B>, limit: usize) -> Result<Collected<B>, B::Error>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    let (mut buf, mut inner) = match body {
+        Replace::Original { inner } => (BytesMut::new(), inner),
+        Replace::Replaced { data } => return Ok(Collected::Complete(data.unwrap_or_default())),
+        Replace::Prefixed { prefix, inner } => {
+            (prefix.map(BytesMut::from).unwrap_or_default(), inner)
+        }
+    };
+
+    while let Some(frame) = inner.frame().await {
+        if let Ok(data) = frame?.into_data() {
+            buf.extend_from_slice(&data);
+            if buf.len() > limit {
+                return Ok(Collected::Exceeded(Replace::prefixed(buf.freeze(), inner)));
+            }
+        }
+    }
+
+    Ok(Collected::Complete(buf.freeze()))
+}
+
+/// Limits for capturing a body with [`Tee`].
+#[derive(Clone, Copy, Debug)]
+pub struct CaptureLimits {
+    /// Maximum number of bytes that are kept in memory.
+    pub memory_limit: usize,
+
+    /// What to do with bytes beyond `memory_limit`.
+    pub overflow: Overflow,
+}
+
+impl Default for CaptureLimits {
+    fn default() -> Self {
+        Self {
+            memory_limit: 1024 * 1024,
+            overflow: Overflow::Spill {
+                file_limit: Some(256 * 1024 * 1024),
+            },
+        }
+    }
+}
+
+impl CaptureLimits {
+    /// Returns the maximum number of bytes that are captured, or `None` if
+    /// there is no limit.
+    pub fn max_size(&self) -> Option<u64> {
+        match self.overflow {
+            Overflow::Truncate => Some(self.memory_limit as u64),
+            Overflow::Spill { file_limit } => file_limit,
+        }
+    }
+}
+
+/// What to do when a captured body exceeds [`CaptureLimits::memory_limit`].
+#[derive(Clone, Copy, Debug)]
+pub enum Overflow {
+    /// Stop capturing and mark the capture as truncated.
+    Truncate,
+
+    /// Move the captured bytes into a temporary file and continue capturing
+    /// there. If `file_limit` is set, the capture is truncated once the file
+    /// reaches that size.
+    Spill { file_limit: Option<u64> },
+}
+
+pin_project! {
+    /// Body wrapper that forwards all frames of the inner body immediately,
+    /// while copying them into a capture buffer.
+    ///
+    /// The captured body is sent to the [`CaptureReceiver`] returned by
+    /// [`Tee::new`], once the stream ends. If the [`Tee`] is dropped before
+    /// that, the partial capture is sent with [`Captured::complete`] set to
+    /// `false`.
+    #[derive(Debug)]
+    pub struct Tee<B> {
+        #[pin]
+        inner: B,
+        capture: Option<Capture>,
+    }
+}
+
+impl<B> Tee<B> {
+    pub fn new(inner: B, limits: CaptureLimits) -> (Self, CaptureReceiver) {
+        let (tx, rx) = oneshot::channel();
+        let tee = Self {
+            inner,
+            capture: Some(Capture::new(limits, tx)),
+        };
+        (tee, CaptureReceiver { rx })
+    }
+
+    /// Wraps `inner` without capturing it.
+    pub fn passthrough(inner: B) -> Self {
+        Self {
+            inner,
+            capture: None,
+        }
+    }
+}
+
+impl<B> Body for Tee<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some(capture) = this.capture {
+                    if let Some(data) = frame.data_ref() {
+                        capture.push_data(data);
+                    }
+                    else if let Some(trailers) = frame.trailers_ref() {
+                        capture.push_trailers(trailers);
+                    }
+                }
+            }
+            Poll::Ready(Some(Err(_))) => {
+                if let Some(capture) = this.capture.take() {
+                    capture.finish(false);
+                }
+            }
+            Poll::Ready(None) => {
+                if let Some(capture) = this.capture.take() {
+                    capture.finish(true);
+                }
+            }
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Future that resolves to the [`Captured`] body, once the [`Tee`] finished.
+#[derive(Debug)]
+pub struct CaptureReceiver {
+    rx: oneshot::Receiver<Captured>,
+}
+
+impl Future for CaptureReceiver {
+    type Output = Option<Captured>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.rx).poll(cx).map(Result::ok)
+    }
+}
+
+/// A body captured by [`Tee`].
+#[derive(Debug)]
+pub struct Captured {
+    /// The captured bytes.
+    pub data: CapturedData,
+
+    /// Total size of the body, including bytes that were not captured.
+    pub size: u64,
+
+    /// Whether the captured data was truncated because of the
+    /// [`CaptureLimits`].
+    pub truncated: bool,
+
+    /// Whether the body stream ended normally. This is `false` if the body
+    /// returned an error, or was dropped before it ended.
+    pub complete: bool,
+
+    /// Trailers, if the body had any.
+    pub trailers: Option<HeaderMap>,
+}
+
+#[derive(Debug)]
+pub enum CapturedData {
+    Memory(Bytes),
+    File { file: File, len: u64 },
+}
+
+impl CapturedData {
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Memory(bytes) => bytes.len() as u64,
+            Self::File { len, .. } => *len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the captured bytes. If the body was spilled to a file, this
+    /// reads the whole file with blocking IO.
+    pub fn into_bytes(self) -> Result<Bytes, std::io::Error> {
+        match self {
+            Self::Memory(bytes) => Ok(bytes),
+            Self::File { mut file, len } => {
+                let mut buf = Vec::with_capacity(len.try_into().unwrap_or_default());
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut buf)?;
+                Ok(buf.into())
+            }
+        }
+    }
+
+    /// Returns at most `limit` of the captured bytes, and whether there were
+    /// more. If the body was spilled to a file, this reads the file with
+    /// blocking IO.
+    pub fn read(self, limit: usize) -> Result<(Bytes, bool), std::io::Error> {
+        let truncated = self.len() > limit as u64;
+        match self {
+            Self::Memory(mut bytes) => {
+                bytes.truncate(limit);
+                Ok((bytes, truncated))
+            }
+            Self::File { mut file, len } => {
+                let mut buf =
+                    Vec::with_capacity(len.min(limit as u64).try_into().unwrap_or_default());
+                file.seek(SeekFrom::Start(0))?;
+                file.take(limit as u64).read_to_end(&mut buf)?;
+                Ok((buf.into(), truncated))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Capture {
+    limits: CaptureLimits,
+    buffer: CaptureBuffer,
+    size: u64,
+    truncated: bool,
+    trailers: Option<HeaderMap>,
+    tx: Option<oneshot::Sender<Captured>>,
+}
+
+#[derive(Debug)]
+enum CaptureBuffer {
+    Memory(BytesMut),
+    File { file: File, len: u64 },
+}
+
+impl Capture {
+    fn new(limits: CaptureLimits, tx: oneshot::Sender<Captured>) -> Self {
+        Self {
+            limits,
+            buffer: CaptureBuffer::Memory(BytesMut::new()),
+            size: 0,
+            truncated: false,
+            trailers: None,
+            tx: Some(tx),
+        }
+    }
+
+    fn push_data(&mut self, data: &[u8]) {
+        self.size += data.len() as u64;
+
+        if self.truncated {
+            return;
+        }
+
+        match &mut self.buffer {
+            CaptureBuffer::Memory(buf) => {
+                let available = self.limits.memory_limit.saturating_sub(buf.len());
+                if data.len() <= available {
+                    buf.extend_from_slice(data);
+                }
+                else {
+                    match self.limits.overflow {
+                        Overflow::Truncate => {
+                            buf.extend_from_slice(&data[..available]);
+                            self.truncated = true;
+                        }
+                        Overflow::Spill { .. } => {
+                            // note: this uses blocking IO, but since the file is buffered by the
+                            // OS, this should be fine for now.
+                            match spill(buf) {
+                                Ok(file) => {
+                                    self.buffer = CaptureBuffer::File {
+                                        file,
+                                        len: buf.len() as u64,
+                                    };
+                                    self.push_file(data);
+                                }
+                                Err(error) => {
+                                    tracing::warn!(?error, "could not spill captured body to file");
+                                    buf.extend_from_slice(&data[..available]);
+                                    self.truncated = true;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            CaptureBuffer::File { .. } => self.push_file(data),
+        }
+    }
+
+    fn push_file(&mut self, mut data: &[u8]) {
+        let CaptureBuffer::File { file, len } = &mut self.buffer
+        else {
+            unreachable!("capture buffer is not a file");
+        };
+
+        if let Overflow::Spill {
+            file_limit: Some(file_limit),
+        } = self.limits.overflow
+        {
+            let available = usize::try_from(file_limit.saturating_sub(*len)).unwrap_or(usize::MAX);
+            if data.len() > available {
+                data = &data[..available];
+                self.truncated = true;
+            }
+        }
+
+        if let Err(error) = file.write_all(data) {
+            tracing::warn!(?error, "could not write captured body to file");
+            self.truncated = true;
+        }
+        else {
+            *len += data.len() as u64;
+        }
+    }
+
+    fn push_trailers(&mut self, trailers: &HeaderMap) {
+        self.trailers.get_or_insert_with(Default::default).extend(
+            trailers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+    }
+
+    fn take_captured(&mut self, complete: bool) -> Captured {
+        let data = match std::mem::replace(&mut self.buffer, CaptureBuffer::Memory(BytesMut::new()))
+        {
+            CaptureBuffer::Memory(buf) => CapturedData::Memory(buf.freeze()),
+            CaptureBuffer::File { file, len } => CapturedData::File { file, len },
+        };
+
+        Captured {
+            data,
+            size: self.size,
+            truncated: self.truncated,
+            complete,
+            trailers: self.trailers.take(),
+        }
+    }
+
+    fn finish(mut self, complete: bool) {
+        if let Some(tx) = self.tx.take() {
+            // if the receiver was dropped, nobody is interested in the captured body.
+            let _ = tx.send(self.take_captured(complete));
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(self.take_captured(false));
+        }
+    }
+}
+
+/// Moves the bytes from `buf` into a new temporary file.
+fn spill(buf: &mut BytesMut) -> Result<File, std::io::Error> {
+    let mut file = tempfile::tempfile()?;
+    file.write_all(buf)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use http_body_util::{
+        BodyExt,
+        StreamBody,
+    };
+    use hyper::body::Frame;
+
+    use super::{
+        CaptureLimits,
+        Captured,
+        CapturedData,
+        Overflow,
+        Tee,
+    };
+
+    /// Sends a body with multiple frames through a [`Tee`], and returns the
+    /// forwarded body and what was captured.
+    fn tee(memory_limit: usize, overflow: Overflow) -> (Bytes, Captured) {
+        let frames =
+            ["hello", " ", "world"].map(|data| Ok::<_, Infallible>(Frame::data(Bytes::from(data))));
+        let body = StreamBody::new(futures::stream::iter(frames));
+        let (body, captured) = Tee::new(
+            body,
+            CaptureLimits {
+                memory_limit,
+                overflow,
+            },
+        );
+        block_on(async {
+            let forwarded = body.collect().await.unwrap().to_bytes();
+            (forwarded, captured.await.unwrap())
+        })
+    }
+
+    #[test]
+    fn it_captures_bodies_in_memory() {
+        let (forwarded, captured) = tee(1024, Overflow::Truncate);
+        assert_eq!(forwarded, "hello world");
+        assert!(matches!(&captured.data, CapturedData::Memory(data) if data == "hello world"));
+        assert_eq!(captured.size, 11);
+        assert!(!captured.truncated);
+        assert!(captured.complete);
+    }
+
+    #[test]
+    fn it_truncates_at_the_memory_limit() {
+        let (forwarded, captured) = tee(4, Overflow::Truncate);
+        assert_eq!(forwarded, "hello world");
+        assert!(matches!(&captured.data, CapturedData::Memory(data) if data == "hell"));
+        assert_eq!(captured.size, 11);
+        assert!(captured.truncated);
+        assert!(captured.complete);
+    }
+
+    #[test]
+    fn it_spills_to_a_file() {
+        let (forwarded, captured) = tee(4, Overflow::Spill { file_limit: None });
+        assert_eq!(forwarded, "hello world");
+        assert!(matches!(captured.data, CapturedData::File { len: 11, .. }));
+        assert!(!captured.truncated);
+        let (data, truncated) = captured.data.read(1024).unwrap();
+        assert_eq!(data, "hello world");
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn it_stops_spilling_at_the_file_limit() {
+        let (forwarded, captured) = tee(
+            4,
+            Overflow::Spill {
+                file_limit: Some(8),
+            },
+        );
+        assert_eq!(forwarded, "hello world");
+        assert!(matches!(captured.data, CapturedData::File { len: 8, .. }));
+        assert_eq!(captured.size, 11);
+        assert!(captured.truncated);
+        let (data, truncated) = captured.data.read(5).unwrap();
+        assert_eq!(data, "hello");
+        assert!(truncated);
+    }
+}
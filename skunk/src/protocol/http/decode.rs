@@ -0,0 +1,523 @@
+//! Decoding of HTTP bodies into structured values.
+//!
+//! A [`Registry`] maps content types to [`Decoder`]s. The default registry
+//! decodes JSON, MessagePack, CBOR, `application/x-www-form-urlencoded` and
+//! `multipart/form-data`. Decoded bodies are represented as JSON values, so
+//! that fields can be addressed uniformly (e.g. `body.user.id`).
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use mime::Mime;
+pub use serde_json::Value;
+use serde_json::{
+    Map,
+    Number,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid JSON")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid MessagePack")]
+    MessagePack(#[from] rmpv::decode::Error),
+
+    #[error("invalid CBOR")]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+
+    #[error("invalid multipart body: {0}")]
+    Multipart(&'static str),
+
+    #[cfg(feature = "protobuf")]
+    #[error("invalid protobuf")]
+    Protobuf(#[from] crate::protocol::protobuf::DecodeError),
+}
+
+/// A decoded body.
+#[derive(Clone, Debug, Default)]
+pub struct Decoded {
+    pub value: Value,
+
+    /// Parts of a multipart body. These should be stored separately from the
+    /// decoded value.
+    pub parts: Vec<Part>,
+}
+
+impl From<Value> for Decoded {
+    fn from(value: Value) -> Self {
+        Self {
+            value,
+            parts: vec![],
+        }
+    }
+}
+
+/// A part of a multipart body.
+#[derive(Clone, Debug)]
+pub struct Part {
+    pub name: Option<String>,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+/// Decodes a body of a specific content type.
+pub trait Decoder: Send + Sync + 'static {
+    /// Decodes `data`. `registry` can be used to decode nested bodies, e.g.
+    /// parts of a multipart body.
+    fn decode(
+        &self,
+        content_type: &Mime,
+        data: &[u8],
+        registry: &Registry,
+    ) -> Result<Decoded, Error>;
+}
+
+/// Decoders keyed by content type.
+///
+/// Decoders are looked up by the essence of the content type (e.g.
+/// `application/json`), and then by its structured syntax suffix (e.g.
+/// `+json` for `application/ld+json`).
+#[derive(Clone)]
+pub struct Registry {
+    decoders: HashMap<String, Arc<dyn Decoder>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let registry = Self::empty()
+            .with_decoder("application/json", Json)
+            .with_decoder("text/json", Json)
+            .with_decoder("+json", Json)
+            .with_decoder("application/msgpack", MessagePack)
+            .with_decoder("application/x-msgpack", MessagePack)
+            .with_decoder("application/vnd.msgpack", MessagePack)
+            .with_decoder("+msgpack", MessagePack)
+            .with_decoder("application/cbor", Cbor)
+            .with_decoder("+cbor", Cbor)
+            .with_decoder("application/x-www-form-urlencoded", FormUrlEncoded)
+            .with_decoder("multipart/form-data", Multipart);
+
+        #[cfg(feature = "protobuf")]
+        let registry = registry
+            .with_decoder("application/protobuf", Protobuf)
+            .with_decoder("application/x-protobuf", Protobuf)
+            .with_decoder("+proto", Protobuf);
+
+        registry
+    }
+}
+
+impl Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registry")
+            .field("content_types", &self.decoders.keys())
+            .finish()
+    }
+}
+
+impl Registry {
+    /// Creates a registry without any decoders. Use [`Registry::default`] to
+    /// get one with the built-in decoders.
+    pub fn empty() -> Self {
+        Self {
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Registers `decoder` for `content_type`, which is either the essence of
+    /// a content type or a suffix like `+json`.
+    pub fn with_decoder(mut self, content_type: &str, decoder: impl Decoder) -> Self {
+        self.register(content_type, decoder);
+        self
+    }
+
+    pub fn register(&mut self, content_type: &str, decoder: impl Decoder) {
+        self.decoders
+            .insert(content_type.to_ascii_lowercase(), Arc::new(decoder));
+    }
+
+    fn get(&self, content_type: &Mime) -> Option<&dyn Decoder> {
+        let essence = content_type.essence_str().to_ascii_lowercase();
+        self.decoders
+            .get(&essence)
+            .or_else(|| {
+                let suffix = content_type.suffix()?;
+                self.decoders
+                    .get(&format!("+{}", suffix.as_str().to_ascii_lowercase()))
+            })
+            .map(|decoder| &**decoder)
+    }
+
+    /// Decodes `data` with the decoder for `content_type`. Returns `None` if
+    /// there's no decoder for it.
+    pub fn decode(&self, content_type: &str, data: &[u8]) -> Option<Result<Decoded, Error>> {
+        let content_type: Mime = content_type.parse().ok()?;
+        let decoder = self.get(&content_type)?;
+        Some(decoder.decode(&content_type, data, self))
+    }
+}
+
+/// Decoder for JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl Decoder for Json {
+    fn decode(&self, _: &Mime, data: &[u8], _: &Registry) -> Result<Decoded, Error> {
+        Ok(serde_json::from_slice::<Value>(data)?.into())
+    }
+}
+
+/// Decoder for MessagePack.
+///
+/// Binary data is decoded as an array of bytes, map keys that aren't strings
+/// are formatted as strings.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+impl Decoder for MessagePack {
+    fn decode(&self, _: &Mime, mut data: &[u8], _: &Registry) -> Result<Decoded, Error> {
+        fn convert(value: rmpv::Value) -> Value {
+            match value {
+                rmpv::Value::Nil => Value::Null,
+                rmpv::Value::Boolean(value) => Value::Bool(value),
+                rmpv::Value::Integer(value) => {
+                    value
+                        .as_u64()
+                        .map(Number::from)
+                        .or_else(|| value.as_i64().map(Number::from))
+                        .map_or(Value::Null, Value::Number)
+                }
+                rmpv::Value::F32(value) => float(value.into()),
+                rmpv::Value::F64(value) => float(value),
+                rmpv::Value::String(value) => {
+                    Value::String(String::from_utf8_lossy(value.as_bytes()).into_owned())
+                }
+                rmpv::Value::Binary(value) => bytes(&value),
+                rmpv::Value::Array(values) => {
+                    Value::Array(values.into_iter().map(convert).collect())
+                }
+                rmpv::Value::Map(entries) => {
+                    Value::Object(
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| {
+                                let key = match key {
+                                    rmpv::Value::String(key) => {
+                                        String::from_utf8_lossy(key.as_bytes()).into_owned()
+                                    }
+                                    key => key.to_string(),
+                                };
+                                (key, convert(value))
+                            })
+                            .collect(),
+                    )
+                }
+                rmpv::Value::Ext(ty, data) => {
+                    let mut object = Map::new();
+                    object.insert("ext".to_owned(), ty.into());
+                    object.insert("data".to_owned(), bytes(&data));
+                    Value::Object(object)
+                }
+            }
+        }
+
+        let value = rmpv::decode::read_value(&mut data)?;
+        Ok(convert(value).into())
+    }
+}
+
+/// Decoder for CBOR.
+///
+/// Binary data is decoded as an array of bytes, tags are dropped, and map keys
+/// that aren't strings are formatted as JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+impl Decoder for Cbor {
+    fn decode(&self, _: &Mime, data: &[u8], _: &Registry) -> Result<Decoded, Error> {
+        fn convert(value: ciborium::Value) -> Value {
+            match value {
+                ciborium::Value::Integer(value) => {
+                    let value = i128::from(value);
+                    u64::try_from(value)
+                        .map(Number::from)
+                        .or_else(|_| i64::try_from(value).map(Number::from))
+                        .map_or_else(|_| Value::String(value.to_string()), Value::Number)
+                }
+                ciborium::Value::Bytes(value) => bytes(&value),
+                ciborium::Value::Float(value) => float(value),
+                ciborium::Value::Text(value) => Value::String(value),
+                ciborium::Value::Bool(value) => Value::Bool(value),
+                ciborium::Value::Null => Value::Null,
+                ciborium::Value::Tag(_, value) => convert(*value),
+                ciborium::Value::Array(values) => {
+                    Value::Array(values.into_iter().map(convert).collect())
+                }
+                ciborium::Value::Map(entries) => {
+                    Value::Object(
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| {
+                                let key = match convert(key) {
+                                    Value::String(key) => key,
+                                    key => key.to_string(),
+                                };
+                                (key, convert(value))
+                            })
+                            .collect(),
+                    )
+                }
+                _ => Value::Null,
+            }
+        }
+
+        let value: ciborium::Value = ciborium::from_reader(data)?;
+        Ok(convert(value).into())
+    }
+}
+
+/// Decoder for `application/x-www-form-urlencoded`.
+///
+/// Fields are decoded into an object. Repeated fields are collected into an
+/// array.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FormUrlEncoded;
+
+impl Decoder for FormUrlEncoded {
+    fn decode(&self, _: &Mime, data: &[u8], _: &Registry) -> Result<Decoded, Error> {
+        let mut object = Map::new();
+        for (name, value) in url::form_urlencoded::parse(data) {
+            insert_field(&mut object, name.into_owned(), value.into_owned().into());
+        }
+        Ok(Value::Object(object).into())
+    }
+}
+
+/// Decoder for `multipart/form-data`.
+///
+/// Fields are decoded into an object. Parts that have a content type with a
+/// decoder in the registry are decoded, other parts are decoded as strings.
+/// Files are only described by their name, content type and size. All parts
+/// are returned in [`Decoded::parts`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Multipart;
+
+impl Decoder for Multipart {
+    fn decode(
+        &self,
+        content_type: &Mime,
+        data: &[u8],
+        registry: &Registry,
+    ) -> Result<Decoded, Error> {
+        let boundary = content_type
+            .get_param(mime::BOUNDARY)
+            .ok_or(Error::Multipart("missing boundary"))?;
+        let parts = parse_multipart(data, boundary.as_str().as_bytes())?;
+
+        let mut object = Map::new();
+        for (i, part) in parts.iter().enumerate() {
+            let value = if let Some(file_name) = &part.file_name {
+                let mut file = Map::new();
+                file.insert("file-name".to_owned(), file_name.clone().into());
+                if let Some(content_type) = &part.content_type {
+                    file.insert("content-type".to_owned(), content_type.clone().into());
+                }
+                file.insert("size".to_owned(), part.data.len().into());
+                Value::Object(file)
+            }
+            else {
+                part.content_type
+                    .as_ref()
+                    .and_then(|content_type| registry.decode(content_type, &part.data))
+                    .and_then(Result::ok)
+                    .map(|decoded| decoded.value)
+                    .unwrap_or_else(|| String::from_utf8_lossy(&part.data).into_owned().into())
+            };
+
+            let name = part.name.clone().unwrap_or_else(|| i.to_string());
+            insert_field(&mut object, name, value);
+        }
+
+        Ok(Decoded {
+            value: Value::Object(object),
+            parts,
+        })
+    }
+}
+
+/// Decoder for protobuf messages without a schema.
+///
+/// Fields are decoded into an object keyed by field number. See
+/// [`protobuf::decode_schemaless`](crate::protocol::protobuf::decode_schemaless).
+#[cfg(feature = "protobuf")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Protobuf;
+
+#[cfg(feature = "protobuf")]
+impl Decoder for Protobuf {
+    fn decode(&self, _: &Mime, data: &[u8], _: &Registry) -> Result<Decoded, Error> {
+        use crate::protocol::protobuf;
+
+        fn convert(fields: Vec<protobuf::Field>) -> Value {
+            let mut object = Map::new();
+            for field in fields {
+                let value = match field.value {
+                    protobuf::Value::Varint(value) | protobuf::Value::Fixed64(value) => {
+                        value.into()
+                    }
+                    protobuf::Value::Fixed32(value) => value.into(),
+                    protobuf::Value::String(value) => value.into(),
+                    protobuf::Value::Bytes(value) => bytes(&value),
+                    protobuf::Value::Message(fields) | protobuf::Value::Group(fields) => {
+                        convert(fields)
+                    }
+                };
+                insert_field(&mut object, field.number.to_string(), value);
+            }
+            Value::Object(object)
+        }
+
+        Ok(convert(protobuf::decode_schemaless(data)?).into())
+    }
+}
+
+fn parse_multipart(data: &[u8], boundary: &[u8]) -> Result<Vec<Part>, Error> {
+    let delimiter = [b"--", boundary].concat();
+    let mut parts = vec![];
+
+    // skip preamble
+    let start = find(data, &delimiter).ok_or(Error::Multipart("missing delimiter"))?;
+    let mut rest = &data[start + delimiter.len()..];
+
+    // parts are separated by CRLF followed by the delimiter
+    let delimiter = [b"\r\n", &delimiter[..]].concat();
+
+    loop {
+        if rest.starts_with(b"--") {
+            // close delimiter
+            break;
+        }
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or(Error::Multipart("expected line break after delimiter"))?;
+
+        let end = find(rest, &delimiter).ok_or(Error::Multipart("unterminated part"))?;
+        let part = &rest[..end];
+        rest = &rest[end + delimiter.len()..];
+
+        let (headers, body) = match find(part, b"\r\n\r\n") {
+            Some(i) => (&part[..i], &part[i + 4..]),
+            None if part.starts_with(b"\r\n") => (&b""[..], &part[2..]),
+            None => return Err(Error::Multipart("missing part headers")),
+        };
+
+        let mut name = None;
+        let mut file_name = None;
+        let mut content_type = None;
+
+        for line in String::from_utf8_lossy(headers).split("\r\n") {
+            let Some((header, value)) = line.split_once(':')
+            else {
+                continue;
+            };
+            let value = value.trim();
+
+            if header.trim().eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    let Some((key, value)) = param.split_once('=')
+                    else {
+                        continue;
+                    };
+                    let value = value.trim().trim_matches('"').to_owned();
+                    match key.trim().to_ascii_lowercase().as_str() {
+                        "name" => name = Some(value),
+                        "filename" => file_name = Some(value),
+                        _ => {}
+                    }
+                }
+            }
+            else if header.trim().eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.to_owned());
+            }
+        }
+
+        parts.push(Part {
+            name,
+            file_name,
+            content_type,
+            data: Bytes::copy_from_slice(body),
+        });
+    }
+
+    Ok(parts)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn insert_field(object: &mut Map<String, Value>, name: String, value: Value) {
+    match object.get_mut(&name) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+        None => {
+            object.insert(name, value);
+        }
+    }
+}
+
+fn bytes(data: &[u8]) -> Value {
+    Value::Array(data.iter().map(|&b| b.into()).collect())
+}
+
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Registry;
+
+    #[test]
+    fn it_decodes_form_urlencoded() {
+        let decoded = Registry::default()
+            .decode(
+                "application/x-www-form-urlencoded",
+                b"user=alice&tag=a&tag=b",
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.value, json!({"user": "alice", "tag": ["a", "b"]}));
+    }
+
+    #[test]
+    fn it_decodes_multipart() {
+        let body = b"preamble\r\n--xyz\r\nContent-Disposition: form-data; name=\"meta\"\r\nContent-Type: application/json\r\n\r\n{\"id\":1}\r\n--xyz\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nhello\r\n--xyz--\r\n";
+        let decoded = Registry::default()
+            .decode("multipart/form-data; boundary=xyz", body)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            decoded.value,
+            json!({
+                "meta": {"id": 1},
+                "file": {"file-name": "a.txt", "size": 5},
+            })
+        );
+        assert_eq!(decoded.parts.len(), 2);
+        assert_eq!(&decoded.parts[1].data[..], b"hello");
+    }
+}
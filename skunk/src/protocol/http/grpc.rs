@@ -0,0 +1,448 @@
+//! [gRPC][1] message framing.
+//!
+//! gRPC bodies are a sequence of length-prefixed messages. The status of a
+//! call is sent in the `grpc-status` and `grpc-message` trailers.
+//!
+//! gRPC runs over HTTP/2, but [`protocol::http`](super) only speaks HTTP/1.1
+//! so far. This only decodes gRPC bodies that are proxied over HTTP/1.1, and
+//! calls of clients that require HTTP/2 are not seen at all.
+//!
+//! Messages are decoded up to a size limit, and larger messages are
+//! truncated.
+//!
+//! [1]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md
+
+use std::{
+    io::Read,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::SystemTime,
+};
+
+use bytes::{
+    Buf,
+    Bytes,
+    BytesMut,
+};
+use hyper::{
+    body::{
+        Body,
+        Frame,
+        SizeHint,
+    },
+    header,
+    HeaderMap,
+};
+use pin_project_lite::pin_project;
+use tokio::sync::mpsc;
+
+use crate::protocol::protobuf::{
+    Descriptors,
+    MethodDescriptor,
+};
+
+pub const MIME_TYPE: &str = "application/grpc";
+
+/// Returns whether the `Content-Type` in `headers` indicates gRPC.
+///
+/// This matches `application/grpc` with any suffix, e.g.
+/// `application/grpc+proto`.
+pub fn is_grpc(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime_type| {
+            let mime_type = mime_type.trim();
+            mime_type.len() >= MIME_TYPE.len()
+                && mime_type[..MIME_TYPE.len()].eq_ignore_ascii_case(MIME_TYPE)
+                && matches!(mime_type.as_bytes().get(MIME_TYPE.len()), None | Some(b'+'))
+        })
+}
+
+/// Message compression, as specified by the `grpc-encoding` header.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Identity,
+    Gzip,
+    Deflate,
+    Other(String),
+}
+
+impl Encoding {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        match headers
+            .get("grpc-encoding")
+            .and_then(|value| value.to_str().ok())
+        {
+            None | Some("identity") => Self::Identity,
+            Some("gzip") => Self::Gzip,
+            Some("deflate") => Self::Deflate,
+            Some(other) => Self::Other(other.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecompressError {
+    #[error("unsupported encoding: {0}")]
+    Unsupported(String),
+
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+}
+
+/// A single length-prefixed message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// Whether the compressed flag was set.
+    pub compressed: bool,
+
+    /// The message as it was sent, i.e. possibly compressed.
+    pub data: Bytes,
+
+    /// Size of the message as it was sent, including bytes that were not
+    /// decoded.
+    pub size: usize,
+
+    /// Whether `data` was truncated because the message exceeded the size
+    /// limit of the [`Decoder`].
+    pub truncated: bool,
+}
+
+impl Message {
+    /// Returns the decompressed message, and whether it was truncated.
+    ///
+    /// At most `limit` bytes are decompressed.
+    pub fn decompress(
+        &self,
+        encoding: &Encoding,
+        limit: usize,
+    ) -> Result<(Bytes, bool), DecompressError> {
+        if !self.compressed {
+            return Ok((self.data.clone(), self.truncated));
+        }
+
+        // read one byte more than the limit, to know if the message was truncated.
+        let take = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+        let mut buf = vec![];
+        match encoding {
+            Encoding::Identity => return Ok((self.data.clone(), self.truncated)),
+            Encoding::Gzip => {
+                flate2::read::GzDecoder::new(&self.data[..])
+                    .take(take)
+                    .read_to_end(&mut buf)?;
+            }
+            Encoding::Deflate => {
+                flate2::read::ZlibDecoder::new(&self.data[..])
+                    .take(take)
+                    .read_to_end(&mut buf)?;
+            }
+            Encoding::Other(other) => return Err(DecompressError::Unsupported(other.clone())),
+        }
+
+        let truncated = buf.len() > limit;
+        buf.truncate(limit);
+        Ok((buf.into(), truncated))
+    }
+}
+
+/// Status of a call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    pub code: u32,
+    pub message: Option<String>,
+}
+
+impl Status {
+    /// Reads the status from trailers, or from the headers of a
+    /// "Trailers-Only" response.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let code = headers.get("grpc-status")?.to_str().ok()?.parse().ok()?;
+        let message = headers
+            .get("grpc-message")
+            .map(|value| percent_decode(value.as_bytes()));
+        Some(Self { code, message })
+    }
+}
+
+fn percent_decode(input: &[u8]) -> String {
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' && i + 2 < input.len() {
+            if let (Some(high), Some(low)) = (hex(input[i + 1]), hex(input[i + 2])) {
+                output.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        output.push(input[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Incremental decoder for length-prefixed messages.
+///
+/// Feed it chunks of a body with [`push`](Self::push) and take decoded
+/// messages with [`next_message`](Self::next_message).
+///
+/// At most `limit` bytes of a message are buffered. The length prefix of a
+/// message is sent by the peer, so the decoder doesn't allocate for it. Larger
+/// messages are returned truncated as soon as `limit` bytes arrived, and the
+/// rest of them is skipped.
+#[derive(Debug)]
+pub struct Decoder {
+    buf: BytesMut,
+    limit: usize,
+
+    /// Number of bytes of a truncated message that still need to be skipped.
+    skip: usize,
+}
+
+impl Decoder {
+    const HEADER_LENGTH: usize = 5;
+
+    pub fn new(limit: usize) -> Self {
+        Self {
+            buf: BytesMut::new(),
+            limit,
+            skip: 0,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        let skipped = self.skip.min(data.len());
+        self.skip -= skipped;
+        self.buf.extend_from_slice(&data[skipped..]);
+    }
+
+    pub fn next_message(&mut self) -> Option<Message> {
+        if self.buf.len() < Self::HEADER_LENGTH {
+            return None;
+        }
+
+        let size = u32::from_be_bytes(self.buf[1..5].try_into().unwrap()) as usize;
+        let length = size.min(self.limit);
+        if self.buf.len() < Self::HEADER_LENGTH + length {
+            return None;
+        }
+
+        let compressed = self.buf[0] != 0;
+        self.buf.advance(Self::HEADER_LENGTH);
+        let data = self.buf.split_to(length).freeze();
+
+        // skip the rest of a truncated message, some of which might already be
+        // buffered.
+        let rest = size - length;
+        let skipped = rest.min(self.buf.len());
+        self.buf.advance(skipped);
+        self.skip = rest - skipped;
+
+        Some(Message {
+            compressed,
+            data,
+            size,
+            truncated: length < size,
+        })
+    }
+
+    /// Returns the number of buffered bytes that don't form a complete
+    /// message yet.
+    pub fn remaining(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+/// Something that happened in a gRPC body.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Message(Message),
+    Status(Status),
+}
+
+pin_project! {
+    /// [`Body`] wrapper that decodes the body as gRPC messages while it's
+    /// streamed, and sends them to an [`EventReceiver`].
+    ///
+    /// The body itself is passed through unchanged.
+    #[derive(Debug)]
+    pub struct Messages<B> {
+        #[pin]
+        inner: B,
+        decoder: Option<(Decoder, mpsc::UnboundedSender<(SystemTime, Event)>)>,
+    }
+}
+
+impl<B> Messages<B> {
+    /// Wraps `inner`, and decodes at most `limit` bytes of each message. See
+    /// [`Decoder`].
+    pub fn new(inner: B, limit: usize) -> (Self, EventReceiver) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            Self {
+                inner,
+                decoder: Some((Decoder::new(limit), tx)),
+            },
+            EventReceiver { rx },
+        )
+    }
+
+    /// Wraps `inner` without decoding it.
+    pub fn passthrough(inner: B) -> Self {
+        Self {
+            inner,
+            decoder: None,
+        }
+    }
+}
+
+impl<B> Body for Messages<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some((decoder, tx)) = this.decoder {
+                    let received_at = SystemTime::now();
+                    let mut closed = false;
+
+                    if let Some(data) = frame.data_ref() {
+                        decoder.push(data);
+                        while let Some(message) = decoder.next_message() {
+                            closed |= tx.send((received_at, Event::Message(message))).is_err();
+                        }
+                    }
+                    else if let Some(status) = frame.trailers_ref().and_then(Status::from_headers)
+                    {
+                        closed |= tx.send((received_at, Event::Status(status))).is_err();
+                    }
+
+                    if closed {
+                        // nobody is interested anymore
+                        *this.decoder = None;
+                    }
+                }
+            }
+            Poll::Ready(_) => {
+                // closes the channel
+                *this.decoder = None;
+            }
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Receives events decoded by [`Messages`].
+#[derive(Debug)]
+pub struct EventReceiver {
+    rx: mpsc::UnboundedReceiver<(SystemTime, Event)>,
+}
+
+impl EventReceiver {
+    /// Returns the next event with the time its last chunk was received, or
+    /// `None` if the body ended.
+    pub async fn next(&mut self) -> Option<(SystemTime, Event)> {
+        self.rx.recv().await
+    }
+}
+
+/// Splits a request path of the form `/package.Service/Method` into service
+/// and method name.
+pub fn parse_path(path: &str) -> Option<(&str, &str)> {
+    let (service, method) = path.strip_prefix('/')?.split_once('/')?;
+    (!service.is_empty() && !method.is_empty() && !method.contains('/'))
+        .then_some((service, method))
+}
+
+/// Looks up the method called by a request to `path`.
+pub fn method(descriptors: &Descriptors, path: &str) -> Option<MethodDescriptor> {
+    let (service, method) = parse_path(path)?;
+    descriptors.method(service, method)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Decoder,
+        Message,
+    };
+
+    #[test]
+    fn it_decodes_messages_split_across_chunks() {
+        let mut decoder = Decoder::new(1024);
+        decoder.push(b"\x00\x00\x00\x00\x02\x08");
+        assert_eq!(decoder.next_message(), None);
+        decoder.push(b"\x01\x01\x00\x00\x00\x00");
+        assert_eq!(
+            decoder.next_message(),
+            Some(Message {
+                compressed: false,
+                data: b"\x08\x01"[..].into(),
+                size: 2,
+                truncated: false,
+            })
+        );
+        assert_eq!(
+            decoder.next_message(),
+            Some(Message {
+                compressed: true,
+                data: b""[..].into(),
+                size: 0,
+                truncated: false,
+            })
+        );
+        assert_eq!(decoder.next_message(), None);
+        assert_eq!(decoder.remaining(), 0);
+    }
+
+    #[test]
+    fn it_truncates_messages_over_the_limit() {
+        let mut decoder = Decoder::new(2);
+        decoder.push(b"\xff\xff\xff\xff\xff\x01");
+        assert_eq!(decoder.next_message(), None);
+        decoder.push(b"\x02\x03");
+        assert_eq!(
+            decoder.next_message(),
+            Some(Message {
+                compressed: true,
+                data: b"\x01\x02"[..].into(),
+                size: 0xffff_ffff,
+                truncated: true,
+            })
+        );
+        assert_eq!(decoder.remaining(), 0);
+
+        decoder.push(b"\x04\x05");
+        assert_eq!(decoder.remaining(), 0);
+    }
+}
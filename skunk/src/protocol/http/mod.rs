@@ -1,6 +1,12 @@
 //! Implementation of HTTP using hyper.
 
 pub mod body;
+#[cfg(feature = "decode")]
+pub mod decode;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod raw;
+pub mod sse;
 
 use std::{
     convert::Infallible,
@@ -24,11 +30,16 @@ use hyper::{
         Incoming,
     },
     service::service_fn,
-    StatusCode,
 };
 pub use hyper::{
+    header,
+    http::Extensions,
+    HeaderMap,
+    Method,
     Request,
     Response,
+    StatusCode,
+    Uri,
 };
 use hyper_util::rt::TokioIo;
 use tokio::{
@@ -43,7 +54,14 @@ use tokio::{
     },
 };
 
-use self::body::Empty;
+use self::{
+    body::Empty,
+    raw::{
+        HeadTap,
+        Heads,
+        Kind,
+    },
+};
 use crate::util::io::{
     Rewind,
     WithoutShutdown,
@@ -69,12 +87,17 @@ where
     <H::ResponseBody as Body>::Error: std::error::Error + Send + Sync + 'static,
 {
     let (tx_req, mut rx_req) = mpsc::channel(16);
+    let (io, heads) = HeadTap::new(io, Kind::Request);
 
     let conn = hyper::server::conn::http1::Builder::new()
+        .preserve_header_case(true)
         .serve_connection(
             TokioIo::new(WithoutShutdown::new(io)),
-            service_fn(move |request: Request<Incoming>| {
+            service_fn(move |mut request: Request<Incoming>| {
                 let tx_req = tx_req.clone();
+                if let Some(raw_headers) = heads.pop() {
+                    request.extensions_mut().insert(raw_headers);
+                }
                 async move {
                     let (tx_resp, rx_resp) = oneshot::channel::<Response<H::ResponseBody>>();
 
@@ -133,7 +156,7 @@ where
             !without_shutdown.was_shutdown(),
             "fixme: underlying IO was shutdown"
         );
-        let io = without_shutdown.into_inner();
+        let io = without_shutdown.into_inner().into_inner();
         Rewind::new(io, parts.read_buf)
     };
 
@@ -194,7 +217,8 @@ where
     T: AsyncRead + AsyncWrite,
     B: Body + 'static,
 {
-    connection: Option<hyper::client::conn::http1::Connection<TokioIo<WithoutShutdown<T>>, B>>,
+    connection:
+        Option<hyper::client::conn::http1::Connection<TokioIo<WithoutShutdown<HeadTap<T>>>, B>>,
 }
 
 impl<T, B> Future for Client<T, B>
@@ -217,7 +241,7 @@ where
                         !without_shutdown.was_shutdown(),
                         "fixme: underlying IO was shutdown"
                     );
-                    let io = without_shutdown.into_inner();
+                    let io = without_shutdown.into_inner().into_inner();
                     let io = Rewind::new(io, parts.read_buf);
                     Poll::Ready(Ok(io))
                 }
@@ -235,6 +259,7 @@ where
     RequestBody: Body + 'static,
 {
     send_request: Arc<Mutex<hyper::client::conn::http1::SendRequest<RequestBody>>>,
+    heads: Heads,
 }
 
 impl<RequestBody> Clone for SendRequest<RequestBody>
@@ -244,6 +269,7 @@ where
     fn clone(&self) -> Self {
         Self {
             send_request: self.send_request.clone(),
+            heads: self.heads.clone(),
         }
     }
 }
@@ -253,9 +279,24 @@ where
     RequestBody: Body + Send + 'static,
     RequestBody::Error: std::error::Error + Send + Sync + 'static,
 {
+    /// Sends a request.
+    ///
+    /// Header names are sent in their original case, if `request` was received
+    /// by [`server`]. The order of headers is only preserved as far as
+    /// [`HeaderMap`] preserves it, i.e. if there are interleaved headers with
+    /// the same name, they will be grouped together.
+    ///
+    /// The response has the original headers in a [`RawHeaders`][1] extension.
+    ///
+    /// [1]: raw::RawHeaders
     pub async fn send(&self, request: Request<RequestBody>) -> Result<Response<Incoming>, Error> {
         let mut send_request = self.send_request.lock().await;
-        Ok(send_request.send_request(request).await?)
+        self.heads.push_method(request.method().clone());
+        let mut response = send_request.send_request(request).await?;
+        if let Some(raw_headers) = self.heads.pop() {
+            response.extensions_mut().insert(raw_headers);
+        }
+        Ok(response)
     }
 }
 
@@ -286,7 +327,9 @@ where
     B::Data: Send,
     B::Error: std::error::Error + Send + Sync + 'static,
 {
+    let (io, heads) = HeadTap::new(io, Kind::Response);
     let (send_request, connection) = hyper::client::conn::http1::Builder::new()
+        .preserve_header_case(true)
         .handshake(TokioIo::new(WithoutShutdown::new(io)))
         .await?;
 
@@ -296,6 +339,7 @@ where
 
     let send_request = SendRequest {
         send_request: Arc::new(Mutex::new(send_request)),
+        heads,
     };
 
     Ok((client, send_request))
@@ -0,0 +1,392 @@
+//! Original header case and order of HTTP/1 messages.
+//!
+//! hyper normalizes header names to lowercase, and [`HeaderMap`][1] groups
+//! headers by name. With `preserve_header_case` hyper still forwards headers
+//! in their original case, but it keeps the original case in a private
+//! extension. So we also parse the message heads as they're read from the
+//! connection and attach them to requests and responses as a [`RawHeaders`]
+//! extension.
+//!
+//! [1]: hyper::HeaderMap
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{
+        Context,
+        Poll,
+    },
+};
+
+use bytes::{
+    Buf,
+    Bytes,
+    BytesMut,
+};
+use hyper::Method;
+use parking_lot::Mutex;
+use pin_project_lite::pin_project;
+use skunk_util::ordered_multimap::OrderedMultiMap;
+use tokio::io::{
+    AsyncRead,
+    AsyncWrite,
+    ReadBuf,
+};
+
+/// Maximum size of a message head. If a head is larger we stop parsing the
+/// connection.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Maximum number of headers in a message head.
+const MAX_HEADERS: usize = 128;
+
+/// Headers of a HTTP/1 message in their original case and order.
+///
+/// This is attached to requests received by [`server`](super::server) and
+/// responses received by [`client`](super::client) as an extension.
+#[derive(Clone, Debug, Default)]
+pub struct RawHeaders(pub OrderedMultiMap<String, Bytes>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Request,
+    Response,
+}
+
+/// Heads parsed by a [`HeadTap`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Heads {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    heads: VecDeque<RawHeaders>,
+
+    /// Methods of requests sent on a client connection. We need these to know
+    /// whether a response has a body.
+    methods: VecDeque<Method>,
+}
+
+impl Heads {
+    /// Returns the head of the next message that was read.
+    pub fn pop(&self) -> Option<RawHeaders> {
+        self.shared.lock().heads.pop_front()
+    }
+
+    /// Notes the method of a request sent on a client connection.
+    pub fn push_method(&self, method: Method) {
+        self.shared.lock().methods.push_back(method);
+    }
+}
+
+pin_project! {
+    /// IO stream wrapper that parses HTTP/1 message heads from the data that
+    /// is read.
+    #[derive(Debug)]
+    pub(crate) struct HeadTap<T> {
+        #[pin]
+        inner: T,
+        parser: Parser,
+    }
+}
+
+impl<T> HeadTap<T> {
+    pub fn new(inner: T, kind: Kind) -> (Self, Heads) {
+        let heads = Heads::default();
+        (
+            Self {
+                inner,
+                parser: Parser {
+                    kind,
+                    state: State::Head,
+                    buf: BytesMut::new(),
+                    shared: heads.shared.clone(),
+                },
+            },
+            heads,
+        )
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for HeadTap<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        let poll = this.inner.poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &poll {
+            this.parser.push(&buf.filled()[filled..]);
+        }
+        poll
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for HeadTap<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Head,
+    Length(u64),
+    ChunkSize,
+    ChunkData(u64),
+    ChunkDataEnd,
+    Trailers,
+
+    /// We can't follow the message framing anymore, e.g. because the
+    /// connection was upgraded.
+    Disabled,
+}
+
+/// Follows the framing of HTTP/1 messages and parses their heads.
+#[derive(Debug)]
+struct Parser {
+    kind: Kind,
+    state: State,
+    buf: BytesMut,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Parser {
+    fn push(&mut self, data: &[u8]) {
+        if matches!(self.state, State::Disabled) {
+            return;
+        }
+        self.buf.extend_from_slice(data);
+
+        loop {
+            match self.state {
+                State::Head => {
+                    let Some(i) = find(&self.buf, b"\r\n\r\n")
+                    else {
+                        if self.buf.len() > MAX_HEAD_SIZE {
+                            self.disable();
+                        }
+                        return;
+                    };
+                    let head = self.buf.split_to(i + 4);
+                    self.state = self.parse_head(&head).unwrap_or(State::Disabled);
+                }
+                State::Length(n) | State::ChunkData(n) => {
+                    let skip = std::cmp::min(n, self.buf.len() as u64);
+                    self.buf.advance(skip as usize);
+                    let n = n - skip;
+                    self.state = match (self.state, n) {
+                        (State::Length(_), 0) => State::Head,
+                        (State::Length(_), n) => State::Length(n),
+                        (_, 0) => State::ChunkDataEnd,
+                        (_, n) => State::ChunkData(n),
+                    };
+                    if n > 0 {
+                        return;
+                    }
+                }
+                State::ChunkSize => {
+                    let Some(line) = self.next_line()
+                    else {
+                        return;
+                    };
+                    let size = std::str::from_utf8(&line).ok().and_then(|line| {
+                        let size = line.split(';').next()?.trim();
+                        u64::from_str_radix(size, 16).ok()
+                    });
+                    self.state = match size {
+                        Some(0) => State::Trailers,
+                        Some(size) => State::ChunkData(size),
+                        None => State::Disabled,
+                    };
+                }
+                State::ChunkDataEnd => {
+                    if self.buf.len() < 2 {
+                        return;
+                    }
+                    self.buf.advance(2);
+                    self.state = State::ChunkSize;
+                }
+                State::Trailers => {
+                    let Some(line) = self.next_line()
+                    else {
+                        return;
+                    };
+                    if line.is_empty() {
+                        self.state = State::Head;
+                    }
+                }
+                State::Disabled => {
+                    self.disable();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn disable(&mut self) {
+        self.state = State::Disabled;
+        self.buf = BytesMut::new();
+    }
+
+    /// Returns the next line without the line break.
+    fn next_line(&mut self) -> Option<Bytes> {
+        let Some(i) = find(&self.buf, b"\r\n")
+        else {
+            if self.buf.len() > MAX_HEAD_SIZE {
+                self.disable();
+            }
+            return None;
+        };
+        let line = self.buf.split_to(i + 2).freeze();
+        Some(line.slice(..i))
+    }
+
+    /// Parses a message head, and returns the state for the message body.
+    fn parse_head(&mut self, head: &[u8]) -> Option<State> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+
+        let (headers, request_method, status) = match self.kind {
+            Kind::Request => {
+                let mut request = httparse::Request::new(&mut headers);
+                request.parse(head).ok()?.is_complete().then_some(())?;
+                let method = Method::from_bytes(request.method?.as_bytes()).ok()?;
+                (request.headers, method, None)
+            }
+            Kind::Response => {
+                let mut response = httparse::Response::new(&mut headers);
+                response.parse(head).ok()?.is_complete().then_some(())?;
+                let status = response.code?;
+                if (100..200).contains(&status) && status != 101 {
+                    // informational responses are handled by hyper
+                    return Some(State::Head);
+                }
+                let method = self.shared.lock().methods.pop_front()?;
+                (response.headers, method, Some(status))
+            }
+        };
+
+        let raw_headers = headers
+            .iter()
+            .map(|header| (header.name.to_owned(), Bytes::copy_from_slice(header.value)))
+            .collect::<OrderedMultiMap<_, _>>();
+
+        let state = body_state(&raw_headers, &request_method, status);
+        self.shared.lock().heads.push_back(RawHeaders(raw_headers));
+        Some(state)
+    }
+}
+
+/// Determines how the body of a message is framed.
+fn body_state(
+    headers: &OrderedMultiMap<String, Bytes>,
+    request_method: &Method,
+    response_status: Option<u16>,
+) -> State {
+    match response_status {
+        None if *request_method == Method::CONNECT => return State::Disabled,
+        Some(101) => return State::Disabled,
+        Some(204 | 304) => return State::Head,
+        Some(_) if *request_method == Method::HEAD => return State::Head,
+        Some(200..=299) if *request_method == Method::CONNECT => return State::Disabled,
+        _ => {}
+    }
+
+    let mut chunked = false;
+    let mut content_length = None;
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value
+                .rsplit(|&b| b == b',')
+                .next()
+                .is_some_and(|coding| coding.trim_ascii().eq_ignore_ascii_case(b"chunked"));
+        }
+        else if name.eq_ignore_ascii_case("content-length") {
+            content_length = std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.trim().parse::<u64>().ok());
+        }
+    }
+
+    if chunked {
+        State::ChunkSize
+    }
+    else if let Some(content_length) = content_length {
+        State::Length(content_length)
+    }
+    else if response_status.is_some() {
+        // the body is read until the connection closes
+        State::Disabled
+    }
+    else {
+        State::Head
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        HeadTap,
+        Kind,
+    };
+
+    #[test]
+    fn it_parses_heads_in_original_case_and_order() {
+        let (mut tap, heads) = HeadTap::new((), Kind::Request);
+        let data = b"POST / HTTP/1.1\r\nX-Foo: 1\r\nHost: example.com\r\nx-foo: 2\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\nGET / HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET /next HTTP/1.1\r\n\r\n";
+
+        // feed the stream in small chunks
+        for chunk in data.chunks(5) {
+            tap.parser.push(chunk);
+        }
+
+        let names = |heads: &super::Heads| {
+            heads
+                .pop()
+                .unwrap()
+                .0
+                .iter()
+                .map(|(name, value)| format!("{name}: {}", String::from_utf8_lossy(value)))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(&heads),
+            vec![
+                "X-Foo: 1",
+                "Host: example.com",
+                "x-foo: 2",
+                "Transfer-Encoding: chunked"
+            ]
+        );
+        assert_eq!(names(&heads), vec!["Content-Length: 2"]);
+        assert_eq!(names(&heads), Vec::<String>::new());
+        assert!(heads.pop().is_none());
+    }
+}
@@ -0,0 +1,441 @@
+//! [Server-Sent Events][1] (`text/event-stream`).
+//!
+//! [1]: https://html.spec.whatwg.org/multipage/server-sent-events.html
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::SystemTime,
+};
+
+use bytes::{
+    Bytes,
+    BytesMut,
+};
+use hyper::{
+    body::{
+        Body,
+        Frame,
+        SizeHint,
+    },
+    header,
+    HeaderMap,
+};
+use pin_project_lite::pin_project;
+use tokio::sync::mpsc;
+
+pub const MIME_TYPE: &str = "text/event-stream";
+
+/// How many parsed events [`Events`] buffers for its [`EventReceiver`]. When
+/// the buffer is full, further events are dropped.
+const EVENT_BUFFER: usize = 16;
+
+/// Returns whether the `Content-Type` in `headers` indicates an event stream.
+pub fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime_type| mime_type.trim().eq_ignore_ascii_case(MIME_TYPE))
+}
+
+/// A single event of an event stream.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    /// The event type. `None` means the default type `message`.
+    pub event: Option<String>,
+
+    /// The last event ID that was set in the stream.
+    pub id: Option<String>,
+
+    /// The event data. Multiple `data` fields are joined by newlines.
+    pub data: String,
+
+    /// The reconnection time in milliseconds, if it was set with this event.
+    pub retry: Option<u64>,
+}
+
+/// A line or event of an event stream was longer than the parser's limit.
+#[derive(Debug, thiserror::Error)]
+#[error("event stream line or event exceeds {limit} bytes")]
+pub struct LimitExceeded {
+    pub limit: usize,
+}
+
+/// Incremental event stream parser.
+///
+/// Feed it chunks of the stream with [`push`](Self::push) and take parsed
+/// events with [`next_event`](Self::next_event). Chunks don't need to be
+/// aligned to lines or events.
+#[derive(Debug)]
+pub struct Parser {
+    limit: usize,
+    line: BytesMut,
+    started: bool,
+    skip_lf: bool,
+    event: Option<String>,
+    data: String,
+    last_event_id: Option<String>,
+    retry: Option<u64>,
+    events: VecDeque<Event>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::with_limit(usize::MAX)
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a parser that fails once an unfinished line or event is longer
+    /// than `limit` bytes.
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            limit,
+            line: BytesMut::new(),
+            started: false,
+            skip_lf: false,
+            event: None,
+            data: String::new(),
+            last_event_id: None,
+            retry: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Parses the next chunk of the stream.
+    ///
+    /// After an error the parser is in an unspecified state and shouldn't be
+    /// used anymore. Events that were parsed before can still be taken.
+    pub fn push(&mut self, mut data: &[u8]) -> Result<(), LimitExceeded> {
+        if !self.started {
+            // a leading byte order mark is ignored
+            let bom = b"\xef\xbb\xbf";
+            let n = std::cmp::min(bom.len() - self.line.len(), data.len());
+            if bom[self.line.len()..].starts_with(&data[..n]) {
+                self.line.extend_from_slice(&data[..n]);
+                data = &data[n..];
+                if self.line.len() < bom.len() {
+                    return Ok(());
+                }
+                self.line.clear();
+            }
+            self.started = true;
+        }
+
+        for &b in data {
+            match b {
+                b'\n' if self.skip_lf => {
+                    self.skip_lf = false;
+                }
+                b'\r' | b'\n' => {
+                    self.skip_lf = b == b'\r';
+                    let line = self.line.split().freeze();
+                    self.process_line(&line)?;
+                }
+                _ => {
+                    self.skip_lf = false;
+                    if self.line.len() >= self.limit {
+                        return Err(LimitExceeded { limit: self.limit });
+                    }
+                    self.line.extend_from_slice(&[b]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn next_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Result<(), LimitExceeded> {
+        if line.is_empty() {
+            self.dispatch();
+            return Ok(());
+        }
+
+        if line[0] == b':' {
+            // comment
+            return Ok(());
+        }
+
+        let (field, value) = match line.iter().position(|&b| b == b':') {
+            Some(i) => {
+                let value = &line[i + 1..];
+                (&line[..i], value.strip_prefix(b" ").unwrap_or(value))
+            }
+            None => (line, &b""[..]),
+        };
+        let value = String::from_utf8_lossy(value);
+
+        match field {
+            b"event" => {
+                self.event = Some(value.into_owned());
+            }
+            b"data" => {
+                if self.data.len() + value.len() + 1 > self.limit {
+                    return Err(LimitExceeded { limit: self.limit });
+                }
+                self.data.push_str(&value);
+                self.data.push('\n');
+            }
+            b"id" if !value.contains('\0') => {
+                self.last_event_id = Some(value.into_owned());
+            }
+            b"retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn dispatch(&mut self) {
+        let event = self.event.take();
+        let retry = self.retry.take();
+
+        if self.data.is_empty() {
+            return;
+        }
+
+        let mut data = std::mem::take(&mut self.data);
+        data.pop();
+
+        self.events.push_back(Event {
+            event,
+            id: self.last_event_id.clone(),
+            data,
+            retry,
+        });
+    }
+}
+
+pin_project! {
+    /// [`Body`] wrapper that parses the body as an event stream while it's
+    /// streamed, and sends the parsed events to an [`EventReceiver`].
+    ///
+    /// The body itself is passed through unchanged. Events are dropped if the
+    /// receiver doesn't keep up, and parsing stops if a line or event is longer
+    /// than the limit.
+    #[derive(Debug)]
+    pub struct Events<B> {
+        #[pin]
+        inner: B,
+        parser: Option<(Parser, mpsc::Sender<(SystemTime, Event)>)>,
+    }
+}
+
+impl<B> Events<B> {
+    /// Wraps `inner` and parses it, with lines and events limited to `limit`
+    /// bytes.
+    pub fn new(inner: B, limit: usize) -> (Self, EventReceiver) {
+        let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+        (
+            Self {
+                inner,
+                parser: Some((Parser::with_limit(limit), tx)),
+            },
+            EventReceiver { rx },
+        )
+    }
+
+    /// Wraps `inner` without parsing it.
+    pub fn passthrough(inner: B) -> Self {
+        Self {
+            inner,
+            parser: None,
+        }
+    }
+}
+
+impl<B> Body for Events<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let poll = this.inner.poll_frame(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let Some((parser, tx)) = this.parser {
+                    if let Some(data) = frame.data_ref() {
+                        let received_at = SystemTime::now();
+                        let result = parser.push(data);
+
+                        let mut closed = false;
+                        while let Some(event) = parser.next_event() {
+                            match tx.try_send((received_at, event)) {
+                                Ok(()) => {}
+                                Err(mpsc::error::TrySendError::Full(_)) => {
+                                    tracing::warn!("Event stream receiver is full, dropping event");
+                                }
+                                Err(mpsc::error::TrySendError::Closed(_)) => {
+                                    // nobody is interested anymore
+                                    closed = true;
+                                    break;
+                                }
+                            }
+                        }
+
+                        if let Err(error) = result {
+                            tracing::warn!(?error, "Stopped parsing event stream");
+                            closed = true;
+                        }
+                        if closed {
+                            *this.parser = None;
+                        }
+                    }
+                }
+            }
+            Poll::Ready(_) => {
+                // closes the channel
+                *this.parser = None;
+            }
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Receives events parsed by [`Events`].
+#[derive(Debug)]
+pub struct EventReceiver {
+    rx: mpsc::Receiver<(SystemTime, Event)>,
+}
+
+impl EventReceiver {
+    /// Returns the next event with the time its last chunk was received, or
+    /// `None` if the body ended or parsing stopped.
+    pub async fn next(&mut self) -> Option<(SystemTime, Event)> {
+        self.rx.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use futures::executor::block_on;
+    use http_body_util::{
+        BodyExt,
+        StreamBody,
+    };
+    use hyper::body::Frame;
+
+    use super::{
+        Event,
+        Events,
+        Parser,
+        EVENT_BUFFER,
+    };
+
+    fn parse(chunks: &[&[u8]]) -> Vec<Event> {
+        let mut parser = Parser::new();
+        let mut events = vec![];
+        for chunk in chunks {
+            parser.push(chunk).unwrap();
+            while let Some(event) = parser.next_event() {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn it_parses_events() {
+        let events = parse(&[
+            b"\xef\xbb\xbf: comment\nevent: update\nid: 1\ndata: foo\ndata: bar\n\n",
+            b"retry: 1000\ndata:baz\n\n",
+        ]);
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    event: Some("update".to_owned()),
+                    id: Some("1".to_owned()),
+                    data: "foo\nbar".to_owned(),
+                    retry: None,
+                },
+                Event {
+                    event: None,
+                    id: Some("1".to_owned()),
+                    data: "baz".to_owned(),
+                    retry: Some(1000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn it_parses_events_split_across_chunks() {
+        let events = parse(&[b"da", b"ta: foo\r", b"\n\r", b"\ndata: bar\r\r"]);
+        assert_eq!(
+            events.iter().map(|e| e.data.as_str()).collect::<Vec<_>>(),
+            vec!["foo", "bar"]
+        );
+    }
+
+    #[test]
+    fn it_stops_at_the_limit() {
+        let mut parser = Parser::with_limit(16);
+        parser.push(b"data: foo bar\n\n").unwrap();
+        assert_eq!(parser.next_event().unwrap().data, "foo bar");
+
+        // an unfinished line
+        let mut parser = Parser::with_limit(16);
+        assert!(parser.push(b"data: foo bar baz").is_err());
+
+        // an event made of short lines
+        let mut parser = Parser::with_limit(16);
+        assert!(parser
+            .push(b"data: foo bar\ndata: foo bar\ndata: foo bar\n")
+            .is_err());
+    }
+
+    #[test]
+    fn it_drops_events_when_the_receiver_is_full() {
+        let frames = (0..2 * EVENT_BUFFER)
+            .map(|i| Ok::<_, Infallible>(Frame::data(Bytes::from(format!("data: {i}\n\n")))));
+        let body = StreamBody::new(futures::stream::iter(frames));
+        let (body, mut events) = Events::new(body, 1024);
+
+        block_on(async {
+            body.collect().await.unwrap();
+
+            let mut received = vec![];
+            while let Some((_, event)) = events.next().await {
+                received.push(event.data);
+            }
+            let expected = (0..EVENT_BUFFER).map(|i| i.to_string()).collect::<Vec<_>>();
+            assert_eq!(received, expected);
+        });
+    }
+}
@@ -2,6 +2,8 @@
 
 #[cfg(feature = "http")]
 pub mod http;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
 #[cfg(feature = "tls")]
 pub mod tls;
 
@@ -0,0 +1,271 @@
+//! [Protocol Buffers][1] decoding.
+//!
+//! Messages can be decoded without a schema into a tree of field numbers and
+//! values (see [`decode_schemaless`]). If the message types are known from a
+//! descriptor set, they can be decoded with named fields (see
+//! [`Descriptors`]).
+//!
+//! [1]: https://protobuf.dev/programming-guides/encoding/
+
+use std::path::Path;
+
+use bytes::Bytes;
+pub use prost_reflect::{
+    DynamicMessage,
+    MessageDescriptor,
+    MethodDescriptor,
+};
+use prost_reflect::DescriptorPool;
+
+/// Maximum nesting depth of messages when decoding without a schema.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid descriptor set")]
+    Descriptor(#[from] prost_reflect::DescriptorError),
+
+    #[error("decode error")]
+    Decode(#[from] prost_reflect::prost::DecodeError),
+}
+
+/// A field of a message decoded without a schema.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub number: u32,
+    pub value: Value,
+}
+
+/// The value of a field decoded without a schema.
+///
+/// Without a schema the wire format is ambiguous: A length-delimited field can
+/// be a string, a nested message, packed repeated scalars, or just bytes. We
+/// decode it as a string if it's printable UTF-8, then as a message if it
+/// parses as one, and otherwise leave it as bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    String(String),
+    Bytes(Bytes),
+    Message(Vec<Field>),
+    Group(Vec<Field>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("unexpected end of input")]
+    Eof,
+
+    #[error("varint too long")]
+    VarintOverflow,
+
+    #[error("invalid wire type: {0}")]
+    InvalidWireType(u8),
+
+    #[error("invalid field number: {0}")]
+    InvalidFieldNumber(u64),
+
+    #[error("unexpected end of group: {0}")]
+    UnexpectedEndGroup(u32),
+
+    #[error("message nested too deeply")]
+    TooDeep,
+}
+
+/// Decodes a message without a schema.
+pub fn decode_schemaless(data: &[u8]) -> Result<Vec<Field>, DecodeError> {
+    let mut reader = Reader { data, depth: 0 };
+    reader.read_fields(None)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read_fields(&mut self, group: Option<u32>) -> Result<Vec<Field>, DecodeError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+
+        let mut fields = vec![];
+
+        while !self.data.is_empty() {
+            let key = self.read_varint()?;
+            let number = key >> 3;
+            if number == 0 || number > u64::from(u32::MAX >> 3) {
+                return Err(DecodeError::InvalidFieldNumber(number));
+            }
+            let number = number as u32;
+
+            let value = match (key & 7) as u8 {
+                0 => Value::Varint(self.read_varint()?),
+                1 => Value::Fixed64(u64::from_le_bytes(self.read_array()?)),
+                2 => {
+                    let length = usize::try_from(self.read_varint()?)
+                        .map_err(|_| DecodeError::Eof)?;
+                    let data = self.read_bytes(length)?;
+                    self.decode_length_delimited(data)
+                }
+                3 => {
+                    self.depth += 1;
+                    let fields = self.read_fields(Some(number));
+                    self.depth -= 1;
+                    Value::Group(fields?)
+                }
+                4 => {
+                    if group == Some(number) {
+                        return Ok(fields);
+                    }
+                    return Err(DecodeError::UnexpectedEndGroup(number));
+                }
+                5 => Value::Fixed32(u32::from_le_bytes(self.read_array()?)),
+                wire_type => return Err(DecodeError::InvalidWireType(wire_type)),
+            };
+
+            fields.push(Field { number, value });
+        }
+
+        if group.is_some() {
+            Err(DecodeError::Eof)
+        }
+        else {
+            Ok(fields)
+        }
+    }
+
+    fn decode_length_delimited(&self, data: &[u8]) -> Value {
+        if let Ok(s) = std::str::from_utf8(data) {
+            if s.chars().all(|c| !c.is_control() || c.is_whitespace()) {
+                return Value::String(s.to_owned());
+            }
+        }
+
+        let mut reader = Reader {
+            data,
+            depth: self.depth + 1,
+        };
+        match reader.read_fields(None) {
+            Ok(fields) => Value::Message(fields),
+            Err(_) => Value::Bytes(Bytes::copy_from_slice(data)),
+        }
+    }
+
+    fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let (&b, rest) = self.data.split_first().ok_or(DecodeError::Eof)?;
+            self.data = rest;
+            value |= u64::from(b & 0x7f) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::VarintOverflow)
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.data.len() < n {
+            return Err(DecodeError::Eof);
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+}
+
+/// A set of message and service descriptors, used to decode messages with
+/// named fields.
+///
+/// Descriptor sets can be generated with `protoc --descriptor_set_out`.
+#[derive(Clone, Debug, Default)]
+pub struct Descriptors {
+    pool: DescriptorPool,
+}
+
+impl Descriptors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an encoded `FileDescriptorSet`.
+    pub fn add_descriptor_set(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.pool.decode_file_descriptor_set(data)?;
+        Ok(())
+    }
+
+    /// Reads and adds an encoded `FileDescriptorSet` from a file.
+    pub fn add_descriptor_set_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let data = std::fs::read(path)?;
+        self.add_descriptor_set(&data)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pool.files().len() == 0
+    }
+
+    /// Returns the message with the fully-qualified `name`.
+    pub fn message(&self, name: &str) -> Option<MessageDescriptor> {
+        self.pool.get_message_by_name(name)
+    }
+
+    /// Returns the method `method` of the service with the fully-qualified
+    /// name `service`.
+    pub fn method(&self, service: &str, method: &str) -> Option<MethodDescriptor> {
+        self.pool
+            .get_service_by_name(service)?
+            .methods()
+            .find(|descriptor| descriptor.name() == method)
+    }
+
+    /// Decodes a message of type `message`.
+    pub fn decode(&self, message: MessageDescriptor, data: &[u8]) -> Result<DynamicMessage, Error> {
+        Ok(DynamicMessage::decode(message, data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_schemaless,
+        Field,
+        Value,
+    };
+
+    #[test]
+    fn it_decodes_without_schema() {
+        // field 1: varint 150, field 2: "hi", field 3: message { field 1: varint 1 }
+        let data = b"\x08\x96\x01\x12\x02hi\x1a\x02\x08\x01";
+        let fields = decode_schemaless(data).unwrap();
+        assert_eq!(
+            fields,
+            vec![
+                Field {
+                    number: 1,
+                    value: Value::Varint(150),
+                },
+                Field {
+                    number: 2,
+                    value: Value::String("hi".to_owned()),
+                },
+                Field {
+                    number: 3,
+                    value: Value::Message(vec![Field {
+                        number: 1,
+                        value: Value::Varint(1),
+                    }]),
+                },
+            ]
+        );
+    }
+}
@@ -44,7 +44,9 @@ use rustls::{
 use tokio::{
     io::{
         AsyncRead,
+        AsyncReadExt,
         AsyncWrite,
+        AsyncWriteExt,
         ReadBuf,
     },
     sync::Mutex,
@@ -57,6 +59,15 @@ use tokio_rustls::{
 
 use crate::util::Lazy;
 
+/// A fatal `handshake_failure` alert record.
+const HANDSHAKE_FAILURE_ALERT: [u8; 7] = [
+    0x15, // content type: alert
+    0x03, 0x03, // version: TLS 1.2, which TLS 1.3 uses for records too
+    0x00, 0x02, // length
+    0x02, // level: fatal
+    0x28, // description: handshake_failure
+];
+
 /// TLS error type
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -289,16 +300,7 @@ impl Context {
         // to connect to the target. we could also use the `TcpAddress` we
         // get from the proxy layer.
         let source_server_name = source_accept.server_name().ok_or(Error::NoServerName)?;
-        let domain = match IpAddr::from_str(&source_server_name) {
-            Ok(ip_address) => ServerName::IpAddress(ip_address.into()),
-            Err(_) => {
-                ServerName::DnsName(source_server_name.to_owned().try_into().map_err(|_| {
-                    Error::InvalidServerName {
-                        hostname: source_server_name.clone(),
-                    }
-                })?)
-            }
-        };
+        let domain = server_name(&source_server_name)?;
 
         // connect to the target
         let target = self.connect(outgoing, domain).await?;
@@ -360,6 +362,26 @@ impl From<Context> for Arc<ClientConfig> {
     }
 }
 
+/// Fails the TLS handshake of a client with a `handshake_failure` alert.
+///
+/// The record with the `ClientHello` is read first, so that the client gets
+/// the alert in response to it.
+pub async fn fail_handshake<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> Result<(), Error> {
+    // record header: content type, version and length.
+    let mut header = [0; 5];
+    stream.read_exact(&mut header).await?;
+    let length = u16::from_be_bytes([header[3], header[4]]);
+    tokio::io::copy(
+        &mut (&mut stream).take(length.into()),
+        &mut tokio::io::sink(),
+    )
+    .await?;
+
+    stream.write_all(&HANDSHAKE_FAILURE_ALERT).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
 /// Process of accepting a TLS server connection
 pub struct Accept<S> {
     start_handshake: StartHandshake<S>,
@@ -437,6 +459,14 @@ impl<Inner> Outgoing<Inner> {
     pub fn get_tls_connection(&self) -> &rustls::ClientConnection {
         self.inner.get_ref().1
     }
+
+    /// Subject of the certificate the server presented.
+    pub fn server_certificate_subject(&self) -> Option<CertificateSubject> {
+        self.get_tls_connection()
+            .peer_certificates()?
+            .first()
+            .and_then(CertificateSubject::from_der)
+    }
 }
 
 impl<Inner: AsyncRead + AsyncWrite + Unpin> AsyncRead for Outgoing<Inner> {
@@ -473,6 +503,31 @@ impl<Inner: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Outgoing<Inner> {
     }
 }
 
+/// Names of the subject of a certificate.
+#[derive(Clone, Debug)]
+pub struct CertificateSubject {
+    pub common_name: Option<String>,
+    pub distinguished_name: String,
+}
+
+impl CertificateSubject {
+    /// Parses the subject from a DER-encoded certificate. Returns `None` if
+    /// the certificate can't be parsed.
+    pub fn from_der(cert: &CertificateDer) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+        let subject = cert.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|common_name| common_name.as_str().ok())
+            .map(ToOwned::to_owned);
+        Some(Self {
+            common_name,
+            distinguished_name: subject.to_string(),
+        })
+    }
+}
+
 /// An incoming (server) connection that is TLS encrypted.
 #[derive(Debug)]
 pub struct Incoming<Inner> {
@@ -550,6 +605,13 @@ pub mod maybe {
                 Outgoing::Unencrypted(_) => None,
             }
         }
+
+        pub fn server_certificate_subject(&self) -> Option<super::CertificateSubject> {
+            match self {
+                Outgoing::Encrypted(inner) => inner.server_certificate_subject(),
+                Outgoing::Unencrypted(_) => None,
+            }
+        }
     }
 
     impl<Inner: AsyncRead + AsyncWrite + Unpin> AsyncRead for Outgoing<Inner> {
@@ -661,6 +723,23 @@ pub mod maybe {
     }
 }
 
+/// Parses a hostname or IP address into a [`ServerName`] that can be used to
+/// [connect][Context::connect] to a server.
+pub fn server_name(hostname: &str) -> Result<ServerName<'static>, Error> {
+    match IpAddr::from_str(hostname) {
+        Ok(ip_address) => Ok(ServerName::IpAddress(ip_address.into())),
+        Err(_) => {
+            Ok(ServerName::DnsName(
+                hostname.to_owned().try_into().map_err(|_| {
+                    Error::InvalidServerName {
+                        hostname: hostname.to_owned(),
+                    }
+                })?,
+            ))
+        }
+    }
+}
+
 /// Returns the default TLS client config. This uses the natively installed root
 /// certificates from [`native_certificates`].
 pub fn default_client_config() -> Result<Arc<ClientConfig>, Error> {
@@ -0,0 +1,302 @@
+//! Simulating bad network conditions for proxied connections.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{
+        Context,
+        Poll,
+    },
+    time::Duration,
+};
+
+use bytes::{
+    Buf,
+    Bytes,
+};
+use pin_project_lite::pin_project;
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+        ReadBuf,
+    },
+    time::Sleep,
+};
+
+use crate::rule::file::NetworkEffect;
+
+/// Network conditions for a [`Conditioned`] stream.
+///
+/// They only apply to data read from the stream. To condition both directions
+/// of a proxied connection, wrap both the incoming and outgoing streams.
+#[derive(Clone, Debug, Default)]
+pub struct Conditions {
+    /// Added to every chunk of data that is read.
+    pub latency: Duration,
+
+    /// Random extra latency of up to this duration.
+    pub jitter: Duration,
+
+    /// Maximum throughput in bytes per second.
+    pub bandwidth: Option<u64>,
+
+    /// Probability that a read fails with a connection reset.
+    pub reset: f64,
+
+    /// Probability that a read never completes.
+    pub stall: f64,
+
+    /// Number of bytes after which the stream ends.
+    pub truncate: Option<u64>,
+}
+
+impl Conditions {
+    /// Returns whether these conditions change anything.
+    pub fn is_none(&self) -> bool {
+        self.latency.is_zero()
+            && self.jitter.is_zero()
+            && self.bandwidth.is_none()
+            && self.reset <= 0.0
+            && self.stall <= 0.0
+            && self.truncate.is_none()
+    }
+
+    fn delay(&self, num_bytes: usize) -> Duration {
+        let mut delay = self.latency;
+        if !self.jitter.is_zero() {
+            delay += self.jitter.mul_f64(fastrand::f64());
+        }
+        if let Some(bandwidth) = self.bandwidth {
+            delay += Duration::from_secs_f64(num_bytes as f64 / bandwidth.max(1) as f64);
+        }
+        delay
+    }
+
+    /// Maximum number of bytes that are read at once. This keeps the delays
+    /// caused by bandwidth limits small.
+    fn chunk_size(&self) -> usize {
+        self.bandwidth
+            .map_or(usize::MAX, |bandwidth| (bandwidth / 10).max(1) as usize)
+    }
+}
+
+impl From<&NetworkEffect> for Conditions {
+    fn from(effect: &NetworkEffect) -> Self {
+        Self {
+            latency: Duration::from_millis(effect.latency_ms.unwrap_or_default()),
+            jitter: Duration::from_millis(effect.jitter_ms.unwrap_or_default()),
+            bandwidth: effect.bandwidth,
+            reset: effect.reset.unwrap_or_default(),
+            stall: effect.stall.unwrap_or_default(),
+            truncate: effect.truncate,
+        }
+    }
+}
+
+pin_project! {
+    /// Wrapper for [`AsyncRead`]/[`AsyncWrite`] streams that delays, throttles
+    /// or breaks reads according to [`Conditions`].
+    ///
+    /// Writes are passed through as they are.
+    #[derive(Debug)]
+    pub struct Conditioned<T> {
+        #[pin]
+        inner: T,
+        conditions: Conditions,
+        delayed: Option<Delayed>,
+        num_read: u64,
+        stalled: bool,
+    }
+}
+
+/// Data that was read, but is only returned after `sleep`.
+#[derive(Debug)]
+struct Delayed {
+    data: Bytes,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<T> Conditioned<T> {
+    pub fn new(inner: T, conditions: Conditions) -> Self {
+        Self {
+            inner,
+            conditions,
+            delayed: None,
+            num_read: 0,
+            stalled: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Conditioned<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = self.project();
+
+        if *this.stalled {
+            // we're never woken up again.
+            return Poll::Pending;
+        }
+
+        if this.conditions.is_none() {
+            return this.inner.poll_read(cx, buf);
+        }
+
+        if let Some(delayed) = this.delayed {
+            if delayed.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            let n = std::cmp::min(delayed.data.len(), buf.remaining());
+            buf.put_slice(&delayed.data[..n]);
+            delayed.data.advance(n);
+            if delayed.data.is_empty() {
+                *this.delayed = None;
+            }
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut limit = std::cmp::min(buf.remaining(), this.conditions.chunk_size());
+        if let Some(truncate) = this.conditions.truncate {
+            let remaining = truncate.saturating_sub(*this.num_read);
+            if remaining == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            limit = limit.min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        }
+
+        let mut data = vec![0; limit];
+        let mut read_buf = ReadBuf::new(&mut data);
+        match this.inner.poll_read(cx, &mut read_buf) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        let n = read_buf.filled().len();
+        if n == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        data.truncate(n);
+        *this.num_read += n as u64;
+
+        if fastrand::f64() < this.conditions.reset {
+            return Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()));
+        }
+        if fastrand::f64() < this.conditions.stall {
+            *this.stalled = true;
+            return Poll::Pending;
+        }
+
+        let delay = this.conditions.delay(n);
+        if delay.is_zero() {
+            buf.put_slice(&data);
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut sleep = Box::pin(tokio::time::sleep(delay));
+        // poll once, so that we're woken up when the delay is over.
+        let _ = sleep.as_mut().poll(cx);
+        *this.delayed = Some(Delayed {
+            data: data.into(),
+            sleep,
+        });
+        Poll::Pending
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Conditioned<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::AsyncReadExt,
+        time::Instant,
+    };
+
+    use super::{
+        Conditioned,
+        Conditions,
+    };
+
+    async fn read(conditions: Conditions, data: &[u8]) -> std::io::Result<(Vec<u8>, Duration)> {
+        let start = Instant::now();
+        let mut stream = Conditioned::new(data, conditions);
+        let mut buf = vec![];
+        stream.read_to_end(&mut buf).await?;
+        Ok((buf, start.elapsed()))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_delays_reads() {
+        let conditions = Conditions {
+            latency: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let (data, elapsed) = read(conditions, b"hello world").await.unwrap();
+        assert_eq!(data, b"hello world");
+        assert!(elapsed >= Duration::from_millis(500));
+        assert!(elapsed < Duration::from_millis(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_throttles_reads() {
+        let conditions = Conditions {
+            bandwidth: Some(100),
+            ..Default::default()
+        };
+        let (data, elapsed) = read(conditions, &[0; 100]).await.unwrap();
+        assert_eq!(data.len(), 100);
+        assert!(elapsed >= Duration::from_millis(990));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_truncates_reads() {
+        let conditions = Conditions {
+            truncate: Some(5),
+            ..Default::default()
+        };
+        let (data, _) = read(conditions, b"hello world").await.unwrap();
+        assert_eq!(data, b"hello");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_resets_connections() {
+        let conditions = Conditions {
+            reset: 1.0,
+            ..Default::default()
+        };
+        let error = read(conditions, b"hello world").await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::ConnectionReset);
+    }
+}
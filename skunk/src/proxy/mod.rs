@@ -1,5 +1,6 @@
 //! Proxy implementations.
 
+pub mod condition;
 //#[cfg(feature = "http")]
 //pub mod http;
 #[cfg(feature = "pcap")]
@@ -63,6 +63,14 @@ use crate::{
 pub struct Incoming {
     inner: Connected<BufStream<TcpStream>, MaybeAuth>,
     destination_address: TcpAddress,
+    client_address: SocketAddr,
+}
+
+impl Incoming {
+    /// Address of the client that made the connection.
+    pub fn client_address(&self) -> SocketAddr {
+        self.client_address
+    }
 }
 
 impl DestinationAddress for Incoming {
@@ -165,9 +173,13 @@ impl Builder {
 
                         tokio::spawn(
                             async move {
-                                if let Err(e) =
-                                    handle_connection(connection, auth, connection_requests_tx)
-                                        .await
+                                if let Err(e) = handle_connection(
+                                    connection,
+                                    address,
+                                    auth,
+                                    connection_requests_tx,
+                                )
+                                .await
                                 {
                                     tracing::error!("{e}");
                                 }
@@ -246,6 +258,7 @@ impl ConnectionRequest {
 /// Handle a single connection
 async fn handle_connection(
     connection: TcpStream,
+    client_address: SocketAddr,
     auth: MaybeAuth,
     connection_requests_tx: mpsc::Sender<Result<ConnectionRequest, Error>>,
 ) -> Result<(), Error> {
@@ -277,6 +290,7 @@ async fn handle_connection(
                         Incoming {
                             inner: connection,
                             destination_address,
+                            client_address,
                         }
                     });
                     let _ = connection_tx.send(result);
@@ -308,8 +322,10 @@ pub enum MaybeAuth {
 
 impl AuthProvider for MaybeAuth {
     type Data = ();
-    type Socket<S> = S
-    where S: AsyncRead + AsyncWrite + Unpin;
+    type Socket<S>
+        = S
+    where
+        S: AsyncRead + AsyncWrite + Unpin;
 
     fn select_method(&self, methods: &[AuthMethod]) -> SelectedAuthMethod {
         let accept = match self {
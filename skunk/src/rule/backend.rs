@@ -0,0 +1,1797 @@
+//! [`Backend`] for [`DefaultFilters`] and [`DefaultEffects`].
+//!
+//! Rules are compiled into an expression [`Graph`], with one input for each
+//! filter. Effects fire once the condition of their scope evaluates to
+//! `true`.
+//!
+//! A filter with a list of values (e.g. `host: [a, b]`) matches if any of the
+//! values match. A list of filters (e.g. `tcp: [{port: 443}, {hostname: a}]`)
+//! matches if all filters match.
+//!
+//! [Filter expressions](super::filter::mitmproxy) are compiled into the same
+//! graph, and their filters are evaluated with the same extractors. They can
+//! also be compiled on their own with [`CompiledFilter`].
+//!
+//! Filters on the body of a HTTP message (e.g. `json`) stay undecided until
+//! [`Evaluator::set_body`] is called. Check [`Evaluator::needs_body`] first,
+//! so that bodies are only read if that can change whether an effect fires.
+//!
+//! Filters on the annotations of a flow (e.g. `~marked`) are only supported
+//! by [`CompiledFilter`], since annotations are set by the effects of rules.
+//!
+//! Stateful filters (e.g. `chance` or `nth`) are only evaluated by
+//! [`Evaluator::set_state`], and only if they can still change whether an
+//! effect fires. So `nth` only counts the requests that match the rest of its
+//! rule's condition. Their counters are shared by all evaluators of the same
+//! rules, and start over when the rules are replaced. Counters per connection
+//! are dropped together with the evaluators of the connection. Of the other
+//! counters, at most [`MAX_COUNTERS`] are kept per filter.
+
+#[cfg(feature = "http")]
+use std::cell::OnceCell;
+use std::{
+    any::{
+        Any,
+        TypeId,
+    },
+    collections::{
+        HashMap,
+        HashSet,
+        VecDeque,
+    },
+    fmt::Debug,
+    hash::Hash,
+    net::IpAddr,
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+use chrono::{
+    DateTime,
+    Local,
+    TimeDelta,
+};
+#[cfg(feature = "http")]
+use hyper::{
+    header,
+    HeaderMap,
+    Method,
+    StatusCode,
+    Uri,
+};
+use parking_lot::{
+    Mutex,
+    RwLock,
+};
+
+use super::{
+    compiler::{
+        self,
+        Compiler,
+        Config,
+    },
+    eval::{
+        self,
+        Extractor,
+        Graph,
+        Match,
+    },
+    file::{
+        CounterKey,
+        DefaultEffects,
+        DefaultFilters,
+        Direction,
+        NthFilter,
+        RateFilter,
+        RulesFile,
+        TcpFilter,
+        TimeWindow,
+        TlsFilter,
+    },
+    filter::mitmproxy::{
+        self,
+        FilterExpression,
+    },
+};
+#[cfg(feature = "http")]
+use super::{
+    file::{
+        HttpFilter,
+        JsonFilter,
+    },
+    regex::Regex,
+};
+use crate::{
+    address::{
+        HostAddress,
+        TcpAddress,
+    },
+    util::boolean::{
+        ExpressionId,
+        Maybe,
+        ModifyGraph,
+        VariableId,
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the {name} filter is not supported, because the `{feature}` feature is disabled")]
+    Unsupported {
+        name: &'static str,
+        feature: &'static str,
+    },
+
+    #[error("the filter {name} is not supported in filter expressions")]
+    UnsupportedExpression { name: &'static str },
+
+    #[error("the filter {name} is only supported for filtering flows, since rules annotate flows")]
+    AnnotationsInRules { name: &'static str },
+
+    #[error("the network effect applies to connections, so its conditions can't depend on HTTP messages")]
+    NetworkEffectOnMessage,
+
+    #[error("chance must be between 0 and 1, but is {chance}")]
+    InvalidChance { chance: f64 },
+}
+
+/// Compiles [`DefaultFilters`] and [`DefaultEffects`] into a [`Graph`].
+#[derive(Debug, Default)]
+pub struct DefaultBackend {
+    builder: eval::Builder,
+    effects: Vec<(ExpressionId, DefaultEffects)>,
+
+    /// Whether filters on annotations of flows are supported.
+    annotations: bool,
+
+    /// Expressions that depend on HTTP messages, e.g. on their method or
+    /// direction.
+    message_conditions: HashSet<ExpressionId>,
+}
+
+impl DefaultBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles the rules of a rules file. Multiple rules files can be
+    /// compiled into the same graph.
+    pub fn add(
+        &mut self,
+        rules: &RulesFile<DefaultFilters, DefaultEffects>,
+        config: &Config,
+    ) -> Result<(), compiler::Error<Self>> {
+        Compiler::new(config, self).compile_file(rules)
+    }
+
+    pub fn build(self) -> Rules {
+        Rules {
+            graph: self.builder.build(),
+            effects: Arc::new(RwLock::new(self.effects.into())),
+        }
+    }
+
+    /// Adds an input for a filter. The filter's [`Debug`] representation is
+    /// used as label.
+    fn input<E, M>(&mut self, extractor: E, matcher: M) -> ExpressionId
+    where
+        E: Extractor + Eq + std::hash::Hash + Send + Sync + 'static,
+        M: Match<E> + Debug + Eq + std::hash::Hash + Send + Sync + 'static,
+    {
+        let label = format!("{matcher:?}");
+        let expression = self.builder.input(extractor, matcher).into();
+        self.label(expression, &label);
+        if is_message_extractor::<E>() {
+            self.message_conditions.insert(expression);
+        }
+        expression
+    }
+
+    /// Returns whether `expression` depends on HTTP messages. Network effects
+    /// can't fire under such conditions, since they apply to connections.
+    pub fn is_message_condition(&self, expression: ExpressionId) -> bool {
+        self.message_conditions.contains(&expression)
+    }
+
+    /// Marks `expression` as depending on HTTP messages if any of its `inputs`
+    /// does.
+    fn propagate_message_condition(&mut self, expression: ExpressionId, inputs: &[ExpressionId]) {
+        if expression != self.literal(true)
+            && expression != self.literal(false)
+            && inputs
+                .iter()
+                .any(|input| self.message_conditions.contains(input))
+        {
+            self.message_conditions.insert(expression);
+        }
+    }
+
+    fn inputs<E, M>(&mut self, extractor: E, matchers: &[M]) -> ExpressionId
+    where
+        E: Extractor + Clone + Eq + std::hash::Hash + Send + Sync + 'static,
+        M: Match<E> + Clone + Debug + Eq + std::hash::Hash + Send + Sync + 'static,
+    {
+        let inputs = matchers
+            .iter()
+            .map(|matcher| self.input(extractor.clone(), matcher.clone()))
+            .collect::<Vec<_>>();
+        self.and(&inputs)
+    }
+
+    fn filter_expression(
+        &mut self,
+        filter: &FilterExpression,
+    ) -> Result<ExpressionId, compiler::Error<Self>> {
+        filter.expression().compile(self, &mut |backend, filter| {
+            backend.mitmproxy_filter(filter)
+        })
+    }
+
+    fn mitmproxy_filter(
+        &mut self,
+        filter: &mitmproxy::Filter,
+    ) -> Result<ExpressionId, compiler::Error<Self>> {
+        use mitmproxy::Filter;
+
+        let expression = match filter {
+            Filter::All => self.literal(true),
+            Filter::Domain(regex) => {
+                self.input(TcpExtractor, TcpFilter::Hostname(vec![regex.clone()]))
+            }
+            Filter::Destination(_) => self.input(TcpExtractor, filter.clone()),
+            Filter::Direction(direction) => {
+                self.input(DirectionExtractor, Direction::from(*direction))
+            }
+            #[cfg(feature = "http")]
+            Filter::Asset
+            | Filter::HttpResponseCode(_)
+            | Filter::Header(..)
+            | Filter::Http
+            | Filter::Method(_)
+            | Filter::ContentType(..)
+            | Filter::Url(_) => self.input(HttpExtractor, filter.clone()),
+            #[cfg(not(feature = "http"))]
+            Filter::Asset
+            | Filter::HttpResponseCode(_)
+            | Filter::Header(..)
+            | Filter::Http
+            | Filter::Method(_)
+            | Filter::ContentType(..)
+            | Filter::Url(_) => {
+                return Err(compiler::Error::Backend(Error::Unsupported {
+                    name: filter.name(),
+                    feature: "http",
+                }));
+            }
+            #[cfg(feature = "http")]
+            Filter::Body(direction, regex) => {
+                let direction = self.input(DirectionExtractor, Direction::from(*direction));
+                let body = self.input(BodyExtractor, HttpFilter::Body(vec![regex.clone()]));
+                self.and(&[direction, body])
+            }
+            #[cfg(not(feature = "http"))]
+            Filter::Body(..) => {
+                return Err(compiler::Error::Backend(Error::Unsupported {
+                    name: filter.name(),
+                    feature: "http",
+                }));
+            }
+            Filter::Comment(_) | Filter::Marked | Filter::Marker(_) | Filter::Tag(_) => {
+                if !self.annotations {
+                    return Err(compiler::Error::Backend(Error::AnnotationsInRules {
+                        name: filter.name(),
+                    }));
+                }
+                self.input(AnnotationsExtractor, filter.clone())
+            }
+            _ => {
+                return Err(compiler::Error::Backend(Error::UnsupportedExpression {
+                    name: filter.name(),
+                }));
+            }
+        };
+        Ok(expression)
+    }
+}
+
+impl ModifyGraph for DefaultBackend {
+    #[inline]
+    fn literal(&self, value: bool) -> ExpressionId {
+        self.builder.literal(value)
+    }
+
+    #[inline]
+    fn variable(&mut self) -> VariableId {
+        self.builder.variable()
+    }
+
+    #[inline]
+    fn not(&mut self, input: ExpressionId) -> ExpressionId {
+        let expression = ModifyGraph::not(&mut self.builder, input);
+        self.propagate_message_condition(expression, &[input]);
+        expression
+    }
+
+    #[inline]
+    fn and(&mut self, inputs: &[ExpressionId]) -> ExpressionId {
+        let expression = ModifyGraph::and(&mut self.builder, inputs);
+        self.propagate_message_condition(expression, inputs);
+        expression
+    }
+
+    #[inline]
+    fn or(&mut self, inputs: &[ExpressionId]) -> ExpressionId {
+        let expression = ModifyGraph::or(&mut self.builder, inputs);
+        self.propagate_message_condition(expression, inputs);
+        expression
+    }
+
+    #[inline]
+    fn pin(&mut self, expression_id: ExpressionId) {
+        self.builder.pin(expression_id)
+    }
+
+    #[inline]
+    fn unpin(&mut self, expression_id: ExpressionId) {
+        self.builder.unpin(expression_id)
+    }
+
+    #[inline]
+    fn label(&mut self, expression_id: ExpressionId, label: &str) {
+        self.builder.label(expression_id, label)
+    }
+}
+
+/// A block of rules, with the condition under which its effects fire.
+#[derive(Clone, Copy, Debug)]
+pub struct Scope {
+    condition: ExpressionId,
+}
+
+impl compiler::Backend for DefaultBackend {
+    type Filter = DefaultFilters;
+    type Effect = DefaultEffects;
+    type Scope = Scope;
+    type Error = Error;
+
+    fn scope(
+        &mut self,
+        _parent: Option<&mut Self::Scope>,
+        condition: Option<ExpressionId>,
+    ) -> Self::Scope {
+        // the compiler already includes the parent's condition in `condition`.
+        Scope {
+            condition: condition.unwrap_or_else(|| self.literal(true)),
+        }
+    }
+
+    fn compile_filter(
+        &mut self,
+        _scope: &mut Self::Scope,
+        filter: &Self::Filter,
+    ) -> Result<ExpressionId, compiler::Error<Self>> {
+        let expression = match filter {
+            DefaultFilters::Direction(direction) => {
+                self.input(DirectionExtractor, direction.clone())
+            }
+            DefaultFilters::Host(hosts) => {
+                self.input(TcpExtractor, TcpFilter::Hostname(hosts.clone()))
+            }
+            DefaultFilters::Tcp(filters) => self.inputs(TcpExtractor, filters),
+            DefaultFilters::Tls(filters) => self.inputs(TlsExtractor, filters),
+            #[cfg(feature = "http")]
+            DefaultFilters::Http(filters) => {
+                let inputs = filters
+                    .iter()
+                    .map(|filter| {
+                        if filter.needs_body() {
+                            self.input(BodyExtractor, filter.clone())
+                        }
+                        else {
+                            self.input(HttpExtractor, filter.clone())
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                self.and(&inputs)
+            }
+            #[cfg(not(feature = "http"))]
+            DefaultFilters::Http(_) => {
+                return Err(compiler::Error::Backend(Error::Unsupported {
+                    name: "http",
+                    feature: "http",
+                }));
+            }
+            DefaultFilters::Filter(filter) => self.filter_expression(filter)?,
+            DefaultFilters::Chance(chance) => {
+                if !(0.0..=1.0).contains(chance) {
+                    return Err(compiler::Error::Backend(Error::InvalidChance {
+                        chance: *chance,
+                    }));
+                }
+                self.input(StateExtractor, Chance(*chance))
+            }
+            DefaultFilters::Time(windows) => {
+                let inputs = windows
+                    .iter()
+                    .map(|window| self.input(StateExtractor, window.clone()))
+                    .collect::<Vec<_>>();
+                self.or(&inputs)
+            }
+            DefaultFilters::Nth(filter) => {
+                self.input(StateExtractor, Counted::<_, u64>::new(filter.clone()))
+            }
+            DefaultFilters::Once(per) => {
+                self.input(
+                    StateExtractor,
+                    Counted::<_, u64>::new(NthFilter {
+                        n: vec![1],
+                        per: *per,
+                    }),
+                )
+            }
+            DefaultFilters::Rate(filter) => {
+                self.input(
+                    StateExtractor,
+                    Counted::<_, VecDeque<DateTime<Local>>>::new(filter.clone()),
+                )
+            }
+        };
+        Ok(expression)
+    }
+
+    fn compile_effect(
+        &mut self,
+        scope: &mut Self::Scope,
+        effect: &Self::Effect,
+    ) -> Result<(), compiler::Error<Self>> {
+        if matches!(effect, DefaultEffects::Network(_))
+            && self.is_message_condition(scope.condition)
+        {
+            return Err(compiler::Error::Backend(Error::NetworkEffectOnMessage));
+        }
+        self.pin(scope.condition);
+        self.effects.push((scope.condition, effect.clone()));
+        Ok(())
+    }
+}
+
+type Effects = Arc<[(ExpressionId, DefaultEffects)]>;
+
+/// Compiled rules.
+#[derive(Clone)]
+pub struct Rules {
+    graph: Graph,
+
+    // the graph and the effects are replaced together, so the effects are
+    // behind a lock as well. Evaluators keep the `Arc`s they were created with.
+    effects: Arc<RwLock<Effects>>,
+}
+
+impl Rules {
+    pub fn compile(
+        rules: &RulesFile<DefaultFilters, DefaultEffects>,
+        config: &Config,
+    ) -> Result<Self, compiler::Error<DefaultBackend>> {
+        let mut backend = DefaultBackend::new();
+        backend.add(rules, config)?;
+        Ok(backend.build())
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.read().is_empty()
+    }
+
+    /// Replaces the rules with the ones compiled by `backend`.
+    ///
+    /// This affects all clones of these [`Rules`]. Evaluators that were
+    /// created before keep evaluating the old rules.
+    pub fn replace(&self, backend: DefaultBackend) {
+        let mut effects = self.effects.write();
+        self.graph.replace(backend.builder);
+        *effects = backend.effects.into();
+    }
+
+    /// Creates an evaluator for a connection.
+    pub fn evaluator(&self) -> Evaluator {
+        let effects = self.effects.read();
+        Evaluator {
+            eval: self.graph.evaluator(),
+            effects: effects.clone(),
+            taken: vec![false; effects.len()],
+            connection: Default::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Rules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rules").finish_non_exhaustive()
+    }
+}
+
+/// Evaluates [`Rules`] as information about a connection becomes available.
+///
+/// Every input can only be set once. To evaluate the messages of a
+/// connection, clone the evaluator of the connection for each message.
+#[derive(Clone, Debug)]
+pub struct Evaluator {
+    eval: eval::Evaluator,
+    effects: Effects,
+    taken: Vec<bool>,
+
+    /// Counters with [`CounterKey::Connection`]. They're shared by the clones
+    /// of the connection's evaluator.
+    connection: Arc<ConnectionCounters>,
+}
+
+impl Evaluator {
+    pub fn set_tcp(&mut self, address: &TcpAddress) {
+        self.eval.update().for_each(|_: &TcpExtractor| address);
+    }
+
+    pub fn set_tls(&mut self, tls: &Tls) {
+        self.eval.update().for_each(|_: &TlsExtractor| tls);
+    }
+
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.eval
+            .update()
+            .for_each(|_: &DirectionExtractor| direction.clone());
+    }
+
+    #[cfg(feature = "http")]
+    pub fn set_http(&mut self, message: &HttpMessage) {
+        self.eval.update().for_each(|_: &HttpExtractor| message);
+    }
+
+    /// Returns whether there are filters on the message body that can still
+    /// change whether an effect fires.
+    ///
+    /// Bodies only need to be read for [`set_body`](Self::set_body) if this
+    /// returns `true`.
+    #[cfg(feature = "http")]
+    pub fn needs_body(&self) -> bool {
+        self.eval.is_relevant::<BodyExtractor>()
+    }
+
+    /// Evaluates the filters on the message body, e.g. `json`. Until this is
+    /// called, they are undecided.
+    #[cfg(feature = "http")]
+    pub fn set_body(&mut self, body: &HttpBody) {
+        self.eval.update().for_each(|_: &BodyExtractor| body);
+    }
+
+    /// Evaluates the stateful filters, e.g. `chance` or `nth`.
+    ///
+    /// Stateful filters are only evaluated if they can still change whether
+    /// an effect fires, so this should be called after everything else is
+    /// set. To count e.g. requests, call this on the evaluator of each
+    /// request.
+    pub fn set_state(&mut self, state: &State) {
+        let input = StateInput {
+            state: *state,
+            connection: &self.connection,
+        };
+        self.eval
+            .update()
+            .for_each_relevant(|_: &StateExtractor| &input);
+    }
+
+    /// Returns the effects that fire with what is known so far.
+    pub fn effects(&self) -> impl Iterator<Item = &DefaultEffects> {
+        self.effects
+            .iter()
+            .filter(|(condition, _)| matches!(self.eval.get(*condition), Maybe::Definite(true)))
+            .map(|(_, effect)| effect)
+    }
+
+    /// Returns the effects that fire with what is known so far, and that
+    /// haven't been taken yet.
+    ///
+    /// Effects taken from a connection's evaluator are not returned again by
+    /// evaluators cloned from it afterwards.
+    pub fn take_effects(&mut self) -> Vec<DefaultEffects> {
+        let mut effects = vec![];
+        for ((condition, effect), taken) in self.effects.iter().zip(&mut self.taken) {
+            if !*taken && matches!(self.eval.get(*condition), Maybe::Definite(true)) {
+                *taken = true;
+                effects.push(effect.clone());
+            }
+        }
+        effects
+    }
+
+    /// Takes a snapshot of the rules graph, with the values of expressions
+    /// known so far.
+    #[cfg(feature = "graph-vis")]
+    pub fn snapshot(&self) -> eval::Snapshot {
+        self.eval.snapshot()
+    }
+
+    /// Returns whether there are effects that haven't been taken, and that
+    /// can't be decided yet with what is known so far.
+    pub fn is_pending(&self) -> bool {
+        self.effects
+            .iter()
+            .zip(&self.taken)
+            .any(|((condition, _), taken)| {
+                !taken && matches!(self.eval.get(*condition), Maybe::Indefinite)
+            })
+    }
+}
+
+/// A [filter expression](FilterExpression) compiled on its own, e.g. to
+/// filter which connections are intercepted.
+#[derive(Clone)]
+pub struct CompiledFilter {
+    graph: Graph,
+    condition: ExpressionId,
+}
+
+impl CompiledFilter {
+    pub fn compile(filter: &FilterExpression) -> Result<Self, compiler::Error<DefaultBackend>> {
+        let mut backend = DefaultBackend {
+            annotations: true,
+            ..Default::default()
+        };
+        let condition = backend.filter_expression(filter)?;
+        backend.pin(condition);
+        Ok(Self {
+            graph: backend.builder.build(),
+            condition,
+        })
+    }
+
+    /// Creates an evaluator for a connection.
+    pub fn evaluator(&self) -> FilterEvaluator {
+        FilterEvaluator {
+            eval: self.graph.evaluator(),
+            condition: self.condition,
+        }
+    }
+}
+
+impl std::fmt::Debug for CompiledFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompiledFilter")
+            .field("condition", &self.condition)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Evaluates a [`CompiledFilter`] as information about a connection becomes
+/// available.
+///
+/// Like with [`Evaluator`], every input can only be set once.
+#[derive(Clone, Debug)]
+pub struct FilterEvaluator {
+    eval: eval::Evaluator,
+    condition: ExpressionId,
+}
+
+impl FilterEvaluator {
+    pub fn set_tcp(&mut self, address: &TcpAddress) {
+        self.eval.update().for_each(|_: &TcpExtractor| address);
+    }
+
+    pub fn set_tls(&mut self, tls: &Tls) {
+        self.eval.update().for_each(|_: &TlsExtractor| tls);
+    }
+
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.eval
+            .update()
+            .for_each(|_: &DirectionExtractor| direction.clone());
+    }
+
+    #[cfg(feature = "http")]
+    pub fn set_http(&mut self, message: &HttpMessage) {
+        self.eval.update().for_each(|_: &HttpExtractor| message);
+    }
+
+    /// Evaluates body filters, e.g. `~b`. Until this is called, they are
+    /// undecided.
+    #[cfg(feature = "http")]
+    pub fn set_body(&mut self, body: &HttpBody) {
+        self.eval.update().for_each(|_: &BodyExtractor| body);
+    }
+
+    pub fn set_annotations(&mut self, annotations: &Annotations) {
+        self.eval
+            .update()
+            .for_each(|_: &AnnotationsExtractor| annotations);
+    }
+
+    /// Returns whether the filter matches with what is known so far, or
+    /// `None` if that can't be decided yet.
+    pub fn matches(&self) -> Option<bool> {
+        self.eval.get(self.condition).into()
+    }
+}
+
+/// Extracts the destination address of a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TcpExtractor;
+
+impl Extractor for TcpExtractor {
+    type Data<'d> = &'d TcpAddress;
+}
+
+impl Match<TcpExtractor> for TcpFilter {
+    fn matches(&self, address: &&TcpAddress) -> Maybe {
+        let hostname = address.host.to_string();
+        let matches = match self {
+            TcpFilter::HostPort(host_ports) => {
+                host_ports.iter().any(|host_port| {
+                    host_port.host.is_match(&hostname) && host_port.port.contains(address.port)
+                })
+            }
+            TcpFilter::Hostname(hostnames) => {
+                hostnames.iter().any(|regex| regex.is_match(&hostname))
+            }
+            TcpFilter::DnsName(dns_names) => {
+                matches!(&address.host, HostAddress::DnsName(name) if dns_names.iter().any(|regex| regex.is_match(name)))
+            }
+            TcpFilter::IpAddress(networks) => {
+                matches!(&address.host, HostAddress::IpAddress(ip_address) if networks.iter().any(|network| network.contains(*ip_address)))
+            }
+            TcpFilter::Port(ports) => ports.iter().any(|ports| ports.contains(address.port)),
+        };
+        matches.into()
+    }
+}
+
+impl Match<TcpExtractor> for mitmproxy::Filter {
+    fn matches(&self, address: &&TcpAddress) -> Maybe {
+        match self {
+            mitmproxy::Filter::Destination(regex) => regex.is_match(&address.to_string()),
+            _ => false,
+        }
+        .into()
+    }
+}
+
+/// Information about a TLS connection.
+#[derive(Clone, Debug, Default)]
+pub struct Tls {
+    /// Server name sent by the client.
+    pub server_name: Option<String>,
+
+    /// Common name of the server's certificate.
+    pub common_name: Option<String>,
+
+    /// Distinguished name of the server's certificate.
+    pub distinguished_name: Option<String>,
+}
+
+/// Extracts information about a TLS connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TlsExtractor;
+
+impl Extractor for TlsExtractor {
+    type Data<'d> = &'d Tls;
+}
+
+impl Match<TlsExtractor> for TlsFilter {
+    fn matches(&self, tls: &&Tls) -> Maybe {
+        let (regexes, value) = match self {
+            TlsFilter::ServerName(regexes) => (regexes, &tls.server_name),
+            TlsFilter::CommonName(regexes) => (regexes, &tls.common_name),
+            TlsFilter::DistinguishedName(regexes) => (regexes, &tls.distinguished_name),
+        };
+        value
+            .as_ref()
+            .is_some_and(|value| regexes.iter().any(|regex| regex.is_match(value)))
+            .into()
+    }
+}
+
+/// Returns whether inputs with the extractor `E` are only set for HTTP
+/// messages, and not for connections.
+fn is_message_extractor<E: 'static>() -> bool {
+    let extractor = TypeId::of::<E>();
+    #[cfg(feature = "http")]
+    if extractor == TypeId::of::<HttpExtractor>() || extractor == TypeId::of::<BodyExtractor>() {
+        return true;
+    }
+    extractor == TypeId::of::<DirectionExtractor>()
+        || extractor == TypeId::of::<AnnotationsExtractor>()
+}
+
+/// Extracts the direction of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DirectionExtractor;
+
+impl Extractor for DirectionExtractor {
+    type Data<'d> = Direction;
+}
+
+impl Match<DirectionExtractor> for Direction {
+    fn matches(&self, direction: &Direction) -> Maybe {
+        matches!(
+            (self, direction),
+            (Direction::Both, _)
+                | (_, Direction::Both)
+                | (Direction::Request, Direction::Request)
+                | (Direction::Response, Direction::Response)
+        )
+        .into()
+    }
+}
+
+/// Annotations of a flow, which are set by the `tag`, `mark` and `comment`
+/// effects.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Annotations<'a> {
+    pub tags: &'a [String],
+    pub marker: Option<&'a str>,
+    pub comment: Option<&'a str>,
+}
+
+/// Extracts the [`Annotations`] of a flow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AnnotationsExtractor;
+
+impl Extractor for AnnotationsExtractor {
+    type Data<'d> = &'d Annotations<'d>;
+}
+
+impl Match<AnnotationsExtractor> for mitmproxy::Filter {
+    fn matches(&self, annotations: &&Annotations) -> Maybe {
+        use mitmproxy::Filter;
+
+        match self {
+            Filter::Comment(regex) => {
+                annotations
+                    .comment
+                    .is_some_and(|comment| regex.is_match(comment))
+            }
+            Filter::Marked => annotations.marker.is_some(),
+            Filter::Marker(regex) => {
+                annotations
+                    .marker
+                    .is_some_and(|marker| regex.is_match(marker))
+            }
+            Filter::Tag(regex) => annotations.tags.iter().any(|tag| regex.is_match(tag)),
+            _ => false,
+        }
+        .into()
+    }
+}
+
+/// Information for evaluating stateful filters.
+#[derive(Clone, Copy, Debug)]
+pub struct State<'a> {
+    /// Destination of the connection.
+    pub destination: &'a TcpAddress,
+
+    /// IP address of the client, if it's known.
+    pub client: Option<IpAddr>,
+
+    /// When the connection or message was received.
+    pub time: DateTime<Local>,
+}
+
+/// A [`State`] and the connection it's evaluated for.
+#[derive(Clone, Copy, Debug)]
+pub struct StateInput<'a> {
+    pub state: State<'a>,
+    pub connection: &'a ConnectionCounters,
+}
+
+/// Counters of stateful filters for a connection, by the ID of the filter.
+#[derive(Debug, Default)]
+pub struct ConnectionCounters {
+    counters: Mutex<HashMap<u64, Box<dyn Any + Send>>>,
+}
+
+/// Extracts the [`State`] for stateful filters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StateExtractor;
+
+impl Extractor for StateExtractor {
+    type Data<'d> = &'d StateInput<'d>;
+}
+
+/// Matches with a probability.
+#[derive(Clone, Copy, Debug)]
+struct Chance(f64);
+
+impl PartialEq for Chance {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Chance {}
+
+impl Hash for Chance {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl Match<StateExtractor> for Chance {
+    fn matches(&self, _input: &&StateInput) -> Maybe {
+        (fastrand::f64() < self.0).into()
+    }
+}
+
+impl Match<StateExtractor> for TimeWindow {
+    fn matches(&self, input: &&StateInput) -> Maybe {
+        self.contains(input.state.time.time()).into()
+    }
+}
+
+/// Maximum number of counters that a stateful filter keeps for hosts or
+/// clients. If there are more, idle counters are forgotten first, and then the
+/// least recently used one.
+pub const MAX_COUNTERS: usize = 4096;
+
+/// What a counter of a [`Counted`] filter is for. Counters per connection are
+/// kept in [`ConnectionCounters`] instead.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Global,
+    Host(String),
+    Client(Option<IpAddr>),
+}
+
+impl Key {
+    fn new(per: CounterKey, input: &StateInput) -> Option<Self> {
+        match per {
+            CounterKey::Global => Some(Self::Global),
+            CounterKey::Host => Some(Self::Host(input.state.destination.host.to_string())),
+            CounterKey::Client => Some(Self::Client(input.state.client)),
+            CounterKey::Connection => None,
+        }
+    }
+}
+
+/// A filter with counters of type `C`.
+///
+/// Inputs with equal filters share a variable, and therefore their counters.
+struct Counted<F, C> {
+    filter: F,
+
+    /// Identifies the filter's counters in [`ConnectionCounters`].
+    id: u64,
+
+    /// The counters, and when they were last used.
+    counters: Mutex<HashMap<Key, (C, DateTime<Local>)>>,
+}
+
+impl<F, C> Counted<F, C> {
+    fn new(filter: F) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            filter,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            counters: Default::default(),
+        }
+    }
+}
+
+impl<F, C: Default + Send + 'static> Counted<F, C> {
+    /// Calls `f` with the counter for `input`.
+    ///
+    /// Counters that weren't used for `expiry` are idle, and forgotten first
+    /// if there are too many counters.
+    fn with_counter<R>(
+        &self,
+        per: CounterKey,
+        expiry: Option<TimeDelta>,
+        input: &StateInput,
+        f: impl FnOnce(&mut C) -> R,
+    ) -> R {
+        let Some(key) = Key::new(per, input)
+        else {
+            let mut counters = input.connection.counters.lock();
+            let counter = counters
+                .entry(self.id)
+                .or_insert_with(|| Box::<C>::default())
+                .downcast_mut::<C>()
+                .expect("counter has a different type");
+            return f(counter);
+        };
+
+        let time = input.state.time;
+        let mut counters = self.counters.lock();
+        if counters.len() >= MAX_COUNTERS && !counters.contains_key(&key) {
+            if let Some(expiry) = expiry {
+                counters
+                    .retain(|_, (_, last_used)| time.signed_duration_since(*last_used) < expiry);
+            }
+            if counters.len() >= MAX_COUNTERS {
+                let least_recently_used = counters
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(key) = least_recently_used {
+                    counters.remove(&key);
+                }
+            }
+        }
+
+        let (counter, last_used) = counters.entry(key).or_insert_with(|| (C::default(), time));
+        *last_used = time;
+        f(counter)
+    }
+}
+
+impl<F: PartialEq, C> PartialEq for Counted<F, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.filter == other.filter
+    }
+}
+
+impl<F: Eq, C> Eq for Counted<F, C> {}
+
+impl<F: Hash, C> Hash for Counted<F, C> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.filter.hash(state);
+    }
+}
+
+impl<F: Debug, C> Debug for Counted<F, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.filter.fmt(f)
+    }
+}
+
+impl Match<StateExtractor> for Counted<NthFilter, u64> {
+    fn matches(&self, input: &&StateInput) -> Maybe {
+        self.with_counter(self.filter.per, None, input, |count| {
+            *count += 1;
+            self.filter.n.contains(count).into()
+        })
+    }
+}
+
+impl Match<StateExtractor> for Counted<RateFilter, VecDeque<DateTime<Local>>> {
+    fn matches(&self, input: &&StateInput) -> Maybe {
+        let window = i64::try_from(self.filter.window_secs)
+            .ok()
+            .and_then(TimeDelta::try_seconds)
+            .unwrap_or(TimeDelta::MAX);
+        let time = input.state.time;
+
+        self.with_counter(self.filter.per, Some(window), input, |times| {
+            while times
+                .front()
+                .is_some_and(|first| time.signed_duration_since(*first) >= window)
+            {
+                times.pop_front();
+            }
+            times.push_back(time);
+
+            // we only need to know whether there are more than `more_than`, so
+            // older times can be forgotten.
+            if times.len() as u64 > self.filter.more_than.saturating_add(1) {
+                times.pop_front();
+            }
+
+            (times.len() as u64 > self.filter.more_than).into()
+        })
+    }
+}
+
+/// A HTTP request or response.
+///
+/// For a response, `method` and `uri` are those of the request it responds
+/// to.
+#[cfg(feature = "http")]
+#[derive(Clone, Copy, Debug)]
+pub struct HttpMessage<'a> {
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+
+    /// Headers of the request.
+    pub request_headers: &'a HeaderMap,
+
+    /// Headers of this message, i.e. the response headers for a response.
+    pub headers: &'a HeaderMap,
+
+    /// Status of the response, or `None` for a request.
+    pub status: Option<StatusCode>,
+}
+
+/// Extracts information about a HTTP message.
+#[cfg(feature = "http")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HttpExtractor;
+
+#[cfg(feature = "http")]
+impl Extractor for HttpExtractor {
+    type Data<'d> = &'d HttpMessage<'d>;
+}
+
+#[cfg(feature = "http")]
+impl Match<HttpExtractor> for HttpFilter {
+    fn matches(&self, message: &&HttpMessage) -> Maybe {
+        fn any_match(regexes: &[Regex], value: &str) -> bool {
+            regexes.iter().any(|regex| regex.is_match(value))
+        }
+
+        let matches = match self {
+            HttpFilter::Method(methods) => any_match(methods, message.method.as_str()),
+            HttpFilter::Url(urls) => any_match(urls, &message.uri.to_string()),
+            HttpFilter::Header { name, value } => {
+                message.headers.iter().any(|(header_name, header_value)| {
+                    name.is_match(header_name.as_str())
+                        && value.is_match(&String::from_utf8_lossy(header_value.as_bytes()))
+                })
+            }
+            HttpFilter::ContentType(content_types) => {
+                header_values(message.headers, header::CONTENT_TYPE)
+                    .any(|content_type| any_match(content_types, content_type))
+            }
+            HttpFilter::Cookie(cookies) => {
+                header_values(message.request_headers, header::COOKIE)
+                    .flat_map(|cookie| cookie.split(';'))
+                    .any(|cookie| any_match(cookies, cookie.trim()))
+            }
+            HttpFilter::Host(hosts) => {
+                header_values(message.request_headers, header::HOST)
+                    .chain(message.uri.host())
+                    .any(|host| any_match(hosts, host))
+            }
+            HttpFilter::Status(statuses) => {
+                message.status.is_some_and(|status| {
+                    statuses
+                        .iter()
+                        .any(|statuses| statuses.contains(status.as_u16()))
+                })
+            }
+            // these are evaluated once the body is known.
+            HttpFilter::Body(_) | HttpFilter::Json(_) | HttpFilter::BodySize { .. } => {
+                return Maybe::Indefinite;
+            }
+        };
+        matches.into()
+    }
+}
+
+/// The body of a HTTP request or response.
+#[cfg(feature = "http")]
+#[derive(Debug)]
+pub struct HttpBody<'a> {
+    data: &'a [u8],
+
+    /// The body parsed as JSON, or `None` if it's not JSON. This is only
+    /// parsed if a filter needs it.
+    json: OnceCell<Option<serde_json::Value>>,
+}
+
+#[cfg(feature = "http")]
+impl<'a> HttpBody<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            json: OnceCell::new(),
+        }
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn json(&self) -> Option<&serde_json::Value> {
+        self.json
+            .get_or_init(|| serde_json::from_slice(self.data).ok())
+            .as_ref()
+    }
+}
+
+/// Extracts the body of a HTTP message.
+#[cfg(feature = "http")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BodyExtractor;
+
+#[cfg(feature = "http")]
+impl Extractor for BodyExtractor {
+    type Data<'d> = &'d HttpBody<'d>;
+}
+
+#[cfg(feature = "http")]
+impl Match<BodyExtractor> for HttpFilter {
+    fn matches(&self, body: &&HttpBody) -> Maybe {
+        let matches = match self {
+            HttpFilter::Body(regexes) => {
+                let text = String::from_utf8_lossy(body.data());
+                regexes.iter().any(|regex| regex.is_match(&text))
+            }
+            HttpFilter::Json(filters) => {
+                body.json()
+                    .is_some_and(|json| filters.iter().all(|filter| json_matches(filter, json)))
+            }
+            HttpFilter::BodySize { min, max } => {
+                let size = body.data().len() as u64;
+                min.unwrap_or(0) <= size && size <= max.unwrap_or(u64::MAX)
+            }
+            _ => false,
+        };
+        matches.into()
+    }
+}
+
+#[cfg(feature = "http")]
+fn json_matches(filter: &JsonFilter, json: &serde_json::Value) -> bool {
+    let values = filter.path.select(json);
+    if !filter.exists {
+        return values.is_empty();
+    }
+
+    values.into_iter().any(|value| {
+        filter
+            .equals
+            .iter()
+            .all(|expected| json_equals(value, expected))
+            && filter.matches.iter().all(|regex| {
+                match value {
+                    serde_json::Value::String(string) => regex.is_match(string),
+                    _ => regex.is_match(&value.to_string()),
+                }
+            })
+    })
+}
+
+/// Compares a JSON value with a value from a rules file.
+#[cfg(feature = "http")]
+fn json_equals(json: &serde_json::Value, yaml: &serde_yml::Value) -> bool {
+    use serde_json::Value as Json;
+    use serde_yml::Value as Yaml;
+
+    match (json, yaml) {
+        (_, Yaml::Tagged(tagged)) => json_equals(json, &tagged.value),
+        (Json::Null, Yaml::Null) => true,
+        (Json::Bool(a), Yaml::Bool(b)) => a == b,
+        (Json::Number(a), Yaml::Number(b)) => {
+            if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+                a == b
+            }
+            else if let (Some(a), Some(b)) = (a.as_u64(), b.as_u64()) {
+                a == b
+            }
+            else {
+                a.as_f64() == b.as_f64()
+            }
+        }
+        (Json::String(a), Yaml::String(b)) => a == b,
+        (Json::Array(a), Yaml::Sequence(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_equals(a, b))
+        }
+        (Json::Object(a), Yaml::Mapping(b)) => {
+            a.len() == b.len()
+                && b.iter().all(|(key, b)| {
+                    key.as_str()
+                        .and_then(|key| a.get(key))
+                        .is_some_and(|a| json_equals(a, b))
+                })
+        }
+        _ => false,
+    }
+}
+
+#[cfg(feature = "http")]
+impl Match<HttpExtractor> for mitmproxy::Filter {
+    fn matches(&self, message: &&HttpMessage) -> Maybe {
+        use mitmproxy::{
+            Direction,
+            Filter,
+        };
+
+        /// Returns the headers of the request and/or response, depending on
+        /// `direction`.
+        fn headers<'a>(
+            message: &'a HttpMessage,
+            direction: &Direction,
+        ) -> impl Iterator<Item = &'a HeaderMap> {
+            let request =
+                (!matches!(direction, Direction::Response)).then_some(message.request_headers);
+            let response = (!matches!(direction, Direction::Request) && message.status.is_some())
+                .then_some(message.headers);
+            request.into_iter().chain(response)
+        }
+
+        let matches = match self {
+            Filter::Asset => {
+                message.status.is_some()
+                    && header_values(message.headers, header::CONTENT_TYPE).any(|content_type| {
+                        let content_type =
+                            content_type.split(';').next().unwrap_or_default().trim();
+                        ASSET_CONTENT_TYPES.contains(&content_type)
+                            || ASSET_CONTENT_TYPE_PREFIXES
+                                .iter()
+                                .any(|prefix| content_type.starts_with(prefix))
+                    })
+            }
+            Filter::HttpResponseCode(code) => {
+                message
+                    .status
+                    .is_some_and(|status| status.as_u16() == *code)
+            }
+            Filter::Header(direction, regex) => {
+                headers(message, direction).any(|headers| {
+                    headers.iter().any(|(name, value)| {
+                        regex.is_match(&format!(
+                            "{}: {}",
+                            name,
+                            String::from_utf8_lossy(value.as_bytes())
+                        ))
+                    })
+                })
+            }
+            Filter::Http => true,
+            Filter::Method(regex) => regex.is_match(message.method.as_str()),
+            Filter::ContentType(direction, regex) => {
+                headers(message, direction).any(|headers| {
+                    header_values(headers, header::CONTENT_TYPE)
+                        .any(|content_type| regex.is_match(content_type))
+                })
+            }
+            Filter::Url(regex) => regex.is_match(&message.uri.to_string()),
+            _ => false,
+        };
+        matches.into()
+    }
+}
+
+/// Content types of responses matched by `~a`. These are the same that
+/// mitmproxy uses.
+#[cfg(feature = "http")]
+const ASSET_CONTENT_TYPES: &[&str] = &[
+    "text/javascript",
+    "application/x-javascript",
+    "application/javascript",
+    "text/css",
+];
+
+#[cfg(feature = "http")]
+const ASSET_CONTENT_TYPE_PREFIXES: &[&str] = &["image/", "font/", "application/font"];
+
+#[cfg(feature = "http")]
+fn header_values(
+    headers: &HeaderMap,
+    name: impl header::AsHeaderName,
+) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Annotations,
+        CompiledFilter,
+        DefaultBackend,
+        Error,
+        Evaluator,
+        Rules,
+        State,
+        Tls,
+    };
+    use crate::{
+        address::TcpAddress,
+        rule::{
+            compiler::{
+                self,
+                Config,
+            },
+            file::{
+                self,
+                DefaultEffects,
+            },
+        },
+    };
+
+    #[test]
+    fn it_evaluates_effects_for_a_connection() {
+        let rules = file::from_reader(
+            r#"
+rules:
+  - if:
+      - tcp:
+          - port: [443]
+    then:
+      rules:
+        - if:
+            - tls:
+                - server-name: ["^example\\.com$"]
+          then:
+            effects:
+              - drop
+          else:
+            effects:
+              - interrupt: {}
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let rules = Rules::compile(&rules, &Config::default()).unwrap();
+
+        let effects = |address: &str, server_name: &str| {
+            let mut eval = rules.evaluator();
+            eval.set_tcp(&address.parse::<TcpAddress>().unwrap());
+            eval.set_tls(&Tls {
+                server_name: Some(server_name.to_owned()),
+                ..Default::default()
+            });
+            eval.effects().map(DefaultEffects::name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(effects("example.com:443", "example.com"), vec!["drop"]);
+        assert_eq!(effects("example.com:443", "example.org"), vec!["interrupt"]);
+        assert!(effects("example.com:80", "example.com").is_empty());
+    }
+
+    #[test]
+    fn it_keeps_old_rules_for_existing_evaluators() {
+        let compile = |yaml: &str| {
+            let mut backend = DefaultBackend::new();
+            backend
+                .add(
+                    &file::from_reader(yaml.as_bytes()).unwrap(),
+                    &Config::default(),
+                )
+                .unwrap();
+            backend
+        };
+
+        let rules = compile("rules:\n  - then:\n      effects:\n        - drop\n").build();
+        let mut old = rules.evaluator();
+
+        rules.replace(compile("rules: []\n"));
+        let mut new = rules.evaluator();
+
+        let address = "example.com:443".parse().unwrap();
+        old.set_tcp(&address);
+        new.set_tcp(&address);
+        assert_eq!(old.effects().count(), 1);
+        assert_eq!(new.effects().count(), 0);
+        assert!(rules.is_empty());
+    }
+
+    #[test]
+    fn it_only_counts_when_the_count_is_relevant() {
+        let rules = file::from_reader(
+            r#"
+rules:
+  - if:
+      - tcp:
+          - port: [443]
+      - nth: {n: [2], per: host}
+    then:
+      effects:
+        - drop
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let rules = Rules::compile(&rules, &Config::default()).unwrap();
+        let time = chrono::Local::now();
+
+        let drops = |address: &str| {
+            let address = address.parse::<TcpAddress>().unwrap();
+            let mut eval = rules.evaluator();
+            eval.set_tcp(&address);
+            eval.set_state(&State {
+                destination: &address,
+                client: None,
+                time,
+            });
+            eval.effects().count() == 1
+        };
+
+        assert!(!drops("example.com:443"));
+        assert!(!drops("example.com:80"));
+        assert!(drops("example.com:443"));
+        assert!(!drops("example.org:443"));
+        assert!(!drops("example.com:443"));
+    }
+
+    #[test]
+    fn it_counts_per_connection() {
+        let rules = file::from_reader(
+            r#"
+rules:
+  - if:
+      - nth: {n: [2], per: connection}
+    then:
+      effects:
+        - drop
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let rules = Rules::compile(&rules, &Config::default()).unwrap();
+        let address = "example.com:443".parse::<TcpAddress>().unwrap();
+        let state = State {
+            destination: &address,
+            client: None,
+            time: chrono::Local::now(),
+        };
+
+        let drops = |connection: &Evaluator| {
+            let mut eval = connection.clone();
+            eval.set_state(&state);
+            eval.effects().count() == 1
+        };
+
+        let connection = rules.evaluator();
+        assert!(!drops(&connection));
+        assert!(drops(&connection));
+        assert!(!drops(&connection));
+
+        let other = rules.evaluator();
+        assert!(!drops(&other));
+        assert!(drops(&other));
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn it_ignores_unused_conditions() {
+        use super::HttpMessage;
+        use crate::rule::file::Direction;
+
+        let rules = file::from_reader(
+            r#"
+conditions:
+  unused:
+    - http:
+        - json:
+            - path: $.error
+              equals: 42
+    - nth: {n: [2], per: connection}
+rules:
+  - if:
+      - host: ["^example\\.com$"]
+    then:
+      effects:
+        - drop
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let rules = Rules::compile(&rules, &Config::default()).unwrap();
+        let address = "example.com:443".parse::<TcpAddress>().unwrap();
+        let method = hyper::Method::GET;
+        let uri = "/".parse().unwrap();
+        let headers = hyper::HeaderMap::new();
+
+        let mut eval = rules.evaluator();
+        eval.set_tcp(&address);
+        eval.set_direction(Direction::Request);
+        eval.set_http(&HttpMessage {
+            method: &method,
+            uri: &uri,
+            request_headers: &headers,
+            headers: &headers,
+            status: None,
+        });
+        assert!(!eval.needs_body());
+
+        eval.set_state(&State {
+            destination: &address,
+            client: None,
+            time: chrono::Local::now(),
+        });
+        assert!(eval.connection.counters.lock().is_empty());
+        assert_eq!(eval.effects().count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn it_evaluates_body_filters_once_the_body_is_known() {
+        use super::{
+            HttpBody,
+            HttpMessage,
+        };
+        use crate::rule::file::Direction;
+
+        let rules = file::from_reader(
+            r#"
+rules:
+  - if:
+      - direction: response
+      - http:
+          - status: [5xx, 400..404]
+          - json:
+              - path: $.error.code
+                equals: 42
+    then:
+      effects:
+        - drop
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let rules = Rules::compile(&rules, &Config::default()).unwrap();
+
+        let drops = |status: u16, body: &str| {
+            let method = hyper::Method::GET;
+            let uri = "/".parse().unwrap();
+            let headers = hyper::HeaderMap::new();
+
+            let mut eval = rules.evaluator();
+            eval.set_tcp(&"example.com:443".parse().unwrap());
+            eval.set_direction(Direction::Response);
+            eval.set_http(&HttpMessage {
+                method: &method,
+                uri: &uri,
+                request_headers: &headers,
+                headers: &headers,
+                status: Some(hyper::StatusCode::from_u16(status).unwrap()),
+            });
+            let needs_body = eval.needs_body();
+            eval.set_body(&HttpBody::new(body.as_bytes()));
+            (needs_body, eval.effects().count() == 1)
+        };
+
+        assert_eq!(drops(500, r#"{"error": {"code": 42}}"#), (true, true));
+        assert_eq!(drops(403, r#"{"error": {"code": 42.0}}"#), (true, true));
+        assert_eq!(drops(500, r#"{"error": {"code": 43}}"#), (true, false));
+        assert_eq!(drops(500, "not json"), (true, false));
+        assert_eq!(drops(200, r#"{"error": {"code": 42}}"#), (false, false));
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn it_evaluates_filter_expressions() {
+        use super::HttpMessage;
+
+        let filter =
+            CompiledFilter::compile(&"~d example.com & ~m POST & !~a".parse().unwrap()).unwrap();
+
+        let matches = |address: &str, method: &str, content_type: Option<&str>| {
+            let mut eval = filter.evaluator();
+            eval.set_tcp(&address.parse().unwrap());
+            let before_http = eval.matches();
+
+            let method = method.parse().unwrap();
+            let uri = "/".parse().unwrap();
+            let request_headers = Default::default();
+            let mut headers = hyper::HeaderMap::new();
+            if let Some(content_type) = content_type {
+                headers.insert(hyper::header::CONTENT_TYPE, content_type.parse().unwrap());
+            }
+            eval.set_http(&HttpMessage {
+                method: &method,
+                uri: &uri,
+                request_headers: &request_headers,
+                headers: &headers,
+                status: content_type.map(|_| hyper::StatusCode::OK),
+            });
+            (before_http, eval.matches())
+        };
+
+        assert_eq!(
+            matches("example.org:443", "POST", None),
+            (Some(false), Some(false))
+        );
+        assert_eq!(matches("example.com:443", "POST", None), (None, Some(true)));
+        assert_eq!(matches("example.com:443", "GET", None), (None, Some(false)));
+        assert_eq!(
+            matches("example.com:443", "POST", Some("image/png")),
+            (None, Some(false))
+        );
+        assert_eq!(
+            matches("example.com:443", "POST", Some("text/html")),
+            (None, Some(true))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "http")]
+    fn it_evaluates_body_filter_expressions() {
+        use super::HttpBody;
+        use crate::rule::file::Direction;
+
+        let filter = CompiledFilter::compile(&"~bs error".parse().unwrap()).unwrap();
+
+        let matches = |direction: Direction, body: &str| {
+            let mut eval = filter.evaluator();
+            eval.set_direction(direction);
+            let before_body = eval.matches();
+            eval.set_body(&HttpBody::new(body.as_bytes()));
+            (before_body, eval.matches())
+        };
+
+        assert_eq!(
+            matches(Direction::Response, "an error occurred"),
+            (None, Some(true))
+        );
+        assert_eq!(matches(Direction::Response, "ok"), (None, Some(false)));
+        assert_eq!(
+            matches(Direction::Request, "an error occurred"),
+            (Some(false), Some(false))
+        );
+    }
+
+    #[test]
+    fn it_evaluates_annotation_filters() {
+        let filter =
+            CompiledFilter::compile(&"~tag ^auth$ & !~marker red".parse().unwrap()).unwrap();
+
+        let matches = |tags: &[&str], marker: Option<&str>| {
+            let tags = tags.iter().map(|tag| tag.to_string()).collect::<Vec<_>>();
+            let mut eval = filter.evaluator();
+            eval.set_annotations(&Annotations {
+                tags: &tags,
+                marker,
+                comment: None,
+            });
+            eval.matches()
+        };
+
+        assert_eq!(matches(&["auth"], None), Some(true));
+        assert_eq!(matches(&["auth"], Some("blue")), Some(true));
+        assert_eq!(matches(&["auth"], Some("red")), Some(false));
+        assert_eq!(matches(&["authz"], None), Some(false));
+
+        let rules = file::from_reader(
+            r#"
+rules:
+  - if:
+      - filter: "~marked"
+    then:
+      effects:
+        - drop
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert!(Rules::compile(&rules, &Config::default()).is_err());
+    }
+
+    #[test]
+    fn it_rejects_network_effects_on_messages() {
+        let compile = |yaml: &str| {
+            let rules = file::from_reader(yaml.as_bytes()).unwrap();
+            Rules::compile(&rules, &Config::default())
+        };
+
+        let result = compile(
+            r#"
+rules:
+  - if:
+      - http:
+          - method: [POST]
+    then:
+      effects:
+        - network:
+            latency-ms: 100
+"#,
+        );
+        assert!(matches!(
+            result,
+            Err(compiler::Error::Backend(Error::NetworkEffectOnMessage))
+        ));
+
+        compile(
+            r#"
+rules:
+  - if:
+      - tcp:
+          - port: [443]
+    then:
+      effects:
+        - network:
+            latency-ms: 100
+"#,
+        )
+        .unwrap();
+    }
+}
@@ -1,10 +1,16 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+};
+
+use indexmap::IndexMap;
 
 use super::file::{
     Block,
     Condition,
     Conditions,
     Rule,
+    RulesFile,
     SubCondition,
 };
 use crate::util::boolean::{
@@ -19,6 +25,9 @@ pub enum Error<B: Backend + ?Sized> {
 
     #[error("the filter {name} requires user interaction")]
     RequiresUserInteraction { name: Cow<'static, str> },
+
+    #[error("unknown condition: {name}")]
+    UnknownCondition { name: String },
 }
 
 #[derive(Debug, Default)]
@@ -30,6 +39,13 @@ pub struct Config {
 pub struct Compiler<'a, B> {
     config: &'a Config,
     backend: &'a mut B,
+
+    /// Path of the rule that is being compiled, e.g. `rules[1].then.rules[0]`.
+    /// Conditions are labelled with it.
+    path: Vec<String>,
+
+    /// Named conditions that were compiled by [`Compiler::compile_conditions`].
+    conditions: HashMap<String, ExpressionId>,
 }
 
 impl<'a, B> Compiler<'a, B>
@@ -37,7 +53,40 @@ where
     B: Backend,
 {
     pub fn new(config: &'a Config, backend: &'a mut B) -> Self {
-        Self { config, backend }
+        Self {
+            config,
+            backend,
+            path: vec![],
+            conditions: HashMap::new(),
+        }
+    }
+
+    pub fn backend(&mut self) -> &mut B {
+        self.backend
+    }
+
+    /// Compiles the named conditions and the rules of a rules file.
+    pub fn compile_file(&mut self, file: &RulesFile<B::Filter, B::Effect>) -> Result<(), Error<B>> {
+        self.compile_conditions(&file.conditions)?;
+        self.compile_block(&file.rules, None, None)
+    }
+
+    /// Compiles named conditions, so that conditions compiled afterwards can
+    /// refer to them. Named conditions with the same name as one that was
+    /// compiled before replace it.
+    pub fn compile_conditions(
+        &mut self,
+        conditions: &IndexMap<String, Conditions<B::Filter>>,
+    ) -> Result<(), Error<B>> {
+        let mut scope = self.backend.scope(None, None);
+        for (name, condition) in conditions {
+            let expression =
+                self.compile_conditions_with(condition, ModifyGraph::and, &mut scope)?;
+            self.backend
+                .label(expression, &format!("conditions.{name}"));
+            self.conditions.insert(name.clone(), expression);
+        }
+        Ok(())
     }
 
     pub fn compile_block(
@@ -48,8 +97,10 @@ where
     ) -> Result<(), Error<B>> {
         let mut scope = self.backend.scope(parent_scope, condition);
 
-        for rule in &block.rules {
+        for (index, rule) in block.rules.iter().enumerate() {
+            self.path.push(format!("rules[{index}]"));
             self.compile_rule(rule, condition, &mut scope)?;
+            self.path.pop();
         }
 
         for effect in &block.effects {
@@ -65,33 +116,58 @@ where
         condition: Option<ExpressionId>,
         scope: &mut B::Scope,
     ) -> Result<(), Error<B>> {
-        if !rule.then.is_empty() || rule.alt.is_empty() {
-            let mut cond_expressions =
-                Vec::with_capacity(rule.condition.0.len() + condition.map_or(0, |_| 1));
-            cond_expressions.extend(condition);
-            self.compile_conditions_into(&rule.condition, &mut cond_expressions, scope)?;
-            let then_cond = self.backend.and(&cond_expressions);
+        if !rule.then.is_empty() || !rule.alt.is_empty() {
+            let rule_cond =
+                self.compile_conditions_with(&rule.condition, ModifyGraph::and, scope)?;
 
             if !rule.then.is_empty() {
+                let then_cond = self.and_with(condition, rule_cond);
+                self.path.push("then".to_owned());
+                self.label(then_cond);
                 self.compile_block(&rule.then, Some(then_cond), Some(scope))?;
+                self.path.pop();
             }
 
             if !rule.alt.is_empty() {
-                let alt_cond = self.backend.not(then_cond);
+                // the else-branch applies if the parent condition holds, but the rule's
+                // condition doesn't.
+                let not_rule_cond = self.backend.not(rule_cond);
+                let alt_cond = self.and_with(condition, not_rule_cond);
+                self.path.push("else".to_owned());
+                self.label(alt_cond);
                 self.compile_block(&rule.alt, Some(alt_cond), Some(scope))?;
+                self.path.pop();
             }
         }
 
         Ok(())
     }
 
+    fn label(&mut self, expression: ExpressionId) {
+        let path = self.path.join(".");
+        self.backend.label(expression, &path);
+    }
+
+    fn and_with(
+        &mut self,
+        condition: Option<ExpressionId>,
+        expression: ExpressionId,
+    ) -> ExpressionId {
+        if let Some(condition) = condition {
+            self.backend.and(&[condition, expression])
+        }
+        else {
+            expression
+        }
+    }
+
     pub fn compile_conditions_into(
         &mut self,
         conditions: &Conditions<B::Filter>,
         expressions: &mut Vec<ExpressionId>,
         scope: &mut B::Scope,
     ) -> Result<(), Error<B>> {
-        expressions.reserve(expressions.len());
+        expressions.reserve(conditions.0.len());
         for condition in &conditions.0 {
             expressions.push(self.compile_condition(condition, scope)?);
         }
@@ -131,6 +207,13 @@ where
                 // explicit or
                 self.compile_conditions_with(or, ModifyGraph::or, scope)?
             }
+            Condition::Ref(reference) => {
+                *self.conditions.get(&reference.condition).ok_or_else(|| {
+                    Error::UnknownCondition {
+                        name: reference.condition.clone(),
+                    }
+                })?
+            }
             Condition::Terminal(terminal) => self.backend.compile_filter(scope, terminal)?,
         };
         Ok(expression)
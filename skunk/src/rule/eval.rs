@@ -30,13 +30,23 @@ use crate::util::boolean::{
     ModifyGraph,
     VariableId,
 };
+#[cfg(feature = "graph-vis")]
+pub use crate::util::boolean::{
+    Snapshot,
+    SnapshotNode,
+    SnapshotNodeKind,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Builder {
     inner: GraphInner,
 }
 
 impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     #[inline]
     pub fn literal(&mut self, value: bool) -> ExpressionId {
         self.inner.graph.literal(value)
@@ -45,8 +55,8 @@ impl Builder {
     #[inline]
     pub fn input<E, M>(&mut self, extractor: E, matcher: M) -> VariableId
     where
-        E: Extractor + Eq + Hash + 'static,
-        M: Match<E> + Eq + Hash + 'static,
+        E: Extractor + Eq + Hash + Send + Sync + 'static,
+        M: Match<E> + Eq + Hash + Send + Sync + 'static,
     {
         self.inner
             .inputs
@@ -68,9 +78,9 @@ impl Builder {
         self.inner.graph.or(inputs)
     }
 
-    #[inline]
-    #[allow(clippy::arc_with_non_send_sync)] // fixme
-    pub fn build(self) -> Graph {
+    /// Optimizes the graph and returns it.
+    pub fn build(mut self) -> Graph {
+        self.inner.graph.optimize();
         Graph {
             inner: Arc::new(RwLock::new(Arc::new(RwLock::new(self.inner)))),
         }
@@ -82,6 +92,48 @@ impl Builder {
     }
 }
 
+impl ModifyGraph for Builder {
+    #[inline]
+    fn literal(&self, value: bool) -> ExpressionId {
+        self.inner.graph.literal(value)
+    }
+
+    #[inline]
+    fn variable(&mut self) -> VariableId {
+        self.inner.graph.variable()
+    }
+
+    #[inline]
+    fn not(&mut self, input: ExpressionId) -> ExpressionId {
+        self.inner.graph.not(input)
+    }
+
+    #[inline]
+    fn and(&mut self, inputs: &[ExpressionId]) -> ExpressionId {
+        self.inner.graph.and(inputs)
+    }
+
+    #[inline]
+    fn or(&mut self, inputs: &[ExpressionId]) -> ExpressionId {
+        self.inner.graph.or(inputs)
+    }
+
+    #[inline]
+    fn pin(&mut self, expression_id: ExpressionId) {
+        self.inner.graph.pin(expression_id)
+    }
+
+    #[inline]
+    fn unpin(&mut self, expression_id: ExpressionId) {
+        self.inner.graph.unpin(expression_id)
+    }
+
+    #[inline]
+    fn label(&mut self, expression_id: ExpressionId, label: &str) {
+        self.inner.graph.label(expression_id, label)
+    }
+}
+
 pub struct Modify {
     inner: ArcRwLockWriteGuard<RawRwLock, GraphInner>,
 }
@@ -95,8 +147,8 @@ impl Modify {
     #[inline]
     pub fn input<E, M>(&mut self, extractor: E, matcher: M) -> VariableId
     where
-        E: Extractor + Eq + Hash + 'static,
-        M: Match<E> + Eq + Hash + 'static,
+        E: Extractor + Eq + Hash + Send + Sync + 'static,
+        M: Match<E> + Eq + Hash + Send + Sync + 'static,
     {
         let inner = self.inner.deref_mut();
         inner
@@ -120,7 +172,7 @@ impl Modify {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct GraphInner {
     graph: boolean::Graph,
     inputs: Inputs,
@@ -141,9 +193,9 @@ impl Graph {
         }
     }
 
-    #[inline]
-    #[allow(clippy::arc_with_non_send_sync)] // fixme
-    pub fn replace(&self, builder: Builder) {
+    /// Optimizes the graph built by `builder` and replaces this graph with it.
+    pub fn replace(&self, mut builder: Builder) {
+        builder.inner.graph.optimize();
         let mut inner = self.inner.write();
         *inner = Arc::new(RwLock::new(builder.inner));
     }
@@ -153,6 +205,20 @@ impl Graph {
         let eval = inner.read().graph.evaluator();
         Evaluator { eval, inner }
     }
+
+    /// Takes a snapshot of the graph for visualization.
+    #[cfg(feature = "graph-vis")]
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.read().read().graph.snapshot(None)
+    }
+
+    /// Writes the graph to `writer` as a [graphviz dot file][1].
+    ///
+    /// [1]: https://graphviz.org/doc/info/lang.html
+    #[cfg(feature = "graph-vis")]
+    pub fn write_dot(&self, writer: impl std::io::Write) -> Result<(), std::io::Error> {
+        self.inner.read().read().graph.write_dot(writer)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -162,15 +228,39 @@ pub struct Evaluator {
 }
 
 impl Evaluator {
-    pub fn update<E, F>(&mut self) -> UpdateInputs
-    where
-        E: Extractor,
-    {
+    pub fn update(&mut self) -> UpdateInputs<'_> {
         UpdateInputs {
             eval: &mut self.eval,
             inner: self.inner.read(),
         }
     }
+
+    #[inline]
+    pub fn get(&self, expression_id: ExpressionId) -> Maybe {
+        self.eval.get(expression_id)
+    }
+
+    /// Returns whether any input with extractor `E` can still change the value
+    /// of an effect's condition.
+    ///
+    /// This can be used to avoid extracting data that isn't needed, e.g. when
+    /// that is expensive.
+    pub fn is_relevant<E>(&self) -> bool
+    where
+        E: Extractor + 'static,
+    {
+        let inner = self.inner.read();
+        inner.inputs.input_set::<E>().is_some_and(|set| {
+            set.iter()
+                .any(|var| self.eval.is_relevant(&inner.graph, var.variable.into()))
+        })
+    }
+
+    /// Takes a snapshot of the graph with the values known so far.
+    #[cfg(feature = "graph-vis")]
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.read().graph.snapshot(Some(&self.eval))
+    }
 }
 
 pub struct UpdateInputs<'a> {
@@ -194,6 +284,32 @@ impl<'a> UpdateInputs<'a> {
         }
     }
 
+    /// Like [`Self::for_each`], but only sets inputs whose value can still
+    /// change the value of an effect's condition.
+    ///
+    /// This is meant for stateful matchers (e.g. counters), which should only
+    /// be evaluated if their result is actually needed.
+    pub fn for_each_relevant<'d, E, F>(&mut self, mut f: F)
+    where
+        E: Extractor + 'static,
+        F: FnMut(&E) -> E::Data<'d>,
+    {
+        if let Some(set) = self.inner.inputs.input_set::<E>() {
+            for var in set.iter() {
+                if !self
+                    .eval
+                    .is_relevant(&self.inner.graph, var.variable.into())
+                {
+                    continue;
+                }
+                let data = f(&var.extractor);
+                if let Maybe::Definite(value) = var.matcher.matches(&data) {
+                    self.eval.set(&self.inner.graph, var.variable, value);
+                }
+            }
+        }
+    }
+
     pub fn fold_map<'d, E, S, F, M>(&mut self, mut init: S, mut fold: F, mut map: M)
     where
         E: Extractor + 'static,
@@ -214,9 +330,9 @@ impl<'a> UpdateInputs<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct Inputs {
-    inputs: HashMap<TypeId, Box<dyn Any>>,
+    inputs: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
 }
 
 impl Inputs {
@@ -227,8 +343,8 @@ impl Inputs {
         create_variable: impl FnOnce() -> VariableId,
     ) -> VariableId
     where
-        E: Extractor + Eq + Hash + 'static,
-        M: Match<E> + Eq + Hash + 'static,
+        E: Extractor + Eq + Hash + Send + Sync + 'static,
+        M: Match<E> + Eq + Hash + Send + Sync + 'static,
     {
         let set = self
             .inputs
@@ -252,7 +368,7 @@ impl Inputs {
 
 pub struct Input<E: Extractor> {
     extractor: E,
-    matcher: Box<dyn Match<E>>,
+    matcher: Box<dyn AnyMatch<E>>,
     hash: u64,
     variable: VariableId,
 }
@@ -263,7 +379,7 @@ impl<E: Extractor> Input<E> {
     }
 
     pub fn matcher(&self) -> &dyn Match<E> {
-        &self.matcher
+        self.matcher.as_match()
     }
 
     pub fn variable_id(&self) -> VariableId {
@@ -294,7 +410,7 @@ where
         create_variable: impl FnOnce() -> VariableId,
     ) -> VariableId
     where
-        M: Match<E> + Eq + Hash + 'static,
+        M: Match<E> + Eq + Hash + Send + Sync + 'static,
         E: Eq + Hash + 'static,
     {
         let mut hasher = DefaultHasher::new();
@@ -305,12 +421,12 @@ where
         match self.inputs.entry(
             hash,
             |other| {
-                extractor == other.extractor && {
-                    // does this try to downcast the Box? then this would always return false
-                    (&other.matcher as &dyn Any)
+                extractor == other.extractor
+                    && other
+                        .matcher
+                        .as_any()
                         .downcast_ref::<M>()
-                        .map_or(false, |other| &matcher == other)
-                }
+                        .is_some_and(|other| &matcher == other)
             },
             |var| var.hash,
         ) {
@@ -341,7 +457,28 @@ pub trait Match<E: Extractor> {
     fn matches(&self, input: &E::Data<'_>) -> Maybe;
 }
 
-impl<E: Extractor> Match<E> for Box<dyn Match<E>> {
+/// A [`Match`] that can be downcast, so that inputs with equal matchers share
+/// a variable.
+trait AnyMatch<E: Extractor>: Match<E> + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_match(&self) -> &dyn Match<E>;
+}
+
+impl<E, M> AnyMatch<E> for M
+where
+    E: Extractor,
+    M: Match<E> + Send + Sync + 'static,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_match(&self) -> &dyn Match<E> {
+        self
+    }
+}
+
+impl<E: Extractor> Match<E> for Box<dyn Match<E> + Send + Sync> {
     fn matches(&self, input: &E::Data<'_>) -> Maybe {
         self.deref().matches(input)
     }
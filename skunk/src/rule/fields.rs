@@ -0,0 +1,199 @@
+//! Values of the fields that [`Template`]s refer to.
+
+use std::borrow::Cow;
+
+use bytes::Bytes;
+use hyper::{
+    http::request,
+    HeaderMap,
+    Method,
+    Uri,
+};
+use percent_encoding::percent_decode_str;
+
+use super::{
+    regex::Regex,
+    template::{
+        Field,
+        Template,
+    },
+};
+
+/// The fields of a request that templates can refer to.
+///
+/// The body and regex captures are only available if they were added with
+/// [`with_body`][Self::with_body] and [`with_captures`][Self::with_captures].
+pub struct RequestFields<'a> {
+    method: &'a Method,
+    uri: &'a Uri,
+    headers: &'a HeaderMap,
+    body: Option<&'a Bytes>,
+    json: Option<serde_json::Value>,
+    captures: Option<regex::Captures<'a>>,
+}
+
+impl<'a> RequestFields<'a> {
+    pub fn new(method: &'a Method, uri: &'a Uri, headers: &'a HeaderMap) -> Self {
+        Self {
+            method,
+            uri,
+            headers,
+            body: None,
+            json: None,
+            captures: None,
+        }
+    }
+
+    pub fn from_parts(parts: &'a request::Parts) -> Self {
+        Self::new(&parts.method, &parts.uri, &parts.headers)
+    }
+
+    /// Adds the request body. If `parse_json` is set, the body is parsed as
+    /// JSON for `json.*` fields.
+    pub fn with_body(mut self, body: &'a Bytes, parse_json: bool) -> Self {
+        self.body = Some(body);
+        self.json = parse_json
+            .then(|| serde_json::from_slice(body).ok())
+            .flatten();
+        self
+    }
+
+    /// Adds the capture groups of `regex` matched against the path and query.
+    pub fn with_captures(mut self, regex: &Regex) -> Self {
+        self.captures = regex.captures(
+            self.uri
+                .path_and_query()
+                .map_or("/", |path_and_query| path_and_query.as_str()),
+        );
+        self
+    }
+
+    pub fn render(&self, template: &Template) -> String {
+        template.render(|field| self.get(field))
+    }
+
+    pub fn get(&self, field: &Field) -> Option<Cow<'a, str>> {
+        let uri = self.uri;
+        match field {
+            Field::Method => Some(self.method.as_str().into()),
+            Field::Url => Some(uri.to_string().into()),
+            Field::Path => Some(uri.path().into()),
+            Field::PathSegment(index) => {
+                uri.path()
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .nth(*index)
+                    .map(|segment| percent_decode_str(segment).decode_utf8_lossy())
+            }
+            Field::Query => uri.query().map(Into::into),
+            Field::QueryParameter(name) => {
+                uri.query()?.split('&').find_map(|pair| {
+                    let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (decode_query(key) == *name).then(|| decode_query(value).into_owned().into())
+                })
+            }
+            Field::Header(name) => {
+                self.headers
+                    .get(name.as_str())
+                    .and_then(|value| value.to_str().ok())
+                    .map(Into::into)
+            }
+            Field::Body => Some(String::from_utf8_lossy(self.body?)),
+            Field::Json(path) => {
+                let mut value = self.json.as_ref()?;
+                for key in path {
+                    value = match value {
+                        serde_json::Value::Array(array) => array.get(key.parse::<usize>().ok()?)?,
+                        serde_json::Value::Object(object) => object.get(key)?,
+                        _ => return None,
+                    };
+                }
+                match value {
+                    serde_json::Value::String(string) => Some(string.clone().into()),
+                    _ => Some(value.to_string().into()),
+                }
+            }
+            Field::Capture(index) => {
+                self.captures
+                    .as_ref()?
+                    .get(*index)
+                    .map(|capture| capture.as_str().into())
+            }
+            Field::NamedCapture(name) => {
+                self.captures
+                    .as_ref()?
+                    .name(name)
+                    .map(|capture| capture.as_str().into())
+            }
+        }
+    }
+}
+
+fn decode_query(s: &str) -> Cow<'_, str> {
+    if s.contains('+') {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8_lossy()
+            .into_owned()
+            .into()
+    }
+    else {
+        percent_decode_str(s).decode_utf8_lossy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use hyper::{
+        Method,
+        Request,
+    };
+
+    use super::RequestFields;
+
+    #[test]
+    fn it_renders_request_fields() {
+        let (parts, ()) = Request::builder()
+            .method(Method::POST)
+            .uri("http://example.com/users/42?q=hello+world&x=%2F")
+            .header("x-user", "alice")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let body = Bytes::from_static(br#"{"user": {"ids": [1, 2]}, "name": "skunk"}"#);
+        let captures = r"^/users/(?<id>\d+)".parse().unwrap();
+        let fields = RequestFields::from_parts(&parts)
+            .with_body(&body, true)
+            .with_captures(&captures);
+
+        let render = |template: &str| fields.render(&template.parse().unwrap());
+        assert_eq!(render("{{ method }} {{ path }}"), "POST /users/42");
+        assert_eq!(render("{{ url }}"), parts.uri.to_string());
+        assert_eq!(render("{{ path.0 }}/{{ path.1 }}"), "users/42");
+        assert_eq!(render("{{ query.q }} {{ query.x }}"), "hello world /");
+        assert_eq!(render("{{ header.x-user }}"), "alice");
+        assert_eq!(render("{{ json.name }} {{ json.user.ids.1 }}"), "skunk 2");
+        assert_eq!(render("{{ json.user.ids }}"), "[1,2]");
+        assert_eq!(render("{{ capture.id }} {{ capture.1 }}"), "42 42");
+
+        // placeholders with no value are replaced by an empty string.
+        assert_eq!(
+            render("[{{ query.nope }}|{{ header.nope }}|{{ path.5 }}|{{ json.nope }}|{{ capture.nope }}]"),
+            "[||||]"
+        );
+    }
+
+    #[test]
+    fn it_only_renders_the_body_if_it_was_added() {
+        let (parts, ()) = Request::builder()
+            .uri("http://example.com/")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let fields = RequestFields::from_parts(&parts);
+        let template = "[{{ body }}|{{ json.name }}|{{ capture.0 }}]"
+            .parse()
+            .unwrap();
+        assert_eq!(fields.render(&template), "[||]");
+    }
+}
@@ -1,4 +1,6 @@
 use std::{
+    collections::HashSet,
+    fmt::Display,
     fs::File,
     io::{
         BufReader,
@@ -6,38 +8,157 @@ use std::{
         Read,
         Write,
     },
-    path::Path,
+    marker::PhantomData,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
 };
 
+use chrono::{
+    NaiveTime,
+    Timelike,
+};
+use indexmap::IndexMap;
 use ip_network::IpNetwork;
 use serde::{
+    de::{
+        self,
+        value::MapAccessDeserializer,
+        DeserializeSeed,
+        IgnoredAny,
+        IntoDeserializer,
+        MapAccess,
+        Visitor,
+    },
     Deserialize,
     Serialize,
 };
 
-use super::regex::Regex;
+use super::{
+    filter::mitmproxy::FilterExpression,
+    json_path::JsonPath,
+    regex::Regex,
+    template::Template,
+};
 use crate::address::Ports;
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(deny_unknown_fields)]
 pub struct RulesFile<F = DefaultFilters, E = DefaultEffects> {
     #[serde(default, skip_serializing_if = "Metadata::is_empty")]
     pub meta: Metadata,
 
+    /// Rules files to include, relative to this file. They're only included
+    /// by [`load`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<Include>,
+
+    /// Parameters that can be used in strings as `{{ param.<name> }}`, e.g.
+    /// in regexes or effects. They're substituted when the file is loaded, so
+    /// values used in regexes must be escaped.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub params: IndexMap<String, String>,
+
+    /// Named conditions, which can be used in conditions as
+    /// `condition: <name>`. A named condition can use the named conditions
+    /// that are defined before it.
+    #[serde(
+        default = "IndexMap::new",
+        skip_serializing_if = "IndexMap::is_empty",
+        deserialize_with = "deserialize_named_conditions"
+    )]
+    #[cfg_attr(feature = "schema", schemars(default))]
+    pub conditions: IndexMap<String, Conditions<F>>,
+
     #[serde(default = "Default::default", flatten)]
+    #[cfg_attr(feature = "schema", schemars(default))]
     pub rules: Block<F, E>,
+
+    /// The files that were included by [`load`].
+    #[serde(skip)]
+    pub included: Vec<Included>,
 }
 
 impl<F, E> Default for RulesFile<F, E> {
     fn default() -> Self {
         Self {
             meta: Default::default(),
+            include: vec![],
+            params: Default::default(),
+            conditions: Default::default(),
             rules: Default::default(),
+            included: vec![],
         }
     }
 }
 
+/// Deserializes the named conditions through a flattened map.
+///
+/// [`serde_yml::Value`] only deserializes enums from tagged values (e.g.
+/// `!http [...]`), so filters like `http: [{method: ...}]` would fail to parse.
+/// A flattened field is buffered by serde first, which accepts maps with a
+/// single key as enums, the same as the flattened rules.
+fn deserialize_named_conditions<'de, D, F>(
+    deserializer: D,
+) -> Result<IndexMap<String, Conditions<F>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    F: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(bound = "F: Deserialize<'de>")]
+    struct Buffered<F> {
+        #[serde(flatten)]
+        conditions: IndexMap<String, Conditions<F>>,
+    }
+
+    Ok(Buffered::deserialize(deserializer)?.conditions)
+}
+
+/// A rules file to include, either just its path, or its path and a version
+/// requirement, e.g. `{path: base.yaml, version: "^1.2"}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum Include {
+    Path(PathBuf),
+    Versioned {
+        path: PathBuf,
+
+        /// Required version of the included file, as semver requirement. It's
+        /// compared with the `version` in the included file's metadata.
+        version: String,
+    },
+}
+
+impl Include {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Path(path) => path,
+            Self::Versioned { path, .. } => path,
+        }
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        match self {
+            Self::Path(_) => None,
+            Self::Versioned { version, .. } => Some(version),
+        }
+    }
+}
+
+/// A file that was included by [`load`].
+#[derive(Debug)]
+pub struct Included {
+    pub path: PathBuf,
+    pub meta: Metadata,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Metadata {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub author: Option<String>,
@@ -58,6 +179,7 @@ pub struct Metadata {
     pub version: Option<String>,
 
     #[serde(flatten)]
+    #[cfg_attr(feature = "schema", schemars(skip))]
     pub other: serde_yml::Mapping,
 }
 
@@ -74,12 +196,15 @@ impl Metadata {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(deny_unknown_fields)]
 pub struct Block<F, E> {
     #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "schema", schemars(default))]
     pub rules: Vec<Rule<F, E>>,
 
     #[serde(default = "Vec::new", skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "schema", schemars(default))]
     pub effects: Vec<E>,
 }
 
@@ -93,12 +218,26 @@ impl<F, E> Default for Block<F, E> {
 }
 
 impl<F, E> Block<F, E> {
+    /// Calls `f` for the effects of this block and of all blocks nested in
+    /// it, until it returns an error.
+    pub fn try_for_each_effect<Er>(
+        &mut self,
+        f: &mut impl FnMut(&mut E) -> Result<(), Er>,
+    ) -> Result<(), Er> {
+        for rule in &mut self.rules {
+            rule.then.try_for_each_effect(f)?;
+            rule.alt.try_for_each_effect(f)?;
+        }
+        self.effects.iter_mut().try_for_each(f)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.rules.is_empty() && self.effects.is_empty()
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(deny_unknown_fields)]
 pub struct Rule<F, E> {
     #[serde(
@@ -106,9 +245,11 @@ pub struct Rule<F, E> {
         default = "Default::default",
         skip_serializing_if = "Conditions::is_empty"
     )]
+    #[cfg_attr(feature = "schema", schemars(default))]
     pub condition: Conditions<F>,
 
     #[serde(default = "Default::default", skip_serializing_if = "Block::is_empty")]
+    #[cfg_attr(feature = "schema", schemars(default))]
     pub then: Block<F, E>,
 
     #[serde(
@@ -116,10 +257,12 @@ pub struct Rule<F, E> {
         default = "Default::default",
         skip_serializing_if = "Block::is_empty"
     )]
+    #[cfg_attr(feature = "schema", schemars(default))]
     pub alt: Block<F, E>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(transparent)]
 pub struct Conditions<F>(pub Vec<Condition<F>>);
 
@@ -135,14 +278,123 @@ impl<F> Conditions<F> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(untagged)]
 pub enum Condition<F> {
     Sub(SubCondition<F>),
+    Ref(ConditionRef),
     Terminal(F),
 }
 
+/// Reference to a named condition, e.g. `condition: is-api`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct ConditionRef {
+    pub condition: String,
+}
+
+impl<'de, F> Deserialize<'de> for Condition<F>
+where
+    F: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // this could be `#[serde(untagged)]`, but then errors in filters (e.g. an
+        // invalid regex) would only be reported as "data did not match any variant".
+        deserializer.deserialize_any(ConditionVisitor(PhantomData))
+    }
+}
+
+struct ConditionVisitor<F>(PhantomData<F>);
+
+impl<'de, F> Visitor<'de> for ConditionVisitor<F>
+where
+    F: Deserialize<'de>,
+{
+    type Value = Condition<F>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "a condition")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        F::deserialize(v.to_owned().into_deserializer()).map(Condition::Terminal)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let Some(key) = map.next_key::<String>()?
+        else {
+            return Err(de::Error::invalid_length(0, &self));
+        };
+
+        let condition = match key.as_str() {
+            "not" => Condition::Sub(SubCondition::Not(map.next_value()?)),
+            "and" => Condition::Sub(SubCondition::And(map.next_value()?)),
+            "or" => Condition::Sub(SubCondition::Or(map.next_value()?)),
+            "condition" => {
+                Condition::Ref(ConditionRef {
+                    condition: map.next_value()?,
+                })
+            }
+            _ => {
+                Condition::Terminal(F::deserialize(MapAccessDeserializer::new(FirstKey {
+                    key: Some(key),
+                    map: &mut map,
+                }))?)
+            }
+        };
+
+        if map.next_key::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::custom("a condition must have exactly one key"));
+        }
+
+        Ok(condition)
+    }
+}
+
+/// [`MapAccess`] that yields a key that was already read, followed by the
+/// rest of the map.
+struct FirstKey<'a, A> {
+    key: Option<String>,
+    map: &'a mut A,
+}
+
+impl<'de, 'a, A> MapAccess<'de> for FirstKey<'a, A>
+where
+    A: MapAccess<'de>,
+{
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.key.take() {
+            Some(key) => seed.deserialize(key.into_deserializer()).map(Some),
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.map.next_value_seed(seed)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum SubCondition<F> {
     Not(Conditions<F>),
@@ -151,6 +403,7 @@ pub enum SubCondition<F> {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum DefaultFilters {
     Direction(Direction),
@@ -158,9 +411,30 @@ pub enum DefaultFilters {
     Tcp(Vec<TcpFilter>),
     Tls(Vec<TlsFilter>),
     Http(Vec<HttpFilter>),
+
+    /// A [mitmproxy-style filter expression](super::filter::mitmproxy).
+    Filter(FilterExpression),
+
+    /// Matches with a probability between 0 and 1, e.g. `chance: 0.1`.
+    Chance(f64),
+
+    /// Matches during any of the time windows, in local time.
+    Time(Vec<TimeWindow>),
+
+    /// Matches the nth time it's evaluated, e.g. for the 3rd request to a
+    /// host.
+    Nth(NthFilter),
+
+    /// Matches only the first time it's evaluated, e.g. `once: connection`.
+    Once(CounterKey),
+
+    /// Matches if it was evaluated more often than a limit within a time
+    /// window, e.g. more than 100 requests per minute from a client.
+    Rate(RateFilter),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum Direction {
     Request,
@@ -180,24 +454,27 @@ impl Direction {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum TcpFilter {
     HostPort(Vec<HostPort>),
     Hostname(Vec<Regex>),
     DnsName(Vec<Regex>),
+    #[cfg_attr(feature = "schema", schemars(with = "Vec<String>"))]
     IpAddress(Vec<IpNetwork>),
     Port(Vec<Ports>),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(deny_unknown_fields)]
 pub struct HostPort {
-    host: Regex,
-    port: Ports,
+    pub host: Regex,
+    pub port: Ports,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum TlsFilter {
     ServerName(Vec<Regex>),
@@ -207,43 +484,508 @@ pub enum TlsFilter {
     DistinguishedName(Vec<Regex>),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for TlsFilter {
+    fn schema_name() -> String {
+        "TlsFilter".to_owned()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{
+            InstanceType,
+            SchemaObject,
+            SubschemaValidation,
+        };
+
+        // the derived schema doesn't know about the aliases.
+        let regexes = gen.subschema_for::<Vec<Regex>>();
+        let variants = [
+            "server-name",
+            "common-name",
+            "cn",
+            "distinguished-name",
+            "dn",
+        ]
+        .into_iter()
+        .map(|name| {
+            let mut variant = SchemaObject {
+                instance_type: Some(InstanceType::Object.into()),
+                ..Default::default()
+            };
+            let object = variant.object();
+            object.properties.insert(name.to_owned(), regexes.clone());
+            object.required.insert(name.to_owned());
+            object.additional_properties = Some(Box::new(false.into()));
+            variant.into()
+        })
+        .collect();
+
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                one_of: Some(variants),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub enum HttpFilter {
     Method(Vec<Regex>),
-    //StatusCode(u16),
     Url(Vec<Regex>),
-    Header { name: Regex, value: Regex },
+    Header {
+        name: Regex,
+        value: Regex,
+    },
     ContentType(Vec<Regex>),
     Cookie(Vec<Regex>),
     Host(Vec<Regex>),
+
+    /// Matches responses with any of the status codes, e.g. `[404, 5xx]`.
+    Status(Vec<StatusCodes>),
+
+    /// Matches if any of the regexes match the body. The body is decoded as
+    /// UTF-8, replacing invalid sequences.
+    Body(Vec<Regex>),
+
+    /// Matches if the body is JSON, and all of the filters match.
+    Json(Vec<JsonFilter>),
+
+    /// Matches if the length of the body in bytes is within the bounds.
+    BodySize {
+        #[serde(default)]
+        min: Option<u64>,
+        #[serde(default)]
+        max: Option<u64>,
+    },
+}
+
+impl HttpFilter {
+    /// Returns whether the filter needs the body of the message.
+    pub fn needs_body(&self) -> bool {
+        matches!(self, Self::Body(_) | Self::Json(_) | Self::BodySize { .. })
+    }
+}
+
+/// A range of status codes.
+///
+/// This is written as a status code (e.g. `404`), a class of status codes
+/// (e.g. `4xx`), or a `..` separated range (e.g. `500..504`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StatusCodes {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl StatusCodes {
+    pub fn contains(&self, status: u16) -> bool {
+        (self.min..=self.max).contains(&status)
+    }
+}
+
+impl From<u16> for StatusCodes {
+    fn from(status: u16) -> Self {
+        Self {
+            min: status,
+            max: status,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid status codes: {0}")]
+pub struct ParseStatusCodesError(String);
+
+impl FromStr for StatusCodes {
+    type Err = ParseStatusCodesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseStatusCodesError(s.to_owned());
+        let parse = |s: &str| s.trim().parse::<u16>().map_err(|_| err());
+
+        if let Some((min, max)) = s.split_once("..") {
+            let (min, max) = (parse(min)?, parse(max)?);
+            Ok(Self {
+                min: min.min(max),
+                max: min.max(max),
+            })
+        }
+        else if let Some(class) = s.strip_suffix("xx").or_else(|| s.strip_suffix("XX")) {
+            let class = class
+                .parse::<u16>()
+                .ok()
+                .filter(|class| (1..=9).contains(class))
+                .ok_or_else(err)?;
+            Ok(Self {
+                min: class * 100,
+                max: class * 100 + 99,
+            })
+        }
+        else {
+            Ok(parse(s)?.into())
+        }
+    }
+}
+
+impl Display for StatusCodes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        }
+        else {
+            write!(f, "{}..{}", self.min, self.max)
+        }
+    }
+}
+
+impl Serialize for StatusCodes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        if self.min == self.max {
+            serializer.serialize_u16(self.min)
+        }
+        else {
+            serializer.collect_str(self)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StatusCodes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(StatusCodesVisitor)
+    }
+}
+
+struct StatusCodesVisitor;
+
+impl<'de> Visitor<'de> for StatusCodesVisitor {
+    type Value = StatusCodes;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a status code, a class like `4xx`, or a .. separated range")
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(u16::try_from(value).map_err(de::Error::custom)?.into())
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(u16::try_from(value).map_err(de::Error::custom)?.into())
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for StatusCodes {
+    fn schema_name() -> String {
+        "StatusCodes".to_owned()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut string = schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            ..Default::default()
+        };
+        string.string().pattern = Some("^([1-9][xX]{2}|[0-9]+|[0-9]+ *\\.\\. *[0-9]+)$".to_owned());
+
+        schemars::schema::SchemaObject {
+            subschemas: Some(Box::new(schemars::schema::SubschemaValidation {
+                any_of: Some(vec![gen.subschema_for::<u16>(), string.into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// Matches values in a JSON body, e.g. `{path: $.error.code, equals: 42}`.
+///
+/// The predicates must all match the same value. Without predicates, this
+/// matches if the path selects any value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct JsonFilter {
+    /// A JSONPath (e.g. `$.items[*].id`) or JSON Pointer (e.g. `/items/0/id`).
+    pub path: JsonPath,
+
+    /// Matches if the value is equal to this. Numbers are equal if they have
+    /// the same value, e.g. `42` and `42.0`.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "serde_json::Value"))]
+    pub equals: Option<serde_yml::Value>,
+
+    /// Matches if the regex matches the value. Strings are matched as they
+    /// are, and other values as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matches: Option<Regex>,
+
+    /// With `exists: false`, this matches if the path doesn't select any
+    /// value, and the other predicates are ignored.
+    #[serde(default = "default_exists", skip_serializing_if = "is_true")]
+    pub exists: bool,
+}
+
+fn default_exists() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+/// Deserializes a present field as `Some`, even if it's `null`.
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// What a stateful filter counts separately.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum CounterKey {
+    /// One counter for everything, for as long as the rules are loaded.
+    #[default]
+    Global,
+
+    /// A counter for each destination host.
+    Host,
+
+    /// A counter for each client IP address.
+    Client,
+
+    /// A counter for each connection, e.g. to count the requests sent on it.
+    Connection,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NthFilter {
+    /// Matches if the count is any of these, starting at 1.
+    pub n: Vec<u64>,
+
+    #[serde(default)]
+    pub per: CounterKey,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RateFilter {
+    /// Matches if it was evaluated more often than this within the window,
+    /// including this time.
+    pub more_than: u64,
+
+    /// Length of the window in seconds. Defaults to 60 seconds.
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+
+    #[serde(default)]
+    pub per: CounterKey,
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+/// A time window within a day, e.g. `{from: "22:00", to: "06:00"}`.
+///
+/// `from` is inclusive and `to` is exclusive. If `to` is before `from`, the
+/// window spans midnight.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TimeWindow {
+    pub from: TimeOfDay,
+    pub to: TimeOfDay,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        let (from, to) = (self.from.0, self.to.0);
+        if from <= to {
+            from <= time && time < to
+        }
+        else {
+            from <= time || time < to
+        }
+    }
+}
+
+/// A time of day, written as `HH:MM` or `HH:MM:SS`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimeOfDay(pub NaiveTime);
+
+impl FromStr for TimeOfDay {
+    type Err = chrono::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NaiveTime::parse_from_str(s, "%H:%M")
+            .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+            .map(Self)
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.second() == 0 {
+            write!(f, "{}", self.0.format("%H:%M"))
+        }
+        else {
+            write!(f, "{}", self.0.format("%H:%M:%S"))
+        }
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s: std::borrow::Cow<'de, str> = Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for TimeOfDay {
+    fn schema_name() -> String {
+        "TimeOfDay".to_owned()
+    }
+
+    fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut schema = schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            ..Default::default()
+        };
+        schema.string().pattern = Some("^[0-9]{1,2}:[0-9]{2}(:[0-9]{2})?$".to_owned());
+        schema.into()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum DefaultEffects {
     Log(LogEffect),
     Interrupt(InterruptEffect),
     Drop,
+    Modify(Box<ModifyEffect>),
+    MapLocal(MapLocalEffect),
+    MapRemote(MapRemoteEffect),
+    Mock(Box<MockEffect>),
+    Network(NetworkEffect),
+
+    /// Adds tags to the flow, e.g. `[auth]`.
+    Tag(Vec<String>),
+
+    /// Marks the flow, e.g. with a color like `red`. A flow has only one
+    /// marker, so this replaces a previous one.
+    Mark(String),
+
+    /// Sets the comment of the flow. The comment can refer to fields of the
+    /// request, except its body.
+    Comment(Template),
+
+    Script(ScriptEffect),
+}
+
+impl DefaultEffects {
+    /// Returns the name of the effect as it's written in a rules file, e.g.
+    /// `map-local`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DefaultEffects::Log(_) => "log",
+            DefaultEffects::Interrupt(_) => "interrupt",
+            DefaultEffects::Drop => "drop",
+            DefaultEffects::Modify(_) => "modify",
+            DefaultEffects::MapLocal(_) => "map-local",
+            DefaultEffects::MapRemote(_) => "map-remote",
+            DefaultEffects::Mock(_) => "mock",
+            DefaultEffects::Network(_) => "network",
+            DefaultEffects::Tag(_) => "tag",
+            DefaultEffects::Mark(_) => "mark",
+            DefaultEffects::Comment(_) => "comment",
+            DefaultEffects::Script(_) => "script",
+        }
+    }
+
+    /// Whether the effect needs a user to decide something, e.g. in the UI.
+    pub fn requires_user_interaction(&self) -> bool {
+        matches!(self, DefaultEffects::Interrupt(_))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct LogEffect {
-    #[serde(skip_serializing_if = "LogTarget::is_user")]
-    target: LogTarget,
+    #[serde(default, skip_serializing_if = "LogTarget::is_user")]
+    pub target: LogTarget,
 
+    /// Name of the rule, which is included in the log record.
     #[serde(skip_serializing_if = "Option::is_none")]
-    name: Option<String>,
+    pub name: Option<String>,
 
+    /// The message can refer to fields of the request, except its body.
     #[serde(skip_serializing_if = "Option::is_none")]
-    message: Option<String>,
+    pub message: Option<Template>,
+
+    /// Name of the file to log to, for the `file` target. Defaults to the
+    /// name of the rule.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case")]
 pub enum LogTarget {
+    /// Log to the terminal.
+    #[default]
     User,
+
+    /// Log JSON Lines records to a file in the data directory.
     File,
 }
 
@@ -257,11 +999,240 @@ impl LogTarget {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Pauses HTTP requests or responses until a user decides what to do with
+/// them, e.g. in the UI.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct InterruptEffect {
+    /// Shown to the user with the interrupted message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+
+    /// Whether to interrupt requests, responses, or both.
+    #[serde(default, skip_serializing_if = "Direction::is_both")]
+    pub direction: Direction,
+
+    /// How long to wait for a decision. Defaults to 60 seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    /// What to do if no decision was made in time.
+    #[serde(default, skip_serializing_if = "InterruptTimeout::is_continue")]
+    pub on_timeout: InterruptTimeout,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum InterruptTimeout {
+    /// Forward the message as it is.
+    #[default]
+    Continue,
+
+    /// Don't forward the message.
+    Drop,
+
+    /// Close the connection.
+    Kill,
+}
+
+impl InterruptTimeout {
+    pub fn is_continue(&self) -> bool {
+        matches!(self, Self::Continue)
+    }
+}
+
+/// Modifies HTTP requests and responses before they're forwarded.
+///
+/// Changes to the method and URL only apply to requests, and changes to the
+/// status only apply to responses.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ModifyEffect {
+    /// Whether to modify requests, responses, or both.
+    #[serde(default, skip_serializing_if = "Direction::is_both")]
+    pub direction: Direction,
+
+    /// Headers to set, replacing existing headers with the same name.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub set_headers: IndexMap<String, String>,
+
+    /// Headers to add, keeping existing headers with the same name.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub add_headers: IndexMap<String, String>,
+
+    /// Names of headers to remove.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove_headers: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+
+    /// Replaces the whole URL. `path` and `query` are applied afterwards.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// The new query string, without the leading `?`. An empty string removes
+    /// the query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<BodyReplacement>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+
+    /// Cookies to inject. For requests they're added to the `Cookie` header,
+    /// and for responses a `Set-Cookie` header is added for each.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub cookies: IndexMap<String, String>,
+}
+
+/// How to replace a body.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum BodyReplacement {
+    /// Replaces the body with a string.
+    Text(String),
+
+    /// Replaces the body with the contents of a file.
+    File(PathBuf),
+
+    /// Replaces all matches of a regex in the body. The replacement can
+    /// refer to capture groups, e.g. `$1`.
+    Replace { regex: Regex, replacement: String },
+}
+
+/// Answers requests with a local file, without contacting the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MapLocalEffect {
+    /// A file or directory. For a directory the request path is looked up in
+    /// it, and `index.html` is served for directories within it.
+    pub path: PathBuf,
+
+    /// Status code for the response. Defaults to 200.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+
+    /// The content type of the response. If not set, it's guessed from the
+    /// file extension.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+/// Sends requests to another server instead, e.g. `http://localhost:8080`.
+///
+/// The path of the `url` is prepended to the request path.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MapRemoteEffect {
+    pub url: String,
+}
+
+/// Answers requests with a response defined in the rules file.
+///
+/// The headers and body are [`Template`]s, so they can refer to the request.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MockEffect {
+    /// Status code for the response. Defaults to 200.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub headers: IndexMap<String, Template>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Template>,
+
+    /// Regex that is matched against the request path and query. Its capture
+    /// groups can be used in the templates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captures: Option<Regex>,
+
+    /// Still send the request to the server, but discard its response.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forward: bool,
+}
+
+/// Simulates a bad network for a connection.
+///
+/// This only applies if the effect fires for the destination address of the
+/// connection, so its conditions can't depend on HTTP messages, e.g. on their
+/// method or direction. The conditions apply to both directions.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct NetworkEffect {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+
+    /// Random extra latency of up to this many milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<u64>,
+
+    /// Maximum throughput in bytes per second.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<u64>,
+
+    /// Probability that a read from the connection fails with a connection
+    /// reset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset: Option<f64>,
+
+    /// Probability that a read from the connection stalls forever.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stall: Option<f64>,
+
+    /// Ends the responses from the server after this many bytes.
     #[serde(skip_serializing_if = "Option::is_none")]
-    prompt: Option<String>,
+    pub truncate: Option<u64>,
+
+    /// Fails the TLS handshake with the client.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fail_tls: bool,
+}
+
+/// Runs a [Rhai](https://rhai.rs) script, which can change HTTP requests and
+/// responses, annotate their flow or drop them.
+///
+/// Scripts are run after modify effects. See [`script`](super::script) for
+/// what scripts can access.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ScriptEffect {
+    /// Path of the script, relative to the rules directory.
+    pub file: PathBuf,
+
+    /// Whether to run the script for requests, responses, or both.
+    #[serde(default, skip_serializing_if = "Direction::is_both")]
+    pub direction: Direction,
+
+    /// How long the script may run, in milliseconds. Defaults to 100.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// Maximum length of strings and blobs in bytes, and of arrays and maps,
+    /// that the script creates. Defaults to 16 MiB.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<usize>,
+
+    /// The compiled script, once it was [loaded](super::script::load).
+    #[cfg(feature = "script")]
+    #[serde(skip)]
+    pub script: Option<super::script::Script>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -271,16 +1242,49 @@ pub enum Error {
 
     #[error("yaml error")]
     Yaml(#[from] serde_yml::Error),
+
+    #[error("unknown parameter: {name}")]
+    UnknownParam { name: String },
+
+    #[error("could not include {}", path.display())]
+    Include {
+        path: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+
+    #[error("{} includes itself", path.display())]
+    IncludeCycle { path: PathBuf },
+
+    #[error("invalid version requirement: {required}")]
+    InvalidVersionRequirement {
+        required: String,
+        #[source]
+        source: semver::Error,
+    },
+
+    #[error("version {version:?} doesn't match the requirement {required}")]
+    IncludeVersion {
+        required: String,
+        version: Option<String>,
+    },
 }
 
+/// Reads a single rules file and substitutes its parameters. Included files
+/// are not loaded.
 pub fn from_reader<F, E>(reader: impl Read) -> Result<RulesFile<F, E>, Error>
 where
     F: for<'de> Deserialize<'de>,
     E: for<'de> Deserialize<'de>,
 {
-    Ok(serde_yml::from_reader(reader)?)
+    let mut value = read_value(reader)?;
+    let header = serde_yml::from_value::<Header>(value.clone())?;
+    substitute_params(&mut value, &header.params)?;
+    Ok(serde_yml::from_value(value)?)
 }
 
+/// Reads a single rules file and substitutes its parameters. Included files
+/// are not loaded.
 pub fn from_file<F, E>(path: impl AsRef<Path>) -> Result<RulesFile<F, E>, Error>
 where
     F: for<'de> Deserialize<'de>,
@@ -291,6 +1295,260 @@ where
     from_reader(reader)
 }
 
+/// Loads a rules file and the rules files it includes.
+///
+/// Included files are loaded relative to the file that includes them, and
+/// every file is only included once. Their rules come before the rules of the
+/// including file, and their named conditions and parameters can be used by
+/// the including file. Named conditions and parameters of the including file
+/// override those of included files, e.g. to use a shared rules file with
+/// parameters set per project.
+///
+/// The returned rules file contains the rules, named conditions and
+/// parameters of all files. The included files are listed in
+/// [`RulesFile::included`].
+pub fn load<F, E>(path: impl AsRef<Path>) -> Result<RulesFile<F, E>, Error>
+where
+    F: for<'de> Deserialize<'de>,
+    E: for<'de> Deserialize<'de>,
+{
+    let path = path.as_ref();
+    let mut loader = Loader::default();
+    loader.seen.insert(path.canonicalize()?);
+    loader.visit(path, read_value(BufReader::new(File::open(path)?))?, None)?;
+
+    let mut merged = RulesFile::default();
+    let root_index = loader.files.len() - 1;
+
+    for (index, (path, mut value)) in loader.files.into_iter().enumerate() {
+        let file = substitute_params(&mut value, &loader.params)
+            .and_then(|()| Ok(serde_yml::from_value::<RulesFile<F, E>>(value)?));
+        let file = if index == root_index {
+            file?
+        }
+        else {
+            file.map_err(|error| include_error(&path, error))?
+        };
+
+        merged.conditions.extend(file.conditions);
+        merged.rules.rules.extend(file.rules.rules);
+        merged.rules.effects.extend(file.rules.effects);
+
+        if index == root_index {
+            merged.meta = file.meta;
+            merged.include = file.include;
+        }
+        else {
+            merged.included.push(Included {
+                path,
+                meta: file.meta,
+            });
+        }
+    }
+
+    merged.params = loader.params;
+
+    Ok(merged)
+}
+
+/// Loads rules files in the order in which they're included.
+#[derive(Debug, Default)]
+struct Loader {
+    /// Canonical paths of the files that are being included, to detect
+    /// cycles.
+    stack: Vec<PathBuf>,
+
+    /// Canonical paths of the files that were already included.
+    seen: HashSet<PathBuf>,
+
+    /// Files in the order in which their rules are compiled, i.e. included
+    /// files before the files that include them.
+    files: Vec<(PathBuf, serde_yml::Value)>,
+
+    params: IndexMap<String, String>,
+}
+
+impl Loader {
+    fn visit(
+        &mut self,
+        path: &Path,
+        value: serde_yml::Value,
+        required_version: Option<&str>,
+    ) -> Result<(), Error> {
+        let header = serde_yml::from_value::<Header>(value.clone())?;
+
+        if let Some(required) = required_version {
+            check_version(required, header.meta.version.as_deref())?;
+        }
+
+        self.stack.push(path.canonicalize()?);
+        let base = path.parent().unwrap_or(Path::new(""));
+
+        for include in &header.include {
+            let include_path = base.join(include.path());
+            let result = include_path
+                .canonicalize()
+                .map_err(Error::from)
+                .and_then(|canonical| {
+                    if self.stack.contains(&canonical) {
+                        Err(Error::IncludeCycle {
+                            path: include_path.clone(),
+                        })
+                    }
+                    else if self.seen.insert(canonical) {
+                        let value = read_value(BufReader::new(File::open(&include_path)?))?;
+                        self.visit(&include_path, value, include.version())
+                    }
+                    else {
+                        Ok(())
+                    }
+                });
+            result.map_err(|error| include_error(&include_path, error))?;
+        }
+
+        self.stack.pop();
+        self.params.extend(header.params);
+        self.files.push((path.to_owned(), value));
+
+        Ok(())
+    }
+}
+
+fn include_error(path: &Path, error: Error) -> Error {
+    Error::Include {
+        path: path.to_owned(),
+        source: Box::new(error),
+    }
+}
+
+fn check_version(required: &str, version: Option<&str>) -> Result<(), Error> {
+    let requirement = semver::VersionReq::parse(required).map_err(|source| {
+        Error::InvalidVersionRequirement {
+            required: required.to_owned(),
+            source,
+        }
+    })?;
+
+    if version
+        .and_then(|version| semver::Version::parse(version).ok())
+        .is_some_and(|version| requirement.matches(&version))
+    {
+        Ok(())
+    }
+    else {
+        Err(Error::IncludeVersion {
+            required: required.to_owned(),
+            version: version.map(ToOwned::to_owned),
+        })
+    }
+}
+
+/// The parts of a rules file that are needed before its rules can be parsed.
+#[derive(Debug, Deserialize)]
+struct Header {
+    #[serde(default)]
+    meta: Metadata,
+
+    #[serde(default)]
+    include: Vec<Include>,
+
+    #[serde(default)]
+    params: IndexMap<String, String>,
+}
+
+fn read_value(reader: impl Read) -> Result<serde_yml::Value, Error> {
+    let value: serde_yml::Value = serde_yml::from_reader(reader)?;
+    // an empty file is an empty rules file.
+    if value.is_null() {
+        Ok(serde_yml::Value::Mapping(Default::default()))
+    }
+    else {
+        Ok(value)
+    }
+}
+
+/// Replaces `{{ param.<name> }}` in all strings, except in the parameters
+/// themselves.
+fn substitute_params(
+    value: &mut serde_yml::Value,
+    params: &IndexMap<String, String>,
+) -> Result<(), Error> {
+    fn substitute(
+        value: &mut serde_yml::Value,
+        params: &IndexMap<String, String>,
+    ) -> Result<(), Error> {
+        match value {
+            serde_yml::Value::String(string) => {
+                if let Some(substituted) = substitute_string(string, params)? {
+                    *string = substituted;
+                }
+            }
+            serde_yml::Value::Sequence(sequence) => {
+                for value in sequence {
+                    substitute(value, params)?;
+                }
+            }
+            serde_yml::Value::Mapping(mapping) => {
+                for value in mapping.values_mut() {
+                    substitute(value, params)?;
+                }
+            }
+            serde_yml::Value::Tagged(tagged) => substitute(&mut tagged.value, params)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    if let serde_yml::Value::Mapping(mapping) = value {
+        for (key, value) in mapping.iter_mut() {
+            if key.as_str() != Some("params") {
+                substitute(value, params)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Substitutes parameters in a string, or returns `None` if it has none.
+/// Other placeholders (e.g. of [`Template`]s) are left as they are.
+fn substitute_string(
+    string: &str,
+    params: &IndexMap<String, String>,
+) -> Result<Option<String>, Error> {
+    let mut output = String::new();
+    let mut rest = string;
+    let mut substituted = false;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start..].find("}}")
+        else {
+            break;
+        };
+        let end = start + length + 2;
+        let placeholder = rest[start + 2..end - 2].trim();
+
+        if let Some(name) = placeholder.strip_prefix("param.") {
+            let value = params.get(name).ok_or_else(|| {
+                Error::UnknownParam {
+                    name: name.to_owned(),
+                }
+            })?;
+            output.push_str(&rest[..start]);
+            output.push_str(value);
+            substituted = true;
+        }
+        else {
+            output.push_str(&rest[..end]);
+        }
+
+        rest = &rest[end..];
+    }
+
+    output.push_str(rest);
+    Ok(substituted.then_some(output))
+}
+
 pub fn to_writer<F, E>(writer: impl Write, rules: &RulesFile<F, E>) -> Result<(), Error>
 where
     F: Serialize,
@@ -309,3 +1567,117 @@ where
     let writer = BufWriter::new(file);
     to_writer(writer, rules)
 }
+
+/// URL of the published JSON Schema for rules files.
+///
+/// YAML language servers pick it up with a modeline at the top of a rules
+/// file: `# yaml-language-server: $schema=<url>`.
+pub const SCHEMA_URL: &str =
+    "https://raw.githubusercontent.com/jgraef/skunk/main/skunk/rules.schema.json";
+
+/// Returns the JSON Schema for rules files with the default filters and
+/// effects.
+///
+/// The published schema at [`SCHEMA_URL`] is generated with `skunk rules
+/// schema`.
+#[cfg(feature = "schema")]
+pub fn json_schema() -> serde_json::Value {
+    let mut schema = schemars::schema_for!(RulesFile<DefaultFilters, DefaultEffects>);
+    let metadata = schema.schema.metadata();
+    metadata.id = Some(SCHEMA_URL.to_owned());
+    metadata.title = Some("skunk rules file".to_owned());
+    serde_json::to_value(schema).expect("failed to serialize schema")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        load,
+        Condition,
+        DefaultEffects,
+        DefaultFilters,
+        Error,
+        RulesFile,
+    };
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn it_matches_the_published_schema() {
+        let published: serde_json::Value =
+            serde_json::from_str(include_str!("../../rules.schema.json")).unwrap();
+        assert!(
+            published == super::json_schema(),
+            "rules.schema.json is outdated. regenerate it with `skunk rules schema > skunk/rules.schema.json`"
+        );
+    }
+
+    #[test]
+    fn it_loads_included_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("base.yaml"),
+            r#"
+meta:
+  name: base
+  version: 1.2.0
+params:
+  host: example\.com
+conditions:
+  is-target:
+    - host: ["^{{ param.host }}$"]
+rules:
+  - if:
+      - condition: is-target
+    then:
+      effects:
+        - drop
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("project.yaml"),
+            r#"
+include:
+  - path: base.yaml
+    version: ^1.0
+params:
+  host: example\.org
+rules:
+  - if:
+      - not:
+          - condition: is-target
+    then:
+      effects:
+        - drop
+"#,
+        )
+        .unwrap();
+
+        let rules: RulesFile<DefaultFilters, DefaultEffects> =
+            load(dir.path().join("project.yaml")).unwrap();
+
+        assert_eq!(rules.rules.rules.len(), 2);
+        assert_eq!(rules.included.len(), 1);
+        assert_eq!(rules.included[0].meta.name.as_deref(), Some("base"));
+        match &rules.conditions["is-target"].0[..] {
+            [Condition::Terminal(DefaultFilters::Host(hosts))] => {
+                assert_eq!(hosts[0].as_str(), r"^example\.org$");
+            }
+            conditions => panic!("unexpected conditions: {conditions:?}"),
+        }
+    }
+
+    #[test]
+    fn it_detects_include_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.yaml"), "include: [b.yaml]").unwrap();
+        std::fs::write(dir.path().join("b.yaml"), "include: [a.yaml]").unwrap();
+
+        let mut error =
+            &load::<DefaultFilters, DefaultEffects>(dir.path().join("a.yaml")).unwrap_err();
+        while let Error::Include { source, .. } = error {
+            error = source;
+        }
+        assert!(matches!(error, Error::IncludeCycle { .. }));
+    }
+}
@@ -1,13 +1,32 @@
+//! [mitmproxy-style filter expressions][1], e.g. `~d example.com & ~m POST &
+//! !~a`.
+//!
+//! Expressions are parsed into an [`Expression`] tree, which can then be
+//! compiled into an expression graph. Which [`Filter`]s are supported depends
+//! on the backend compiling them.
+//!
+//! [1]: https://docs.mitmproxy.org/stable/concepts-filters/
+
 use std::{
+    fmt::Display,
     hash::Hash,
     str::FromStr,
+    sync::Arc,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
 };
 
 use crate::{
-    rule::regex::Regex,
-    util::bool_expr::{
-        ContinuousEvaluation,
-        Or,
+    rule::{
+        file,
+        regex::Regex,
+    },
+    util::boolean::{
+        ExpressionId,
+        ModifyGraph,
     },
 };
 
@@ -20,6 +39,16 @@ pub enum Direction {
     Both,
 }
 
+impl From<Direction> for file::Direction {
+    fn from(value: Direction) -> Self {
+        match value {
+            Direction::Request => Self::Request,
+            Direction::Response => Self::Response,
+            Direction::Both => Self::Both,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Filter {
     Asset,
@@ -41,15 +70,122 @@ pub enum Filter {
     Replay(Direction),
     Source(Regex),
     ContentType(Direction, Regex),
+    /// Not in mitmproxy. Matches if any tag of the flow matches.
+    Tag(Regex),
     Tcp,
     Url(Regex),
     Udp,
     Websocket,
 }
 
+impl Filter {
+    /// Returns the name of the filter as it's written in an expression, e.g.
+    /// `~d`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Asset => "~a",
+            Filter::All => "~all",
+            Filter::Body(Direction::Both, _) => "~b",
+            Filter::Body(Direction::Request, _) => "~bq",
+            Filter::Body(Direction::Response, _) => "~bs",
+            Filter::HttpResponseCode(_) => "~c",
+            Filter::Comment(_) => "~comment",
+            Filter::Domain(_) => "~d",
+            Filter::Dns => "~dns",
+            Filter::Destination(_) => "~dst",
+            Filter::Error => "~e",
+            Filter::Header(Direction::Both, _) => "~h",
+            Filter::Header(Direction::Request, _) => "~hq",
+            Filter::Header(Direction::Response, _) => "~hs",
+            Filter::Http => "~http",
+            Filter::Method(_) => "~m",
+            Filter::Marked => "~marked",
+            Filter::Marker(_) => "~marker",
+            Filter::Meta(_) => "~meta",
+            Filter::Direction(Direction::Request) => "~q",
+            Filter::Direction(Direction::Response) => "~s",
+            Filter::Direction(Direction::Both) => "~q | ~s",
+            Filter::Replay(Direction::Both) => "~replay",
+            Filter::Replay(Direction::Request) => "~replayq",
+            Filter::Replay(Direction::Response) => "~replays",
+            Filter::Source(_) => "~src",
+            Filter::ContentType(Direction::Both, _) => "~t",
+            Filter::ContentType(Direction::Request, _) => "~tq",
+            Filter::ContentType(Direction::Response, _) => "~ts",
+            Filter::Tag(_) => "~tag",
+            Filter::Tcp => "~tcp",
+            Filter::Url(_) => "~u",
+            Filter::Udp => "~udp",
+            Filter::Websocket => "~websocket",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expression {
+    Filter(Filter),
+    Not(Box<Expression>),
+    And(Vec<Expression>),
+    Or(Vec<Expression>),
+}
+
+impl Expression {
+    /// Compiles the expression into `graph`.
+    ///
+    /// `filter` is called to compile the individual filters.
+    pub fn compile<G, E>(
+        &self,
+        graph: &mut G,
+        filter: &mut impl FnMut(&mut G, &Filter) -> Result<ExpressionId, E>,
+    ) -> Result<ExpressionId, E>
+    where
+        G: ModifyGraph,
+    {
+        let expression = match self {
+            Expression::Filter(f) => filter(graph, f)?,
+            Expression::Not(expression) => {
+                let expression = expression.compile(graph, filter)?;
+                graph.not(expression)
+            }
+            Expression::And(expressions) => {
+                let expressions = expressions
+                    .iter()
+                    .map(|expression| expression.compile(graph, filter))
+                    .collect::<Result<Vec<_>, E>>()?;
+                graph.and(&expressions)
+            }
+            Expression::Or(expressions) => {
+                let expressions = expressions
+                    .iter()
+                    .map(|expression| expression.compile(graph, filter))
+                    .collect::<Result<Vec<_>, E>>()?;
+                graph.or(&expressions)
+            }
+        };
+        Ok(expression)
+    }
+}
+
+/// A parsed filter expression.
+///
+/// This keeps the string it was parsed from, which is used when it's
+/// displayed or serialized.
 #[derive(Clone, Debug)]
 pub struct FilterExpression {
-    expression: Or<Filter, ()>,
+    string: Arc<str>,
+    expression: Expression,
+}
+
+impl FilterExpression {
+    #[inline]
+    pub fn expression(&self) -> &Expression {
+        &self.expression
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
 }
 
 // todo: i don't know how we can get a VerboseError that is 'static. maybe
@@ -63,32 +199,108 @@ impl FromStr for FilterExpression {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let (_rest, expression) = self::parser::parse(s).map_err(|e| ParseError(e.to_string()))?;
-        Ok(FilterExpression { expression })
+        Ok(FilterExpression {
+            string: s.into(),
+            expression,
+        })
     }
 }
 
-impl FilterExpression {
-    pub fn begin_evaluate(&self) -> Evaluator {
-        Evaluator {
-            evaluator: ContinuousEvaluation::new(self.expression.clone()),
-        }
+impl PartialEq for FilterExpression {
+    fn eq(&self, other: &Self) -> bool {
+        self.expression == other.expression
+    }
+}
+
+impl Eq for FilterExpression {}
+
+impl Hash for FilterExpression {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.expression.hash(state);
+    }
+}
+
+impl Display for FilterExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.string)
     }
 }
 
-#[derive(Debug)]
-pub struct Evaluator {
-    evaluator: ContinuousEvaluation<Filter, ()>,
+impl Serialize for FilterExpression {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.string.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FilterExpression {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s: std::borrow::Cow<'de, str> = Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
 }
 
-impl Evaluator {
-    pub fn get(&self) -> Option<bool> {
-        self.evaluator.get().into()
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for FilterExpression {
+    fn schema_name() -> String {
+        "FilterExpression".to_owned()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        <String as schemars::JsonSchema>::json_schema(gen)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Direction,
+        Expression,
+        Filter,
+        FilterExpression,
+    };
+
+    fn parse(s: &str) -> Expression {
+        s.parse::<FilterExpression>().unwrap().expression
+    }
+
+    #[test]
+    fn it_parses_expressions() {
+        let domain = |s: &str| Expression::Filter(Filter::Domain(s.parse().unwrap()));
+        let method = |s: &str| Expression::Filter(Filter::Method(s.parse().unwrap()));
 
-    pub fn push<'f>(
-        &mut self,
-        assignments: impl IntoIterator<Item = (&'f Filter, bool)>,
-    ) -> Option<bool> {
-        self.evaluator.push(assignments).into()
+        assert_eq!(
+            parse("~d example.com & ~m POST & !~a"),
+            Expression::And(vec![
+                domain("example.com"),
+                method("POST"),
+                Expression::Not(Box::new(Expression::Filter(Filter::Asset))),
+            ])
+        );
+        assert_eq!(
+            parse("~d a ~d b | !(~all)"),
+            Expression::Or(vec![
+                Expression::And(vec![domain("a"), domain("b")]),
+                Expression::Not(Box::new(Expression::Filter(Filter::All))),
+            ])
+        );
+        assert_eq!(
+            parse(r#"~hq "x-foo: \"\d+\"" ~c 404"#),
+            Expression::And(vec![
+                Expression::Filter(Filter::Header(
+                    Direction::Request,
+                    r#"x-foo: "\d+""#.parse().unwrap()
+                )),
+                Expression::Filter(Filter::HttpResponseCode(404)),
+            ])
+        );
+        assert!("~d".parse::<FilterExpression>().is_err());
+        assert!("~nope".parse::<FilterExpression>().is_err());
+        assert!("".parse::<FilterExpression>().is_err());
     }
 }
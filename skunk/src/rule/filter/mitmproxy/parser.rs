@@ -1,21 +1,23 @@
 use nom::{
     branch::alt,
     bytes::complete::{
-        escaped_transform,
         is_not,
         tag,
+        take_till1,
     },
     character::complete::{
+        anychar,
         char,
         digit1,
         multispace0,
-        multispace1,
+        satisfy,
     },
     combinator::{
         all_consuming,
         map,
         map_res,
-        opt,
+        not,
+        recognize,
         success,
         value,
     },
@@ -26,9 +28,10 @@ use nom::{
         VerboseError,
     },
     multi::{
+        fold_many0,
         many0_count,
         many1,
-        separated_list0,
+        separated_list1,
     },
     sequence::{
         delimited,
@@ -41,28 +44,20 @@ use nom::{
 
 use super::{
     Direction,
+    Expression,
     Filter,
 };
-use crate::{
-    rule::regex::Regex,
-    util::bool_expr::{
-        And,
-        Expression,
-        Or,
-        Term,
-        Variable,
-    },
-};
+use crate::rule::regex::Regex;
 
 type Res<'a, U> = IResult<&'a str, U, VerboseError<&'a str>>;
 
 /// consumes a single comment
-fn consume_comment(input: &str) -> Res<()> {
+fn consume_comment(input: &str) -> Res<'_, ()> {
     value((), pair(char('#'), is_not("\r\n")))(input)
 }
 
 /// consumes whitespace and comments
-fn consume_wsc<'a>(input: &'a str) -> Res<'a, ()> {
+fn consume_wsc(input: &str) -> Res<'_, ()> {
     value(
         (),
         terminated(
@@ -72,212 +67,165 @@ fn consume_wsc<'a>(input: &'a str) -> Res<'a, ()> {
     )(input)
 }
 
-/// consumes whitespace and comments, but at least one whitespace
-fn consume1_wsc<'a>(input: &'a str) -> Res<'a, ()> {
-    value(
-        (),
-        terminated(
-            many0_count(preceded(multispace0, consume_comment)),
-            multispace1,
-        ),
-    )(input)
-}
-
 /// consumes all whitespace and comments before calling the parser `f`
 fn wsc<'a, U>(f: impl FnMut(&'a str) -> Res<'a, U>) -> impl FnMut(&'a str) -> Res<'a, U> {
     preceded(consume_wsc, f)
 }
 
-pub fn parse(input: &str) -> Res<Or<Filter>> {
+pub fn parse(input: &str) -> Res<'_, Expression> {
     all_consuming(terminated(parse_or, consume_wsc))(input)
 }
 
-fn parse_or(input: &str) -> Res<Or<Filter>> {
+fn parse_or(input: &str) -> Res<'_, Expression> {
     context(
         "or",
-        map(separated_list0(char('|'), parse_and), |ands| {
-            Or { ands, data: () }
-        }),
-    )(input)
-}
-
-fn parse_and(input: &str) -> Res<And<Filter>> {
-    context(
-        "and",
-        map(separated_list0(char('&'), many1(parse_term)), |terms| {
-            And {
-                terms: terms.into_iter().flatten().collect(),
-                data: (),
+        map(separated_list1(wsc(char('|')), parse_and), |mut ands| {
+            if ands.len() == 1 {
+                ands.pop().unwrap()
+            }
+            else {
+                Expression::Or(ands)
             }
         }),
     )(input)
 }
 
-fn parse_term(input: &str) -> Res<Term<Filter>> {
+fn parse_and(input: &str) -> Res<'_, Expression> {
+    // terms that follow each other without an operator are also joined with an
+    // and.
     context(
-        "term",
+        "and",
         map(
-            pair(opt(char('!')), parse_expr),
-            |(exclamation_mark, expression)| {
-                Term {
-                    negative: exclamation_mark.is_some(),
-                    expression,
-                    data: (),
+            separated_list1(wsc(char('&')), many1(parse_term)),
+            |terms| {
+                let mut terms = terms.into_iter().flatten().collect::<Vec<_>>();
+                if terms.len() == 1 {
+                    terms.pop().unwrap()
+                }
+                else {
+                    Expression::And(terms)
                 }
             },
         ),
     )(input)
 }
 
-/*
-Asset,
-    All,
-    Body(Direction, Regex),
-    HttpResponseCode(u16),
-    Comment(Regex),
-    Domain(Regex),
-    Dns,
-    Destination(Regex),
-    Error,
-    Header(Direction, Regex),
-    Http,
-    Method(Regex),
-    Marked,
-    Marker(Regex),
-    Meta(Regex),
-    Direction(Direction),
-    Replay(Direction),
-    Source(Regex),
-    ContentType(Direction, Regex),
-    Tcp,
-    Url(Regex),
-    Udp,
-    Websocket,
-*/
+fn parse_term(input: &str) -> Res<'_, Expression> {
+    context(
+        "term",
+        alt((
+            map(preceded(wsc(char('!')), parse_term), |term| {
+                Expression::Not(Box::new(term))
+            }),
+            parse_expr,
+        )),
+    )(input)
+}
 
-fn parse_expr(input: &str) -> Res<Expression<Filter>> {
+fn parse_expr(input: &str) -> Res<'_, Expression> {
     context(
         "expr",
         alt((
-            map(delimited(wsc(char('(')), parse_or, wsc(char(')'))), |or| {
-                Expression::Or(or)
-            }),
-            map(
-                preceded(
-                    wsc(char('~')),
-                    alt((
-                        alt((
-                            parse_filter_variant("a", success(()), |()| Filter::Asset),
-                            parse_filter_variant("all", success(()), |()| Filter::All),
-                            parse_filter_variant("b", parse_regex, |regex| {
-                                Filter::Body(Direction::Both, regex)
-                            }),
-                            parse_filter_variant("bq", parse_regex, |regex| {
-                                Filter::Body(Direction::Request, regex)
-                            }),
-                            parse_filter_variant("bs", parse_regex, |regex| {
-                                Filter::Body(Direction::Response, regex)
-                            }),
-                            parse_filter_variant("c", map_res(digit1, str::parse), |code| {
-                                Filter::HttpResponseCode(code)
-                            }),
-                            parse_filter_variant("comment", parse_regex, |regex| {
-                                Filter::Comment(regex)
-                            }),
-                            parse_filter_variant("d", parse_regex, |regex| Filter::Domain(regex)),
-                            parse_filter_variant("dns", success(()), |()| Filter::Dns),
-                            parse_filter_variant("dst", parse_regex, |regex| {
-                                Filter::Destination(regex)
-                            }),
-                            parse_filter_variant("e", success(()), |()| Filter::Error),
-                            parse_filter_variant("h", parse_regex, |regex| {
-                                Filter::Header(Direction::Both, regex)
-                            }),
-                            parse_filter_variant("hq", parse_regex, |regex| {
-                                Filter::Header(Direction::Request, regex)
-                            }),
-                            parse_filter_variant("hs", parse_regex, |regex| {
-                                Filter::Header(Direction::Response, regex)
-                            }),
-                            parse_filter_variant("http", success(()), |()| Filter::Http),
-                            parse_filter_variant("method", parse_regex, |regex| {
-                                Filter::Method(regex)
-                            }),
-                            parse_filter_variant("marked", success(()), |()| Filter::Marked),
-                            parse_filter_variant("marker", parse_regex, |regex| {
-                                Filter::Marker(regex)
-                            }),
-                            parse_filter_variant("meta", parse_regex, |regex| Filter::Meta(regex)),
-                            parse_filter_variant("q", success(()), |()| {
-                                Filter::Direction(Direction::Request)
-                            }),
-                        )),
-                        alt((
-                            parse_filter_variant("replay", success(()), |()| {
-                                Filter::Replay(Direction::Both)
-                            }),
-                            parse_filter_variant("replayq", success(()), |()| {
-                                Filter::Replay(Direction::Request)
-                            }),
-                            parse_filter_variant("replays", success(()), |()| {
-                                Filter::Replay(Direction::Response)
-                            }),
-                            parse_filter_variant("s", success(()), |()| {
-                                Filter::Direction(Direction::Response)
-                            }),
-                            parse_filter_variant("src", parse_regex, |regex| Filter::Source(regex)),
-                            parse_filter_variant("t", parse_regex, |regex| {
-                                Filter::ContentType(Direction::Both, regex)
-                            }),
-                            parse_filter_variant("tq", parse_regex, |regex| {
-                                Filter::ContentType(Direction::Request, regex)
-                            }),
-                            parse_filter_variant("ts", parse_regex, |regex| {
-                                Filter::ContentType(Direction::Response, regex)
-                            }),
-                            parse_filter_variant("tcp", success(()), |()| Filter::Tcp),
-                            parse_filter_variant("u", parse_regex, |regex| Filter::Url(regex)),
-                            parse_filter_variant("udp", success(()), |()| Filter::Udp),
-                            parse_filter_variant("websocket", success(()), |()| Filter::Websocket),
-                        )),
-                    )),
-                ),
-                |filter| {
-                    Expression::Variable(Variable {
-                        variable: filter,
-                        data: (),
-                    })
-                },
-            ),
+            delimited(wsc(char('(')), parse_or, wsc(char(')'))),
+            map(preceded(wsc(char('~')), parse_filter), Expression::Filter),
         )),
     )(input)
 }
 
+fn parse_filter(input: &str) -> Res<'_, Filter> {
+    alt((
+        alt((
+            parse_filter_variant("a", success(()), |()| Filter::Asset),
+            parse_filter_variant("all", success(()), |()| Filter::All),
+            parse_filter_variant("b", parse_regex, |regex| {
+                Filter::Body(Direction::Both, regex)
+            }),
+            parse_filter_variant("bq", parse_regex, |regex| {
+                Filter::Body(Direction::Request, regex)
+            }),
+            parse_filter_variant("bs", parse_regex, |regex| {
+                Filter::Body(Direction::Response, regex)
+            }),
+            parse_filter_variant("c", wsc(map_res(digit1, str::parse)), |code| {
+                Filter::HttpResponseCode(code)
+            }),
+            parse_filter_variant("comment", parse_regex, Filter::Comment),
+            parse_filter_variant("d", parse_regex, Filter::Domain),
+            parse_filter_variant("dns", success(()), |()| Filter::Dns),
+            parse_filter_variant("dst", parse_regex, Filter::Destination),
+            parse_filter_variant("e", success(()), |()| Filter::Error),
+            parse_filter_variant("h", parse_regex, |regex| {
+                Filter::Header(Direction::Both, regex)
+            }),
+            parse_filter_variant("hq", parse_regex, |regex| {
+                Filter::Header(Direction::Request, regex)
+            }),
+            parse_filter_variant("hs", parse_regex, |regex| {
+                Filter::Header(Direction::Response, regex)
+            }),
+            parse_filter_variant("http", success(()), |()| Filter::Http),
+            parse_filter_variant("m", parse_regex, Filter::Method),
+            parse_filter_variant("marked", success(()), |()| Filter::Marked),
+            parse_filter_variant("marker", parse_regex, Filter::Marker),
+            parse_filter_variant("meta", parse_regex, Filter::Meta),
+        )),
+        alt((
+            parse_filter_variant("q", success(()), |()| Filter::Direction(Direction::Request)),
+            parse_filter_variant("replay", success(()), |()| Filter::Replay(Direction::Both)),
+            parse_filter_variant("replayq", success(()), |()| {
+                Filter::Replay(Direction::Request)
+            }),
+            parse_filter_variant("replays", success(()), |()| {
+                Filter::Replay(Direction::Response)
+            }),
+            parse_filter_variant("s", success(()), |()| {
+                Filter::Direction(Direction::Response)
+            }),
+            parse_filter_variant("src", parse_regex, Filter::Source),
+            parse_filter_variant("t", parse_regex, |regex| {
+                Filter::ContentType(Direction::Both, regex)
+            }),
+            parse_filter_variant("tq", parse_regex, |regex| {
+                Filter::ContentType(Direction::Request, regex)
+            }),
+            parse_filter_variant("ts", parse_regex, |regex| {
+                Filter::ContentType(Direction::Response, regex)
+            }),
+            parse_filter_variant("tag", parse_regex, Filter::Tag),
+            parse_filter_variant("tcp", success(()), |()| Filter::Tcp),
+            parse_filter_variant("u", parse_regex, Filter::Url),
+            parse_filter_variant("udp", success(()), |()| Filter::Udp),
+            parse_filter_variant("websocket", success(()), |()| Filter::Websocket),
+        )),
+    ))(input)
+}
+
+/// Parses a filter with the name `filter_tag`. The name must not be followed
+/// by more alphanumeric characters, so that e.g. `~all` isn't parsed as `~a`.
 fn parse_filter_variant<'a, U>(
     filter_tag: &'a str,
     args: impl FnMut(&'a str) -> Res<'a, U>,
     kind: impl FnMut(U) -> Filter,
 ) -> impl FnMut(&'a str) -> Res<'a, Filter> {
-    map(preceded(tag(filter_tag), args), kind)
+    map(
+        preceded(
+            terminated(tag(filter_tag), not(satisfy(char::is_alphanumeric))),
+            args,
+        ),
+        kind,
+    )
 }
 
-fn parse_regex(input: &str) -> Res<Regex> {
+fn parse_regex(input: &str) -> Res<'_, Regex> {
     context(
         "regex",
         map_res(
-            wsc(delimited(
-                char('"'),
-                escaped_transform(
-                    is_not("\\\""),
-                    '\\',
-                    alt((
-                        value("\\", tag("\\")),
-                        value("\"", tag("\"")),
-                        // todo: regex escapes
-                    )),
-                ),
-                char('"'),
-            )),
+            wsc(alt((
+                parse_quoted('"'),
+                parse_quoted('\''),
+                // unquoted regexes end at whitespace or a closing parenthesis
+                map(is_not(" \t\r\n\"')"), ToOwned::to_owned),
+            ))),
             |regex| {
                 regex
                     .parse()
@@ -286,3 +234,26 @@ fn parse_regex(input: &str) -> Res<Regex> {
         ),
     )(input)
 }
+
+/// Parses a string delimited by `quote`.
+///
+/// Escaped quotes are unescaped. All other escape sequences are kept as they
+/// are, since they're regex escapes.
+fn parse_quoted<'a>(quote: char) -> impl FnMut(&'a str) -> Res<'a, String> {
+    delimited(
+        char(quote),
+        fold_many0(
+            alt((
+                preceded(char('\\'), recognize(char(quote))),
+                recognize(pair(char('\\'), anychar)),
+                take_till1(move |c| c == quote || c == '\\'),
+            )),
+            String::new,
+            |mut string, part| {
+                string.push_str(part);
+                string
+            },
+        ),
+        char(quote),
+    )
+}
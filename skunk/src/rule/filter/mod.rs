@@ -1,2 +1 @@
-// todo: use new expression graph
-//pub mod mitmproxy;
+pub mod mitmproxy;
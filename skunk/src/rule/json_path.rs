@@ -0,0 +1,361 @@
+//! Paths into JSON values, for filters on JSON bodies.
+//!
+//! A path is either a [JSON Pointer][1] (e.g. `/items/0/id`), or a subset of
+//! [JSONPath][2] starting with `$`:
+//!
+//! - `.name` or `['name']`: A member of an object.
+//! - `[n]`: The `n`-th element of an array. Negative indices count from the
+//!   end.
+//! - `.*` or `[*]`: All members of an object or elements of an array.
+//! - `..name`, `..*` or `..[n]`: Like the above, but applied to the value and
+//!   all values nested in it.
+//!
+//! A path can select any number of values. Like in JSON Pointer, a member
+//! name that is a number also selects that element of an array.
+//!
+//! [1]: https://www.rfc-editor.org/rfc/rfc6901
+//! [2]: https://www.rfc-editor.org/rfc/rfc9535
+
+use std::{
+    borrow::Cow,
+    fmt::{
+        Debug,
+        Display,
+    },
+    hash::Hash,
+    str::FromStr,
+    sync::Arc,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Selector {
+    Member(String),
+    Index(i64),
+    Wildcard,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Step {
+    /// Whether the selector is applied to all nested values too.
+    descendants: bool,
+    selector: Selector,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid path {path:?}: {reason}")]
+pub struct ParseError {
+    path: String,
+    reason: &'static str,
+}
+
+/// A parsed JSONPath or JSON Pointer.
+///
+/// This keeps the string it was parsed from, which is used when it's
+/// displayed or serialized.
+#[derive(Clone)]
+pub struct JsonPath {
+    string: Arc<str>,
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    /// Returns the values in `value` that the path selects.
+    #[cfg(feature = "http")]
+    pub fn select<'v>(&self, value: &'v serde_json::Value) -> Vec<&'v serde_json::Value> {
+        let mut nodes = vec![value];
+        for step in &self.steps {
+            let mut selected = vec![];
+            for node in nodes {
+                if step.descendants {
+                    with_descendants(node, &mut |node| step.selector.select(node, &mut selected));
+                }
+                else {
+                    step.selector.select(node, &mut selected);
+                }
+            }
+            nodes = selected;
+        }
+        nodes
+    }
+}
+
+#[cfg(feature = "http")]
+impl Selector {
+    fn select<'v>(&self, value: &'v serde_json::Value, selected: &mut Vec<&'v serde_json::Value>) {
+        use serde_json::Value;
+
+        match (self, value) {
+            (Self::Member(name), Value::Object(object)) => selected.extend(object.get(name)),
+            (Self::Member(name), Value::Array(array)) => {
+                selected.extend(
+                    name.parse::<usize>()
+                        .ok()
+                        .and_then(|index| array.get(index)),
+                )
+            }
+            (Self::Index(index), Value::Array(array)) => {
+                let index = if *index < 0 {
+                    usize::try_from(index.unsigned_abs())
+                        .ok()
+                        .and_then(|index| array.len().checked_sub(index))
+                }
+                else {
+                    usize::try_from(*index).ok()
+                };
+                selected.extend(index.and_then(|index| array.get(index)));
+            }
+            (Self::Wildcard, Value::Object(object)) => selected.extend(object.values()),
+            (Self::Wildcard, Value::Array(array)) => selected.extend(array),
+            _ => {}
+        }
+    }
+}
+
+/// Calls `f` with `value` and all values nested in it.
+#[cfg(feature = "http")]
+fn with_descendants<'v>(value: &'v serde_json::Value, f: &mut impl FnMut(&'v serde_json::Value)) {
+    use serde_json::Value;
+
+    f(value);
+    match value {
+        Value::Object(object) => {
+            for value in object.values() {
+                with_descendants(value, f);
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                with_descendants(value, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Parses a JSON Pointer, without the leading `/`.
+fn parse_pointer(s: &str) -> Vec<Step> {
+    s.split('/')
+        .map(|token| {
+            Step {
+                descendants: false,
+                selector: Selector::Member(token.replace("~1", "/").replace("~0", "~")),
+            }
+        })
+        .collect()
+}
+
+/// Parses a JSONPath, without the leading `$`.
+fn parse_path(mut s: &str) -> Result<Vec<Step>, &'static str> {
+    let mut steps = vec![];
+
+    while !s.is_empty() {
+        let descendants = if let Some(rest) = s.strip_prefix("..") {
+            s = rest;
+            true
+        }
+        else if let Some(rest) = s.strip_prefix('.') {
+            s = rest;
+            false
+        }
+        else if s.starts_with('[') {
+            false
+        }
+        else {
+            return Err("expected `.` or `[`");
+        };
+
+        let selector = if let Some(rest) = s.strip_prefix('[') {
+            let (selector, rest) = parse_bracket(rest)?;
+            s = rest;
+            selector
+        }
+        else {
+            let end = s.find(['.', '[']).unwrap_or(s.len());
+            let (name, rest) = s.split_at(end);
+            s = rest;
+            match name {
+                "" => return Err("expected a member name"),
+                "*" => Selector::Wildcard,
+                _ => Selector::Member(name.to_owned()),
+            }
+        };
+
+        steps.push(Step {
+            descendants,
+            selector,
+        });
+    }
+
+    Ok(steps)
+}
+
+/// Parses the inside of `[...]`, and returns the rest after the `]`.
+fn parse_bracket(s: &str) -> Result<(Selector, &str), &'static str> {
+    if let Some(quote) = s.chars().next().filter(|c| *c == '\'' || *c == '"') {
+        let mut name = String::new();
+        let mut chars = s[1..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => name.push(chars.next().ok_or("unclosed string")?.1),
+                _ if c == quote => {
+                    let rest = s[i + 2..].strip_prefix(']').ok_or("expected `]`")?;
+                    return Ok((Selector::Member(name), rest));
+                }
+                _ => name.push(c),
+            }
+        }
+        Err("unclosed string")
+    }
+    else {
+        let (inner, rest) = s.split_once(']').ok_or("expected `]`")?;
+        let selector = match inner.trim() {
+            "*" => Selector::Wildcard,
+            index => Selector::Index(index.parse().map_err(|_| "invalid index")?),
+        };
+        Ok((selector, rest))
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Arc::<str>::from(s).try_into()
+    }
+}
+
+impl TryFrom<Arc<str>> for JsonPath {
+    type Error = ParseError;
+
+    fn try_from(string: Arc<str>) -> Result<Self, Self::Error> {
+        let steps = if string.is_empty() {
+            vec![]
+        }
+        else if let Some(pointer) = string.strip_prefix('/') {
+            parse_pointer(pointer)
+        }
+        else if let Some(path) = string.strip_prefix('$') {
+            parse_path(path).map_err(|reason| {
+                ParseError {
+                    path: string.to_string(),
+                    reason,
+                }
+            })?
+        }
+        else {
+            return Err(ParseError {
+                path: string.to_string(),
+                reason: "must start with `$` or `/`",
+            });
+        };
+        Ok(Self { string, steps })
+    }
+}
+
+impl PartialEq for JsonPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.string == other.string
+    }
+}
+
+impl Eq for JsonPath {}
+
+impl Hash for JsonPath {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.string.hash(state);
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.string)
+    }
+}
+
+impl Debug for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.string)
+    }
+}
+
+impl Serialize for JsonPath {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.string.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonPath {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s: Cow<'de, str> = Deserialize::deserialize(deserializer)?;
+        JsonPath::try_from(Arc::<str>::from(s)).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for JsonPath {
+    fn schema_name() -> String {
+        "JsonPath".to_owned()
+    }
+
+    fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        let mut schema = schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            ..Default::default()
+        };
+        schema.string().pattern = Some("^([$/]|$)".to_owned());
+        schema.into()
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "http")]
+mod tests {
+    use serde_json::json;
+
+    use super::JsonPath;
+
+    #[test]
+    fn it_selects_values() {
+        let value = json!({
+            "error": {"code": 42},
+            "items": [{"id": 1}, {"id": 2, "tags": {"id": 3}}],
+            "a/b": true,
+        });
+        let select = |path: &str| {
+            path.parse::<JsonPath>()
+                .unwrap()
+                .select(&value)
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select("$.error.code"), vec![json!(42)]);
+        assert_eq!(select("/error/code"), vec![json!(42)]);
+        assert_eq!(select("$['error']['code']"), vec![json!(42)]);
+        assert_eq!(select("$.items[-1].id"), vec![json!(2)]);
+        assert_eq!(select("/items/0/id"), vec![json!(1)]);
+        assert_eq!(select("$.items[*].id"), vec![json!(1), json!(2)]);
+        assert_eq!(select("$..id"), vec![json!(1), json!(2), json!(3)]);
+        assert_eq!(select("/a~1b"), vec![json!(true)]);
+        assert!(select("$.error.message").is_empty());
+        assert_eq!(select(""), vec![value.clone()]);
+    }
+}
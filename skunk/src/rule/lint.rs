@@ -0,0 +1,762 @@
+//! Static analysis of rules files.
+//!
+//! [`check`] reports problems that don't prevent rules from being compiled,
+//! but are likely mistakes, e.g. conditions that are always false, or two
+//! effects that change the same thing. [`check_reader`] additionally reports
+//! errors in the rules file itself, e.g. invalid regexes, with their
+//! location.
+
+use std::{
+    fmt::Display,
+    io::Read,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use serde::de::{
+    self,
+    DeserializeSeed,
+    MapAccess,
+    SeqAccess,
+    Visitor,
+};
+
+use super::{
+    backend::DefaultBackend,
+    compiler::{
+        self,
+        Backend as _,
+        Compiler,
+        Config,
+    },
+    file::{
+        self,
+        Block,
+        BodyReplacement,
+        DefaultEffects,
+        DefaultFilters,
+        Direction,
+        ModifyEffect,
+        Rule,
+        RulesFile,
+    },
+    regex::Regex,
+};
+use crate::util::boolean::{
+    ExpressionId,
+    ModifyGraph,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Position in a rules file. Lines and columns start at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub lint: Lint,
+
+    /// Path of the rule or effect the diagnostic is about, e.g.
+    /// `rules[1].then.effects[0]`. Empty for the whole file.
+    ///
+    /// If the file includes other files, the path refers to the rules after
+    /// they were included, so rules of included files come first.
+    pub path: String,
+
+    /// Only known for errors parsing the file, since the parsed rules don't
+    /// keep their location.
+    pub location: Option<Location>,
+
+    /// The included file that the diagnostic is about, or `None` for the
+    /// checked file.
+    pub file: Option<PathBuf>,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        self.lint.severity()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Lint {
+    /// The rules file can't be parsed, e.g. because a regex is invalid.
+    Parse { message: String },
+
+    /// The rules can't be compiled, e.g. because a filter is not supported.
+    Compile { message: String },
+
+    /// The rule has conditions, but they're always true.
+    AlwaysTrue,
+
+    /// The rule's conditions are always false, so its `then` block never
+    /// applies.
+    AlwaysFalse,
+
+    /// A `then` or `else` block never applies, e.g. because the conditions
+    /// are always true, or contradict the conditions of an enclosing rule.
+    Unreachable { block: &'static str },
+
+    /// The rule has neither a `then` nor an `else` block.
+    EmptyRule,
+
+    /// Two effects that can fire for the same message change the same thing
+    /// differently.
+    Conflict { what: String, other: String },
+
+    /// The effect requires user interaction, but the rules are compiled
+    /// without.
+    RequiresUserInteraction { effect: &'static str },
+
+    /// A network effect depends on conditions on HTTP messages, but it
+    /// applies to connections.
+    NetworkEffectOnMessage,
+}
+
+impl Lint {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::Parse { .. }
+            | Self::Compile { .. }
+            | Self::RequiresUserInteraction { .. }
+            | Self::NetworkEffectOnMessage => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse { message } | Self::Compile { message } => write!(f, "{message}"),
+            Self::AlwaysTrue => write!(f, "condition is always true"),
+            Self::AlwaysFalse => {
+                write!(f, "condition is always false, so `then` never applies")
+            }
+            Self::Unreachable { block } => write!(f, "`{block}` block never applies"),
+            Self::EmptyRule => write!(f, "rule has neither a `then` nor an `else` block"),
+            Self::Conflict { what, other } => {
+                write!(f, "effect conflicts with {other}: both change {what}")
+            }
+            Self::RequiresUserInteraction { effect } => {
+                write!(
+                    f,
+                    "the {effect} effect requires user interaction, which is not available"
+                )
+            }
+            Self::NetworkEffectOnMessage => {
+                write!(
+                    f,
+                    "the network effect applies to connections, so its conditions can't depend on HTTP messages"
+                )
+            }
+        }
+    }
+}
+
+/// Parses a rules file and checks it. Included files are not loaded, so
+/// this should only be used for files that don't include other files.
+///
+/// Errors parsing the file are returned as [`Lint::Parse`] diagnostics. Only
+/// IO errors are returned as errors.
+pub fn check_reader(
+    mut reader: impl Read,
+    config: &Config,
+) -> Result<Vec<Diagnostic>, std::io::Error> {
+    let mut source = String::new();
+    reader.read_to_string(&mut source)?;
+
+    match file::from_reader(source.as_bytes()) {
+        Ok(rules) => Ok(check(&rules, config)),
+        Err(error) => Ok(vec![parse_error(&error, Some(&source), None)]),
+    }
+}
+
+/// Loads a rules file with the files it includes, and checks it.
+///
+/// Errors loading the files are returned as [`Lint::Parse`] diagnostics.
+/// Only IO errors reading the file itself are returned as errors.
+pub fn check_file(
+    path: impl AsRef<Path>,
+    config: &Config,
+) -> Result<Vec<Diagnostic>, std::io::Error> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)?;
+
+    match file::load(path) {
+        Ok(rules) => Ok(check(&rules, config)),
+        Err(error) => {
+            // find the included file that the error is in.
+            let mut error = &error;
+            let mut included = None;
+            while let file::Error::Include { path, source } = error {
+                included = Some(path);
+                error = source;
+            }
+
+            let diagnostic = match included {
+                Some(included) => {
+                    let source = std::fs::read_to_string(included).ok();
+                    parse_error(error, source.as_deref(), Some(included.clone()))
+                }
+                None => parse_error(error, Some(&source), None),
+            };
+            Ok(vec![diagnostic])
+        }
+    }
+}
+
+fn parse_error(error: &file::Error, source: Option<&str>, file: Option<PathBuf>) -> Diagnostic {
+    let mut location = None;
+
+    let message = match error {
+        file::Error::Yaml(error) => {
+            let mut message = error.to_string();
+            if let Some(error_location) = error.location() {
+                let suffix = format!(
+                    " at line {} column {}",
+                    error_location.line(),
+                    error_location.column()
+                );
+                if let Some(stripped) = message.strip_suffix(&suffix) {
+                    message.truncate(stripped.len());
+                }
+                location = Some(Location {
+                    line: error_location.line(),
+                    column: error_location.column(),
+                });
+            }
+            if let Some(regex_location) =
+                source.and_then(|source| locate_invalid_regex(source, &message))
+            {
+                location = Some(regex_location);
+            }
+            message
+        }
+        error => {
+            let mut message = error.to_string();
+            let mut source = std::error::Error::source(error);
+            while let Some(error) = source {
+                message.push_str(&format!(": {error}"));
+                source = error.source();
+            }
+            message
+        }
+    };
+
+    Diagnostic {
+        lint: Lint::Parse { message },
+        path: String::new(),
+        location,
+        file,
+    }
+}
+
+/// The rules of a rules file are buffered while parsing it (because of
+/// `#[serde(flatten)]`), so errors in them are reported at the start of the
+/// file. For an invalid regex we can do better: This looks for a string in
+/// the file that fails to parse as regex with the same error.
+fn locate_invalid_regex(source: &str, message: &str) -> Option<Location> {
+    let error = FindInvalidRegex { message }
+        .deserialize(serde_yml::Deserializer::from_str(source))
+        .err()?;
+    if !error.to_string().contains(FindInvalidRegex::FOUND) {
+        return None;
+    }
+    let location = error.location()?;
+    Some(Location {
+        line: location.line(),
+        column: location.column(),
+    })
+}
+
+#[derive(Clone, Copy)]
+struct FindInvalidRegex<'a> {
+    message: &'a str,
+}
+
+impl<'a> FindInvalidRegex<'a> {
+    const FOUND: &'static str = "found invalid regex";
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for FindInvalidRegex<'a> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for FindInvalidRegex<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "any YAML value")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        match v.parse::<Regex>() {
+            Err(error)
+                if std::error::Error::source(&error)
+                    .is_some_and(|source| self.message.contains(&source.to_string())) =>
+            {
+                Err(E::custom(Self::FOUND))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        while seq.next_element_seed(self)?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        while map.next_key_seed(self)?.is_some() {
+            map.next_value_seed(self)?;
+        }
+        Ok(())
+    }
+
+    fn visit_bool<E>(self, _v: bool) -> Result<Self::Value, E> {
+        Ok(())
+    }
+
+    fn visit_i64<E>(self, _v: i64) -> Result<Self::Value, E> {
+        Ok(())
+    }
+
+    fn visit_u64<E>(self, _v: u64) -> Result<Self::Value, E> {
+        Ok(())
+    }
+
+    fn visit_f64<E>(self, _v: f64) -> Result<Self::Value, E> {
+        Ok(())
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(())
+    }
+}
+
+/// Checks a rules file. The diagnostics are ordered by where they are in
+/// the file, except for conflicting effects, which come last.
+pub fn check(
+    rules: &RulesFile<DefaultFilters, DefaultEffects>,
+    config: &Config,
+) -> Vec<Diagnostic> {
+    // only used to compile conditions. Effects are not added, so the conditions
+    // are not pinned.
+    let mut backend = DefaultBackend::new();
+    let mut checker = Checker {
+        config,
+        compiler: Compiler::new(config, &mut backend),
+        path: vec![],
+        effects: vec![],
+        diagnostics: vec![],
+    };
+
+    let result = checker
+        .compiler
+        .compile_conditions(&rules.conditions)
+        .and_then(|()| checker.check_block(&rules.rules, None));
+    if let Err(error) = result {
+        let message = match error {
+            compiler::Error::Backend(error) => error.to_string(),
+            error => error.to_string(),
+        };
+        checker.push(Lint::Compile { message });
+    }
+    else {
+        checker.check_conflicts();
+    }
+
+    checker.diagnostics
+}
+
+struct Checker<'a> {
+    config: &'a Config,
+    compiler: Compiler<'a, DefaultBackend>,
+
+    path: Vec<String>,
+
+    /// All effects that can fire, with their path and condition.
+    effects: Vec<(String, ExpressionId, &'a DefaultEffects)>,
+
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn push(&mut self, lint: Lint) {
+        self.diagnostics.push(Diagnostic {
+            lint,
+            path: self.path.join("."),
+            location: None,
+            file: None,
+        });
+    }
+
+    fn check_block(
+        &mut self,
+        block: &'a Block<DefaultFilters, DefaultEffects>,
+        condition: Option<ExpressionId>,
+    ) -> Result<(), compiler::Error<DefaultBackend>> {
+        for (index, rule) in block.rules.iter().enumerate() {
+            self.path.push(format!("rules[{index}]"));
+            self.check_rule(rule, condition)?;
+            self.path.pop();
+        }
+
+        let condition = condition.unwrap_or_else(|| self.compiler.backend().literal(true));
+        for (index, effect) in block.effects.iter().enumerate() {
+            self.path.push(format!("effects[{index}]"));
+            if effect.requires_user_interaction() && !self.config.with_user_interaction {
+                self.push(Lint::RequiresUserInteraction {
+                    effect: effect.name(),
+                });
+            }
+            if matches!(effect, DefaultEffects::Network(_))
+                && self.compiler.backend().is_message_condition(condition)
+            {
+                self.push(Lint::NetworkEffectOnMessage);
+            }
+            if condition != self.compiler.backend().literal(false) {
+                self.effects.push((self.path.join("."), condition, effect));
+            }
+            self.path.pop();
+        }
+
+        Ok(())
+    }
+
+    fn check_rule(
+        &mut self,
+        rule: &'a Rule<DefaultFilters, DefaultEffects>,
+        condition: Option<ExpressionId>,
+    ) -> Result<(), compiler::Error<DefaultBackend>> {
+        if rule.then.is_empty() && rule.alt.is_empty() {
+            self.push(Lint::EmptyRule);
+            return Ok(());
+        }
+
+        let mut scope = self.compiler.backend().scope(None, None);
+        let rule_condition =
+            self.compiler
+                .compile_conditions_with(&rule.condition, ModifyGraph::and, &mut scope)?;
+        let always = self.compiler.backend().literal(true);
+        let never = self.compiler.backend().literal(false);
+
+        // an empty `if` is a common way to group rules, so it's not reported.
+        if rule_condition == always && !rule.condition.is_empty() {
+            self.push(Lint::AlwaysTrue);
+        }
+        if rule_condition == never {
+            self.push(Lint::AlwaysFalse);
+        }
+
+        if !rule.then.is_empty() {
+            let then_condition = self.and_with(condition, rule_condition);
+            if then_condition == never && rule_condition != never {
+                self.push(Lint::Unreachable { block: "then" });
+            }
+            self.path.push("then".to_owned());
+            self.check_block(&rule.then, Some(then_condition))?;
+            self.path.pop();
+        }
+
+        if !rule.alt.is_empty() {
+            let not_rule_condition = self.compiler.backend().not(rule_condition);
+            let alt_condition = self.and_with(condition, not_rule_condition);
+            if alt_condition == never {
+                self.push(Lint::Unreachable { block: "else" });
+            }
+            self.path.push("else".to_owned());
+            self.check_block(&rule.alt, Some(alt_condition))?;
+            self.path.pop();
+        }
+
+        Ok(())
+    }
+
+    fn and_with(
+        &mut self,
+        condition: Option<ExpressionId>,
+        expression: ExpressionId,
+    ) -> ExpressionId {
+        if let Some(condition) = condition {
+            self.compiler.backend().and(&[condition, expression])
+        }
+        else {
+            expression
+        }
+    }
+
+    /// Reports effects that conflict, unless their conditions exclude each
+    /// other.
+    fn check_conflicts(&mut self) {
+        let never = self.compiler.backend().literal(false);
+
+        for (index, (path, condition, effect)) in self.effects.iter().enumerate() {
+            for (other_path, other_condition, other_effect) in &self.effects[..index] {
+                let Some(what) = conflict(other_effect, effect)
+                else {
+                    continue;
+                };
+                if self.compiler.backend().and(&[*other_condition, *condition]) == never {
+                    continue;
+                }
+                self.diagnostics.push(Diagnostic {
+                    lint: Lint::Conflict {
+                        what,
+                        other: other_path.clone(),
+                    },
+                    path: path.clone(),
+                    location: None,
+                    file: None,
+                });
+            }
+        }
+    }
+}
+
+/// Returns what both effects change differently, if anything.
+fn conflict(first: &DefaultEffects, second: &DefaultEffects) -> Option<String> {
+    use DefaultEffects::*;
+
+    match (first, second) {
+        (Modify(first), Modify(second)) => modify_conflict(first, second),
+        (MapLocal(_) | MapRemote(_) | Mock(_), MapLocal(_) | MapRemote(_) | Mock(_)) => {
+            Some("where the request is sent".to_owned())
+        }
+        (Network(_), Network(_)) => Some("the network conditions".to_owned()),
+        (Mark(first), Mark(second)) if first != second => Some("the marker".to_owned()),
+        (Comment(first), Comment(second)) if first != second => Some("the comment".to_owned()),
+        _ => None,
+    }
+}
+
+fn modify_conflict(first: &ModifyEffect, second: &ModifyEffect) -> Option<String> {
+    fn differ<T: PartialEq>(first: &Option<T>, second: &Option<T>) -> bool {
+        matches!((first, second), (Some(first), Some(second)) if first != second)
+    }
+
+    let requests = applies_to(&first.direction, Direction::Request)
+        && applies_to(&second.direction, Direction::Request);
+    let responses = applies_to(&first.direction, Direction::Response)
+        && applies_to(&second.direction, Direction::Response);
+    if !requests && !responses {
+        return None;
+    }
+
+    if requests {
+        if differ(&first.method, &second.method) {
+            return Some("the method".to_owned());
+        }
+        if differ(&first.url, &second.url) {
+            return Some("the URL".to_owned());
+        }
+        if differ(&first.path, &second.path) {
+            return Some("the path".to_owned());
+        }
+        if differ(&first.query, &second.query) {
+            return Some("the query".to_owned());
+        }
+    }
+
+    if responses && differ(&first.status, &second.status) {
+        return Some("the status code".to_owned());
+    }
+
+    // regex replacements are applied one after another, but any other body
+    // replacement discards the body.
+    match (&first.body, &second.body) {
+        (Some(BodyReplacement::Replace { .. }), Some(BodyReplacement::Replace { .. })) => {}
+        (Some(_), Some(_)) => return Some("the body".to_owned()),
+        _ => {}
+    }
+
+    for (name, value) in &first.set_headers {
+        let other_value = second
+            .set_headers
+            .iter()
+            .find(|(other_name, _)| other_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value);
+        if other_value.is_some_and(|other_value| other_value != value) {
+            return Some(format!("the {name} header"));
+        }
+    }
+
+    None
+}
+
+fn applies_to(direction: &Direction, message: Direction) -> bool {
+    direction.is_both() || *direction == message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_reader,
+        Lint,
+    };
+    use crate::rule::compiler::Config;
+
+    fn check(rules: &str) -> Vec<(String, Lint)> {
+        check_reader(rules.as_bytes(), &Config::default())
+            .unwrap()
+            .into_iter()
+            .map(|diagnostic| (diagnostic.path, diagnostic.lint))
+            .collect()
+    }
+
+    #[test]
+    fn it_reports_constant_conditions() {
+        let diagnostics = check(
+            r#"
+rules:
+  - if:
+      - or: []
+    then:
+      effects:
+        - drop
+  - if:
+      - not:
+          - or: []
+    then:
+      effects:
+        - drop
+    else:
+      effects:
+        - drop
+  - if:
+      - host: ["^example\\.com$"]
+"#,
+        );
+
+        assert!(matches!(
+            &diagnostics[..],
+            [
+                (first, Lint::AlwaysFalse),
+                (second, Lint::AlwaysTrue),
+                (third, Lint::Unreachable { block: "else" }),
+                (fourth, Lint::EmptyRule),
+            ] if first == "rules[0]" && second == "rules[1]" && third == "rules[1]" && fourth == "rules[2]"
+        ));
+    }
+
+    #[test]
+    fn it_reports_conflicting_effects() {
+        let diagnostics = check(
+            r#"
+rules:
+  - if:
+      - host: ["^example\\.com$"]
+    then:
+      effects:
+        - modify:
+            status: 404
+    else:
+      effects:
+        - modify:
+            status: 500
+effects:
+  - modify:
+      status: 200
+  - interrupt: {}
+"#,
+        );
+
+        let lints = diagnostics
+            .iter()
+            .map(|(path, lint)| format!("{path}: {lint}"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            [
+                "effects[1]: the interrupt effect requires user interaction, which is not available",
+                "effects[0]: effect conflicts with rules[0].then.effects[0]: both change the status code",
+                "effects[0]: effect conflicts with rules[0].else.effects[0]: both change the status code",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_network_effects_on_messages() {
+        let diagnostics = check(
+            r#"
+rules:
+  - if:
+      - host: ["^example\\.com$"]
+    then:
+      effects:
+        - network:
+            latency-ms: 100
+  - if:
+      - filter: "~m POST"
+    then:
+      effects:
+        - network:
+            latency-ms: 100
+"#,
+        );
+
+        let paths = diagnostics
+            .iter()
+            .filter(|(_, lint)| matches!(lint, Lint::NetworkEffectOnMessage))
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["rules[1].then.effects[0]"]);
+    }
+
+    #[test]
+    fn it_reports_invalid_regexes_with_their_location() {
+        let diagnostics = check_reader(
+            r#"
+rules:
+  - if:
+      - host: ["(example"]
+    then:
+      effects:
+        - drop
+"#
+            .as_bytes(),
+            &Config::default(),
+        )
+        .unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(diagnostics[0].lint, Lint::Parse { .. }));
+        assert_eq!(diagnostics[0].location.unwrap().line, 4);
+    }
+}
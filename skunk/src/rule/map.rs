@@ -0,0 +1,349 @@
+//! Answers requests with local files ([`MapLocalEffect`]), or sends them to
+//! another server ([`MapRemoteEffect`]).
+
+use std::{
+    io::ErrorKind,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::{
+    header::{
+        self,
+        HeaderValue,
+    },
+    http::{
+        request,
+        uri::Authority,
+    },
+    Request,
+    Response,
+    StatusCode,
+    Uri,
+};
+use percent_encoding::percent_decode_str;
+
+use super::file::MapLocalEffect;
+use crate::{
+    address::{
+        HostAddress,
+        TcpAddress,
+    },
+    protocol::http::{
+        self,
+        body::{
+            Body,
+            Replace,
+        },
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid URL: {url}")]
+    InvalidUrl { url: String },
+
+    #[error("unsupported URL scheme: {scheme}")]
+    UnsupportedScheme { scheme: String },
+
+    #[error("invalid status code: {status}")]
+    InvalidStatus { status: u16 },
+
+    #[error("invalid content type: {content_type}")]
+    InvalidContentType { content_type: String },
+
+    #[error("could not read file: {path}")]
+    ReadFile {
+        #[source]
+        error: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("could not connect to {address}")]
+    Connect {
+        #[source]
+        error: std::io::Error,
+        address: TcpAddress,
+    },
+
+    #[cfg(feature = "tls")]
+    #[error("tls error")]
+    Tls(#[from] crate::protocol::tls::Error),
+
+    #[error("http error")]
+    Http(#[from] http::Error),
+}
+
+/// Answers `request` with the file the `effect` maps it to.
+///
+/// The request body is still read to the end, since the client expects it to
+/// be. If there is no such file, the response is a `404 Not Found`.
+pub async fn map_local<Bq, Bs>(
+    request: Request<Bq>,
+    effect: &MapLocalEffect,
+) -> Result<Response<Replace<Bs>>, Error>
+where
+    Bq: Body,
+{
+    let (parts, body) = request.into_parts();
+    // we don't care about the body, so errors reading it don't matter either.
+    let _ = body.collect().await;
+
+    let Some(path) = local_path(&effect.path, parts.uri.path()).await
+    else {
+        return Ok(not_found());
+    };
+
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(not_found()),
+        Err(error) => return Err(Error::ReadFile { error, path }),
+    };
+
+    let status = effect
+        .status
+        .map(|status| StatusCode::from_u16(status).map_err(|_| Error::InvalidStatus { status }))
+        .transpose()?
+        .unwrap_or(StatusCode::OK);
+
+    let content_type = if let Some(content_type) = &effect.content_type {
+        HeaderValue::from_str(content_type).map_err(|_| {
+            Error::InvalidContentType {
+                content_type: content_type.clone(),
+            }
+        })?
+    }
+    else {
+        HeaderValue::from_static(guess_content_type(&path))
+    };
+
+    let content_length = data.len();
+    let mut response = Response::new(Replace::replaced(data));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, content_type);
+    headers.insert(header::CONTENT_LENGTH, content_length.into());
+
+    Ok(response)
+}
+
+fn not_found<B>() -> Response<Replace<B>> {
+    let mut response = Response::new(Replace::replaced(Bytes::new()));
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
+
+/// Returns the file that `request_path` maps to.
+///
+/// If `base` is a directory, the request path is looked up in it. Paths that
+/// would leave `base` map to nothing.
+async fn local_path(base: &Path, request_path: &str) -> Option<PathBuf> {
+    if !is_dir(base).await {
+        return Some(base.to_owned());
+    }
+
+    let mut path = base.to_owned();
+    for segment in request_path.split('/') {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        match &*segment {
+            "" | "." => {}
+            ".." => return None,
+            segment if segment.contains(['/', '\\']) => return None,
+            segment => path.push(segment),
+        }
+    }
+
+    if is_dir(&path).await {
+        path.push("index.html");
+    }
+
+    Some(path)
+}
+
+async fn is_dir(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+}
+
+fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The server a [`MapRemoteEffect`] sends requests to.
+#[derive(Clone, Debug)]
+struct RemoteTarget {
+    address: TcpAddress,
+    authority: Authority,
+    tls: bool,
+    path: String,
+}
+
+impl RemoteTarget {
+    fn new(url: &str) -> Result<Self, Error> {
+        let invalid_url = || {
+            Error::InvalidUrl {
+                url: url.to_owned(),
+            }
+        };
+
+        let uri = url.parse::<Uri>().map_err(|_| invalid_url())?;
+        let tls = match uri.scheme_str() {
+            Some("http") => false,
+            Some("https") => true,
+            Some(scheme) => {
+                return Err(Error::UnsupportedScheme {
+                    scheme: scheme.to_owned(),
+                })
+            }
+            None => return Err(invalid_url()),
+        };
+        let authority = uri.authority().ok_or_else(invalid_url)?.clone();
+        let host = authority
+            .host()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<HostAddress>()
+            .map_err(|_| invalid_url())?;
+        let port = authority.port_u16().unwrap_or(if tls { 443 } else { 80 });
+
+        Ok(Self {
+            address: TcpAddress::new(host, port),
+            authority,
+            tls,
+            path: uri.path().trim_end_matches('/').to_owned(),
+        })
+    }
+
+    /// Changes the request so that it's sent to this target.
+    fn rewrite(&self, parts: &mut request::Parts) -> Result<(), Error> {
+        let path_and_query = format!(
+            "{}{}",
+            self.path,
+            parts
+                .uri
+                .path_and_query()
+                .map_or("/", |path_and_query| path_and_query.as_str())
+        );
+        parts.uri = path_and_query.parse().map_err(|_| {
+            Error::InvalidUrl {
+                url: path_and_query.clone(),
+            }
+        })?;
+        parts.headers.insert(
+            header::HOST,
+            HeaderValue::from_str(self.authority.as_str())
+                .expect("authority is a valid header value"),
+        );
+        Ok(())
+    }
+}
+
+/// Sends `request` to the server that the `effect` maps it to, and returns
+/// the response.
+///
+/// This opens a new connection using `connect` for every request. If the
+/// target uses HTTPS, the connection is encrypted using `tls`.
+#[cfg(feature = "tls")]
+pub async fn map_remote<C, B>(
+    connect: &C,
+    tls: &crate::protocol::tls::Context,
+    effect: &super::file::MapRemoteEffect,
+    request: Request<B>,
+) -> Result<Response<hyper::body::Incoming>, Error>
+where
+    C: crate::connect::Connect,
+    C::Connection: 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let target = RemoteTarget::new(&effect.url)?;
+
+    let (mut parts, body) = request.into_parts();
+    target.rewrite(&mut parts)?;
+    let request = Request::from_parts(parts, body);
+
+    let stream = connect.connect(&target.address).await.map_err(|error| {
+        Error::Connect {
+            error,
+            address: target.address.clone(),
+        }
+    })?;
+
+    if target.tls {
+        let server_name = crate::protocol::tls::server_name(&target.address.host.to_string())?;
+        let stream = tls.connect(stream, server_name).await?;
+        send(stream, request).await
+    }
+    else {
+        send(stream, request).await
+    }
+}
+
+#[cfg(feature = "tls")]
+async fn send<T, B>(io: T, request: Request<B>) -> Result<Response<hyper::body::Incoming>, Error>
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let (client, send_request) = http::client(io).await?;
+    tokio::spawn(async move {
+        if let Err(error) = client.await {
+            tracing::debug!(?error, "Connection to remote target failed");
+        }
+    });
+    Ok(send_request.send(request).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Request;
+
+    use super::RemoteTarget;
+
+    #[test]
+    fn it_rewrites_requests_for_remote_target() {
+        let target = RemoteTarget::new("https://localhost:8443/api/").unwrap();
+        assert_eq!(target.address.to_string(), "localhost:8443");
+        assert!(target.tls);
+
+        let (mut parts, ()) = Request::get("/users?id=1")
+            .header("host", "example.com")
+            .body(())
+            .unwrap()
+            .into_parts();
+        target.rewrite(&mut parts).unwrap();
+        assert_eq!(parts.uri, "/api/users?id=1");
+        assert_eq!(parts.headers["host"], "localhost:8443");
+
+        assert!(RemoteTarget::new("ftp://example.com").is_err());
+    }
+}
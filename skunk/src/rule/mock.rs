@@ -0,0 +1,208 @@
+//! Answers requests with responses from [`MockEffect`]s.
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::{
+    header::{
+        self,
+        HeaderName,
+        HeaderValue,
+    },
+    Request,
+    Response,
+    StatusCode,
+};
+
+use super::{
+    fields::RequestFields,
+    file::MockEffect,
+    template::{
+        Field,
+        Template,
+    },
+};
+use crate::protocol::http::body::{
+    Body,
+    Replace,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid status code: {status}")]
+    InvalidStatus { status: u16 },
+
+    #[error("invalid header name: {name}")]
+    InvalidHeaderName { name: String },
+
+    #[error("invalid value for header {name}")]
+    InvalidHeaderValue { name: String },
+
+    #[error("could not read request body")]
+    Body(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Renders the response of a mock `effect` for `request`.
+///
+/// The request body is read, since the templates might refer to it. The
+/// request is returned with the body that was read, so that it can still be
+/// forwarded.
+pub async fn mock<B, Bs>(
+    request: Request<Replace<B>>,
+    effect: &MockEffect,
+) -> Result<(Request<Replace<B>>, Response<Replace<Bs>>), Error>
+where
+    B: Body<Data = Bytes>,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let (parts, body) = request.into_parts();
+    let body = body
+        .collect()
+        .await
+        .map_err(|error| Error::Body(Box::new(error)))?
+        .to_bytes();
+
+    // only parse the body if it's needed.
+    let has_json_field = effect
+        .body
+        .iter()
+        .chain(effect.headers.values())
+        .flat_map(Template::fields)
+        .any(|field| matches!(field, Field::Json(_)));
+    let mut fields = RequestFields::from_parts(&parts).with_body(&body, has_json_field);
+    if let Some(regex) = &effect.captures {
+        fields = fields.with_captures(regex);
+    }
+
+    let status = effect
+        .status
+        .map(|status| StatusCode::from_u16(status).map_err(|_| Error::InvalidStatus { status }))
+        .transpose()?
+        .unwrap_or(StatusCode::OK);
+    let response_body = effect
+        .body
+        .as_ref()
+        .map(|template| fields.render(template))
+        .unwrap_or_default();
+
+    let mut response = Response::builder().status(status);
+    for (name, template) in &effect.headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+            Error::InvalidHeaderName {
+                name: name.to_owned(),
+            }
+        })?;
+        let header_value = HeaderValue::from_str(&fields.render(template)).map_err(|_| {
+            Error::InvalidHeaderValue {
+                name: name.to_owned(),
+            }
+        })?;
+        response = response.header(header_name, header_value);
+    }
+    let response = response
+        .header(header::CONTENT_LENGTH, response_body.len())
+        .body(Replace::replaced(response_body))
+        .expect("invalid response");
+
+    Ok((
+        Request::from_parts(parts, Replace::replaced(body)),
+        response,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use http_body_util::BodyExt;
+    use hyper::{
+        header,
+        Method,
+        Request,
+        Response,
+        StatusCode,
+    };
+
+    use super::mock;
+    use crate::{
+        protocol::http::body::{
+            Empty,
+            Replace,
+        },
+        rule::file::MockEffect,
+    };
+
+    fn effect(yaml: &str) -> MockEffect {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn it_renders_mock_responses() {
+        let effect = effect(
+            r#"
+status: 201
+headers:
+  content-type: application/json
+  x-user: "{{ header.x-user }}"
+body: '{"id": {{ capture.id }}, "name": "{{ json.name }}", "q": "{{ query.q }}", "missing": "{{ query.nope }}"}'
+captures: "^/users/(?<id>\\d+)"
+"#,
+        );
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("http://example.com/users/42?q=hi")
+            .header("x-user", "alice")
+            .body(Replace::<Empty>::replaced(r#"{"name": "skunk"}"#))
+            .unwrap();
+
+        block_on(async {
+            let (request, response): (_, Response<Replace<Empty>>) =
+                mock(request, &effect).await.unwrap();
+
+            // the request body was read, but is still there.
+            let request_body = request.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(request_body, r#"{"name": "skunk"}"#);
+
+            assert_eq!(response.status(), StatusCode::CREATED);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            assert_eq!(response.headers()["x-user"], "alice");
+            let expected = r#"{"id": 42, "name": "skunk", "q": "hi", "missing": ""}"#;
+            assert_eq!(
+                response.headers()[header::CONTENT_LENGTH],
+                expected.len().to_string()
+            );
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, expected);
+        });
+    }
+
+    #[test]
+    fn it_defaults_to_an_empty_ok_response() {
+        let request = Request::builder()
+            .uri("http://example.com/")
+            .body(Replace::<Empty>::replaced(""))
+            .unwrap();
+
+        block_on(async {
+            let (_, response): (_, Response<Replace<Empty>>) =
+                mock(request, &effect("{}")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_LENGTH], "0");
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert!(body.is_empty());
+        });
+    }
+
+    #[test]
+    fn it_rejects_invalid_headers() {
+        let request = Request::builder()
+            .uri("http://example.com/")
+            .body(Replace::<Empty>::replaced(""))
+            .unwrap();
+        let effect = effect("headers:\n  x-value: \"{{ header.nope }}\\n\"\n");
+
+        let result = block_on(mock::<_, Empty>(request, &effect));
+        assert!(matches!(
+            result,
+            Err(super::Error::InvalidHeaderValue { name }) if name == "x-value"
+        ));
+    }
+}
@@ -2,8 +2,22 @@
 //!
 //! This is undocumented as it is likely to change a lot.
 
+pub mod backend;
 pub mod compiler;
 pub mod eval;
+#[cfg(feature = "http")]
+pub mod fields;
 pub mod file;
 pub mod filter;
+pub mod json_path;
+pub mod lint;
+#[cfg(feature = "http")]
+pub mod map;
+#[cfg(feature = "http")]
+pub mod mock;
+#[cfg(feature = "http")]
+pub mod modify;
 pub mod regex;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod template;
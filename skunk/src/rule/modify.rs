@@ -0,0 +1,330 @@
+//! Applies [`ModifyEffect`]s to HTTP requests and responses.
+//!
+//! Bodies are replaced as they are, i.e. they're not compressed. A replaced
+//! body is therefore sent without a `Content-Encoding`. Regex replacements
+//! are skipped for bodies that have a `Content-Encoding`.
+
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use hyper::{
+    header::{
+        self,
+        HeaderName,
+        HeaderValue,
+    },
+    http::{
+        request,
+        response,
+        uri::PathAndQuery,
+    },
+    HeaderMap,
+    Method,
+    Request,
+    Response,
+    StatusCode,
+    Uri,
+};
+
+use super::file::{
+    BodyReplacement,
+    Direction,
+    ModifyEffect,
+};
+use crate::protocol::http::body::{
+    Body,
+    Replace,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid header name: {name}")]
+    InvalidHeaderName { name: String },
+
+    #[error("invalid value for header {name}")]
+    InvalidHeaderValue { name: String },
+
+    #[error("invalid method: {method}")]
+    InvalidMethod { method: String },
+
+    #[error("invalid URL: {url}")]
+    InvalidUrl { url: String },
+
+    #[error("invalid status code: {status}")]
+    InvalidStatus { status: u16 },
+
+    #[error("could not read body from file: {path}")]
+    ReadFile {
+        #[source]
+        error: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("could not read body")]
+    Body(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Applies the `effects` that modify requests to `request`.
+///
+/// The request body is a [`Replace`], so that requests whose body was already
+/// read (e.g. to evaluate body filters) can be modified too.
+pub async fn modify_request<'a, B>(
+    request: Request<Replace<B>>,
+    effects: impl IntoIterator<Item = &'a ModifyEffect>,
+) -> Result<Request<Replace<B>>, Error>
+where
+    B: Body<Data = Bytes>,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let (mut parts, body) = request.into_parts();
+    let mut body = ModifyBody::from(body);
+
+    for effect in effects {
+        if matches!(effect.direction, Direction::Response) {
+            continue;
+        }
+
+        if let Some(method) = &effect.method {
+            parts.method = Method::from_bytes(method.as_bytes()).map_err(|_| {
+                Error::InvalidMethod {
+                    method: method.clone(),
+                }
+            })?;
+        }
+        modify_uri(&mut parts, effect)?;
+        modify_headers(&mut parts.headers, effect)?;
+        if !effect.cookies.is_empty() {
+            add_cookies(&mut parts.headers, effect)?;
+        }
+        body = body.modify(&parts.headers, effect).await?;
+    }
+
+    let body = body.finish(&mut parts.headers);
+    Ok(Request::from_parts(parts, body))
+}
+
+/// Applies the `effects` that modify responses to `response`.
+///
+/// The response body is a [`Replace`], so that responses that didn't come
+/// from the server (e.g. from a map-local effect) can be modified too.
+pub async fn modify_response<'a, B>(
+    response: Response<Replace<B>>,
+    effects: impl IntoIterator<Item = &'a ModifyEffect>,
+) -> Result<Response<Replace<B>>, Error>
+where
+    B: Body<Data = Bytes>,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let (mut parts, body) = response.into_parts();
+    let mut body = ModifyBody::from(body);
+
+    for effect in effects {
+        if matches!(effect.direction, Direction::Request) {
+            continue;
+        }
+
+        if let Some(status) = effect.status {
+            parts.status =
+                StatusCode::from_u16(status).map_err(|_| Error::InvalidStatus { status })?;
+        }
+        modify_headers(&mut parts.headers, effect)?;
+        if !effect.cookies.is_empty() {
+            add_set_cookies(&mut parts, effect)?;
+        }
+        body = body.modify(&parts.headers, effect).await?;
+    }
+
+    let body = body.finish(&mut parts.headers);
+    Ok(Response::from_parts(parts, body))
+}
+
+/// Replaces the URL, path and query of a request.
+///
+/// If the request has a path only (which is usually the case), and the new
+/// URL has a host, the host is set as `Host` header instead.
+fn modify_uri(parts: &mut request::Parts, effect: &ModifyEffect) -> Result<(), Error> {
+    let invalid_url = |url: &str| {
+        Error::InvalidUrl {
+            url: url.to_owned(),
+        }
+    };
+
+    if let Some(url) = &effect.url {
+        let uri = url.parse::<Uri>().map_err(|_| invalid_url(url))?;
+        if parts.uri.authority().is_none() {
+            if let Some(authority) = uri.authority() {
+                parts.headers.insert(
+                    header::HOST,
+                    HeaderValue::from_str(authority.as_str()).map_err(|_| invalid_url(url))?,
+                );
+            }
+            parts.uri = uri
+                .path_and_query()
+                .cloned()
+                .unwrap_or_else(|| PathAndQuery::from_static("/"))
+                .into();
+        }
+        else {
+            parts.uri = uri;
+        }
+    }
+
+    if effect.path.is_some() || effect.query.is_some() {
+        let path = effect.path.as_deref().unwrap_or_else(|| parts.uri.path());
+        let query = effect.query.as_deref().or_else(|| parts.uri.query());
+        let path_and_query = match query {
+            Some(query) if !query.is_empty() => format!("{path}?{query}"),
+            _ => path.to_owned(),
+        };
+
+        let mut uri_parts = parts.uri.clone().into_parts();
+        uri_parts.path_and_query = Some(
+            path_and_query
+                .parse()
+                .map_err(|_| invalid_url(&path_and_query))?,
+        );
+        parts.uri = Uri::from_parts(uri_parts).map_err(|_| invalid_url(&path_and_query))?;
+    }
+
+    Ok(())
+}
+
+fn modify_headers(headers: &mut HeaderMap, effect: &ModifyEffect) -> Result<(), Error> {
+    for name in &effect.remove_headers {
+        headers.remove(header_name(name)?);
+    }
+    for (name, value) in &effect.set_headers {
+        headers.insert(header_name(name)?, header_value(name, value)?);
+    }
+    for (name, value) in &effect.add_headers {
+        headers.append(header_name(name)?, header_value(name, value)?);
+    }
+    Ok(())
+}
+
+/// Adds cookies to the `Cookie` header of a request.
+fn add_cookies(headers: &mut HeaderMap, effect: &ModifyEffect) -> Result<(), Error> {
+    // HTTP/1 only allows one `Cookie` header, so we merge them.
+    let cookies = headers
+        .get_all(header::COOKIE)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+        .chain(
+            effect
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{name}={value}")),
+        )
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    headers.insert(
+        header::COOKIE,
+        header_value(header::COOKIE.as_str(), &cookies)?,
+    );
+    Ok(())
+}
+
+/// Adds a `Set-Cookie` header to a response for each cookie.
+fn add_set_cookies(parts: &mut response::Parts, effect: &ModifyEffect) -> Result<(), Error> {
+    for (name, value) in &effect.cookies {
+        parts.headers.append(
+            header::SET_COOKIE,
+            header_value(header::SET_COOKIE.as_str(), &format!("{name}={value}"))?,
+        );
+    }
+    Ok(())
+}
+
+fn header_name(name: &str) -> Result<HeaderName, Error> {
+    HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+        Error::InvalidHeaderName {
+            name: name.to_owned(),
+        }
+    })
+}
+
+fn header_value(name: &str, value: &str) -> Result<HeaderValue, Error> {
+    HeaderValue::from_str(value).map_err(|_| {
+        Error::InvalidHeaderValue {
+            name: name.to_owned(),
+        }
+    })
+}
+
+enum ModifyBody<B> {
+    /// The body as it was passed in. This might already have been read, e.g.
+    /// to evaluate body filters, but it's still in its content encoding.
+    Unchanged(Replace<B>),
+    Replaced(Bytes),
+}
+
+impl<B> From<Replace<B>> for ModifyBody<B> {
+    fn from(value: Replace<B>) -> Self {
+        Self::Unchanged(value)
+    }
+}
+
+impl<B> ModifyBody<B>
+where
+    B: Body<Data = Bytes>,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    async fn modify(self, headers: &HeaderMap, effect: &ModifyEffect) -> Result<Self, Error> {
+        let Some(replacement) = &effect.body
+        else {
+            return Ok(self);
+        };
+
+        let body = match replacement {
+            BodyReplacement::Text(text) => Self::Replaced(Bytes::from(text.clone())),
+            BodyReplacement::File(path) => {
+                let data = tokio::fs::read(path).await.map_err(|error| {
+                    Error::ReadFile {
+                        error,
+                        path: path.clone(),
+                    }
+                })?;
+                Self::Replaced(data.into())
+            }
+            BodyReplacement::Replace { regex, replacement } => {
+                if matches!(self, Self::Unchanged(_))
+                    && headers.contains_key(header::CONTENT_ENCODING)
+                {
+                    tracing::warn!("Not replacing in body, because it has a content encoding");
+                    return Ok(self);
+                }
+                let data = match self {
+                    Self::Unchanged(body) => {
+                        body.collect()
+                            .await
+                            .map_err(|error| Error::Body(Box::new(error)))?
+                            .to_bytes()
+                    }
+                    Self::Replaced(data) => data,
+                };
+                let text = String::from_utf8_lossy(&data);
+                Self::Replaced(Bytes::from(
+                    regex.replace_all(&text, replacement).into_owned(),
+                ))
+            }
+        };
+
+        Ok(body)
+    }
+
+    /// Returns the body, and updates the headers if it was replaced.
+    fn finish(self, headers: &mut HeaderMap) -> Replace<B> {
+        match self {
+            Self::Unchanged(body) => body,
+            Self::Replaced(data) => {
+                headers.remove(header::TRANSFER_ENCODING);
+                headers.remove(header::CONTENT_ENCODING);
+                headers.insert(header::CONTENT_LENGTH, data.len().into());
+                Replace::replaced(data)
+            }
+        }
+    }
+}
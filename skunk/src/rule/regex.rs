@@ -20,6 +20,29 @@ pub struct Regex {
     regex: regex::Regex,
 }
 
+impl Regex {
+    #[inline]
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.regex.is_match(haystack)
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    /// Returns the capture groups of the first match.
+    #[inline]
+    pub fn captures<'h>(&self, haystack: &'h str) -> Option<regex::Captures<'h>> {
+        self.regex.captures(haystack)
+    }
+
+    #[inline]
+    pub fn replace_all<'h>(&self, haystack: &'h str, replacement: &str) -> Cow<'h, str> {
+        self.regex.replace_all(haystack, replacement)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 #[error("regex parse error")]
 pub struct RegexParseError {
@@ -112,6 +135,23 @@ impl<'de> Deserialize<'de> for Regex {
         D: serde::Deserializer<'de>,
     {
         let s: Cow<'de, str> = Deserialize::deserialize(deserializer)?;
-        s.try_into().map_err(serde::de::Error::custom)
+        // the regex error shows where in the regex the error is.
+        Regex::try_from(s).map_err(|error| serde::de::Error::custom(error.source))
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Regex {
+    fn schema_name() -> String {
+        "Regex".to_owned()
+    }
+
+    fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            format: Some("regex".to_owned()),
+            ..Default::default()
+        }
+        .into()
     }
 }
@@ -0,0 +1,783 @@
+//! Runs [`ScriptEffect`]s, which are [Rhai][1] scripts.
+//!
+//! Scripts are compiled by [`load`] when the rules are loaded, so they're
+//! reloaded together with the rules. A script is run with these variables:
+//!
+//! - `message`: The request or response, as a map with:
+//!   - `direction`: `"request"` or `"response"`.
+//!   - `method` and `url`: The method and URL of the request.
+//!   - `status`: The status code of a response, or `()` for a request.
+//!   - `headers`: A map from lowercase header names to their value, or to an
+//!     array of values if the header is set multiple times.
+//!   - `body`: The body as blob. A `gzip` or `deflate` `Content-Encoding` is
+//!     decoded, other encodings are passed as is.
+//!   - `json`: The body parsed as JSON, or `()` if it's not JSON.
+//! - `flow`: The flow of the message, as a map with `id`, `destination`,
+//!   `tags`, `marker` and `comment`. The tags, marker and comment start out
+//!   empty, and what the script sets is added to the flow's annotations.
+//! - `drop`: Set this to `true` to drop the message.
+//!
+//! A script can change the method and URL of a request, the status code of a
+//! response, and the headers and body of both. If it changes `json`, the body
+//! is replaced with it. A replaced body is sent without a `Content-Encoding`.
+//!
+//! Scripts are stopped once their timeout expires, and the size of the
+//! strings, blobs, arrays and maps they create is limited. A script is not run
+//! for a message whose (decoded) body is larger than this limit.
+//!
+//! [1]: https://rhai.rs
+
+use std::{
+    fmt::Debug,
+    io::Read,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use bytes::Bytes;
+use hyper::{
+    header::{
+        self,
+        HeaderName,
+        HeaderValue,
+    },
+    HeaderMap,
+    Method,
+    Request,
+    Response,
+    StatusCode,
+    Uri,
+};
+use rhai::{
+    Array,
+    Blob,
+    Dynamic,
+    Engine,
+    EvalAltResult,
+    Map,
+    Scope,
+    AST,
+};
+
+use super::file::{
+    DefaultEffects,
+    DefaultFilters,
+    Direction,
+    RulesFile,
+    ScriptEffect,
+};
+use crate::protocol::http::body::{
+    collect_limited,
+    Body,
+    Collected,
+    Replace,
+};
+
+/// How long scripts may run, if their effect doesn't set a timeout.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Maximum size of values that scripts create, if their effect doesn't set
+/// one.
+pub const DEFAULT_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// How many operations a script runs between checking its timeout.
+const OPERATIONS_PER_TIMEOUT_CHECK: u64 = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("could not read script: {path}")]
+    ReadFile {
+        #[source]
+        error: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("could not compile script: {path}")]
+    Compile {
+        #[source]
+        error: rhai::ParseError,
+        path: PathBuf,
+    },
+
+    #[error("script was not loaded: {path}")]
+    NotLoaded { path: PathBuf },
+
+    #[error("script failed: {path}")]
+    Run {
+        #[source]
+        error: Box<EvalAltResult>,
+        path: PathBuf,
+    },
+
+    #[error("script timed out: {path}")]
+    Timeout { path: PathBuf },
+
+    #[error("script set an invalid {what}: {path}")]
+    InvalidValue { what: &'static str, path: PathBuf },
+
+    #[error("could not read body")]
+    Body(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// A compiled script.
+#[derive(Clone)]
+pub struct Script {
+    path: PathBuf,
+    ast: Arc<AST>,
+}
+
+impl Script {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|error| {
+            Error::ReadFile {
+                error,
+                path: path.to_owned(),
+            }
+        })?;
+        let mut ast = Engine::new().compile(source).map_err(|error| {
+            Error::Compile {
+                error,
+                path: path.to_owned(),
+            }
+        })?;
+        ast.set_source(path.to_string_lossy().as_ref());
+
+        Ok(Self {
+            path: path.to_owned(),
+            ast: Arc::new(ast),
+        })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Debug for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Script")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Loads the scripts of all script effects in `rules`. Their paths are
+/// relative to `dir`.
+///
+/// Returns the paths of the loaded scripts.
+pub fn load(
+    rules: &mut RulesFile<DefaultFilters, DefaultEffects>,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = vec![];
+    rules.rules.try_for_each_effect(&mut |effect| {
+        if let DefaultEffects::Script(effect) = effect {
+            let script = Script::load(dir.join(&effect.file))?;
+            paths.push(script.path().to_owned());
+            effect.script = Some(script);
+        }
+        Ok(())
+    })?;
+    Ok(paths)
+}
+
+/// The flow that scripts are run for.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptFlow {
+    pub id: Option<String>,
+    pub destination: String,
+}
+
+/// What scripts did, besides changing the message.
+#[derive(Clone, Debug, Default)]
+pub struct Outcome {
+    /// Whether a script set `drop`. Scripts after it are not run.
+    pub drop: bool,
+
+    pub tags: Vec<String>,
+    pub marker: Option<String>,
+    pub comment: Option<String>,
+}
+
+/// Runs the `effects` that apply to requests with `request`.
+pub async fn script_request<'a, B>(
+    request: Request<Replace<B>>,
+    effects: impl IntoIterator<Item = &'a ScriptEffect>,
+    flow: &ScriptFlow,
+) -> Result<(Request<Replace<B>>, Outcome), Error>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let effects = select(effects, Direction::Request);
+    if effects.is_empty() {
+        return Ok((request, Outcome::default()));
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let body = match collect(body, &effects).await? {
+        Collected::Complete(body) => body,
+        Collected::Exceeded(body) => {
+            return Ok((Request::from_parts(parts, body), Outcome::default()))
+        }
+    };
+    let message = Message {
+        direction: Direction::Request,
+        method: parts.method.clone(),
+        uri: parts.uri.clone(),
+        status: None,
+        headers: std::mem::take(&mut parts.headers),
+        body,
+        original_body: None,
+        body_replaced: false,
+    };
+
+    let (mut message, outcome) = run_blocking(effects, message, flow.clone()).await?;
+    let body = message.take_body();
+    parts.method = message.method;
+    parts.uri = message.uri;
+    parts.headers = message.headers;
+    Ok((Request::from_parts(parts, body), outcome))
+}
+
+/// Runs the `effects` that apply to responses with `response`.
+///
+/// `method` and `uri` are from the request the response is for.
+pub async fn script_response<'a, B>(
+    response: Response<Replace<B>>,
+    method: &Method,
+    uri: &Uri,
+    effects: impl IntoIterator<Item = &'a ScriptEffect>,
+    flow: &ScriptFlow,
+) -> Result<(Response<Replace<B>>, Outcome), Error>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let effects = select(effects, Direction::Response);
+    if effects.is_empty() {
+        return Ok((response, Outcome::default()));
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match collect(body, &effects).await? {
+        Collected::Complete(body) => body,
+        Collected::Exceeded(body) => {
+            return Ok((Response::from_parts(parts, body), Outcome::default()))
+        }
+    };
+    let message = Message {
+        direction: Direction::Response,
+        method: method.clone(),
+        uri: uri.clone(),
+        status: Some(parts.status),
+        headers: std::mem::take(&mut parts.headers),
+        body,
+        original_body: None,
+        body_replaced: false,
+    };
+
+    let (mut message, outcome) = run_blocking(effects, message, flow.clone()).await?;
+    parts.status = message.status.unwrap_or(parts.status);
+    let body = message.take_body();
+    parts.headers = message.headers;
+    Ok((Response::from_parts(parts, body), outcome))
+}
+
+fn select<'a>(
+    effects: impl IntoIterator<Item = &'a ScriptEffect>,
+    direction: Direction,
+) -> Vec<ScriptEffect> {
+    effects
+        .into_iter()
+        .filter(|effect| effect.direction.is_both() || effect.direction == direction)
+        .cloned()
+        .collect()
+}
+
+fn max_size(effect: &ScriptEffect) -> usize {
+    effect.max_size.unwrap_or(DEFAULT_MAX_SIZE)
+}
+
+/// Reads the body, unless it's too large for all of the `effects`.
+async fn collect<B>(body: Replace<B>, effects: &[ScriptEffect]) -> Result<Collected<B>, Error>
+where
+    B: Body<Data = Bytes> + Unpin,
+    B::Error: std::error::Error + Send + Sync + 'static,
+{
+    let limit = effects.iter().map(max_size).max().unwrap_or_default();
+    let collected = collect_limited(body, limit)
+        .await
+        .map_err(|error| Error::Body(Box::new(error)))?;
+    if let Collected::Exceeded(_) = &collected {
+        tracing::warn!(limit, "Not running scripts, because the body is too large");
+    }
+    Ok(collected)
+}
+
+/// Runs the scripts on a blocking thread, since they can run until their
+/// timeout expires.
+async fn run_blocking(
+    effects: Vec<ScriptEffect>,
+    mut message: Message,
+    flow: ScriptFlow,
+) -> Result<(Message, Outcome), Error> {
+    tokio::task::spawn_blocking(move || {
+        let mut outcome = Outcome::default();
+        let limit = effects.iter().map(max_size).max().unwrap_or_default();
+        if !message.decode(limit) {
+            tracing::warn!(limit, "Not running scripts, because the decoded body is too large");
+            return Ok((message, outcome));
+        }
+
+        for effect in &effects {
+            if message.body.len() > max_size(effect) {
+                tracing::warn!(path = %effect.file.display(), "Not running script, because the body is too large");
+                continue;
+            }
+            run(effect, &mut message, &flow, &mut outcome)?;
+            if outcome.drop {
+                break;
+            }
+        }
+        Ok((message, outcome))
+    })
+    .await
+    .expect("script task panicked")
+}
+
+/// A HTTP message as it's passed to scripts.
+#[derive(Debug)]
+struct Message {
+    direction: Direction,
+    method: Method,
+    uri: Uri,
+    status: Option<StatusCode>,
+    headers: HeaderMap,
+    body: Bytes,
+
+    /// The body as it was received, if `body` was decoded.
+    original_body: Option<Bytes>,
+
+    body_replaced: bool,
+}
+
+impl Message {
+    /// Decodes the body if it has a `Content-Encoding` that can be decoded.
+    ///
+    /// Returns `false` if the decoded body is larger than `limit`.
+    fn decode(&mut self, limit: usize) -> bool {
+        let Some(encoding) = self
+            .headers
+            .get(header::CONTENT_ENCODING)
+            .and_then(|encoding| encoding.to_str().ok())
+        else {
+            return true;
+        };
+
+        // read one byte more than the limit, to know if the body is too large.
+        let take = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+        let mut buf = vec![];
+        let result = match encoding.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => {
+                flate2::read::GzDecoder::new(&self.body[..])
+                    .take(take)
+                    .read_to_end(&mut buf)
+            }
+            "deflate" => {
+                flate2::read::ZlibDecoder::new(&self.body[..])
+                    .take(take)
+                    .read_to_end(&mut buf)
+            }
+            _ => return true,
+        };
+
+        match result {
+            Ok(_) if buf.len() > limit => false,
+            Ok(_) => {
+                self.original_body = Some(std::mem::replace(&mut self.body, buf.into()));
+                true
+            }
+            Err(error) => {
+                tracing::warn!(%error, "Could not decode body, passing it as is");
+                true
+            }
+        }
+    }
+
+    /// Returns the body, and updates the headers if it was replaced.
+    ///
+    /// If the body was decoded, but not replaced, the body as it was received
+    /// is returned.
+    fn take_body<B>(&mut self) -> Replace<B> {
+        if self.body_replaced {
+            self.headers.remove(header::TRANSFER_ENCODING);
+            self.headers.remove(header::CONTENT_ENCODING);
+            self.headers
+                .insert(header::CONTENT_LENGTH, self.body.len().into());
+        }
+        else if let Some(original_body) = self.original_body.take() {
+            return Replace::replaced(original_body);
+        }
+        Replace::replaced(std::mem::take(&mut self.body))
+    }
+}
+
+fn run(
+    effect: &ScriptEffect,
+    message: &mut Message,
+    flow: &ScriptFlow,
+    outcome: &mut Outcome,
+) -> Result<(), Error> {
+    let script = effect.script.as_ref().ok_or_else(|| {
+        Error::NotLoaded {
+            path: effect.file.clone(),
+        }
+    })?;
+    let path = &script.path;
+
+    let timeout = effect
+        .timeout_ms
+        .map_or(DEFAULT_TIMEOUT, Duration::from_millis);
+    let max_size = effect.max_size.unwrap_or(DEFAULT_MAX_SIZE);
+    let mut engine = Engine::new();
+    engine
+        .set_max_string_size(max_size)
+        .set_max_array_size(max_size)
+        .set_max_map_size(max_size);
+    let deadline = Instant::now() + timeout;
+    engine.on_progress(move |operations| {
+        (operations % OPERATIONS_PER_TIMEOUT_CHECK == 0 && Instant::now() >= deadline)
+            .then_some(Dynamic::UNIT)
+    });
+
+    let json = serde_json::from_slice::<serde_json::Value>(&message.body).ok();
+    let mut scope = Scope::new();
+    scope.push("message", message_map(message, json.as_ref()));
+    scope.push("flow", flow_map(flow));
+    scope.push("drop", false);
+
+    engine
+        .run_ast_with_scope(&mut scope, &script.ast)
+        .map_err(|error| {
+            match *error {
+                EvalAltResult::ErrorTerminated(..) => Error::Timeout { path: path.clone() },
+                _ => {
+                    Error::Run {
+                        error,
+                        path: path.clone(),
+                    }
+                }
+            }
+        })?;
+
+    let invalid = |what| {
+        Error::InvalidValue {
+            what,
+            path: path.clone(),
+        }
+    };
+
+    let map = scope
+        .get_value::<Map>("message")
+        .ok_or_else(|| invalid("message"))?;
+    match message.direction {
+        Direction::Response => {
+            let status = map
+                .get("status")
+                .and_then(|status| status.as_int().ok())
+                .and_then(|status| u16::try_from(status).ok())
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .ok_or_else(|| invalid("status"))?;
+            message.status = Some(status);
+        }
+        _ => {
+            message.method = string(&map, "method")
+                .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+                .ok_or_else(|| invalid("method"))?;
+            message.uri = string(&map, "url")
+                .and_then(|url| url.parse().ok())
+                .ok_or_else(|| invalid("url"))?;
+        }
+    }
+    message.headers = map
+        .get("headers")
+        .and_then(|headers| headers.read_lock::<Map>())
+        .and_then(|headers| header_map(&headers))
+        .ok_or_else(|| invalid("headers"))?;
+
+    let new_json = map
+        .get("json")
+        .filter(|json| !json.is_unit())
+        .map(rhai::serde::from_dynamic::<serde_json::Value>)
+        .transpose()
+        .map_err(|_| invalid("json"))?;
+    if new_json != json {
+        let json = new_json.unwrap_or(serde_json::Value::Null);
+        message.body = serde_json::to_vec(&json)
+            .map_err(|_| invalid("json"))?
+            .into();
+        message.body_replaced = true;
+    }
+    else {
+        let body = map
+            .get("body")
+            .and_then(|body| {
+                body.read_lock::<Blob>()
+                    .map(|blob| Bytes::copy_from_slice(&blob))
+                    .or_else(|| {
+                        body.read_lock::<rhai::ImmutableString>()
+                            .map(|text| Bytes::copy_from_slice(text.as_bytes()))
+                    })
+            })
+            .ok_or_else(|| invalid("body"))?;
+        if body != message.body {
+            message.body = body;
+            message.body_replaced = true;
+        }
+    }
+
+    outcome.drop = scope
+        .get_value::<bool>("drop")
+        .ok_or_else(|| invalid("drop"))?;
+
+    let flow = scope
+        .get_value::<Map>("flow")
+        .ok_or_else(|| invalid("flow"))?;
+    let tags = flow
+        .get("tags")
+        .and_then(|tags| tags.read_lock::<Array>())
+        .ok_or_else(|| invalid("tags"))?;
+    for tag in tags.iter() {
+        let tag = tag.clone().into_string().map_err(|_| invalid("tag"))?;
+        if !outcome.tags.contains(&tag) {
+            outcome.tags.push(tag);
+        }
+    }
+    if let Some(marker) = string(&flow, "marker") {
+        outcome.marker = Some(marker);
+    }
+    if let Some(comment) = string(&flow, "comment") {
+        outcome.comment = Some(comment);
+    }
+
+    Ok(())
+}
+
+fn message_map(message: &Message, json: Option<&serde_json::Value>) -> Map {
+    let direction = match message.direction {
+        Direction::Response => "response",
+        _ => "request",
+    };
+
+    let mut map = Map::new();
+    map.insert("direction".into(), direction.into());
+    map.insert("method".into(), message.method.as_str().into());
+    map.insert("url".into(), message.uri.to_string().into());
+    map.insert(
+        "status".into(),
+        message
+            .status
+            .map_or(Dynamic::UNIT, |status| i64::from(status.as_u16()).into()),
+    );
+    map.insert("headers".into(), headers_map(&message.headers).into());
+    map.insert("body".into(), Dynamic::from_blob(message.body.to_vec()));
+    map.insert(
+        "json".into(),
+        json.and_then(|json| rhai::serde::to_dynamic(json).ok())
+            .unwrap_or(Dynamic::UNIT),
+    );
+    map
+}
+
+fn flow_map(flow: &ScriptFlow) -> Map {
+    let mut map = Map::new();
+    map.insert(
+        "id".into(),
+        flow.id.clone().map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    map.insert("destination".into(), flow.destination.clone().into());
+    map.insert("tags".into(), Array::new().into());
+    map.insert("marker".into(), Dynamic::UNIT);
+    map.insert("comment".into(), Dynamic::UNIT);
+    map
+}
+
+fn headers_map(headers: &HeaderMap) -> Map {
+    let mut map = Map::new();
+    for name in headers.keys() {
+        let mut values = headers
+            .get_all(name)
+            .iter()
+            .map(|value| Dynamic::from(String::from_utf8_lossy(value.as_bytes()).into_owned()))
+            .collect::<Array>();
+        let value = if values.len() == 1 {
+            values.remove(0)
+        }
+        else {
+            values.into()
+        };
+        map.insert(name.as_str().into(), value);
+    }
+    map
+}
+
+/// Converts the headers a script set back into a [`HeaderMap`]. Headers set
+/// to `()` are removed.
+fn header_map(headers: &Map) -> Option<HeaderMap> {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+        let values = if value.is_unit() {
+            vec![]
+        }
+        else if let Some(values) = value.read_lock::<Array>() {
+            values.clone()
+        }
+        else {
+            vec![value.clone()]
+        };
+        for value in values {
+            let value = value.into_string().ok()?;
+            header_map.append(&name, HeaderValue::from_str(&value).ok()?);
+        }
+    }
+    Some(header_map)
+}
+
+fn string(map: &Map, key: &str) -> Option<String> {
+    map.get(key)?.clone().into_string().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use bytes::Bytes;
+    use hyper::{
+        header,
+        HeaderMap,
+        Method,
+    };
+
+    use super::{
+        run,
+        Message,
+        Outcome,
+        Script,
+        ScriptFlow,
+    };
+    use crate::{
+        protocol::http::body::{
+            Empty,
+            Replace,
+        },
+        rule::file::{
+            Direction,
+            ScriptEffect,
+        },
+    };
+
+    fn effect(source: &str, timeout_ms: Option<u64>) -> (tempfile::NamedTempFile, ScriptEffect) {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(source.as_bytes()).unwrap();
+        let effect = ScriptEffect {
+            file: file.path().to_owned(),
+            direction: Direction::Both,
+            timeout_ms,
+            max_size: None,
+            script: Some(Script::load(file.path()).unwrap()),
+        };
+        (file, effect)
+    }
+
+    fn message() -> Message {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        Message {
+            direction: Direction::Request,
+            method: Method::GET,
+            uri: "http://example.com/items".parse().unwrap(),
+            status: None,
+            headers,
+            body: r#"{"items":[1,2]}"#.into(),
+            original_body: None,
+            body_replaced: false,
+        }
+    }
+
+    #[test]
+    fn it_decodes_gzip_bodies() {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(br#"{"items":[1,2]}"#).unwrap();
+        let compressed = Bytes::from(encoder.finish().unwrap());
+
+        let mut message = message();
+        message
+            .headers
+            .insert(header::CONTENT_ENCODING, "gzip".parse().unwrap());
+        message.body = compressed.clone();
+
+        assert!(!message.decode(4));
+        assert_eq!(message.body, compressed);
+
+        assert!(message.decode(1024));
+        assert_eq!(&message.body[..], br#"{"items":[1,2]}"#);
+        assert!(matches!(
+            message.take_body::<Empty>(),
+            Replace::Replaced { data: Some(data) } if data == compressed
+        ));
+        assert_eq!(message.headers[header::CONTENT_ENCODING], "gzip");
+    }
+
+    #[test]
+    fn it_runs_scripts() {
+        let (_file, effect) = effect(
+            r#"
+                message.method = "POST";
+                message.headers["x-script"] = "1";
+                message.json.items.push(3);
+                flow.tags.push("scripted");
+                flow.comment = `from ${flow.destination}`;
+            "#,
+            None,
+        );
+        let flow = ScriptFlow {
+            id: None,
+            destination: "example.com:80".to_owned(),
+        };
+        let mut message = message();
+        let mut outcome = Outcome::default();
+        run(&effect, &mut message, &flow, &mut outcome).unwrap();
+
+        assert_eq!(message.method, Method::POST);
+        assert_eq!(message.headers["x-script"], "1");
+        assert_eq!(message.headers["content-type"], "application/json");
+        assert!(message.body_replaced);
+        assert_eq!(&message.body[..], br#"{"items":[1,2,3]}"#);
+        assert!(!outcome.drop);
+        assert_eq!(outcome.tags, ["scripted"]);
+        assert_eq!(outcome.comment.as_deref(), Some("from example.com:80"));
+    }
+
+    #[test]
+    fn it_stops_scripts_after_timeout() {
+        let (_file, effect) = effect("loop {}", Some(10));
+        let result = run(
+            &effect,
+            &mut message(),
+            &ScriptFlow::default(),
+            &mut Outcome::default(),
+        );
+        assert!(matches!(result, Err(super::Error::Timeout { .. })));
+    }
+}
@@ -0,0 +1,242 @@
+//! Templates for strings in rules files, e.g. `Hello {{ query.name }}`.
+//!
+//! Placeholders are written as `{{ field }}`, where field is one of:
+//!
+//! - `method`: The request method.
+//! - `url`: The request URL.
+//! - `path`: The request path.
+//! - `path.<n>`: The `n`-th segment of the request path, starting at 0.
+//! - `query`: The query string.
+//! - `query.<name>`: The value of a query parameter.
+//! - `header.<name>`: The value of a request header.
+//! - `body`: The request body.
+//! - `json.<path>`: A field of the request body parsed as JSON, e.g.
+//!   `json.user.ids.0`. Strings are inserted as they are, and other values as
+//!   JSON.
+//! - `capture.<n>` or `capture.<name>`: A capture group of a regex.
+//!
+//! Placeholders with no value are replaced by an empty string.
+
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::Arc,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+/// A field that a placeholder refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Field {
+    Method,
+    Url,
+    Path,
+    PathSegment(usize),
+    Query,
+    QueryParameter(String),
+    Header(String),
+    Body,
+    Json(Vec<String>),
+    Capture(usize),
+    NamedCapture(String),
+}
+
+impl FromStr for Field {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            ParseError::InvalidField {
+                field: s.to_owned(),
+            }
+        };
+
+        let field = match s.split_once('.') {
+            None => {
+                match s {
+                    "method" => Self::Method,
+                    "url" => Self::Url,
+                    "path" => Self::Path,
+                    "query" => Self::Query,
+                    "body" => Self::Body,
+                    _ => return Err(invalid()),
+                }
+            }
+            Some((_, "")) => return Err(invalid()),
+            Some(("path", index)) => Self::PathSegment(index.parse().map_err(|_| invalid())?),
+            Some(("query", name)) => Self::QueryParameter(name.to_owned()),
+            Some(("header", name)) => Self::Header(name.to_owned()),
+            Some(("json", path)) => Self::Json(path.split('.').map(ToOwned::to_owned).collect()),
+            Some(("capture", group)) => {
+                match group.parse() {
+                    Ok(index) => Self::Capture(index),
+                    Err(_) => Self::NamedCapture(group.to_owned()),
+                }
+            }
+            Some(_) => return Err(invalid()),
+        };
+
+        Ok(field)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("unclosed placeholder")]
+    Unclosed,
+
+    #[error("invalid field: {field}")]
+    InvalidField { field: String },
+}
+
+/// A parsed template.
+///
+/// This keeps the string it was parsed from, which is used when it's
+/// displayed or serialized.
+#[derive(Clone, Debug)]
+pub struct Template {
+    string: Arc<str>,
+    parts: Vec<Part>,
+}
+
+impl Template {
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    /// Returns the fields that the template refers to.
+    pub fn fields(&self) -> impl Iterator<Item = &Field> {
+        self.parts.iter().filter_map(|part| {
+            match part {
+                Part::Literal(_) => None,
+                Part::Field(field) => Some(field),
+            }
+        })
+    }
+
+    /// Renders the template. `value` is called to get the value of each
+    /// field.
+    pub fn render<V: Display>(&self, mut value: impl FnMut(&Field) -> Option<V>) -> String {
+        let mut output = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => output.push_str(literal),
+                Part::Field(field) => {
+                    if let Some(value) = value(field) {
+                        output.push_str(&value.to_string());
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+impl FromStr for Template {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut rest = s;
+
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            rest = &rest[start + 2..];
+            let end = rest.find("}}").ok_or(ParseError::Unclosed)?;
+            parts.push(Part::Field(rest[..end].trim().parse()?));
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+
+        Ok(Self {
+            string: s.into(),
+            parts,
+        })
+    }
+}
+
+impl PartialEq for Template {
+    fn eq(&self, other: &Self) -> bool {
+        self.string == other.string
+    }
+}
+
+impl Eq for Template {}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.string)
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.string.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s: std::borrow::Cow<'de, str> = Deserialize::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for Template {
+    fn schema_name() -> String {
+        "Template".to_owned()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        <String as schemars::JsonSchema>::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Field,
+        Template,
+    };
+
+    #[test]
+    fn it_renders_templates() {
+        let template =
+            r#"{"id": {{ path.1 }}, "name": "{{json.user.name}}", "x": "{{ capture.x }}"}"#
+                .parse::<Template>()
+                .unwrap();
+        let output = template.render(|field| {
+            match field {
+                Field::PathSegment(1) => Some("42".to_owned()),
+                Field::Json(path) if path == &["user", "name"] => Some("skunk".to_owned()),
+                _ => None,
+            }
+        });
+        assert_eq!(output, r#"{"id": 42, "name": "skunk", "x": ""}"#);
+
+        assert!("{{ nope }}".parse::<Template>().is_err());
+        assert!("{{ path".parse::<Template>().is_err());
+        assert!("{{ path.x }}".parse::<Template>().is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use std::{
-    collections::HashMap,
+    collections::{
+        HashMap,
+        HashSet,
+    },
     num::NonZeroUsize,
 };
 
@@ -101,6 +104,46 @@ impl Evaluator {
             .map(|state| state.value)
             .unwrap_or_default()
     }
+
+    /// Returns whether the value of an expression can still change the value
+    /// of a pinned expression.
+    ///
+    /// This is `false` once the expression itself is definite, or all
+    /// expressions that depend on it are (e.g. an `and` that already has a
+    /// `false` input). Expressions that no pinned expression depends on, e.g.
+    /// variables of unused conditions, are never relevant.
+    pub fn is_relevant(&self, graph: &Graph, expression_id: ExpressionId) -> bool {
+        expression_id.expect_instance(self.instance_id);
+
+        let mut stack = vec![expression_id.node_index];
+        let mut visited = HashSet::new();
+
+        while let Some(node_index) = stack.pop() {
+            if !visited.insert(node_index)
+                || matches!(
+                    self.values.get(&node_index).map(|state| state.value),
+                    Some(Maybe::Definite(_))
+                )
+            {
+                continue;
+            }
+
+            let is_output = graph
+                .graph
+                .node_weight(node_index)
+                .is_some_and(|node| node.pin_count > 0);
+            if is_output {
+                return true;
+            }
+            stack.extend(
+                graph
+                    .graph
+                    .neighbors_directed(node_index, Direction::Outgoing),
+            );
+        }
+
+        false
+    }
 }
 
 /// helper to propagate value recursively
@@ -127,11 +170,17 @@ fn propagate(
             }
         });
 
-        dependant_state
+        dependant_state.num_inputs_not_evaluated = dependant_state
             .num_inputs_not_evaluated
             .checked_sub(1)
             .expect("node received more inputs than expected");
 
+        if let Maybe::Definite(_) = dependant_state.value {
+            // the value was already determined by another input (e.g. a `false` input to
+            // an `and`), so the remaining inputs don't matter.
+            continue;
+        }
+
         // compute new value
         let dependant_new_value = match (
             new_value,
@@ -17,6 +17,12 @@ use petgraph::{
     Direction,
 };
 
+#[cfg(feature = "graph-vis")]
+pub use self::vis::{
+    Snapshot,
+    SnapshotNode,
+    SnapshotNodeKind,
+};
 pub use self::{
     eval::Evaluator,
     maybe::Maybe,
@@ -145,9 +151,18 @@ impl Graph {
     }
 
     fn binary(&mut self, inputs: &[ExpressionId], kind: NodeKind) -> ExpressionId {
-        // note: this assumes there's at least 1 input, and that `kind` is either `And`
-        // or `Or`.
-
+        // note: this assumes that `kind` is either `And` or `Or`.
+
+        // before looking for an equivalent node, we simplify the inputs:
+        //
+        // - literals are folded: a `false` input to an `and` makes it `false`, a `true`
+        //   input is ignored (and vice-versa for `or`).
+        // - inputs of the same kind are flattened, e.g. `a & (b & c)` becomes `a & b &
+        //   c`. since all nodes are created here, their inputs are already flat.
+        // - duplicate inputs are removed.
+        // - an input together with its negation decides the result, e.g. `a & !a` is
+        //   `false`.
+        //
         // to check whether this node already exists, we iterate over its inputs.
         // for each input we look at the outgoing edges, i.e. where it's used as input,
         // called *dependants*. we calculate the intersection of these input
@@ -155,14 +170,42 @@ impl Graph {
         // doesn't exist yet, or with a set with one element, which is the equivalent
         // node.
 
+        // the literal that decides the output on its own.
+        let dominant = matches!(kind, NodeKind::Or);
+
+        let mut inputs_simplified = Vec::with_capacity(inputs.len());
         for input in inputs {
             input.expect_instance(self.instance_id);
+            match self.node(input.node_index).kind {
+                NodeKind::Literal(value) if value == dominant => return self.literal(dominant),
+                NodeKind::Literal(_) => {}
+                input_kind if input_kind == kind => {
+                    inputs_simplified.extend(
+                        self.graph
+                            .neighbors_directed(input.node_index, Direction::Incoming),
+                    );
+                }
+                _ => inputs_simplified.push(input.node_index),
+            }
+        }
+        inputs_simplified.sort_unstable();
+        inputs_simplified.dedup();
+
+        let has_complement = inputs_simplified.iter().any(|index| {
+            self.negated(*index)
+                .is_some_and(|input| inputs_simplified.binary_search(&input).is_ok())
+        });
+        if has_complement {
+            return self.literal(dominant);
         }
 
-        // first, if the input consists of only 1 node, we can just return that.
-        if inputs.len() == 1 {
-            return inputs[0];
+        // if the input consists of none or only 1 node, we can just return that.
+        match inputs_simplified[..] {
+            [] => return self.literal(!dominant),
+            [input] => return self.expression_id(input),
+            _ => {}
         }
+        let inputs = inputs_simplified;
 
         // helper to get the inputs' dependants filtered for the node kind we're looking
         // for.
@@ -175,7 +218,7 @@ impl Graph {
         // we initialize our intersection with the first input's dependants.
         let mut input_iter = inputs.iter();
         let mut dependants_intersection_1 =
-            dependants(input_iter.next().expect("no inputs").node_index).collect::<HashSet<_>>();
+            dependants(*input_iter.next().expect("no inputs")).collect::<HashSet<_>>();
         // instead of creating a new hash set for each intersection, we just use 2 and
         // swap them around.
         let mut dependants_intersection_2 = HashSet::with_capacity(dependants_intersection_1.len());
@@ -186,8 +229,7 @@ impl Graph {
         for input in input_iter {
             dependants_intersection_2.clear();
             dependants_intersection_2.extend(
-                dependants(input.node_index)
-                    .filter(|index| dependants_intersection_1.contains(index)),
+                dependants(*input).filter(|index| dependants_intersection_1.contains(index)),
             );
             std::mem::swap(
                 &mut dependants_intersection_1,
@@ -199,12 +241,21 @@ impl Graph {
             }
         }
 
+        // a node with more inputs than these is in the intersection too, but is not
+        // equivalent.
+        dependants_intersection_1.retain(|index| {
+            self.graph
+                .neighbors_directed(*index, Direction::Incoming)
+                .count()
+                == inputs.len()
+        });
+
         let index = match dependants_intersection_1.len() {
             0 => {
                 // create a new node
                 let index = self.graph.add_node(Node::new(kind));
-                for input in inputs {
-                    self.graph.add_edge(input.node_index, index, ());
+                for input in &inputs {
+                    self.graph.add_edge(*input, index, ());
                 }
                 index
             }
@@ -217,6 +268,67 @@ impl Graph {
 
         self.expression_id(index)
     }
+
+    #[inline]
+    fn node(&self, node_index: NodeIndex) -> &Node {
+        self.graph
+            .node_weight(node_index)
+            .unwrap_or_else(|| panic!("missing node: {node_index:?}"))
+    }
+
+    /// Returns the input of `node_index`, if it's a `not` node.
+    fn negated(&self, node_index: NodeIndex) -> Option<NodeIndex> {
+        matches!(self.node(node_index).kind, NodeKind::Not).then(|| {
+            self.graph
+                .neighbors_directed(node_index, Direction::Incoming)
+                .next()
+                .expect("not node without input")
+        })
+    }
+
+    /// Removes expressions that are neither pinned nor used by other
+    /// expressions.
+    ///
+    /// Literals are folded and equivalent expressions are shared when they're
+    /// created. But this can leave unused nodes behind, e.g. when a nested
+    /// `and` was flattened into its parent. These would still be evaluated
+    /// for every evaluator. Variables are kept, since they're still referred to
+    /// by their inputs.
+    pub fn optimize(&mut self) {
+        let is_unused = |graph: &StableGraph<Node, ()>, node_index: NodeIndex| {
+            graph.node_weight(node_index).is_some_and(|node| {
+                node.pin_count == 0
+                    && matches!(node.kind, NodeKind::Not | NodeKind::And | NodeKind::Or)
+                    && graph
+                        .neighbors_directed(node_index, Direction::Outgoing)
+                        .next()
+                        .is_none()
+            })
+        };
+
+        let mut unused = self
+            .graph
+            .node_indices()
+            .filter(|node_index| is_unused(&self.graph, *node_index))
+            .collect::<Vec<_>>();
+
+        while let Some(node_index) = unused.pop() {
+            if !is_unused(&self.graph, node_index) {
+                // already removed
+                continue;
+            }
+            let inputs = self
+                .graph
+                .neighbors_directed(node_index, Direction::Incoming)
+                .collect::<Vec<_>>();
+            self.graph.remove_node(node_index);
+            unused.extend(
+                inputs
+                    .into_iter()
+                    .filter(|input| is_unused(&self.graph, *input)),
+            );
+        }
+    }
 }
 
 impl Debug for Graph {
@@ -235,6 +347,13 @@ pub trait ModifyGraph {
     fn or(&mut self, inputs: &[ExpressionId]) -> ExpressionId;
     fn pin(&mut self, expression_id: ExpressionId);
     fn unpin(&mut self, expression_id: ExpressionId);
+
+    /// Adds a label to an expression, e.g. a description of a filter. This is
+    /// only used for visualization.
+    ///
+    /// Since equivalent expressions are shared, an expression can have
+    /// multiple labels.
+    fn label(&mut self, expression_id: ExpressionId, label: &str);
 }
 
 impl ModifyGraph for Graph {
@@ -252,6 +371,14 @@ impl ModifyGraph for Graph {
     fn not(&mut self, input: ExpressionId) -> ExpressionId {
         input.expect_instance(self.instance_id);
 
+        if let NodeKind::Literal(value) = self.node(input.node_index).kind {
+            return self.literal(!value);
+        }
+        if let Some(negated) = self.negated(input.node_index) {
+            // double negation
+            return self.expression_id(negated);
+        }
+
         for index in self
             .graph
             .neighbors_directed(input.node_index, Direction::Outgoing)
@@ -269,22 +396,12 @@ impl ModifyGraph for Graph {
 
     #[inline]
     fn and(&mut self, inputs: &[ExpressionId]) -> ExpressionId {
-        if inputs.is_empty() {
-            self.literal(true)
-        }
-        else {
-            self.binary(inputs, NodeKind::And)
-        }
+        self.binary(inputs, NodeKind::And)
     }
 
     #[inline]
     fn or(&mut self, inputs: &[ExpressionId]) -> ExpressionId {
-        if inputs.is_empty() {
-            self.literal(false)
-        }
-        else {
-            self.binary(inputs, NodeKind::Or)
-        }
+        self.binary(inputs, NodeKind::Or)
     }
 
     #[inline]
@@ -326,4 +443,89 @@ impl ModifyGraph for Graph {
             remove(&mut self.graph, expression_id.node_index);
         }
     }
+
+    fn label(&mut self, expression_id: ExpressionId, label: &str) {
+        expression_id.expect_instance(self.instance_id);
+        let node = self
+            .graph
+            .node_weight_mut(expression_id.node_index)
+            .unwrap_or_else(|| panic!("missing expression: {expression_id:?}"));
+
+        match (&node.kind, &node.label) {
+            (NodeKind::Literal(_), _) => {
+                // literals are shared by everything, so labels would be
+                // meaningless.
+            }
+            (_, None) => node.label = Some(label.into()),
+            (_, Some(labels)) => {
+                if !labels.lines().any(|existing| existing == label) {
+                    node.label = Some(format!("{labels}\n{label}").into());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Graph,
+        ModifyGraph,
+    };
+
+    #[test]
+    fn it_folds_literals() {
+        let mut graph = Graph::default();
+        let a = graph.variable().into();
+        let t = graph.literal(true);
+        let f = graph.literal(false);
+
+        assert_eq!(graph.and(&[a, t]), a);
+        assert_eq!(graph.and(&[a, f]), f);
+        assert_eq!(graph.or(&[a, f]), a);
+        assert_eq!(graph.or(&[a, t]), t);
+        assert_eq!(graph.not(t), f);
+        assert_eq!(graph.and(&[]), t);
+        assert_eq!(graph.or(&[]), f);
+    }
+
+    #[test]
+    fn it_simplifies_expressions() {
+        let mut graph = Graph::default();
+        let a = graph.variable().into();
+        let b = graph.variable().into();
+        let c = graph.variable().into();
+
+        let not_a = graph.not(a);
+        assert_eq!(graph.not(not_a), a);
+        assert_eq!(graph.and(&[a, not_a]), graph.literal(false));
+        assert_eq!(graph.or(&[not_a, a]), graph.literal(true));
+
+        let b_and_c = graph.and(&[b, c]);
+        let flat = graph.and(&[a, b, c]);
+        assert_eq!(graph.and(&[a, b_and_c]), flat);
+        assert_eq!(graph.and(&[c, a, b, a]), flat);
+    }
+
+    #[test]
+    fn it_removes_unused_expressions() {
+        let mut graph = Graph::default();
+        let variables = [graph.variable(), graph.variable(), graph.variable()];
+        let [a, b, c] = variables.map(Into::into);
+
+        let b_and_c = graph.and(&[b, c]);
+        let condition = graph.and(&[a, b_and_c]);
+        graph.pin(condition);
+        graph.optimize();
+
+        // 2 literals, 3 variables and the pinned `and`
+        assert_eq!(graph.graph.node_count(), 6);
+
+        let mut eval = graph.evaluator();
+        for variable in variables {
+            assert!(eval.get(condition) != true);
+            eval.set(&graph, variable, true);
+        }
+        assert!(eval.get(condition) == true);
+    }
 }
@@ -1,13 +1,18 @@
 use std::{
     fmt::Display,
     io::Write,
+    sync::Arc,
 };
 
-use petgraph::visit::IntoNodeReferences;
+use petgraph::{
+    visit::IntoNodeReferences,
+    Direction,
+};
 
 use super::{
+    Evaluator,
     Graph,
-    Node,
+    Maybe,
     NodeKind,
 };
 
@@ -16,21 +21,112 @@ impl Graph {
     /// the `graph-vis` feature to be enabled.
     ///
     /// [1]: https://graphviz.org/doc/info/lang.html
+    pub fn write_dot(&self, writer: impl Write) -> Result<(), std::io::Error> {
+        self.snapshot(None).write_dot(writer)
+    }
+
+    /// Takes a snapshot of the graph. If an `evaluator` is given, the
+    /// snapshot contains the values of the expressions.
+    pub fn snapshot(&self, evaluator: Option<&Evaluator>) -> Snapshot {
+        let nodes = self
+            .graph
+            .node_references()
+            .map(|(node_index, node)| {
+                SnapshotNode {
+                    id: node_index.index(),
+                    kind: match node.kind {
+                        NodeKind::Literal(value) => SnapshotNodeKind::Literal(value),
+                        NodeKind::Variable => SnapshotNodeKind::Variable,
+                        NodeKind::Not => SnapshotNodeKind::Not,
+                        NodeKind::And => SnapshotNodeKind::And,
+                        NodeKind::Or => SnapshotNodeKind::Or,
+                    },
+                    label: node.label.clone(),
+                    is_output: node.pin_count > 0,
+                    value: evaluator.and_then(|evaluator| {
+                        match evaluator.get(self.expression_id(node_index)) {
+                            Maybe::Definite(value) => Some(value),
+                            Maybe::Indefinite => None,
+                        }
+                    }),
+                }
+            })
+            .collect();
+
+        let edges = self
+            .graph
+            .node_indices()
+            .flat_map(|node_index| {
+                self.graph
+                    .neighbors_directed(node_index, Direction::Outgoing)
+                    .map(move |dependant| (node_index.index(), dependant.index()))
+            })
+            .collect();
+
+        Snapshot { nodes, edges }
+    }
+}
+
+/// A snapshot of a [`Graph`], e.g. to render it.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub nodes: Vec<SnapshotNode>,
+
+    /// Edges from an expression to the expressions that use it as input.
+    pub edges: Vec<(usize, usize)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SnapshotNode {
+    pub id: usize,
+    pub kind: SnapshotNodeKind,
+
+    /// Labels of the expression, separated by newlines.
+    pub label: Option<Arc<str>>,
+
+    /// Whether the expression is used outside of the graph, e.g. as condition
+    /// for effects.
+    pub is_output: bool,
+
+    /// The value of the expression, if the snapshot was taken with an
+    /// evaluator and the value is definite.
+    pub value: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotNodeKind {
+    Literal(bool),
+    Variable,
+    Not,
+    And,
+    Or,
+}
+
+impl Snapshot {
+    /// Writes the snapshot to `writer` as a [graphviz dot file][1].
+    ///
+    /// Outputs are drawn with a double border. Expressions with a definite
+    /// value are colored green (`true`) or red (`false`).
+    ///
+    /// [1]: https://graphviz.org/doc/info/lang.html
     pub fn write_dot(&self, mut writer: impl Write) -> Result<(), std::io::Error> {
         writeln!(writer, "strict digraph {{")?;
 
-        for (node_index, node) in self.graph.node_references() {
-            writeln!(
-                writer,
-                "  {} [label=<{}>];",
-                node_index.index(),
-                NodeLabel(node),
-            )?;
+        for node in &self.nodes {
+            write!(writer, "  {} [label=<{}>", node.id, NodeLabel(node))?;
+            if node.is_output {
+                write!(writer, ", peripheries=2")?;
+            }
+            match node.value {
+                Some(true) => write!(writer, ", color=green")?,
+                Some(false) => write!(writer, ", color=red")?,
+                None => {}
+            }
+            writeln!(writer, "];")?;
         }
 
-        for edge_index in self.graph.edge_indices() {
-            let edge = self.graph.edge_endpoints(edge_index).unwrap();
-            writeln!(writer, "  {} -> {};", edge.0.index(), edge.1.index(),)?;
+        for (from, to) in &self.edges {
+            writeln!(writer, "  {from} -> {to};")?;
         }
 
         write!(writer, "}}")?;
@@ -40,24 +136,34 @@ impl Graph {
 }
 
 /// Helper struct to format node labels
-struct NodeLabel<'a>(&'a Node);
+struct NodeLabel<'a>(&'a SnapshotNode);
 
 impl<'a> Display for NodeLabel<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0.kind {
-            NodeKind::Literal(false) => write!(f, "&#8869;"),
-            NodeKind::Literal(true) => write!(f, "&#8868;"),
-            NodeKind::Variable => {
-                if let Some(label) = &self.0.label {
-                    write!(f, "input: {label}")
-                }
-                else {
-                    write!(f, "input")
+            SnapshotNodeKind::Literal(false) => write!(f, "&#8869;")?,
+            SnapshotNodeKind::Literal(true) => write!(f, "&#8868;")?,
+            SnapshotNodeKind::Variable => write!(f, "input")?,
+            SnapshotNodeKind::Not => write!(f, "&#172;")?,
+            SnapshotNodeKind::And => write!(f, "&#8743;")?,
+            SnapshotNodeKind::Or => write!(f, "&#8744;")?,
+        }
+
+        if let Some(label) = &self.0.label {
+            for line in label.lines() {
+                write!(f, "<br/>")?;
+                for c in line.chars() {
+                    match c {
+                        '<' => write!(f, "&lt;")?,
+                        '>' => write!(f, "&gt;")?,
+                        '&' => write!(f, "&amp;")?,
+                        '"' => write!(f, "&quot;")?,
+                        _ => write!(f, "{c}")?,
+                    }
                 }
             }
-            NodeKind::Not => write!(f, "&#172;"),
-            NodeKind::And => write!(f, "&#8743;"),
-            NodeKind::Or => write!(f, "&#8744;"),
         }
+
+        Ok(())
     }
 }